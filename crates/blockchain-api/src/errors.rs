@@ -1,3 +1,4 @@
+use axum::http::header::{RETRY_AFTER, WWW_AUTHENTICATE};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
@@ -7,7 +8,13 @@ use serde_json::json;
 pub enum ApiError {
     BadRequest(String),
     NotFound(String),
+    /// Missing or wrong `Authorization: Bearer` token.
+    Unauthorized,
+    /// The route is disabled on this node.
+    Forbidden(String),
     Internal(String),
+    /// Client exceeded its rate limit; may retry after this many seconds.
+    TooManyRequests(u64),
 }
 
 impl IntoResponse for ApiError {
@@ -15,7 +22,18 @@ impl IntoResponse for ApiError {
         let (status, message) = match self {
             Self::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             Self::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            Self::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
             Self::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
+            Self::Unauthorized => {
+                let body = Json(json!({ "error": "Missing or invalid API token" }));
+                let header = [(WWW_AUTHENTICATE, "Bearer")];
+                return (StatusCode::UNAUTHORIZED, header, body).into_response();
+            }
+            Self::TooManyRequests(retry_after) => {
+                let body = Json(json!({ "error": "Too many requests" }));
+                let header = [(RETRY_AFTER, retry_after.to_string())];
+                return (StatusCode::TOO_MANY_REQUESTS, header, body).into_response();
+            }
         };
 
         let body = Json(json!({ "error": message }));
@@ -33,6 +51,11 @@ impl From<blockchain_core::errors::CoreError> for ApiError {
             blockchain_core::errors::CoreError::InsufficientBalance { .. }
             | blockchain_core::errors::CoreError::InvalidTransaction(_)
             | blockchain_core::errors::CoreError::InvalidSignature(_)
+            | blockchain_core::errors::CoreError::InvalidMnemonic(_)
+            | blockchain_core::errors::CoreError::InvalidKey(_)
+            | blockchain_core::errors::CoreError::InvalidBlock(_)
+            | blockchain_core::errors::CoreError::UnknownParent(_)
+            | blockchain_core::errors::CoreError::InvalidNonce { .. }
             | blockchain_core::errors::CoreError::DuplicateTransaction(_) => {
                 Self::BadRequest(err.to_string())
             }
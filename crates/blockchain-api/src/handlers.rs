@@ -1,10 +1,27 @@
-use axum::extract::{Path, State};
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::body::{Body, Bytes};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
+use futures::stream;
+use tokio::sync::broadcast::error::RecvError;
 
+use blockchain_core::address;
+use blockchain_core::block::Block;
+use blockchain_core::chain::Blockchain;
+use blockchain_core::merkle::MerkleTree;
+use blockchain_core::receipt::TransactionReceipt;
+use blockchain_core::state::WorldState;
 use blockchain_core::transaction::Transaction;
 use blockchain_core::wallet::Wallet;
 use blockchain_vm::compiler;
 use blockchain_vm::contract::ContractExecutor;
+use blockchain_vm::errors::VmError;
 
 use crate::errors::ApiError;
 use crate::models::*;
@@ -12,8 +29,38 @@ use crate::state::{AppState, NetworkCommand};
 
 // --- Chain ---
 
+/// Blocks returned by `GET /api/chain` when no `limit` is given.
+pub const DEFAULT_CHAIN_PAGE: u64 = 100;
+/// Upper bound on `limit` for `GET /api/chain`.
+pub const MAX_CHAIN_PAGE: u64 = 500;
+
 pub async fn get_chain(
     State(state): State<AppState>,
+    Query(query): Query<ChainPageQuery>,
+) -> Json<ChainPageResponse> {
+    let bc = state.blockchain.lock().await;
+    let total = bc.height();
+    let limit = query.limit.unwrap_or(DEFAULT_CHAIN_PAGE).min(MAX_CHAIN_PAGE);
+    let offset = query.offset.unwrap_or_else(|| total.saturating_sub(limit));
+    let blocks = bc
+        .chain()
+        .iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .cloned()
+        .collect();
+    Json(ChainPageResponse {
+        blocks,
+        total,
+        offset,
+        limit,
+    })
+}
+
+/// The whole chain in one response, as `GET /api/chain` returned before
+/// it was paginated.
+pub async fn get_full_chain(
+    State(state): State<AppState>,
 ) -> Result<Json<serde_json::Value>, ApiError> {
     let bc = state.blockchain.lock().await;
     Ok(Json(serde_json::to_value(bc.chain()).map_err(|e| {
@@ -21,6 +68,76 @@ pub async fn get_chain(
     })?))
 }
 
+/// Snapshot of the whole `Blockchain` (blocks, state and mempool) for
+/// backups and migrations; `POST /api/chain/import` takes it back. The
+/// chain is cloned under the lock and serialized off it, streaming the
+/// JSON as it is written.
+pub async fn export_chain(State(state): State<AppState>) -> Response {
+    let snapshot = state.blockchain.lock().await.clone();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(EXPORT_CHANNEL_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        let errors = tx.clone();
+        let mut writer = BufWriter::with_capacity(EXPORT_CHUNK_BYTES, ChannelWriter(tx));
+        let written = serde_json::to_writer(&mut writer, &snapshot)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.flush());
+        if let Err(e) = written {
+            tracing::warn!("Chain export failed: {}", e);
+            // Ends the body with an error so the client sees a failed transfer
+            let _ = errors.blocking_send(Err(e));
+        }
+    });
+    let body = stream::poll_fn(move |cx| rx.poll_recv(cx));
+    ([(CONTENT_TYPE, "application/json")], Body::from_stream(body)).into_response()
+}
+
+/// Size of the chunks `export_chain` streams.
+const EXPORT_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Chunks `export_chain` buffers ahead of a slow client.
+const EXPORT_CHANNEL_CHUNKS: usize = 4;
+
+/// Sends everything written to it as a body chunk.
+struct ChannelWriter(tokio::sync::mpsc::Sender<io::Result<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Export client went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Adopts the blocks of an exported `Blockchain` if they form a valid
+/// chain that fork choice prefers over ours. State is rebuilt from the
+/// blocks rather than trusted from the snapshot. Refused unless the node
+/// enables imports.
+pub async fn import_chain(
+    State(state): State<AppState>,
+    Json(snapshot): Json<Blockchain>,
+) -> Result<Json<ChainImportResponse>, ApiError> {
+    if !state.allow_chain_import {
+        return Err(ApiError::Forbidden("Chain import is disabled".into()));
+    }
+    let mut bc = state.blockchain.lock().await;
+    // A rejected snapshot is the caller's to fix, not a server fault
+    bc.replace_chain(snapshot.chain().to_vec())
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    state.persist(&bc);
+    let tip = bc.latest_block().clone();
+    state.publish_block(&tip);
+    tracing::info!(height = bc.height(), "Imported chain snapshot");
+    Ok(Json(ChainImportResponse {
+        height: bc.height(),
+        tip_hash: tip.hash,
+    }))
+}
+
 pub async fn validate_chain(
     State(state): State<AppState>,
 ) -> Json<ChainValidResponse> {
@@ -31,16 +148,54 @@ pub async fn validate_chain(
     })
 }
 
+pub async fn chain_hash(
+    State(state): State<AppState>,
+) -> Json<ChainHashResponse> {
+    let bc = state.blockchain.lock().await;
+    Json(ChainHashResponse {
+        hash: bc.chain_hash(),
+        height: bc.height(),
+    })
+}
+
+pub async fn sync_status(
+    State(state): State<AppState>,
+) -> Json<SyncStatusResponse> {
+    let height = state.blockchain.lock().await.height();
+    let status = state.sync.lock().await.clone();
+    Json(SyncStatusResponse { height, status })
+}
+
 // --- Blocks ---
 
+/// Mines pending transactions. The body is optional; without one the
+/// reward goes to the node's configured miner address.
 pub async fn mine_block(
     State(state): State<AppState>,
+    body: Bytes,
 ) -> Result<Json<MineResponse>, ApiError> {
+    let req: MineRequest = if body.is_empty() {
+        MineRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| ApiError::BadRequest(e.to_string()))?
+    };
+    let miner = match req.miner_address {
+        Some(address) if !Wallet::is_valid_address(&address) => {
+            return Err(ApiError::BadRequest(format!(
+                "Invalid miner address: {}",
+                address
+            )));
+        }
+        Some(address) => address,
+        None => state.miner_address.clone(),
+    };
+
     let mut bc = state.blockchain.lock().await;
-    // Use a default miner address; in production this would come from config
     let block = bc
-        .mine_pending("miner-node")
+        .mine_pending(&miner)
         .map_err(|e| ApiError::Internal(e.to_string()))?;
+    state.persist(&bc);
+    state.publish_block(&block);
 
     let response = MineResponse {
         block_index: block.header.index,
@@ -56,6 +211,27 @@ pub async fn mine_block(
     Ok(Json(response))
 }
 
+/// Accepts a block mined outside this node, e.g. by a pool miner.
+pub async fn submit_block(
+    State(state): State<AppState>,
+    Json(block): Json<Block>,
+) -> Result<Json<SubmitBlockResponse>, ApiError> {
+    let mut bc = state.blockchain.lock().await;
+    let response = SubmitBlockResponse {
+        block_index: block.header.index,
+        block_hash: block.hash.clone(),
+    };
+    bc.try_append_block(block.clone())?;
+    state.persist(&bc);
+    state.publish_block(&block);
+
+    if let Some(tx) = &state.network_tx {
+        let _ = tx.send(NetworkCommand::BroadcastBlock(block)).await;
+    }
+
+    Ok(Json(response))
+}
+
 pub async fn get_block(
     State(state): State<AppState>,
     Path(index): Path<u64>,
@@ -69,16 +245,114 @@ pub async fn get_block(
     ))
 }
 
+/// Inclusion proof of a transaction in a block, for light clients that
+/// only keep block headers.
+pub async fn get_transaction_proof(
+    State(state): State<AppState>,
+    Path((index, tx_id)): Path<(u64, String)>,
+) -> Result<Json<MerkleProofResponse>, ApiError> {
+    let bc = state.blockchain.lock().await;
+    let block = bc
+        .get_block(index)
+        .ok_or_else(|| ApiError::NotFound(format!("Block {} not found", index)))?;
+    let position = block
+        .transactions
+        .iter()
+        .position(|tx| tx.id == tx_id)
+        .ok_or_else(|| {
+            ApiError::NotFound(format!("Transaction {} not found in block {}", tx_id, index))
+        })?;
+    let proof = MerkleTree::proof(&block.transactions, position);
+    Ok(Json(MerkleProofResponse {
+        block_index: index,
+        tx_hash: block.transactions[position].hash(),
+        merkle_root: block.header.merkle_root.clone(),
+        proof: proof
+            .into_iter()
+            .map(|(hash, is_left)| ProofStep { hash, is_left })
+            .collect(),
+    }))
+}
+
+/// Streams every new block to the client as a JSON text frame.
+pub async fn ws_blocks(State(state): State<AppState>, ws: WebSocketUpgrade) -> Response {
+    let blocks = state.block_events.subscribe();
+    ws.on_upgrade(move |socket| stream_blocks(socket, blocks))
+}
+
+async fn stream_blocks(
+    mut socket: WebSocket,
+    mut blocks: tokio::sync::broadcast::Receiver<Block>,
+) {
+    loop {
+        tokio::select! {
+            block = blocks.recv() => {
+                let block = match block {
+                    Ok(block) => block,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Block subscriber lagged, skipped {} blocks", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let Ok(text) = serde_json::to_string(&block) else {
+                    continue;
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => {
+                // Client messages are ignored; stop on close or error
+                match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+    tracing::debug!("Block subscriber disconnected");
+}
+
 // --- Transactions ---
 
 pub async fn create_transaction(
     State(state): State<AppState>,
     Json(req): Json<CreateTransactionRequest>,
 ) -> Result<Json<serde_json::Value>, ApiError> {
-    let mut tx = Transaction::new_transfer(req.sender, req.recipient, req.amount);
+    let nonce = resolve_nonce(&state, &req.sender, req.nonce).await;
+    let mut tx =
+        Transaction::new_transfer(req.sender, req.recipient, req.amount)
+            .with_fee(req.fee)
+            .with_nonce(nonce);
+    attach_signature(&mut tx, req.signature, req.public_key)?;
+    submit_transaction(&state, tx).await
+}
+
+/// Submits a transaction the client built and signed in full, keeping its
+/// id, timestamp and nonce as given.
+pub async fn submit_raw_transaction(
+    State(state): State<AppState>,
+    Json(tx): Json<Transaction>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    submit_transaction(&state, tx).await
+}
+
+/// The client's nonce if given, otherwise the sender's next expected one.
+async fn resolve_nonce(state: &AppState, sender: &str, nonce: Option<u64>) -> u64 {
+    match nonce {
+        Some(nonce) => nonce,
+        None => state.blockchain.lock().await.next_nonce(sender),
+    }
+}
 
-    // Apply signature if provided
-    if let (Some(sig_hex), Some(pk_hex)) = (req.signature, req.public_key) {
+/// Applies a hex signature and public key to `tx` if both were provided.
+fn attach_signature(
+    tx: &mut Transaction,
+    signature: Option<String>,
+    public_key: Option<String>,
+) -> Result<(), ApiError> {
+    if let (Some(sig_hex), Some(pk_hex)) = (signature, public_key) {
         tx.signature = Some(
             hex::decode(&sig_hex)
                 .map_err(|e| ApiError::BadRequest(format!("Invalid signature hex: {}", e)))?,
@@ -88,9 +362,28 @@ pub async fn create_transaction(
                 .map_err(|e| ApiError::BadRequest(format!("Invalid public key hex: {}", e)))?,
         );
     }
+    Ok(())
+}
+
+/// Checks `tx`'s signature, then adds it to the mempool, persists, and
+/// gossips it to peers. Unsigned transactions only get through when the
+/// node doesn't require signatures.
+async fn submit_transaction(
+    state: &AppState,
+    tx: Transaction,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if tx.sender == address::SYSTEM {
+        return Err(ApiError::BadRequest(
+            "Transactions from the system account cannot be submitted".into(),
+        ));
+    }
 
     let mut bc = state.blockchain.lock().await;
+    if tx.signature.is_some() || bc.require_signatures() {
+        tx.verify(bc.chain_id())?;
+    }
     bc.add_transaction(tx.clone())?;
+    state.persist_mempool(&bc);
 
     // Broadcast to network
     if let Some(net_tx) = &state.network_tx {
@@ -106,19 +399,83 @@ pub async fn create_transaction(
 
 pub async fn get_pending_transactions(
     State(state): State<AppState>,
-) -> Result<Json<serde_json::Value>, ApiError> {
+    Query(query): Query<PendingQuery>,
+) -> Json<Vec<PendingTransactionEntry>> {
     let bc = state.blockchain.lock().await;
-    Ok(Json(
-        serde_json::to_value(bc.pending_transactions())
-            .map_err(|e| ApiError::Internal(e.to_string()))?,
-    ))
+    let mining_order = bc.mining_order();
+    let per_block = bc.max_block_transactions();
+    let next_block = bc.height();
+    let rank: HashMap<&str, usize> = mining_order
+        .iter()
+        .enumerate()
+        .map(|(rank, tx)| (tx.id.as_str(), rank))
+        .collect();
+    let estimated_block = |id: &str| next_block + (rank[id] / per_block) as u64;
+
+    let transactions = match query.sort.unwrap_or_default() {
+        PendingSort::Fee => mining_order.clone(),
+        PendingSort::Time => bc.pending_transactions().to_vec(),
+    };
+    Json(
+        transactions
+            .into_iter()
+            .enumerate()
+            .map(|(position, transaction)| PendingTransactionEntry {
+                estimated_block: estimated_block(&transaction.id),
+                position,
+                transaction,
+            })
+            .collect(),
+    )
+}
+
+pub async fn get_transaction(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<TransactionLookupResponse>, ApiError> {
+    let bc = state.blockchain.lock().await;
+    let (tx, index) = bc
+        .find_transaction(&id)
+        .ok_or_else(|| ApiError::NotFound(format!("Transaction {} not found", id)))?;
+    let (block, confirmations) = match index {
+        Some(index) => (TransactionBlock::Index(index), bc.height() - index),
+        None => (TransactionBlock::Pending, 0),
+    };
+    Ok(Json(TransactionLookupResponse {
+        transaction: tx.clone(),
+        block,
+        confirmations,
+    }))
+}
+
+/// Receipt of a mined transaction; 404 while it is pending or unknown.
+pub async fn get_transaction_receipt(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<TransactionReceipt>, ApiError> {
+    let bc = state.blockchain.lock().await;
+    bc.receipt(&id)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("No receipt for transaction {}", id)))
 }
 
 // --- Wallet ---
 
-pub async fn create_wallet() -> Json<serde_json::Value> {
-    let wallet = Wallet::new();
-    Json(serde_json::to_value(wallet.info()).unwrap())
+pub async fn create_wallet() -> Result<Json<WalletResponse>, ApiError> {
+    let mnemonic = Wallet::generate_mnemonic();
+    let info = Wallet::from_mnemonic(&mnemonic, 0)?.info();
+    Ok(Json(WalletResponse {
+        address: info.address,
+        public_key: info.public_key,
+        mnemonic,
+    }))
+}
+
+pub async fn new_mnemonic() -> Json<MnemonicResponse> {
+    Json(MnemonicResponse {
+        mnemonic: Wallet::generate_mnemonic(),
+    })
 }
 
 pub async fn get_balance(
@@ -130,52 +487,913 @@ pub async fn get_balance(
     Json(BalanceResponse { address, balance })
 }
 
+pub async fn get_account(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Json<AccountResponse> {
+    let bc = state.blockchain.lock().await;
+    let account = bc.state().get_account(&address);
+    Json(AccountResponse {
+        exists: account.is_some(),
+        balance: account.map_or(0, |a| a.balance),
+        nonce: account.map_or(0, |a| a.nonce),
+        address,
+    })
+}
+
+/// Entries returned by the address history endpoint when no `limit` is
+/// given.
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+/// Upper bound on `limit` for the address history endpoint.
+pub const MAX_HISTORY_LIMIT: usize = 1_000;
+
+/// Transactions sent or received by `address`, newest first: pending ones,
+/// then each block from the tip down. Includes mining rewards.
+pub async fn get_address_history(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<AddressHistoryResponse> {
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).min(MAX_HISTORY_LIMIT);
+    let involves = |tx: &&Transaction| tx.sender == address || tx.recipient == address;
+
+    let bc = state.blockchain.lock().await;
+    let pending = bc
+        .pending_transactions()
+        .iter()
+        .rev()
+        .filter(involves)
+        .map(|tx| HistoryEntry {
+            transaction: tx.clone(),
+            block: TransactionBlock::Pending,
+            timestamp: tx.timestamp,
+        });
+    let confirmed = bc.chain().iter().rev().flat_map(|block| {
+        block.transactions.iter().rev().filter(involves).map(|tx| HistoryEntry {
+            transaction: tx.clone(),
+            block: TransactionBlock::Index(block.header.index),
+            timestamp: block.header.timestamp,
+        })
+    });
+    let transactions = pending.chain(confirmed).take(limit).collect();
+
+    Json(AddressHistoryResponse {
+        address,
+        transactions,
+    })
+}
+
+/// Upper bound on addresses per `POST /api/balances` request.
+pub const MAX_BALANCE_QUERY: usize = 100;
+
+pub async fn get_balances(
+    State(state): State<AppState>,
+    Json(req): Json<BalancesRequest>,
+) -> Result<Json<BalancesResponse>, ApiError> {
+    if req.addresses.len() > MAX_BALANCE_QUERY {
+        return Err(ApiError::BadRequest(format!(
+            "Too many addresses: {} (max {})",
+            req.addresses.len(),
+            MAX_BALANCE_QUERY
+        )));
+    }
+
+    let bc = state.blockchain.lock().await;
+    let world = bc.state();
+    let balances = req
+        .addresses
+        .into_iter()
+        .map(|address| {
+            let balance = world.get_balance(&address);
+            (address, balance)
+        })
+        .collect();
+    Ok(Json(BalancesResponse { balances }))
+}
+
+// --- Names ---
+
+pub async fn register_name(
+    State(state): State<AppState>,
+    Json(req): Json<RegisterNameRequest>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let nonce = resolve_nonce(&state, &req.sender, req.nonce).await;
+    let mut tx = Transaction::new_register_name(req.sender, req.name).with_nonce(nonce);
+    attach_signature(&mut tx, req.signature, req.public_key)?;
+    submit_transaction(&state, tx).await
+}
+
+pub async fn resolve_name(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<NameResponse>, ApiError> {
+    let bc = state.blockchain.lock().await;
+    let address = bc
+        .state()
+        .resolve_name(&name)
+        .ok_or_else(|| ApiError::NotFound(format!("Name {} not registered", name)))?
+        .to_string();
+    Ok(Json(NameResponse { name, address }))
+}
+
+// --- State ---
+
+pub async fn prune_state(
+    State(state): State<AppState>,
+) -> Json<PruneResponse> {
+    let mut bc = state.blockchain.lock().await;
+    let pruned_accounts = bc.state_mut().prune_empty_accounts();
+    tracing::info!("Pruned {} empty accounts", pruned_accounts);
+    Json(PruneResponse {
+        pruned_accounts,
+        total_supply: bc.state().total_supply(),
+    })
+}
+
 // --- Contracts ---
 
+/// Compiles the source and submits it as a `ContractDeploy` transaction.
+/// The contract exists once that transaction is mined.
 pub async fn deploy_contract(
     State(state): State<AppState>,
     Json(req): Json<DeployContractRequest>,
 ) -> Result<Json<ContractDeployResponse>, ApiError> {
     let bytecode = compiler::compile(&req.source_code)?;
-    let mut bc = state.blockchain.lock().await;
-    let address = ContractExecutor::deploy(bc.state_mut(), &req.sender, bytecode)?;
-    Ok(Json(ContractDeployResponse { address }))
+    let nonce = resolve_nonce(&state, &req.sender, req.nonce).await;
+    let address = WorldState::contract_address(&req.sender, nonce);
+    let mut tx = Transaction::new_contract_deploy(req.sender, bytecode)
+        .with_fee(req.fee)
+        .with_nonce(nonce);
+    attach_signature(&mut tx, req.signature, req.public_key)?;
+    let transaction_id = tx.id.clone();
+    let Json(_) = submit_transaction(&state, tx).await?;
+    Ok(Json(ContractDeployResponse {
+        address,
+        transaction_id,
+    }))
 }
 
+/// Runs a call against the current state and returns what it did. Nothing
+/// is persisted; changing contract state takes a mined `ContractCall`
+/// transaction.
 pub async fn call_contract(
     State(state): State<AppState>,
     Json(req): Json<CallContractRequest>,
 ) -> Result<Json<ContractCallResponse>, ApiError> {
     let call_data = req.call_data.unwrap_or_default().into_bytes();
-    let mut bc = state.blockchain.lock().await;
+    let bc = state.blockchain.lock().await;
     let result =
-        ContractExecutor::call(bc.state_mut(), &req.contract_address, &call_data)?;
+        ContractExecutor::simulate(bc.state(), &req.sender, &req.contract_address, &call_data)?;
     Ok(Json(ContractCallResponse {
         logs: result.logs,
+        events: result.events,
         result: result.stack_top,
+        return_value: result.return_value,
         steps_used: result.steps_used,
     }))
 }
 
+/// Dry-runs a call against the current state; nothing is persisted.
+pub async fn estimate_contract_call(
+    State(state): State<AppState>,
+    Json(req): Json<CallContractRequest>,
+) -> Result<Json<ContractEstimateResponse>, ApiError> {
+    let call_data = req.call_data.unwrap_or_default().into_bytes();
+    let bc = state.blockchain.lock().await;
+    if bc.state().get_contract(&req.contract_address).is_none() {
+        return Err(ApiError::NotFound(format!(
+            "Contract {} not found",
+            req.contract_address
+        )));
+    }
+    let simulated =
+        ContractExecutor::simulate(bc.state(), &req.sender, &req.contract_address, &call_data);
+    let response = match simulated {
+        Ok(result) => ContractEstimateResponse {
+            success: true,
+            steps_used: Some(result.steps_used),
+            gas_used: Some(result.gas_used),
+            return_value: result.return_value,
+            error: None,
+        },
+        Err(VmError::Reverted { reason, gas_used }) => ContractEstimateResponse {
+            success: false,
+            steps_used: None,
+            gas_used: Some(gas_used),
+            return_value: None,
+            error: Some(reason.to_string()),
+        },
+        Err(e) => ContractEstimateResponse {
+            success: false,
+            steps_used: None,
+            gas_used: None,
+            return_value: None,
+            error: Some(e.to_string()),
+        },
+    };
+    Ok(Json(response))
+}
+
+/// Dry-runs a contract, or source compiled on the fly, and returns every
+/// instruction it executed. Nothing is persisted.
+pub async fn trace_contract(
+    State(state): State<AppState>,
+    Json(req): Json<TraceContractRequest>,
+) -> Result<Json<ContractTraceResponse>, ApiError> {
+    let bc = state.blockchain.lock().await;
+    let (result, trace) = match (&req.contract_address, &req.source_code) {
+        (Some(address), None) => {
+            if bc.state().get_contract(address).is_none() {
+                return Err(ApiError::NotFound(format!("Contract {} not found", address)));
+            }
+            ContractExecutor::simulate_traced(bc.state(), &req.sender, address, &[])?
+        }
+        (None, Some(source)) => {
+            let bytecode = compiler::compile(source)?;
+            ContractExecutor::trace_bytecode(bc.state(), &req.sender, &bytecode)?
+        }
+        _ => {
+            return Err(ApiError::BadRequest(
+                "Give exactly one of contract_address and source_code".into(),
+            ))
+        }
+    };
+    Ok(Json(ContractTraceResponse {
+        result: result.stack_top,
+        return_value: result.return_value,
+        steps_used: result.steps_used,
+        gas_used: result.gas_used,
+        trace: trace
+            .into_iter()
+            .map(|entry| TraceStep {
+                pc: entry.pc,
+                opcode: entry.opcode.mnemonic().to_string(),
+                stack_depth: entry.stack_depth,
+                gas_used: entry.gas_used,
+            })
+            .collect(),
+    }))
+}
+
+pub async fn get_contract(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Query(query): Query<ContractQuery>,
+) -> Result<Json<ContractStateResponse>, ApiError> {
+    let bc = state.blockchain.lock().await;
+    let contract = bc
+        .state()
+        .get_contract(&address)
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} not found", address)))?;
+    Ok(Json(ContractStateResponse {
+        owner: contract.owner.clone(),
+        storage: contract.storage.iter().map(|(k, v)| (*k, *v)).collect(),
+        bytecode_len: contract.bytecode.len(),
+        events: contract
+            .events
+            .iter()
+            .filter(|event| query.topic.is_none_or(|topic| event.topic == topic))
+            .copied()
+            .collect(),
+        address,
+    }))
+}
+
 // --- Peers & Node ---
 
 pub async fn get_peers(
     State(state): State<AppState>,
-) -> Json<serde_json::Value> {
-    let count = *state.peer_count.lock().await;
-    Json(serde_json::json!({ "peer_count": count }))
+) -> Json<PeersResponse> {
+    let mut peer_ids: Vec<String> = state.connected_peers.lock().await.iter().cloned().collect();
+    peer_ids.sort();
+    let peers = state.peers.lock().await.values().cloned().collect();
+    Json(PeersResponse {
+        peer_count: peer_ids.len(),
+        peer_ids,
+        peers,
+    })
+}
+
+/// Liveness: answers as soon as the API is serving.
+pub async fn health() -> Json<HealthResponse> {
+    Json(HealthResponse { status: "ok" })
+}
+
+/// Readiness: 503 until a peer has connected or the initial chain sync
+/// has been processed.
+pub async fn ready(State(state): State<AppState>) -> (StatusCode, Json<HealthResponse>) {
+    if state.is_ready() {
+        (StatusCode::OK, Json(HealthResponse { status: "ok" }))
+    } else {
+        let body = Json(HealthResponse { status: "not_ready" });
+        (StatusCode::SERVICE_UNAVAILABLE, body)
+    }
 }
 
 pub async fn node_info(
     State(state): State<AppState>,
 ) -> Json<NodeInfoResponse> {
     let bc = state.blockchain.lock().await;
-    let peer_count = *state.peer_count.lock().await;
+    let peer_count = state.connected_peers.lock().await.len();
     Json(NodeInfoResponse {
+        chain_id: bc.chain_id(),
         chain_length: bc.height(),
         difficulty: bc.difficulty(),
         mining_reward: bc.mining_reward(),
         pending_transactions: bc.pending_transactions().len(),
         peer_count,
+        peer_id: state.peer_id.clone(),
+    })
+}
+
+/// What this node runs and speaks, for clients and peers checking
+/// compatibility.
+pub async fn node_version(State(state): State<AppState>) -> Json<NodeVersionResponse> {
+    let chain_id = state.blockchain.lock().await.chain_id();
+    Json(NodeVersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: state.protocol_version.clone(),
+        chain_id,
+        peer_id: state.peer_id.clone(),
+        gossip_topics: state.gossip_topics.clone(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain_core::chain::Blockchain;
+    use blockchain_core::receipt::ReceiptStatus;
+    use blockchain_core::state::ContractEvent;
+    use blockchain_core::transaction::DEFAULT_CHAIN_ID;
+
+    #[tokio::test]
+    async fn test_bulk_balances() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        {
+            let mut bc = state.blockchain.lock().await;
+            bc.state_mut().credit("alice", 10);
+            bc.state_mut().credit("bob", 25);
+        }
+
+        let req = BalancesRequest {
+            addresses: vec!["alice".into(), "bob".into(), "carol".into()],
+        };
+        let Json(res) = get_balances(State(state), Json(req)).await.unwrap();
+
+        assert_eq!(res.balances.len(), 3);
+        assert_eq!(res.balances["alice"], 10);
+        assert_eq!(res.balances["bob"], 25);
+        assert_eq!(res.balances["carol"], 0);
+    }
+
+    async fn externally_mined_block(state: &AppState, previous_hash: Option<String>) -> Block {
+        let bc = state.blockchain.lock().await;
+        let reward = Transaction::new_transfer("system".into(), "pool-miner".into(), 50);
+        let previous_hash = previous_hash.unwrap_or_else(|| bc.latest_block().hash.clone());
+        let mut block = Block::new(bc.height(), previous_hash, vec![reward], bc.difficulty());
+        block.mine().unwrap();
+        block
+    }
+
+    #[tokio::test]
+    async fn test_submit_external_block() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        let block = externally_mined_block(&state, None).await;
+
+        let Json(res) = submit_block(State(state.clone()), Json(block.clone()))
+            .await
+            .unwrap();
+        assert_eq!(res.block_index, 1);
+        assert_eq!(res.block_hash, block.hash);
+
+        let bc = state.blockchain.lock().await;
+        assert_eq!(bc.height(), 2);
+        assert_eq!(bc.state().get_balance("pool-miner"), 50);
+        assert!(bc.is_chain_valid());
+    }
+
+    #[tokio::test]
+    async fn test_submit_block_with_bad_previous_hash() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        let block = externally_mined_block(&state, Some("f".repeat(64))).await;
+
+        let result = submit_block(State(state.clone()), Json(block)).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+        assert_eq!(state.blockchain.lock().await.height(), 1);
+    }
+
+    /// Deploys `source` from a fresh sender and mines it, returning the
+    /// sender and the deploy response.
+    async fn deploy_and_mine(state: &AppState, source: &str) -> (String, ContractDeployResponse) {
+        let sender = Wallet::new().address;
+        let req = DeployContractRequest {
+            sender: sender.clone(),
+            source_code: source.into(),
+            fee: 0,
+            nonce: None,
+            signature: None,
+            public_key: None,
+        };
+        let Json(deployed) = deploy_contract(State(state.clone()), Json(req)).await.unwrap();
+        state.blockchain.lock().await.mine_pending("miner").unwrap();
+        (sender, deployed)
+    }
+
+    #[tokio::test]
+    async fn test_deploy_contract_via_transaction() {
+        let state = AppState::new(Blockchain::new(1, 50).with_require_signatures(false));
+        let sender = Wallet::new().address;
+        let req = DeployContractRequest {
+            sender: sender.clone(),
+            source_code: "PUSH 1\nHALT".into(),
+            fee: 0,
+            nonce: None,
+            signature: None,
+            public_key: None,
+        };
+        let Json(deployed) = deploy_contract(State(state.clone()), Json(req)).await.unwrap();
+        let read = || {
+            let query = Query(ContractQuery::default());
+            get_contract(State(state.clone()), Path(deployed.address.clone()), query)
+        };
+        assert!(matches!(read().await, Err(ApiError::NotFound(_))));
+        {
+            let bc = state.blockchain.lock().await;
+            assert_eq!(bc.pending_transactions()[0].id, deployed.transaction_id);
+        }
+
+        state.blockchain.lock().await.mine_pending("miner").unwrap();
+        let Json(res) = read().await.unwrap();
+        assert_eq!(res.owner, sender);
+        assert_eq!(res.bytecode_len, 10);
+    }
+
+    /// Chain that runs mined contract calls, without signature checks.
+    fn state_with_runtime() -> AppState {
+        let bc = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_contract_runtime(std::sync::Arc::new(ContractExecutor));
+        AppState::new(bc)
+    }
+
+    /// Mines a `ContractCall` from `sender` to `contract`.
+    async fn mine_call(state: &AppState, sender: &str, contract: &str) {
+        let mut bc = state.blockchain.lock().await;
+        let nonce = bc.next_nonce(sender);
+        let call = Transaction::new_contract_call(sender.into(), contract.into(), Vec::new())
+            .with_nonce(nonce);
+        bc.add_transaction(call).unwrap();
+        bc.mine_pending("miner").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_contract_state_after_call() {
+        let state = state_with_runtime();
+        let (alice, deployed) = deploy_and_mine(&state, "PUSH 7\nPUSH 42\nSTORE\nHALT").await;
+
+        // The endpoint only dry-runs the call
+        let req = CallContractRequest {
+            sender: alice.clone(),
+            contract_address: deployed.address.clone(),
+            call_data: None,
+        };
+        let Json(called) = call_contract(State(state.clone()), Json(req)).await.unwrap();
+        assert!(called.result.is_none());
+        {
+            let bc = state.blockchain.lock().await;
+            assert!(bc.state().get_contract(&deployed.address).unwrap().storage.is_empty());
+        }
+
+        mine_call(&state, &alice, &deployed.address).await;
+
+        let query = Query(ContractQuery::default());
+        let Json(res) = get_contract(State(state.clone()), Path(deployed.address.clone()), query)
+            .await
+            .unwrap();
+        assert_eq!(res.address, deployed.address);
+        assert_eq!(res.owner, alice);
+        assert_eq!(res.storage.get(&7), Some(&42));
+        assert_eq!(res.bytecode_len, 20);
+
+        let query = Query(ContractQuery::default());
+        let result = get_contract(State(state), Path("0xmissing".into()), query).await;
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_contract_events_filtered_by_topic() {
+        let state = state_with_runtime();
+        let source = "PUSH 1\nPUSH 100\nEMIT\nPUSH 2\nPUSH 200\nEMIT\nHALT";
+        let (alice, deployed) = deploy_and_mine(&state, source).await;
+
+        let req = CallContractRequest {
+            sender: alice.clone(),
+            contract_address: deployed.address.clone(),
+            call_data: None,
+        };
+        let Json(called) = call_contract(State(state.clone()), Json(req)).await.unwrap();
+        assert_eq!(called.events.len(), 2);
+        mine_call(&state, &alice, &deployed.address).await;
+
+        let read = |topic| {
+            let query = Query(ContractQuery { topic });
+            get_contract(State(state.clone()), Path(deployed.address.clone()), query)
+        };
+        let Json(all) = read(None).await.unwrap();
+        assert_eq!(all.events, called.events);
+        let Json(filtered) = read(Some(2)).await.unwrap();
+        assert_eq!(filtered.events, vec![ContractEvent { topic: 2, data: 200 }]);
+    }
+
+    #[tokio::test]
+    async fn test_estimate_matches_call_and_persists_nothing() {
+        let state = AppState::new(Blockchain::new(1, 50).with_require_signatures(false));
+        let source = "PUSH 7\nPUSH 42\nSTORE\nPUSH 5\nRETURN";
+        let (alice, deployed) = deploy_and_mine(&state, source).await;
+        let request = || CallContractRequest {
+            sender: alice.clone(),
+            contract_address: deployed.address.clone(),
+            call_data: None,
+        };
+
+        let Json(estimate) =
+            estimate_contract_call(State(state.clone()), Json(request())).await.unwrap();
+        assert!(estimate.success);
+        assert_eq!(estimate.return_value, Some(5));
+        {
+            let bc = state.blockchain.lock().await;
+            assert!(bc.state().get_contract(&deployed.address).unwrap().storage.is_empty());
+        }
+
+        let Json(called) = call_contract(State(state.clone()), Json(request())).await.unwrap();
+        assert_eq!(estimate.steps_used, Some(called.steps_used));
+    }
+
+    #[tokio::test]
+    async fn test_estimate_reports_trap_reason() {
+        let state = AppState::new(Blockchain::new(1, 50).with_require_signatures(false));
+        let (alice, deployed) = deploy_and_mine(&state, "PUSH 9\nREVERT").await;
+        let req = CallContractRequest {
+            sender: alice.clone(),
+            contract_address: deployed.address,
+            call_data: None,
+        };
+
+        let Json(estimate) = estimate_contract_call(State(state), Json(req)).await.unwrap();
+        assert!(!estimate.success);
+        assert!(estimate.gas_used.is_some());
+        assert!(estimate.error.unwrap().contains("code 9"));
+    }
+
+    #[tokio::test]
+    async fn test_trace_deployed_contract_and_source() {
+        let state = AppState::new(Blockchain::new(1, 50).with_require_signatures(false));
+        let source = "PUSH 2\nPUSH 3\nADD\nRETURN";
+        let (alice, deployed) = deploy_and_mine(&state, source).await;
+
+        let req = TraceContractRequest {
+            sender: alice.clone(),
+            contract_address: Some(deployed.address),
+            source_code: None,
+        };
+        let Json(traced) = trace_contract(State(state.clone()), Json(req)).await.unwrap();
+        assert_eq!(traced.return_value, Some(5));
+        assert_eq!(traced.trace.len() as u64, traced.steps_used);
+        let opcodes: Vec<_> = traced.trace.iter().map(|step| step.opcode.as_str()).collect();
+        assert_eq!(opcodes, ["PUSH", "PUSH", "ADD", "RETURN"]);
+
+        let req = TraceContractRequest {
+            sender: alice.clone(),
+            contract_address: None,
+            source_code: Some(source.into()),
+        };
+        let Json(fresh) = trace_contract(State(state.clone()), Json(req)).await.unwrap();
+        assert_eq!(fresh.trace.len(), traced.trace.len());
+        assert_eq!(fresh.gas_used, traced.gas_used);
+
+        let req = TraceContractRequest {
+            sender: alice,
+            contract_address: None,
+            source_code: None,
+        };
+        let result = trace_contract(State(state), Json(req)).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+    }
+
+    async fn chain_page(
+        state: &AppState,
+        offset: Option<u64>,
+        limit: Option<u64>,
+    ) -> ChainPageResponse {
+        let query = ChainPageQuery { offset, limit };
+        let Json(page) = get_chain(State(state.clone()), Query(query)).await;
+        page
+    }
+
+    #[tokio::test]
+    async fn test_chain_pagination() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        {
+            let mut bc = state.blockchain.lock().await;
+            for _ in 0..4 {
+                bc.mine_pending("miner").unwrap();
+            }
+        }
+
+        let page = chain_page(&state, Some(1), Some(2)).await;
+        let indices: Vec<_> = page.blocks.iter().map(|b| b.header.index).collect();
+        assert_eq!(indices, vec![1, 2]);
+        assert_eq!((page.total, page.offset, page.limit), (5, 1, 2));
+
+        let page = chain_page(&state, None, Some(2)).await;
+        let indices: Vec<_> = page.blocks.iter().map(|b| b.header.index).collect();
+        assert_eq!(indices, vec![3, 4]);
+
+        let page = chain_page(&state, Some(10), None).await;
+        assert!(page.blocks.is_empty());
+        assert_eq!(page.total, 5);
+
+        let page = chain_page(&state, None, Some(10_000)).await;
+        assert_eq!(page.limit, MAX_CHAIN_PAGE);
+        assert_eq!(page.blocks.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_mine_block_miner_address() {
+        let configured = Wallet::new().address;
+        let state =
+            AppState::new(Blockchain::new(1, 50)).with_miner_address(configured.clone());
+
+        let Json(mined) = mine_block(State(state.clone()), Bytes::new()).await.unwrap();
+        assert_eq!(mined.block_index, 1);
+        assert_eq!(state.blockchain.lock().await.state().get_balance(&configured), 50);
+
+        let other = Wallet::new().address;
+        let body = serde_json::to_vec(&serde_json::json!({ "miner_address": other })).unwrap();
+        let Json(mined) = mine_block(State(state.clone()), Bytes::from(body)).await.unwrap();
+        assert_eq!(mined.block_index, 2);
+        let bc = state.blockchain.lock().await;
+        assert_eq!(bc.state().get_balance(&other), 50);
+        assert_eq!(bc.state().get_balance(&configured), 50);
+        drop(bc);
+
+        let body = br#"{"miner_address": "not-an-address"}"#.to_vec();
+        let result = mine_block(State(state.clone()), Bytes::from(body)).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+        assert_eq!(state.blockchain.lock().await.height(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_created_wallet_recovers_from_mnemonic() {
+        let Json(res) = create_wallet().await.unwrap();
+        let recovered = Wallet::from_mnemonic(&res.mnemonic, 0).unwrap();
+        assert_eq!(recovered.address, res.address);
+        assert_eq!(recovered.public_key_hex(), res.public_key);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_balances_cap() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        let req = BalancesRequest {
+            addresses: (0..=MAX_BALANCE_QUERY).map(|i| format!("addr-{}", i)).collect(),
+        };
+        let result = get_balances(State(state), Json(req)).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_transaction_lookup() {
+        let state = AppState::new(Blockchain::new(1, 50).with_require_signatures(false));
+        let alice = Wallet::new().address;
+        let bob = Wallet::new().address;
+        let (mined, pending) = {
+            let mut bc = state.blockchain.lock().await;
+            bc.state_mut().credit(&alice, 100);
+            let mined = Transaction::new_transfer(alice.clone(), bob.clone(), 10);
+            bc.add_transaction(mined.clone()).unwrap();
+            bc.mine_pending("miner").unwrap();
+            bc.mine_pending("miner").unwrap();
+            let pending = Transaction::new_transfer(alice.clone(), bob.clone(), 5).with_nonce(1);
+            bc.add_transaction(pending.clone()).unwrap();
+            (mined, pending)
+        };
+
+        let Json(res) = get_transaction(State(state.clone()), Path(mined.id.clone()))
+            .await
+            .unwrap();
+        assert_eq!(res.transaction.id, mined.id);
+        assert_eq!(res.block, TransactionBlock::Index(1));
+        assert_eq!(res.confirmations, 2);
+
+        let Json(res) = get_transaction(State(state.clone()), Path(pending.id.clone()))
+            .await
+            .unwrap();
+        assert_eq!(res.block, TransactionBlock::Pending);
+        assert_eq!(res.confirmations, 0);
+        assert_eq!(serde_json::to_value(&res).unwrap()["block"], "pending");
+
+        let result = get_transaction(State(state.clone()), Path("missing".into())).await;
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+
+        let Json(receipt) = get_transaction_receipt(State(state.clone()), Path(mined.id.clone()))
+            .await
+            .unwrap();
+        assert_eq!(receipt.tx_id, mined.id);
+        assert_eq!(receipt.block_index, 1);
+        assert_eq!(receipt.status, ReceiptStatus::Success);
+        assert_eq!(serde_json::to_value(&receipt).unwrap()["status"], "success");
+        let result = get_transaction_receipt(State(state), Path(pending.id.clone())).await;
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_address_history() {
+        let state = AppState::new(Blockchain::new(1, 50).with_require_signatures(false));
+        let miner = Wallet::new().address;
+        let bob = Wallet::new().address;
+        let transfer = {
+            let mut bc = state.blockchain.lock().await;
+            bc.mine_pending(&miner).unwrap();
+            let transfer = Transaction::new_transfer(miner.clone(), bob.clone(), 10);
+            bc.add_transaction(transfer.clone()).unwrap();
+            bc.mine_pending(&bob).unwrap();
+            transfer
+        };
+
+        let history = |limit| {
+            let query = HistoryQuery { limit };
+            get_address_history(State(state.clone()), Path(miner.clone()), Query(query))
+        };
+        let Json(res) = history(None).await;
+        assert_eq!(res.transactions.len(), 2);
+        assert_eq!(res.transactions[0].transaction.id, transfer.id);
+        assert_eq!(res.transactions[0].block, TransactionBlock::Index(2));
+        assert_eq!(res.transactions[1].transaction.sender, "system");
+        assert_eq!(res.transactions[1].block, TransactionBlock::Index(1));
+        assert!(res.transactions[0].timestamp >= res.transactions[1].timestamp);
+
+        let Json(res) = history(Some(1)).await;
+        assert_eq!(res.transactions.len(), 1);
+        assert_eq!(res.transactions[0].transaction.id, transfer.id);
+    }
+
+    fn signed_transfer(wallet: &Wallet, amount: u64) -> Transaction {
+        let recipient = Wallet::new().address;
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), recipient, amount);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        tx
+    }
+
+    #[tokio::test]
+    async fn test_submit_raw_transaction() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        let wallet = Wallet::new();
+        state.blockchain.lock().await.state_mut().credit(&wallet.address, 100);
+
+        let tx = signed_transfer(&wallet, 10);
+        let Json(res) = submit_raw_transaction(State(state.clone()), Json(tx.clone()))
+            .await
+            .unwrap();
+        assert_eq!(res["id"], tx.id);
+        assert_eq!(state.blockchain.lock().await.pending_transactions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_signature_rejected() {
+        // Even a node that doesn't require signatures rejects a bad one
+        let state = AppState::new(Blockchain::new(1, 50).with_require_signatures(false));
+        let wallet = Wallet::new();
+        state.blockchain.lock().await.state_mut().credit(&wallet.address, 100);
+
+        let mut tx = signed_transfer(&wallet, 10);
+        tx.amount = 90;
+        let result = submit_raw_transaction(State(state.clone()), Json(tx)).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+
+        let tx = signed_transfer(&Wallet::new(), 10);
+        let req = CreateTransactionRequest {
+            sender: wallet.address.clone(),
+            recipient: tx.recipient.clone(),
+            amount: 10,
+            fee: 0,
+            nonce: None,
+            signature: tx.signature.as_deref().map(hex::encode),
+            public_key: tx.public_key.as_deref().map(hex::encode),
+        };
+        let result = create_transaction(State(state.clone()), Json(req)).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+
+        let reward = Transaction::new_transfer("system".into(), wallet.address.clone(), 1_000);
+        let result = submit_raw_transaction(State(state.clone()), Json(reward)).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+        assert!(state.blockchain.lock().await.pending_transactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_account() {
+        let state = AppState::new(Blockchain::new(1, 50).with_require_signatures(false));
+        let alice = Wallet::new().address;
+        {
+            let mut bc = state.blockchain.lock().await;
+            bc.state_mut().credit(&alice, 100);
+            let tx = Transaction::new_transfer(alice.clone(), Wallet::new().address, 10);
+            bc.add_transaction(tx).unwrap();
+            bc.mine_pending("miner").unwrap();
+        }
+
+        let Json(res) = get_account(State(state.clone()), Path(alice.clone())).await;
+        assert!(res.exists);
+        assert_eq!((res.balance, res.nonce), (90, 1));
+
+        let Json(res) = get_account(State(state), Path(Wallet::new().address)).await;
+        assert!(!res.exists);
+        assert_eq!((res.balance, res.nonce), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_pending_sorted_by_fee_or_time() {
+        let state = AppState::new(
+            Blockchain::new(1, 50)
+                .with_require_signatures(false)
+                .with_max_block_transactions(2),
+        );
+        let senders: Vec<String> = (0..3).map(|_| Wallet::new().address).collect();
+        let bob = Wallet::new().address;
+        let ids: Vec<String> = {
+            let mut bc = state.blockchain.lock().await;
+            [1, 5, 3]
+                .iter()
+                .zip(&senders)
+                .map(|(&fee, sender)| {
+                    bc.state_mut().credit(sender, 100);
+                    let tx = Transaction::new_transfer(sender.clone(), bob.clone(), 10)
+                        .with_fee(fee);
+                    bc.add_transaction(tx.clone()).unwrap();
+                    tx.id
+                })
+                .collect()
+        };
+
+        let pending = |sort| {
+            get_pending_transactions(State(state.clone()), Query(PendingQuery { sort }))
+        };
+        let Json(by_fee) = pending(Some(PendingSort::Fee)).await;
+        let fees: Vec<u64> = by_fee.iter().map(|e| e.transaction.fee).collect();
+        assert_eq!(fees, vec![5, 3, 1]);
+        let blocks: Vec<u64> = by_fee.iter().map(|e| e.estimated_block).collect();
+        assert_eq!(blocks, vec![1, 1, 2]);
+
+        let Json(by_time) = pending(None).await;
+        let order: Vec<&str> = by_time.iter().map(|e| e.transaction.id.as_str()).collect();
+        assert_eq!(order, ids);
+        assert_eq!(by_time[0].position, 0);
+        assert_eq!(by_time[0].estimated_block, 2);
+
+        // Sorting is a view; the mempool keeps arrival order
+        let bc = state.blockchain.lock().await;
+        assert_eq!(bc.pending_transactions()[0].id, ids[0]);
+    }
+
+    #[tokio::test]
+    async fn test_node_version() {
+        let state = AppState::new(Blockchain::new(1, 50))
+            .with_peer_id("peer-1")
+            .with_protocol("1.0.0", &["blocks", "txs"]);
+        let Json(version) = node_version(State(state)).await;
+        assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(version.protocol_version.as_deref(), Some("1.0.0"));
+        assert_eq!(version.chain_id, DEFAULT_CHAIN_ID);
+        assert_eq!(version.peer_id.as_deref(), Some("peer-1"));
+        assert_eq!(version.gossip_topics, ["blocks", "txs"]);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_proof_verifies_against_block_root() {
+        let mut bc = Blockchain::new(1, 50).with_require_signatures(false);
+        let alice = Wallet::new().address;
+        let bob = Wallet::new().address;
+        bc.state_mut().credit(&alice, 100);
+        for nonce in 0..4 {
+            let tx = Transaction::new_transfer(alice.clone(), bob.clone(), 1).with_nonce(nonce);
+            bc.add_transaction(tx).unwrap();
+        }
+        let block = bc.mine_pending("miner").unwrap();
+        let state = AppState::new(bc);
+
+        for tx in &block.transactions {
+            let path = Path((1, tx.id.clone()));
+            let Json(res) = get_transaction_proof(State(state.clone()), path).await.unwrap();
+            assert_eq!(res.merkle_root, block.header.merkle_root);
+            assert_eq!(res.tx_hash, tx.hash());
+            let proof: Vec<_> = res.proof.into_iter().map(|s| (s.hash, s.is_left)).collect();
+            assert!(MerkleTree::verify_proof(&res.tx_hash, &proof, &res.merkle_root));
+        }
+
+        let missing = get_transaction_proof(State(state.clone()), Path((1, "nope".into()))).await;
+        assert!(matches!(missing, Err(ApiError::NotFound(_))));
+        let tx_id = block.transactions[0].id.clone();
+        let missing = get_transaction_proof(State(state), Path((9, tx_id))).await;
+        assert!(matches!(missing, Err(ApiError::NotFound(_))));
+    }
+}
@@ -1,5 +1,7 @@
 pub mod errors;
 pub mod handlers;
+pub mod middleware;
 pub mod models;
 pub mod routes;
+pub mod rpc;
 pub mod state;
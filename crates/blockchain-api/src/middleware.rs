@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderName, HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+use crate::state::AppState;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Tags each request with an id (the client's `X-Request-Id` if sent,
+/// otherwise a fresh UUID), runs the handler inside a span carrying it,
+/// and echoes it back on the response.
+pub async fn request_id(req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = %req.uri().path(),
+    );
+
+    let mut response = next.run(req).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Which browser origins may call the API, and with what. With no origins
+/// no CORS headers are sent; `*` allows any origin.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+}
+
+impl CorsConfig {
+    /// Builds the layer, or `None` when no origins are configured.
+    pub fn layer(&self) -> Result<Option<CorsLayer>, String> {
+        if self.origins.is_empty() {
+            return Ok(None);
+        }
+        let origins = if self.origins.iter().any(|origin| origin == "*") {
+            AllowOrigin::any()
+        } else {
+            let origins = self
+                .origins
+                .iter()
+                .map(|origin| {
+                    HeaderValue::from_str(origin)
+                        .map_err(|_| format!("Invalid CORS origin: {}", origin))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            AllowOrigin::list(origins)
+        };
+        let methods = self
+            .methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map_err(|_| format!("Invalid CORS method: {}", method))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let headers = self
+            .headers
+            .iter()
+            .map(|header| {
+                HeaderName::from_bytes(header.as_bytes())
+                    .map_err(|_| format!("Invalid CORS header: {}", header))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(
+            CorsLayer::new()
+                .allow_origin(origins)
+                .allow_methods(methods)
+                .allow_headers(headers)
+                .expose_headers([HeaderName::from_static(REQUEST_ID_HEADER)]),
+        ))
+    }
+}
+
+/// Clients tracked per limiter before idle (fully refilled) buckets are
+/// dropped.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Per-client-IP token bucket: each request takes a token, and tokens
+/// refill at `per_second` up to `burst`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// `per_second` and `burst` are raised to at least 1.
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        Self {
+            per_second: per_second.max(1) as f64,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for `client`, or returns how long until one is free.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+}
+
+/// Rejects the request with 429 once the client's bucket in `limiter` is
+/// empty. Clients are told apart by peer address, so the server must be
+/// run with `into_make_service_with_connect_info::<SocketAddr>`; without
+/// it every request shares one bucket.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    limiter.check(client).map_err(|wait| {
+        tracing::debug!(%client, path = %req.uri().path(), "Rate limited");
+        ApiError::TooManyRequests(wait.as_secs_f64().ceil().max(1.0) as u64)
+    })?;
+    Ok(next.run(req).await)
+}
+
+/// Rejects the request with 401 unless it carries
+/// `Authorization: Bearer <token>` matching `state.api_token`. Passes
+/// everything through when no token is configured.
+pub async fn require_token(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if let Some(expected) = &state.api_token {
+        let given = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if !given.is_some_and(|given| tokens_match(given.as_bytes(), expected.as_bytes())) {
+            tracing::debug!(path = %req.uri().path(), "Rejected request without a valid token");
+            return Err(ApiError::Unauthorized);
+        }
+    }
+    Ok(next.run(req).await)
+}
+
+/// Compares in time independent of where the tokens differ.
+fn tokens_match(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::header::{
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_HEADERS,
+        ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, RETRY_AFTER,
+    };
+    use axum::http::StatusCode;
+    use blockchain_core::chain::Blockchain;
+    use tower::ServiceExt;
+
+    use crate::routes::create_router;
+    use crate::state::AppState;
+
+    fn router() -> axum::Router {
+        create_router(AppState::new(Blockchain::new(1, 50)))
+    }
+
+    #[tokio::test]
+    async fn test_client_request_id_is_echoed() {
+        let req = Request::builder()
+            .uri("/api/node/info")
+            .header(REQUEST_ID_HEADER, "client-abc-123")
+            .body(Body::empty())
+            .unwrap();
+
+        let res = router().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[REQUEST_ID_HEADER], "client-abc-123");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_generated_when_missing() {
+        let req = Request::builder()
+            .uri("/api/node/info")
+            .body(Body::empty())
+            .unwrap();
+
+        let res = router().oneshot(req).await.unwrap();
+        let id = res.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(Uuid::parse_str(id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cors_headers_only_for_allowed_origins() {
+        let cors = CorsConfig {
+            origins: vec!["https://app.example".into()],
+            methods: vec!["get".into(), "post".into()],
+            headers: vec!["content-type".into()],
+        };
+        let state = AppState::new(Blockchain::new(1, 50)).with_cors(cors.layer().unwrap().unwrap());
+        let router = create_router(state);
+        let request = |origin: &str| {
+            Request::builder()
+                .uri("/api/node/info")
+                .header(ORIGIN, origin)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = router.clone().oneshot(request("https://app.example")).await.unwrap();
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example");
+        let res = router.clone().oneshot(request("https://evil.example")).await.unwrap();
+        assert!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let preflight = Request::builder()
+            .method("OPTIONS")
+            .uri("/api/transactions")
+            .header(ORIGIN, "https://app.example")
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())
+            .unwrap();
+        let res = router.oneshot(preflight).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example");
+        let methods = res.headers()[ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap();
+        assert!(methods.contains("POST"), "{}", methods);
+    }
+
+    #[test]
+    fn test_cors_disabled_without_origins() {
+        let cors = CorsConfig {
+            origins: Vec::new(),
+            methods: vec!["GET".into()],
+            headers: Vec::new(),
+        };
+        assert!(cors.layer().unwrap().is_none());
+        let cors = CorsConfig {
+            origins: vec!["*".into()],
+            methods: vec!["NOT A METHOD".into()],
+            headers: Vec::new(),
+        };
+        assert!(cors.layer().is_err());
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = RateLimiter::new(2, 3);
+        let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at(client, start).is_ok());
+        }
+        let wait = limiter.check_at(client, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        assert!(limiter.check_at(other, start).is_ok());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_at(client, later).is_ok());
+        assert!(limiter.check_at(client, later).is_err());
+
+        // Refill stops at the burst size
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.check_at(client, much_later).is_ok());
+        }
+        assert!(limiter.check_at(client, much_later).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exceeding_burst_returns_429_with_retry_after() {
+        let state = AppState::new(Blockchain::new(1, 50))
+            .with_mining_rate_limit(RateLimiter::new(1, 2));
+        let router = create_router(state);
+        let request = |uri: &str, ip: [u8; 4]| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .extension(ConnectInfo(SocketAddr::from((ip, 4000))))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for _ in 0..2 {
+            let res = router.clone().oneshot(request("/api/blocks/mine", [1, 1, 1, 1])).await;
+            assert_eq!(res.unwrap().status(), StatusCode::OK);
+        }
+        let res = router.clone().oneshot(request("/api/blocks/mine", [1, 1, 1, 1])).await;
+        let res = res.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[RETRY_AFTER], "1");
+
+        // Other clients and other routes are unaffected
+        let res = router.clone().oneshot(request("/api/blocks/mine", [2, 2, 2, 2])).await;
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+        let res = router.oneshot(request("/api/wallet/mnemonic", [1, 1, 1, 1])).await;
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_post_routes_require_bearer_token() {
+        let state = AppState::new(Blockchain::new(1, 50)).with_api_token("s3cret");
+        let router = create_router(state.clone());
+        let mine = |token: Option<&str>| {
+            let mut req = Request::builder().method("POST").uri("/api/blocks/mine");
+            if let Some(token) = token {
+                req = req.header(AUTHORIZATION, token);
+            }
+            router.clone().oneshot(req.body(Body::empty()).unwrap())
+        };
+
+        let res = mine(None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers()["www-authenticate"], "Bearer");
+        for wrong in ["Bearer s3cre", "Bearer s3cret!", "Basic s3cret", "s3cret"] {
+            assert_eq!(mine(Some(wrong)).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(state.blockchain.lock().await.height(), 1);
+
+        assert_eq!(mine(Some("Bearer s3cret")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(state.blockchain.lock().await.height(), 2);
+
+        // Reads stay public
+        let get = Request::builder().uri("/api/chain").body(Body::empty()).unwrap();
+        assert_eq!(router.oneshot(get).await.unwrap().status(), StatusCode::OK);
+    }
+}
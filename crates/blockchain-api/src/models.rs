@@ -1,3 +1,10 @@
+use std::collections::BTreeMap;
+
+use blockchain_core::block::Block;
+use blockchain_core::peer::PeerInfo;
+use blockchain_core::state::ContractEvent;
+use blockchain_core::transaction::Transaction;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
@@ -5,14 +12,71 @@ pub struct CreateTransactionRequest {
     pub sender: String,
     pub recipient: String,
     pub amount: u64,
+    /// Paid to the miner; defaults to 0.
+    #[serde(default)]
+    pub fee: u64,
+    /// Defaults to the sender's next expected nonce.
+    pub nonce: Option<u64>,
+    pub signature: Option<String>,
+    pub public_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterNameRequest {
+    pub sender: String,
+    pub name: String,
+    /// Defaults to the sender's next expected nonce.
+    pub nonce: Option<u64>,
     pub signature: Option<String>,
     pub public_key: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BalancesRequest {
+    pub addresses: Vec<String>,
+}
+
+/// `?offset=&limit=` for `GET /api/chain`. Without an offset the most
+/// recent `limit` blocks are returned.
+#[derive(Debug, Default, Deserialize)]
+pub struct ChainPageQuery {
+    pub offset: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+/// `?limit=` for `GET /api/address/:address/history`.
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    pub limit: Option<usize>,
+}
+
+/// `?sort=` for `GET /api/transactions/pending`.
+#[derive(Debug, Default, Deserialize)]
+pub struct PendingQuery {
+    pub sort: Option<PendingSort>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PendingSort {
+    /// Mining priority: highest fee first, each sender kept in nonce order.
+    Fee,
+    /// Arrival order in the mempool.
+    #[default]
+    Time,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct DeployContractRequest {
     pub sender: String,
     pub source_code: String,
+    /// Paid to the miner; defaults to 0.
+    #[serde(default)]
+    pub fee: u64,
+    /// Defaults to the sender's next expected nonce.
+    pub nonce: Option<u64>,
+    pub signature: Option<String>,
+    pub public_key: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -22,6 +86,29 @@ pub struct CallContractRequest {
     pub call_data: Option<String>,
 }
 
+/// Body of `POST /api/contracts/trace`. `source_code` is compiled and run
+/// as a freshly deployed contract; otherwise the contract at
+/// `contract_address` runs. Exactly one of them must be given.
+#[derive(Debug, Deserialize)]
+pub struct TraceContractRequest {
+    pub sender: String,
+    pub contract_address: Option<String>,
+    pub source_code: Option<String>,
+}
+
+/// `?topic=` for `GET /api/contracts/:address`; only events with that
+/// topic are returned.
+#[derive(Debug, Default, Deserialize)]
+pub struct ContractQuery {
+    pub topic: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct MineRequest {
+    /// Overrides the node's configured miner address for this block.
+    pub miner_address: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct MineResponse {
     pub block_index: u64,
@@ -29,35 +116,269 @@ pub struct MineResponse {
     pub transactions_count: usize,
 }
 
+/// Where a looked-up transaction sits: a block index, or `"pending"`
+/// while it is still in the mempool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionBlock {
+    Index(u64),
+    Pending,
+}
+
+impl Serialize for TransactionBlock {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Index(index) => serializer.serialize_u64(*index),
+            Self::Pending => serializer.serialize_str("pending"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionLookupResponse {
+    pub transaction: Transaction,
+    pub block: TransactionBlock,
+    /// Blocks on top of (and including) the one holding the transaction;
+    /// 0 while pending.
+    pub confirmations: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub transaction: Transaction,
+    pub block: TransactionBlock,
+    /// The block's timestamp once mined, the transaction's own before.
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PendingTransactionEntry {
+    pub transaction: Transaction,
+    /// Zero-based position in the requested ordering.
+    pub position: usize,
+    /// Index of the block expected to include the transaction, assuming
+    /// nothing else arrives and every block is filled in mining order.
+    pub estimated_block: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AddressHistoryResponse {
+    pub address: String,
+    /// Newest first.
+    pub transactions: Vec<HistoryEntry>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ContractDeployResponse {
+    /// Where the contract will live once the deploy transaction is mined.
     pub address: String,
+    pub transaction_id: String,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ContractCallResponse {
     pub logs: Vec<i64>,
+    pub events: Vec<ContractEvent>,
+    /// Top of the stack when the call ended.
     pub result: Option<i64>,
+    /// Value the contract passed to RETURN, if any.
+    pub return_value: Option<i64>,
     pub steps_used: u64,
 }
 
+/// Outcome of `POST /api/contracts/estimate`. A failing call is reported
+/// with `success: false` and the trap reason rather than as an error.
+#[derive(Debug, Serialize)]
+pub struct ContractEstimateResponse {
+    pub success: bool,
+    /// Set when the call succeeds.
+    pub steps_used: Option<u64>,
+    /// Set when the call succeeds or traps during execution.
+    pub gas_used: Option<u64>,
+    pub return_value: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// One executed instruction in a `ContractTraceResponse`.
+#[derive(Debug, Serialize)]
+pub struct TraceStep {
+    pub pc: usize,
+    pub opcode: String,
+    /// Stack depth before the instruction ran.
+    pub stack_depth: usize,
+    /// Gas used so far, including this instruction.
+    pub gas_used: u64,
+}
+
+/// Outcome of `POST /api/contracts/trace`; nothing is persisted.
+#[derive(Debug, Serialize)]
+pub struct ContractTraceResponse {
+    /// Top of the stack when the run ended.
+    pub result: Option<i64>,
+    pub return_value: Option<i64>,
+    pub steps_used: u64,
+    pub gas_used: u64,
+    pub trace: Vec<TraceStep>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContractStateResponse {
+    pub address: String,
+    pub owner: String,
+    pub storage: BTreeMap<u64, i64>,
+    pub bytecode_len: usize,
+    /// Recent events, oldest first, filtered by `?topic=` if given.
+    pub events: Vec<ContractEvent>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubmitBlockResponse {
+    pub block_index: u64,
+    pub block_hash: String,
+}
+
+/// A new wallet and the mnemonic that recovers it (index 0).
+#[derive(Debug, Serialize)]
+pub struct WalletResponse {
+    pub address: String,
+    pub public_key: String,
+    pub mnemonic: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MnemonicResponse {
+    pub mnemonic: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct BalanceResponse {
     pub address: String,
     pub balance: u64,
 }
 
+#[derive(Debug, Serialize)]
+pub struct AccountResponse {
+    pub address: String,
+    /// False for an address the chain has never touched; balance and
+    /// nonce are then 0.
+    pub exists: bool,
+    pub balance: u64,
+    /// Confirmed nonce; pending transactions are not counted.
+    pub nonce: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalancesResponse {
+    pub balances: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NameResponse {
+    pub name: String,
+    pub address: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChainPageResponse {
+    pub blocks: Vec<Block>,
+    pub total: u64,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChainHashResponse {
+    pub hash: String,
+    pub height: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PruneResponse {
+    pub pruned_accounts: usize,
+    pub total_supply: u64,
+}
+
+/// One level of a merkle proof: the sibling hash and whether it sits on
+/// the left of the running hash.
+#[derive(Debug, Serialize)]
+pub struct ProofStep {
+    pub hash: String,
+    pub is_left: bool,
+}
+
+/// Response of `GET /api/blocks/:index/proof/:tx_id`. Hashing `tx_hash`
+/// with each step in order yields `merkle_root`.
+#[derive(Debug, Serialize)]
+pub struct MerkleProofResponse {
+    pub block_index: u64,
+    pub tx_hash: String,
+    pub merkle_root: String,
+    pub proof: Vec<ProofStep>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChainImportResponse {
+    pub height: u64,
+    pub tip_hash: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ChainValidResponse {
     pub valid: bool,
     pub length: u64,
 }
 
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    pub status: &'static str,
+}
+
 #[derive(Debug, Serialize)]
 pub struct NodeInfoResponse {
+    /// Network id transactions must be signed for.
+    pub chain_id: u64,
     pub chain_length: u64,
     pub difficulty: u32,
     pub mining_reward: u64,
     pub pending_transactions: usize,
     pub peer_count: usize,
+    pub peer_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeVersionResponse {
+    /// Version of the node software.
+    pub version: String,
+    pub protocol_version: Option<String>,
+    pub chain_id: u64,
+    pub peer_id: Option<String>,
+    pub gossip_topics: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeersResponse {
+    pub peer_count: usize,
+    /// Sorted PeerIds of connected peers.
+    pub peer_ids: Vec<String>,
+    pub peers: Vec<PeerInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncRejection {
+    pub reason: String,
+    pub incoming_length: usize,
+    pub at: DateTime<Utc>,
+}
+
+/// Outcome of the most recent chain sync attempts from peers.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncStatus {
+    pub last_accepted_at: Option<DateTime<Utc>>,
+    pub last_rejection: Option<SyncRejection>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncStatusResponse {
+    pub height: u64,
+    #[serde(flatten)]
+    pub status: SyncStatus,
 }
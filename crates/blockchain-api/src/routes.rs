@@ -1,31 +1,286 @@
+use std::sync::Arc;
+
+use axum::extract::DefaultBodyLimit;
 use axum::routing::{get, post};
 use axum::Router;
+use tower_http::cors::CorsLayer;
 
 use crate::handlers;
+use crate::middleware::{self, RateLimiter};
+use crate::rpc;
 use crate::state::AppState;
 
+/// Largest snapshot `POST /api/chain/import` reads; whole chains outgrow
+/// the default request body limit.
+pub const MAX_IMPORT_BYTES: usize = 256 * 1024 * 1024;
+
+/// Full API: queries plus mining, submission and admin routes.
 pub fn create_router(state: AppState) -> Router {
+    let routes = query_routes().merge(mutating_routes(&state));
+    let router = rate_limited(routes, &state.rate_limit)
+        .layer(axum::middleware::from_fn(middleware::request_id));
+    with_cors(router, &state.cors).with_state(state)
+}
+
+/// Read-only API for a public listener when mutating routes are served
+/// on a separate internal port.
+pub fn create_public_router(state: AppState) -> Router {
+    let router = rate_limited(query_routes(), &state.rate_limit)
+        .layer(axum::middleware::from_fn(middleware::request_id));
+    with_cors(router, &state.cors).with_state(state)
+}
+
+/// Applies `limiter` to every route already in `router`, if configured.
+fn rate_limited(router: Router<AppState>, limiter: &Option<Arc<RateLimiter>>) -> Router<AppState> {
+    match limiter {
+        Some(limiter) => router.layer(axum::middleware::from_fn_with_state(
+            limiter.clone(),
+            middleware::rate_limit,
+        )),
+        None => router,
+    }
+}
+
+/// Outermost layer, so preflight requests are answered before rate
+/// limiting and routing.
+fn with_cors(router: Router<AppState>, cors: &Option<CorsLayer>) -> Router<AppState> {
+    match cors {
+        Some(cors) => router.layer(cors.clone()),
+        None => router,
+    }
+}
+
+fn query_routes() -> Router<AppState> {
     Router::new()
+        // Probes
+        .route("/health", get(handlers::health))
+        .route("/ready", get(handlers::ready))
         // Chain
         .route("/api/chain", get(handlers::get_chain))
+        .route("/api/chain/full", get(handlers::get_full_chain))
+        .route("/api/chain/export", get(handlers::export_chain))
         .route("/api/chain/valid", get(handlers::validate_chain))
+        .route("/api/chain/hash", get(handlers::chain_hash))
+        .route("/api/sync/status", get(handlers::sync_status))
         // Blocks
-        .route("/api/blocks/mine", post(handlers::mine_block))
         .route("/api/blocks/:index", get(handlers::get_block))
+        .route(
+            "/api/blocks/:index/proof/:tx_id",
+            get(handlers::get_transaction_proof),
+        )
+        .route("/api/ws/blocks", get(handlers::ws_blocks))
         // Transactions
-        .route("/api/transactions", post(handlers::create_transaction))
         .route(
             "/api/transactions/pending",
             get(handlers::get_pending_transactions),
         )
+        .route("/api/transactions/:id", get(handlers::get_transaction))
+        .route(
+            "/api/transactions/:id/receipt",
+            get(handlers::get_transaction_receipt),
+        )
         // Wallet
-        .route("/api/wallet/new", post(handlers::create_wallet))
         .route("/api/balance/:address", get(handlers::get_balance))
+        // POST only to carry the address list; reads nothing but balances
+        .route("/api/balances", post(handlers::get_balances))
+        .route("/api/accounts/:address", get(handlers::get_account))
+        .route(
+            "/api/address/:address/history",
+            get(handlers::get_address_history),
+        )
+        // Names
+        .route("/api/names/:name", get(handlers::resolve_name))
         // Contracts
-        .route("/api/contracts/deploy", post(handlers::deploy_contract))
-        .route("/api/contracts/call", post(handlers::call_contract))
+        .route("/api/contracts/:address", get(handlers::get_contract))
         // Network
         .route("/api/peers", get(handlers::get_peers))
         .route("/api/node/info", get(handlers::node_info))
-        .with_state(state)
+        .route("/api/node/version", get(handlers::node_version))
+}
+
+fn mutating_routes(state: &AppState) -> Router<AppState> {
+    let mining = Router::new().route("/api/blocks/mine", post(handlers::mine_block));
+    let routes = rate_limited(mining, &state.mining_rate_limit)
+        // Chain
+        .route(
+            "/api/chain/import",
+            post(handlers::import_chain).layer(DefaultBodyLimit::max(MAX_IMPORT_BYTES)),
+        )
+        // Blocks
+        .route("/api/blocks/submit", post(handlers::submit_block))
+        // Transactions
+        .route("/api/transactions", post(handlers::create_transaction))
+        .route("/api/transactions/raw", post(handlers::submit_raw_transaction))
+        // Wallet
+        .route("/api/wallet/new", post(handlers::create_wallet))
+        .route("/api/wallet/mnemonic", post(handlers::new_mnemonic))
+        // Names
+        .route("/api/names", post(handlers::register_name))
+        // State
+        .route("/api/state/prune", post(handlers::prune_state))
+        // Contracts
+        .route("/api/contracts/deploy", post(handlers::deploy_contract))
+        .route("/api/contracts/call", post(handlers::call_contract))
+        .route("/api/contracts/estimate", post(handlers::estimate_contract_call))
+        .route("/api/contracts/trace", post(handlers::trace_contract))
+        // JSON-RPC; can submit transactions, so it stays with the mutating routes
+        .route("/rpc", post(rpc::handle));
+    // Only these routes; GETs stay public
+    routes.route_layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        middleware::require_token,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use blockchain_core::chain::Blockchain;
+    use blockchain_core::transaction::DEFAULT_CHAIN_ID;
+    use tower::ServiceExt;
+
+    fn post(uri: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mutating_routes_only_on_internal_router() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        let public = create_public_router(state.clone());
+        let internal = create_router(state.clone());
+
+        let res = public.clone().oneshot(post("/api/state/prune")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = public.clone().oneshot(post("/api/blocks/mine")).await.unwrap();
+        assert!(!res.status().is_success());
+        assert_eq!(state.blockchain.lock().await.height(), 1);
+
+        let res = internal.oneshot(post("/api/blocks/mine")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(state.blockchain.lock().await.height(), 2);
+
+        let get = Request::builder().uri("/api/chain").body(Body::empty()).unwrap();
+        assert_eq!(public.clone().oneshot(get).await.unwrap().status(), StatusCode::OK);
+
+        // Bulk balances is a query despite being a POST
+        let balances = Request::builder()
+            .method("POST")
+            .uri("/api/balances")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"addresses":["alice"]}"#))
+            .unwrap();
+        assert_eq!(public.oneshot(balances).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_ws_receives_mined_block() {
+        use futures::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let state = AppState::new(Blockchain::new(1, 50));
+        let router = create_router(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = router.clone();
+        tokio::spawn(async move { axum::serve(listener, server).await });
+
+        let url = format!("ws://{}/api/ws/blocks", addr);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        let res = router.oneshot(post("/api/blocks/mine")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let Message::Text(text) = frame else {
+            panic!("expected a text frame, got {:?}", frame);
+        };
+        let block: blockchain_core::block::Block = serde_json::from_str(&text).unwrap();
+        assert_eq!(block.header.index, 1);
+    }
+
+    #[tokio::test]
+    async fn test_raw_transaction_with_bad_signature_is_400() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        let wallet = blockchain_core::wallet::Wallet::new();
+        let mut tx = blockchain_core::transaction::Transaction::new_transfer(
+            wallet.address.clone(),
+            blockchain_core::wallet::Wallet::new().address,
+            10,
+        );
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        tx.signature.as_mut().unwrap()[0] ^= 0xff;
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/api/transactions/raw")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&tx).unwrap()))
+            .unwrap();
+        let res = create_router(state).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_chain_export_imports_into_another_node() {
+        let mut source = Blockchain::new(1, 50);
+        source.mine_pending("miner").unwrap();
+        source.mine_pending("miner").unwrap();
+        let get = Request::builder().uri("/api/chain/export").body(Body::empty()).unwrap();
+        let res = create_public_router(AppState::new(source.clone())).oneshot(get).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let snapshot = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+
+        let import = |state: AppState| {
+            let req = Request::builder()
+                .method("POST")
+                .uri("/api/chain/import")
+                .header("content-type", "application/json")
+                .body(Body::from(snapshot.clone()))
+                .unwrap();
+            create_router(state).oneshot(req)
+        };
+
+        // Refused unless the node opts in
+        let locked = AppState::new(Blockchain::new(1, 50));
+        assert_eq!(import(locked.clone()).await.unwrap().status(), StatusCode::FORBIDDEN);
+        assert_eq!(locked.blockchain.lock().await.height(), 1);
+
+        let target = AppState::new(Blockchain::new(1, 50)).with_chain_import(true);
+        assert_eq!(import(target.clone()).await.unwrap().status(), StatusCode::OK);
+        {
+            let bc = target.blockchain.lock().await;
+            assert_eq!(bc.height(), 3);
+            assert_eq!(bc.latest_block().hash, source.latest_block().hash);
+            assert_eq!(bc.state().get_balance("miner"), source.state().get_balance("miner"));
+        }
+
+        // The same chain again is not heavier than ours
+        assert_eq!(import(target).await.unwrap().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_ready_probe_turns_ok_after_peer_connects() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        let router = create_public_router(state.clone());
+        let get = |uri| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let res = router.clone().oneshot(get("/health")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = router.clone().oneshot(get("/ready")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        state.peer_connected("peer".into()).await;
+        let res = router.oneshot(get("/ready")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
 }
@@ -0,0 +1,230 @@
+//! JSON-RPC 2.0 front end. Each method is dispatched to the same handler
+//! logic as its REST route.
+
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use blockchain_core::transaction::Transaction;
+
+use crate::errors::ApiError;
+use crate::handlers;
+use crate::state::AppState;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Server-defined: the request was understood but rejected (REST 400).
+pub const REJECTED: i64 = -32000;
+/// Server-defined: the requested item does not exist (REST 404).
+pub const NOT_FOUND: i64 = -32001;
+/// Server-defined: the client is over its rate limit (REST 429).
+pub const RATE_LIMITED: i64 = -32005;
+
+#[derive(Debug, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    #[serde(default)]
+    pub id: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcResponse {
+    pub jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ApiError> for RpcError {
+    fn from(err: ApiError) -> Self {
+        match err {
+            ApiError::BadRequest(msg) | ApiError::Forbidden(msg) => Self::new(REJECTED, msg),
+            ApiError::NotFound(msg) => Self::new(NOT_FOUND, msg),
+            ApiError::Unauthorized => Self::new(REJECTED, "Missing or invalid API token"),
+            ApiError::Internal(msg) => Self::new(INTERNAL_ERROR, msg),
+            ApiError::TooManyRequests(retry_after) => Self::new(
+                RATE_LIMITED,
+                format!("Too many requests; retry after {}s", retry_after),
+            ),
+        }
+    }
+}
+
+// Params may be given by name (`{"index": 1}`) or by position (`[1]`).
+
+#[derive(Debug, Deserialize)]
+struct IndexParams {
+    index: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct AddressParams {
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionParams {
+    transaction: Transaction,
+}
+
+/// `POST /rpc`. Always answers 200; failures are reported in `error`.
+pub async fn handle(State(state): State<AppState>, body: Bytes) -> Json<RpcResponse> {
+    let request: RpcRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            let code = if serde_json::from_slice::<Value>(&body).is_ok() {
+                INVALID_REQUEST
+            } else {
+                PARSE_ERROR
+            };
+            return Json(respond(Value::Null, Err(RpcError::new(code, e.to_string()))));
+        }
+    };
+    if request.jsonrpc != "2.0" {
+        let error = RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+        return Json(respond(request.id, Err(error)));
+    }
+
+    let outcome = dispatch(state, &request.method, request.params).await;
+    Json(respond(request.id, outcome))
+}
+
+fn respond(id: Value, outcome: Result<Value, RpcError>) -> RpcResponse {
+    let (result, error) = match outcome {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+    RpcResponse {
+        jsonrpc: "2.0",
+        result,
+        error,
+        id,
+    }
+}
+
+async fn dispatch(state: AppState, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "chain_height" => {
+            let height = state.blockchain.lock().await.height();
+            Ok(Value::from(height))
+        }
+        "chain_getBlockByIndex" => {
+            let IndexParams { index } = parse_params(params)?;
+            let Json(block) = handlers::get_block(State(state), Path(index)).await?;
+            Ok(block)
+        }
+        "tx_submit" => {
+            let TransactionParams { transaction } = parse_params(params)?;
+            let Json(tx) = handlers::submit_raw_transaction(State(state), Json(transaction)).await?;
+            Ok(tx)
+        }
+        "wallet_getBalance" => {
+            let AddressParams { address } = parse_params(params)?;
+            let Json(balance) = handlers::get_balance(State(state), Path(address)).await;
+            to_value(balance)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Method not found: {}", method),
+        )),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain_core::chain::Blockchain;
+    use serde_json::json;
+
+    async fn call(state: &AppState, request: Value) -> Value {
+        let body = Bytes::from(serde_json::to_vec(&request).unwrap());
+        let Json(response) = handle(State(state.clone()), body).await;
+        serde_json::to_value(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_successful_calls() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        state.blockchain.lock().await.state_mut().credit("alice", 10);
+
+        let res = call(&state, json!({"jsonrpc": "2.0", "method": "chain_height", "id": 1})).await;
+        assert_eq!(res, json!({"jsonrpc": "2.0", "result": 1, "id": 1}));
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "wallet_getBalance",
+            "params": {"address": "alice"},
+            "id": "a",
+        });
+        let res = call(&state, request).await;
+        assert_eq!(res["result"]["balance"], 10);
+        assert_eq!(res["id"], "a");
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "chain_getBlockByIndex",
+            "params": [0],
+            "id": 2,
+        });
+        let res = call(&state, request).await;
+        assert_eq!(res["result"]["header"]["index"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_errors_carry_json_rpc_codes() {
+        let state = AppState::new(Blockchain::new(1, 50));
+
+        let res = call(&state, json!({"jsonrpc": "2.0", "method": "chain_nope", "id": 3})).await;
+        assert_eq!(res["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(res["id"], 3);
+        assert!(res.get("result").is_none());
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "chain_getBlockByIndex",
+            "params": {"index": 99},
+            "id": 4,
+        });
+        assert_eq!(call(&state, request).await["error"]["code"], NOT_FOUND);
+
+        let request = json!({"jsonrpc": "2.0", "method": "chain_getBlockByIndex", "id": 5});
+        assert_eq!(call(&state, request).await["error"]["code"], INVALID_PARAMS);
+
+        let Json(res) = handle(State(state), Bytes::from_static(b"{not json")).await;
+        assert_eq!(res.error.unwrap().code, PARSE_ERROR);
+    }
+}
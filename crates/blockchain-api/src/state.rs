@@ -1,17 +1,65 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use blockchain_core::block::Block;
 use blockchain_core::chain::Blockchain;
-use tokio::sync::Mutex;
+use blockchain_core::errors::CoreResult;
+use blockchain_core::peer::PeerInfo;
+use blockchain_core::storage::ChainStore;
+use chrono::Utc;
+use tokio::sync::{broadcast, Mutex};
+use tower_http::cors::CorsLayer;
+
+use crate::middleware::RateLimiter;
+use crate::models::{SyncRejection, SyncStatus};
 
 /// Shared application state passed to all API handlers.
 #[derive(Clone)]
 pub struct AppState {
     pub blockchain: Arc<Mutex<Blockchain>>,
-    pub peer_count: Arc<Mutex<usize>>,
+    /// PeerIds of currently connected peers.
+    pub connected_peers: Arc<Mutex<HashSet<String>>>,
+    /// Identify metadata and last gossiped height, keyed by peer id.
+    pub peers: Arc<Mutex<HashMap<String, PeerInfo>>>,
     /// Channel to send commands to the network layer (if connected).
     pub network_tx: Option<tokio::sync::mpsc::Sender<NetworkCommand>>,
+    /// On-disk store for the chain and mempool (if persistence is enabled).
+    pub store: Option<Arc<ChainStore>>,
+    /// Result of the latest chain sync attempts, for the sync-status endpoint.
+    pub sync: Arc<Mutex<SyncStatus>>,
+    /// New chain tips, fanned out to `/api/ws/blocks` subscribers.
+    pub block_events: broadcast::Sender<Block>,
+    /// Credited with the reward when `/api/blocks/mine` names no miner.
+    pub miner_address: String,
+    /// libp2p PeerId of this node, once the network layer is set up.
+    pub peer_id: Option<String>,
+    /// Peer-to-peer protocol version, once the network layer is set up.
+    pub protocol_version: Option<String>,
+    /// Gossipsub topics the network layer subscribes to.
+    pub gossip_topics: Vec<String>,
+    /// Set once a peer connects or the first chain sync is processed;
+    /// backs the `/ready` probe.
+    pub ready: Arc<AtomicBool>,
+    /// Per-client limit applied to every route.
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    /// Stricter per-client limit for `/api/blocks/mine`, on top of
+    /// `rate_limit`.
+    pub mining_rate_limit: Option<Arc<RateLimiter>>,
+    /// CORS policy for browser clients; none sends no CORS headers.
+    pub cors: Option<CorsLayer>,
+    /// Whether `POST /api/chain/import` may replace the chain.
+    pub allow_chain_import: bool,
+    /// Bearer token required on mutating routes; none leaves them open.
+    pub api_token: Option<Arc<str>>,
 }
 
+/// Reward recipient used until a miner address is configured.
+pub const DEFAULT_MINER_ADDRESS: &str = "miner-node";
+
+/// Blocks buffered per WebSocket subscriber before it starts lagging.
+const BLOCK_EVENT_CAPACITY: usize = 64;
+
 /// Commands sent from the API to the network layer.
 #[derive(Debug, Clone)]
 pub enum NetworkCommand {
@@ -24,8 +72,22 @@ impl AppState {
     pub fn new(blockchain: Blockchain) -> Self {
         Self {
             blockchain: Arc::new(Mutex::new(blockchain)),
-            peer_count: Arc::new(Mutex::new(0)),
+            connected_peers: Arc::new(Mutex::new(HashSet::new())),
+            peers: Arc::new(Mutex::new(HashMap::new())),
             network_tx: None,
+            store: None,
+            sync: Arc::new(Mutex::new(SyncStatus::default())),
+            block_events: broadcast::channel(BLOCK_EVENT_CAPACITY).0,
+            miner_address: DEFAULT_MINER_ADDRESS.to_string(),
+            peer_id: None,
+            protocol_version: None,
+            gossip_topics: Vec::new(),
+            ready: Arc::new(AtomicBool::new(false)),
+            rate_limit: None,
+            mining_rate_limit: None,
+            cors: None,
+            allow_chain_import: false,
+            api_token: None,
         }
     }
 
@@ -36,4 +98,217 @@ impl AppState {
         self.network_tx = Some(tx);
         self
     }
+
+    pub fn with_miner_address(mut self, address: impl Into<String>) -> Self {
+        self.miner_address = address.into();
+        self
+    }
+
+    pub fn with_peer_id(mut self, peer_id: impl Into<String>) -> Self {
+        self.peer_id = Some(peer_id.into());
+        self
+    }
+
+    pub fn with_protocol(mut self, version: impl Into<String>, topics: &[&str]) -> Self {
+        self.protocol_version = Some(version.into());
+        self.gossip_topics = topics.iter().map(|t| t.to_string()).collect();
+        self
+    }
+
+    pub fn with_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limit = Some(Arc::new(limiter));
+        self
+    }
+
+    pub fn with_mining_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.mining_rate_limit = Some(Arc::new(limiter));
+        self
+    }
+
+    pub fn with_cors(mut self, cors: CorsLayer) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    pub fn with_api_token(mut self, token: impl Into<String>) -> Self {
+        self.api_token = Some(Arc::from(token.into()));
+        self
+    }
+
+    pub fn with_chain_import(mut self, allow: bool) -> Self {
+        self.allow_chain_import = allow;
+        self
+    }
+
+    pub fn with_store(mut self, store: ChainStore) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Writes the chain and mempool to disk if persistence is enabled.
+    /// Failures are logged rather than surfaced to the client.
+    pub fn persist(&self, blockchain: &Blockchain) {
+        if let Some(store) = &self.store {
+            if let Err(e) = store.save(blockchain) {
+                tracing::warn!("Failed to persist chain: {}", e);
+            }
+        }
+    }
+
+    /// Writes only the mempool, for changes that add no blocks, such as an
+    /// accepted transaction.
+    pub fn persist_mempool(&self, blockchain: &Blockchain) {
+        if let Some(store) = &self.store {
+            if let Err(e) = store.save_mempool(blockchain.pending_transactions()) {
+                tracing::warn!("Failed to persist mempool: {}", e);
+            }
+        }
+    }
+
+    /// Notifies block subscribers; a no-op when nobody is listening.
+    pub fn publish_block(&self, block: &Block) {
+        let _ = self.block_events.send(block.clone());
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Marks the node ready to serve traffic; it stays ready from then on.
+    pub fn mark_ready(&self) {
+        if !self.ready.swap(true, Ordering::Relaxed) {
+            tracing::info!("Node is ready");
+        }
+    }
+
+    /// Records a connected peer; repeated connects are counted once.
+    pub async fn peer_connected(&self, peer: String) {
+        self.connected_peers.lock().await.insert(peer);
+        self.mark_ready();
+    }
+
+    /// Forgets a peer along with its identify metadata.
+    pub async fn peer_disconnected(&self, peer: &str) {
+        self.connected_peers.lock().await.remove(peer);
+        self.peers.lock().await.remove(peer);
+    }
+
+    /// Tries to adopt a chain received from a peer, recording the outcome
+    /// (including the rejection reason) in `sync`. Either way the initial
+    /// sync counts as done.
+    pub async fn apply_chain_response(&self, chain: Vec<Block>) -> CoreResult<()> {
+        let incoming_length = chain.len();
+        let mut bc = self.blockchain.lock().await;
+        let result = bc.replace_chain(chain);
+
+        let mut sync = self.sync.lock().await;
+        match &result {
+            Ok(()) => {
+                self.persist(&bc);
+                sync.last_accepted_at = Some(Utc::now());
+            }
+            Err(e) => {
+                tracing::warn!(
+                    reason = %e,
+                    incoming_length,
+                    local_length = bc.height(),
+                    "Chain sync rejected"
+                );
+                sync.last_rejection = Some(SyncRejection {
+                    reason: e.to_string(),
+                    incoming_length,
+                    at: Utc::now(),
+                });
+            }
+        }
+        self.mark_ready();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain_core::transaction::Transaction;
+    use blockchain_core::wallet::Wallet;
+
+    #[tokio::test]
+    async fn test_rejected_chain_reason_is_recorded() {
+        let mut bc = Blockchain::new(1, 50);
+        bc.mine_pending("miner").unwrap();
+        let state = AppState::new(bc.clone());
+
+        // Same length and same tip: never preferred
+        let result = state.apply_chain_response(bc.chain().to_vec()).await;
+        assert!(result.is_err());
+
+        let sync = state.sync.lock().await;
+        let rejection = sync.last_rejection.as_ref().unwrap();
+        assert_eq!(rejection.incoming_length, 2);
+        assert!(rejection.reason.contains("not preferred"));
+        assert!(sync.last_accepted_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_peer_events_converge_to_connected_set() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        state.peer_connected("a".into()).await;
+        state.peer_connected("b".into()).await;
+        state.peer_connected("a".into()).await;
+        state.peer_disconnected("b").await;
+        state.peer_disconnected("c").await;
+        state.peer_connected("c".into()).await;
+
+        let connected = state.connected_peers.lock().await;
+        let expected: HashSet<String> = ["a".into(), "c".into()].into();
+        assert_eq!(*connected, expected);
+    }
+
+    #[tokio::test]
+    async fn test_accepted_chain_is_recorded() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        let mut longer = state.blockchain.lock().await.clone();
+        longer.mine_pending("miner").unwrap();
+
+        state.apply_chain_response(longer.chain().to_vec()).await.unwrap();
+        assert_eq!(state.blockchain.lock().await.height(), 2);
+        assert!(state.sync.lock().await.last_accepted_at.is_some());
+    }
+
+    #[test]
+    fn test_persisting_mempool_leaves_chain_file_alone() {
+        let nanos = std::time::UNIX_EPOCH.elapsed().unwrap().as_nanos();
+        let dir = std::env::temp_dir().join(format!("mempool-only-{}", nanos));
+        let (alice, bob) = (Wallet::new().address, Wallet::new().address);
+        let mut bc = Blockchain::new(1, 50).with_require_signatures(false);
+        bc.state_mut().credit(&alice, 100);
+        let state = AppState::new(bc).with_store(ChainStore::open(&dir).unwrap());
+
+        let mut bc = state.blockchain.blocking_lock();
+        bc.add_transaction(Transaction::new_transfer(alice, bob, 10)).unwrap();
+        state.persist_mempool(&bc);
+        drop(bc);
+
+        let mempool = std::fs::read(dir.join("mempool.json")).unwrap();
+        let pending: Vec<serde_json::Value> = serde_json::from_slice(&mempool).unwrap();
+        let chain_written = dir.join("chain.json").exists();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(pending.len(), 1);
+        assert!(!chain_written);
+    }
+
+    #[tokio::test]
+    async fn test_ready_after_peer_or_sync() {
+        let state = AppState::new(Blockchain::new(1, 50));
+        assert!(!state.is_ready());
+        state.peer_connected("a".into()).await;
+        assert!(state.is_ready());
+        state.peer_disconnected("a").await;
+        assert!(state.is_ready());
+
+        let state = AppState::new(Blockchain::new(1, 50));
+        let chain = state.blockchain.lock().await.chain().to_vec();
+        assert!(state.apply_chain_response(chain).await.is_err());
+        assert!(state.is_ready());
+    }
 }
@@ -0,0 +1,64 @@
+use sha2::{Digest, Sha256};
+
+/// Sender of block rewards; not a real account and never checksummed.
+pub const SYSTEM: &str = "system";
+
+/// Mixed-case checksummed form of a `0x` + 40 hex address, EIP-55 style
+/// but hashing with SHA-256: a letter is uppercased when the matching
+/// nibble of the hash of the lowercase hex is 8 or more.
+pub fn to_checksum(address: &str) -> String {
+    let hex = address.trim_start_matches("0x").to_ascii_lowercase();
+    let hash = hex::encode(Sha256::digest(hex.as_bytes()));
+    let checksummed: String = hex
+        .chars()
+        .zip(hash.bytes())
+        .map(|(c, h)| if h >= b'8' { c.to_ascii_uppercase() } else { c })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+/// True for `0x` + 40 hex characters whose letter case matches the
+/// checksum, so a mistyped character is caught.
+pub fn is_valid(address: &str) -> bool {
+    address
+        .strip_prefix("0x")
+        .is_some_and(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        && to_checksum(address) == address
+}
+
+/// Deterministic checksummed address standing in for `label` in tests.
+#[cfg(test)]
+pub(crate) fn test_address(label: &str) -> String {
+    to_checksum(&hex::encode(Sha256::digest(label.as_bytes()))[..40])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::Wallet;
+
+    #[test]
+    fn test_checksummed_address_is_valid() {
+        let address = Wallet::new().address;
+        assert!(is_valid(&address));
+        assert_eq!(to_checksum(&address.to_ascii_lowercase()), address);
+    }
+
+    #[test]
+    fn test_wrong_case_is_invalid() {
+        let address = to_checksum("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+        let pos = address[2..].find(|c: char| c.is_ascii_alphabetic()).unwrap() + 2;
+        let mut flipped = address.clone().into_bytes();
+        flipped[pos] ^= 0x20;
+        let flipped = String::from_utf8(flipped).unwrap();
+
+        assert!(is_valid(&address));
+        assert!(!is_valid(&flipped));
+        assert!(!is_valid("0x1234"));
+    }
+
+    #[test]
+    fn test_system_is_not_an_address() {
+        assert!(!is_valid(SYSTEM));
+    }
+}
@@ -1,9 +1,23 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+use crate::address;
+use crate::errors::{CoreError, CoreResult};
 use crate::merkle::MerkleTree;
-use crate::transaction::Transaction;
+use crate::transaction::{Transaction, TransactionType};
+use crate::wallet::Wallet;
+
+/// Highest usable difficulty: a SHA-256 hex digest has 64 characters.
+pub const MAX_DIFFICULTY: u32 = 64;
+
+/// Fixed genesis timestamp (2024-01-01T00:00:00Z) so every node derives
+/// the same genesis hash.
+const GENESIS_TIMESTAMP: i64 = 1_704_067_200;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockHeader {
@@ -13,6 +27,9 @@ pub struct BlockHeader {
     pub merkle_root: String,
     pub nonce: u64,
     pub difficulty: u32,
+    /// Proof-of-stake producer; absent on proof-of-work blocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub producer: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +37,12 @@ pub struct Block {
     pub header: BlockHeader,
     pub hash: String,
     pub transactions: Vec<Transaction>,
+    /// Producer's ed25519 signature over `hash`, set by `sign`. Optional,
+    /// so unsigned proof-of-work blocks stay valid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub producer_signature: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub producer_pubkey: Option<Vec<u8>>,
 }
 
 impl Block {
@@ -37,68 +60,245 @@ impl Block {
             merkle_root,
             nonce: 0,
             difficulty,
+            producer: None,
         };
         let hash = Self::calculate_hash(&header);
         Self {
             header,
             hash,
             transactions,
+            producer_signature: None,
+            producer_pubkey: None,
         }
     }
 
     pub fn genesis() -> Self {
+        Self::genesis_with_allocations(&HashMap::new())
+    }
+
+    /// Genesis block crediting each address in `allocations` through a
+    /// system transfer. Transfers are ordered by address and take the
+    /// genesis timestamp, so equal allocations give equal hashes.
+    pub fn genesis_with_allocations(allocations: &HashMap<String, u64>) -> Self {
+        let timestamp =
+            DateTime::from_timestamp(GENESIS_TIMESTAMP, 0).expect("genesis timestamp is in range");
+        let mut allocations: Vec<_> = allocations.iter().collect();
+        allocations.sort();
+        let transactions: Vec<Transaction> = allocations
+            .into_iter()
+            .map(|(address, &amount)| {
+                let mut tx =
+                    Transaction::new_transfer(address::SYSTEM.into(), address.clone(), amount);
+                tx.id = format!("genesis-{}", address);
+                tx.timestamp = timestamp;
+                tx
+            })
+            .collect();
+
         let header = BlockHeader {
             index: 0,
-            timestamp: Utc::now(),
+            timestamp,
             previous_hash: "0".repeat(64),
-            merkle_root: MerkleTree::root(&[]),
+            merkle_root: MerkleTree::root(&transactions),
             nonce: 0,
             difficulty: 1,
+            producer: None,
         };
         let hash = Self::calculate_hash(&header);
         Self {
             header,
             hash,
-            transactions: vec![],
+            transactions,
+            producer_signature: None,
+            producer_pubkey: None,
         }
     }
 
-    pub fn mine(&mut self) {
-        let target = "0".repeat(self.header.difficulty as usize);
+    pub fn mine(&mut self) -> CoreResult<()> {
+        self.check_mineable()?;
         loop {
             self.hash = Self::calculate_hash(&self.header);
-            if self.hash.starts_with(&target) {
-                tracing::info!(
-                    "Block {} mined: {} (nonce: {})",
-                    self.header.index,
-                    &self.hash[..16],
-                    self.header.nonce
-                );
-                break;
+            if Self::meets_target(&self.hash, self.header.difficulty) {
+                self.log_mined();
+                return Ok(());
             }
             self.header.nonce += 1;
         }
     }
 
+    /// Like `mine`, but `threads` workers search interleaved nonces and all
+    /// stop once one finds a hash meeting the target. Which valid nonce
+    /// wins can vary between runs. With `threads` of 0 or 1 this is `mine`.
+    pub fn mine_parallel(&mut self, threads: usize) -> CoreResult<()> {
+        if threads <= 1 {
+            return self.mine();
+        }
+        self.check_mineable()?;
+
+        let found = AtomicBool::new(false);
+        let start = self.header.nonce;
+        let winners: Vec<(u64, String)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads as u64)
+                .map(|offset| {
+                    let mut header = self.header.clone();
+                    let found = &found;
+                    scope.spawn(move || {
+                        let mut nonce = start.checked_add(offset)?;
+                        while !found.load(Ordering::Relaxed) {
+                            header.nonce = nonce;
+                            let hash = Self::calculate_hash(&header);
+                            if Self::meets_target(&hash, header.difficulty) {
+                                found.store(true, Ordering::Relaxed);
+                                return Some((nonce, hash));
+                            }
+                            nonce = nonce.checked_add(threads as u64)?;
+                        }
+                        None
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("mining worker panicked"))
+                .collect()
+        });
+
+        let (nonce, hash) = winners
+            .into_iter()
+            .min_by_key(|(nonce, _)| *nonce)
+            .ok_or_else(|| CoreError::InvalidBlock("Nonce space exhausted".into()))?;
+        self.header.nonce = nonce;
+        self.hash = hash;
+        self.log_mined();
+        Ok(())
+    }
+
+    fn check_mineable(&self) -> CoreResult<()> {
+        if self.header.difficulty > MAX_DIFFICULTY {
+            return Err(CoreError::InvalidBlock(format!(
+                "Difficulty {} exceeds maximum of {}",
+                self.header.difficulty, MAX_DIFFICULTY
+            )));
+        }
+        Ok(())
+    }
+
+    fn log_mined(&self) {
+        tracing::info!(
+            block_index = self.header.index,
+            hash = %self.hash,
+            nonce = self.header.nonce,
+            "Block mined"
+        );
+    }
+
+    /// Seals the block for proof of stake: names the key's address as
+    /// producer and signs the resulting hash instead of searching nonces.
+    pub fn forge(&mut self, signing_key: &SigningKey) {
+        self.header.producer = Some(Wallet::derive_address(signing_key));
+        self.hash = Self::calculate_hash(&self.header);
+        self.sign(signing_key);
+        tracing::info!(block_index = self.header.index, hash = %self.hash, "Block forged");
+    }
+
+    /// Attributes the block to `signing_key` by signing its hash. Must
+    /// come after `mine`, since any change to the hash voids it.
+    pub fn sign(&mut self, signing_key: &SigningKey) {
+        self.producer_signature = Some(signing_key.sign(self.hash.as_bytes()).to_bytes().to_vec());
+        self.producer_pubkey = Some(signing_key.verifying_key().to_bytes().to_vec());
+    }
+
+    /// True for unsigned blocks; otherwise the signature must be over
+    /// `hash` and, if the header names a producer, made with its key.
+    pub fn verify_signature(&self) -> bool {
+        let (signature, public_key) = match (&self.producer_signature, &self.producer_pubkey) {
+            (None, None) => return true,
+            (Some(signature), Some(public_key)) => (signature, public_key),
+            _ => return false,
+        };
+        let Ok(public_key) = <[u8; 32]>::try_from(public_key.as_slice()) else {
+            return false;
+        };
+        let Ok(signature) = <[u8; 64]>::try_from(signature.as_slice()) else {
+            return false;
+        };
+        let Ok(key) = VerifyingKey::from_bytes(&public_key) else {
+            return false;
+        };
+        let producer_matches = self
+            .header
+            .producer
+            .as_ref()
+            .is_none_or(|producer| Wallet::address_from_public_key(&key) == *producer);
+        producer_matches
+            && key
+                .verify(self.hash.as_bytes(), &Signature::from_bytes(&signature))
+                .is_ok()
+    }
+
+    /// Whether the block names a producer and carries that producer's
+    /// signature over its hash.
+    pub fn is_signed_by_producer(&self) -> bool {
+        self.header.producer.is_some()
+            && self.producer_signature.is_some()
+            && self.verify_signature()
+    }
+
+    /// True when `hash` starts with `difficulty` zeros. Difficulties above
+    /// `MAX_DIFFICULTY` can never be met.
+    pub fn meets_target(hash: &str, difficulty: u32) -> bool {
+        difficulty <= MAX_DIFFICULTY
+            && hash.len() >= difficulty as usize
+            && hash.bytes().take(difficulty as usize).all(|b| b == b'0')
+    }
+
     pub fn calculate_hash(header: &BlockHeader) -> String {
+        // The producer is appended only when present, so proof-of-work
+        // hashes are unchanged
         let data = format!(
-            "{}{}{}{}{}{}",
+            "{}{}{}{}{}{}{}",
             header.index,
             header.timestamp,
             header.previous_hash,
             header.merkle_root,
             header.nonce,
-            header.difficulty
+            header.difficulty,
+            header.producer.as_deref().unwrap_or_default()
         );
         let mut hasher = Sha256::new();
         hasher.update(data.as_bytes());
         hex::encode(hasher.finalize())
     }
 
+    /// Genesis blocks carry no proof of work, so only their shape is
+    /// checked: index 0, no parent, a matching hash and merkle root, and
+    /// only system transfers (the allocations).
+    pub fn is_valid_genesis(&self) -> bool {
+        self.header.index == 0
+            && self.header.previous_hash == "0".repeat(64)
+            && Self::calculate_hash(&self.header) == self.hash
+            && self.has_valid_merkle_root()
+            && self.transactions.iter().all(|tx| {
+                tx.sender == address::SYSTEM && tx.tx_type == TransactionType::Transfer
+            })
+    }
+
+    /// Checks the proof of work, that the hash matches the header, that
+    /// the transactions match the header's merkle root, and the producer
+    /// signature if there is one.
     pub fn is_valid(&self) -> bool {
-        let target = "0".repeat(self.header.difficulty as usize);
-        let calculated = Self::calculate_hash(&self.header);
-        calculated == self.hash && self.hash.starts_with(&target)
+        if !Self::meets_target(&self.hash, self.header.difficulty) {
+            return false;
+        }
+        Self::calculate_hash(&self.header) == self.hash
+            && self.has_valid_merkle_root()
+            && self.verify_signature()
+    }
+
+    /// Whether `transactions` are the ones the header commits to. The hash
+    /// covers only the header, so this is what ties the body to it.
+    pub fn has_valid_merkle_root(&self) -> bool {
+        MerkleTree::root(&self.transactions) == self.header.merkle_root
     }
 }
 
@@ -112,24 +312,142 @@ mod tests {
         assert_eq!(genesis.header.index, 0);
         assert!(genesis.transactions.is_empty());
         assert_eq!(genesis.header.previous_hash, "0".repeat(64));
+        assert!(genesis.is_valid_genesis());
+        assert_eq!(genesis.hash, Block::genesis().hash);
+    }
+
+    #[test]
+    fn test_genesis_allocations_are_deterministic() {
+        let allocations: HashMap<String, u64> =
+            [("bob".to_string(), 5), ("alice".to_string(), 10)].into();
+        let genesis = Block::genesis_with_allocations(&allocations);
+        assert!(genesis.is_valid_genesis());
+        assert_eq!(genesis.hash, Block::genesis_with_allocations(&allocations.clone()).hash);
+        assert_ne!(genesis.hash, Block::genesis().hash);
+        let recipients: Vec<_> = genesis.transactions.iter().map(|tx| &tx.recipient).collect();
+        assert_eq!(recipients, ["alice", "bob"]);
+
+        // Anything but a system transfer makes the genesis invalid
+        let mut forged = genesis.clone();
+        forged.transactions[0].sender = "alice".into();
+        forged.header.merkle_root = MerkleTree::root(&forged.transactions);
+        forged.hash = Block::calculate_hash(&forged.header);
+        assert!(!forged.is_valid_genesis());
     }
 
     #[test]
     fn test_mine_block() {
         let txs = vec![Transaction::new_transfer("a".into(), "b".into(), 10)];
         let mut block = Block::new(1, "0".repeat(64), txs, 1);
-        block.mine();
+        block.mine().unwrap();
         assert!(block.hash.starts_with("0"));
         assert!(block.is_valid());
     }
 
+    #[test]
+    fn test_parallel_mining_yields_valid_block() {
+        let txs = vec![Transaction::new_transfer("a".into(), "b".into(), 10)];
+        let mut block = Block::new(1, "0".repeat(64), txs, 3);
+        let mut serial = block.clone();
+
+        block.mine_parallel(4).unwrap();
+        assert!(block.hash.starts_with("000"));
+        assert!(block.is_valid());
+
+        // A single thread is exactly the serial search
+        let mut single = serial.clone();
+        serial.mine().unwrap();
+        single.mine_parallel(1).unwrap();
+        assert_eq!(single.header.nonce, serial.header.nonce);
+        assert_eq!(single.hash, serial.hash);
+
+        let mut absurd = Block::new(1, "0".repeat(64), vec![], 10_000);
+        assert!(matches!(absurd.mine_parallel(4), Err(CoreError::InvalidBlock(_))));
+    }
+
     #[test]
     fn test_block_validity() {
         let mut block = Block::new(1, "0".repeat(64), vec![], 1);
-        block.mine();
+        block.mine().unwrap();
         assert!(block.is_valid());
 
         block.header.nonce += 1;
         assert!(!block.is_valid());
     }
+
+    #[test]
+    fn test_tampered_transactions_invalidate_block() {
+        let txs = vec![Transaction::new_transfer("a".into(), "b".into(), 10)];
+        let mut block = Block::new(1, "0".repeat(64), txs, 1);
+        block.mine().unwrap();
+        assert!(block.is_valid());
+
+        // The header and its hash are untouched, but the body no longer
+        // matches the merkle root
+        block.transactions[0].amount = 1_000;
+        assert_eq!(Block::calculate_hash(&block.header), block.hash);
+        assert!(!block.has_valid_merkle_root());
+        assert!(!block.is_valid());
+    }
+
+    #[test]
+    fn test_forged_block_is_signed_by_producer() {
+        let wallet = Wallet::new();
+        let mut block = Block::new(1, "0".repeat(64), vec![], 0);
+        block.forge(wallet.signing_key());
+        assert_eq!(block.header.producer.as_deref(), Some(wallet.address.as_str()));
+        assert!(block.is_valid());
+        assert!(block.is_signed_by_producer());
+
+        // Naming someone else as producer breaks the hash and the signature
+        let mut forged = block.clone();
+        forged.header.producer = Some(Wallet::new().address);
+        assert!(!forged.is_valid());
+        forged.hash = Block::calculate_hash(&forged.header);
+        assert!(!forged.is_signed_by_producer());
+    }
+
+    #[test]
+    fn test_signed_block_detects_tampering() {
+        let wallet = Wallet::new();
+        let mut block = Block::new(1, "0".repeat(64), vec![], 1);
+        block.mine().unwrap();
+        assert!(block.verify_signature());
+        block.sign(wallet.signing_key());
+        assert!(block.verify_signature());
+        assert!(block.is_valid());
+        // Signed, but no producer is named in a proof-of-work header
+        assert!(!block.is_signed_by_producer());
+
+        // Re-mining changes the hash the signature covers
+        let mut remined = block.clone();
+        remined.header.nonce += 1;
+        remined.mine().unwrap();
+        assert!(!remined.verify_signature());
+        assert!(!remined.is_valid());
+
+        let mut forged = block.clone();
+        forged.producer_signature.as_mut().unwrap()[0] ^= 0xff;
+        assert!(!forged.verify_signature());
+
+        let mut swapped = block.clone();
+        let other = Wallet::new().signing_key().verifying_key();
+        swapped.producer_pubkey = Some(other.to_bytes().to_vec());
+        assert!(!swapped.verify_signature());
+
+        // A signature without its key proves nothing
+        let mut stripped = block;
+        stripped.producer_pubkey = None;
+        assert!(!stripped.is_valid());
+    }
+
+    #[test]
+    fn test_absurd_difficulty_rejected() {
+        let mut block = Block::new(1, "0".repeat(64), vec![], 10_000);
+        assert!(!block.is_valid());
+        assert!(matches!(block.mine(), Err(CoreError::InvalidBlock(_))));
+
+        block.header.difficulty = u32::MAX;
+        assert!(!block.is_valid());
+    }
 }
@@ -1,9 +1,45 @@
-use crate::block::Block;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::address;
+use crate::block::{Block, MAX_DIFFICULTY};
 use crate::errors::{CoreError, CoreResult};
-use crate::state::WorldState;
-use crate::transaction::{Transaction, TransactionType};
+use crate::merkle::MerkleTree;
+use crate::receipt::{CallOutcome, ContractRuntime, TransactionReceipt};
+use crate::state::{MultisigConfig, WorldState};
+use crate::storage::StorageBackend;
+use crate::transaction::{Transaction, TransactionType, DEFAULT_CHAIN_ID};
+use crate::wallet::Wallet;
 
+use chrono::Utc;
+use ed25519_dalek::SigningKey;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// How `replace_chain` resolves a fork between two chains of equal total work.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ForkTieBreaker {
+    /// Keep the local chain; peers on different tips stay split.
+    KeepLocal,
+    /// Adopt whichever chain has the lexicographically lowest tip hash, so
+    /// every honest node converges on the same tip.
+    #[default]
+    LowestHash,
+}
+
+/// How blocks are sealed and who may seal them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConsensusMode {
+    /// Anyone may mine a block by meeting the difficulty target.
+    #[default]
+    Pow,
+    /// Each block is signed by a producer drawn by stake (balance) from
+    /// the tip's state; difficulty is 0.
+    Pos,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Blockchain {
@@ -12,18 +48,339 @@ pub struct Blockchain {
     difficulty: u32,
     mining_reward: u64,
     state: WorldState,
+    #[serde(default)]
+    tie_breaker: ForkTieBreaker,
+    #[serde(default)]
+    consensus: ConsensusMode,
+    /// Fee charged per bytecode byte when deploying a contract.
+    #[serde(default)]
+    deploy_fee_per_byte: u64,
+    /// When false, non-system transactions are accepted without signature
+    /// checks. Insecure; only for load testing and local development.
+    #[serde(default = "default_require_signatures")]
+    require_signatures: bool,
+    /// Network id mixed into every transaction signature, so transactions
+    /// signed for another network are rejected here.
+    #[serde(default = "default_chain_id")]
+    chain_id: u64,
+    /// How far (in seconds) a transaction timestamp may be from the local
+    /// clock, in either direction, before `add_transaction` rejects it.
+    #[serde(default = "default_timestamp_tolerance_secs")]
+    timestamp_tolerance_secs: u64,
+    /// Optional ceiling on total supply; block rewards are clamped so it
+    /// is never exceeded.
+    #[serde(default)]
+    max_supply: Option<u64>,
+    /// Most transactions the mempool holds before evicting by fee.
+    #[serde(default = "default_max_mempool")]
+    max_mempool: usize,
+    /// Most transactions one block may carry, not counting the reward.
+    /// `mine_pending` fills blocks up to it and accepted blocks must not
+    /// exceed it.
+    #[serde(default = "default_max_block_transactions")]
+    max_block_transactions: usize,
+    /// Desired spacing between blocks. When set, `next_difficulty`
+    /// retargets from recent block times; otherwise difficulty is fixed.
+    #[serde(default)]
+    target_block_time: Option<Duration>,
+    /// Transaction id -> (block index, position in block) for every
+    /// confirmed transaction. Kept in step with `chain`.
+    #[serde(skip)]
+    tx_index: HashMap<String, (u64, usize)>,
+    /// Ids of the pending transactions, so resubmissions are rejected
+    /// without scanning the mempool. Confirmed ids are in `tx_index`.
+    #[serde(skip)]
+    seen_tx_ids: HashSet<String>,
+    /// Executes mined contract calls; without one they only bump the
+    /// sender's nonce.
+    #[serde(skip)]
+    contract_runtime: Option<Arc<dyn ContractRuntime>>,
+    /// Receives contract storage as blocks change it; without one it
+    /// lives only in `state`.
+    #[serde(skip)]
+    contract_storage: Option<Arc<dyn StorageBackend>>,
+    /// Threads `mine_pending` searches nonces with; 0 or 1 mines on the
+    /// calling thread.
+    #[serde(skip)]
+    mining_threads: usize,
+    /// Receipt of every confirmed transaction, by id. Rebuilt from the
+    /// chain rather than persisted.
+    #[serde(skip)]
+    receipts: HashMap<String, TransactionReceipt>,
+    /// Blocks already found valid, so `is_chain_valid` rechecks only
+    /// what changed.
+    #[serde(skip)]
+    validated: ValidationCache,
+    /// Proof-of-stake keys this node can forge with, by address.
+    #[serde(skip)]
+    validator_keys: HashMap<String, SigningKey>,
+    /// Signs proof-of-work blocks mined here, attributing them to this
+    /// node.
+    #[serde(skip)]
+    producer_key: Option<SigningKey>,
+}
+
+/// Fingerprint of each block found valid, by index: a SHA-256 digest of
+/// the whole serialized block, so a changed block can't pass for one
+/// already checked. Only blocks that changed since they were checked pay
+/// for hashing and signature verification again.
+#[derive(Debug, Default)]
+struct ValidationCache {
+    entries: Mutex<Vec<Option<[u8; 32]>>>,
+    /// Blocks checked in full because the cache had no match.
+    full_checks: AtomicUsize,
+}
+
+impl ValidationCache {
+    /// `None` if the block can't be serialized; it is then never cached.
+    fn fingerprint(block: &Block) -> Option<[u8; 32]> {
+        let bytes = serde_json::to_vec(block).ok()?;
+        Some(Sha256::digest(bytes).into())
+    }
+
+    /// `block.is_valid()`, answered from the cache when this exact block
+    /// was checked before.
+    fn is_valid(&self, block: &Block) -> bool {
+        let index = block.header.index as usize;
+        let fingerprint = Self::fingerprint(block);
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if fingerprint.is_some() && entries.get(index) == Some(&fingerprint) {
+            return true;
+        }
+        self.full_checks.fetch_add(1, Ordering::Relaxed);
+        if !block.is_valid() {
+            return false;
+        }
+        if entries.len() <= index {
+            entries.resize(index + 1, None);
+        }
+        entries[index] = fingerprint;
+        true
+    }
+
+    fn full_checks(&self) -> usize {
+        self.full_checks.load(Ordering::Relaxed)
+    }
+}
+
+impl Clone for ValidationCache {
+    fn clone(&self) -> Self {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        Self {
+            entries: Mutex::new(entries.clone()),
+            full_checks: AtomicUsize::new(self.full_checks()),
+        }
+    }
+}
+
+fn default_require_signatures() -> bool {
+    true
+}
+
+fn default_chain_id() -> u64 {
+    DEFAULT_CHAIN_ID
+}
+
+pub const DEFAULT_TIMESTAMP_TOLERANCE_SECS: u64 = 600;
+
+/// How far (in seconds) a block timestamp may run ahead of the local clock.
+pub const MAX_BLOCK_FUTURE_SECS: i64 = 120;
+
+/// Largest contract bytecode accepted in a deploy transaction.
+pub const MAX_BYTECODE_SIZE: usize = 24 * 1024;
+
+fn default_timestamp_tolerance_secs() -> u64 {
+    DEFAULT_TIMESTAMP_TOLERANCE_SECS
 }
 
+pub const DEFAULT_MAX_MEMPOOL: usize = 10_000;
+
+fn default_max_mempool() -> usize {
+    DEFAULT_MAX_MEMPOOL
+}
+
+pub const DEFAULT_MAX_BLOCK_TRANSACTIONS: usize = 1_000;
+
+fn default_max_block_transactions() -> usize {
+    DEFAULT_MAX_BLOCK_TRANSACTIONS
+}
+
+/// Chain parameters that decide how a mined transaction changes state.
+#[derive(Debug, Clone)]
+struct ApplyRules {
+    /// Whether signers must be authorized for the sender's account.
+    require_signatures: bool,
+    chain_id: u64,
+    max_supply: Option<u64>,
+    deploy_fee_per_byte: u64,
+    contract_runtime: Option<Arc<dyn ContractRuntime>>,
+}
+
+impl ApplyRules {
+    /// Per-byte fee burned when deploy `tx` is applied.
+    fn deploy_fee(&self, tx: &Transaction) -> u64 {
+        (tx.data.len() as u64).saturating_mul(self.deploy_fee_per_byte)
+    }
+}
+
+/// Number of most recent blocks whose timestamps drive retargeting.
+pub const RETARGET_WINDOW: usize = 10;
+
 impl Blockchain {
     pub fn new(difficulty: u32, mining_reward: u64) -> Self {
         let genesis = Block::genesis();
-        Self {
-            chain: vec![genesis],
+        let mut blockchain = Self {
+            chain: Vec::new(),
             pending_transactions: Vec::new(),
             difficulty,
             mining_reward,
             state: WorldState::new(),
+            tie_breaker: ForkTieBreaker::default(),
+            consensus: ConsensusMode::default(),
+            deploy_fee_per_byte: 0,
+            require_signatures: true,
+            chain_id: DEFAULT_CHAIN_ID,
+            timestamp_tolerance_secs: DEFAULT_TIMESTAMP_TOLERANCE_SECS,
+            max_supply: None,
+            max_mempool: DEFAULT_MAX_MEMPOOL,
+            max_block_transactions: DEFAULT_MAX_BLOCK_TRANSACTIONS,
+            target_block_time: None,
+            tx_index: HashMap::new(),
+            seen_tx_ids: HashSet::new(),
+            contract_runtime: None,
+            contract_storage: None,
+            mining_threads: 1,
+            receipts: HashMap::new(),
+            validated: ValidationCache::default(),
+            validator_keys: HashMap::new(),
+            producer_key: None,
+        };
+        blockchain.push_block(genesis);
+        blockchain
+    }
+
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Runs `ContractCall` transactions through `runtime` as blocks are
+    /// applied. Set it before any chain is restored or replaced.
+    pub fn with_contract_runtime(mut self, runtime: Arc<dyn ContractRuntime>) -> Self {
+        self.contract_runtime = Some(runtime);
+        self
+    }
+
+    /// Persists contract storage to `backend` whenever blocks change it.
+    /// Set it before any chain is restored or replaced.
+    pub fn with_contract_storage(mut self, backend: Arc<dyn StorageBackend>) -> Self {
+        self.contract_storage = Some(backend);
+        self
+    }
+
+    /// Writes contracts whose storage changed to the storage backend.
+    /// Failures are logged and retried on the next call.
+    fn persist_contract_storage(&mut self) {
+        let Some(backend) = &self.contract_storage else { return };
+        match self.state.persist_contract_storage(backend.as_ref()) {
+            Ok(0) => {}
+            Ok(written) => tracing::debug!("Persisted storage of {} contract(s)", written),
+            Err(e) => tracing::warn!("Failed to persist contract storage: {}", e),
+        }
+    }
+
+    /// Swaps in a rebuilt state, keeping contracts that only the old one
+    /// had pending removal from the storage backend.
+    fn replace_state(&mut self, state: WorldState, receipts: HashMap<String, TransactionReceipt>) {
+        let previous = std::mem::replace(&mut self.state, state);
+        self.state.mark_contracts_dirty(previous.contracts().keys().cloned());
+        self.receipts = receipts;
+        self.persist_contract_storage();
+    }
+
+    /// Splits the proof-of-work nonce search across `threads` threads.
+    pub fn with_mining_threads(mut self, threads: usize) -> Self {
+        self.mining_threads = threads;
+        self
+    }
+
+    pub fn with_tie_breaker(mut self, tie_breaker: ForkTieBreaker) -> Self {
+        self.tie_breaker = tie_breaker;
+        self
+    }
+
+    pub fn with_consensus(mut self, consensus: ConsensusMode) -> Self {
+        self.consensus = consensus;
+        self
+    }
+
+    /// Lets `mine_pending` forge blocks when `signing_key`'s address is
+    /// the selected proof-of-stake producer.
+    pub fn with_validator_key(mut self, signing_key: SigningKey) -> Self {
+        self.validator_keys
+            .insert(Wallet::derive_address(&signing_key), signing_key);
+        self
+    }
+
+    /// Signs every block `mine_pending` mines with `signing_key`.
+    /// Proof-of-stake blocks are signed by their producer regardless.
+    pub fn with_producer_key(mut self, signing_key: SigningKey) -> Self {
+        self.producer_key = Some(signing_key);
+        self
+    }
+
+    pub fn with_deploy_fee_per_byte(mut self, fee_per_byte: u64) -> Self {
+        self.deploy_fee_per_byte = fee_per_byte;
+        self
+    }
+
+    pub fn with_require_signatures(mut self, require_signatures: bool) -> Self {
+        if !require_signatures {
+            tracing::warn!("Signature verification is DISABLED; do not use in production");
         }
+        self.require_signatures = require_signatures;
+        self
+    }
+
+    pub fn with_timestamp_tolerance_secs(mut self, secs: u64) -> Self {
+        self.timestamp_tolerance_secs = secs;
+        self
+    }
+
+    pub fn with_max_supply(mut self, max_supply: u64) -> Self {
+        self.max_supply = Some(max_supply);
+        self
+    }
+
+    pub fn with_max_mempool(mut self, max_mempool: usize) -> Self {
+        self.max_mempool = max_mempool.max(1);
+        self
+    }
+
+    pub fn with_max_block_transactions(mut self, max: usize) -> Self {
+        self.max_block_transactions = max.max(1);
+        self
+    }
+
+    /// Starts the chain from a genesis block that credits `allocations`.
+    /// Meant for a fresh chain: any blocks past genesis are dropped. Nodes
+    /// only accept each other's chains when their allocations match.
+    pub fn with_genesis_allocations(mut self, allocations: HashMap<String, u64>) -> Self {
+        let genesis = Block::genesis_with_allocations(&allocations);
+        // A lone genesis has no producer to check, so replaying it can't fail
+        let (state, receipts) = self
+            .rebuild_state(std::slice::from_ref(&genesis))
+            .expect("genesis replays");
+        self.replace_state(state, receipts);
+        self.pending_transactions.clear();
+        self.seen_tx_ids.clear();
+        self.set_chain(vec![genesis]);
+        self
+    }
+
+    pub fn with_target_block_time(mut self, target: Duration) -> Self {
+        self.target_block_time = Some(target);
+        self
     }
 
     pub fn chain(&self) -> &[Block] {
@@ -34,6 +391,20 @@ impl Blockchain {
         &self.pending_transactions
     }
 
+    /// Pending transactions in the order `mine_pending` would try them.
+    pub fn mining_order(&self) -> Vec<Transaction> {
+        self.order_by_fee(self.pending_transactions.clone())
+    }
+
+    pub fn max_block_transactions(&self) -> usize {
+        self.max_block_transactions
+    }
+
+    /// Receipt of confirmed transaction `tx_id`; `None` while pending.
+    pub fn receipt(&self, tx_id: &str) -> Option<&TransactionReceipt> {
+        self.receipts.get(tx_id)
+    }
+
     pub fn state(&self) -> &WorldState {
         &self.state
     }
@@ -42,14 +413,90 @@ impl Blockchain {
         &mut self.state
     }
 
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
     pub fn difficulty(&self) -> u32 {
         self.difficulty
     }
 
+    pub fn target_block_time(&self) -> Option<Duration> {
+        self.target_block_time
+    }
+
+    /// Difficulty the next block must be mined at. Without a target block
+    /// time this is the configured difficulty. With one, it starts from
+    /// the tip's difficulty and moves one step up when the last
+    /// `RETARGET_WINDOW` blocks came in under half the target spacing, or
+    /// one step down (never below 1) when they took over twice as long.
+    /// Proof-of-stake blocks carry no work and always take difficulty 0.
+    pub fn next_difficulty(&self) -> u32 {
+        self.difficulty_after(&self.chain)
+    }
+
+    /// Difficulty of the block that extends `chain`; see `next_difficulty`.
+    fn difficulty_after(&self, chain: &[Block]) -> u32 {
+        if self.consensus == ConsensusMode::Pos {
+            return 0;
+        }
+        let Some(target) = self.target_block_time else {
+            return self.difficulty;
+        };
+        // Genesis has a fixed difficulty and timestamp, so it never counts
+        let mined = &chain[1..];
+        let current = mined.last().map_or(self.difficulty, |b| b.header.difficulty);
+        if mined.len() < RETARGET_WINDOW {
+            return current;
+        }
+
+        let window = &mined[mined.len() - RETARGET_WINDOW..];
+        let elapsed = (window[RETARGET_WINDOW - 1].header.timestamp - window[0].header.timestamp)
+            .num_milliseconds()
+            .max(0) as u128;
+        let intervals = (RETARGET_WINDOW - 1) as u128;
+        let expected = target.as_millis() * intervals;
+        if elapsed * 2 < expected {
+            (current + 1).min(MAX_DIFFICULTY)
+        } else if elapsed > expected * 2 {
+            current.saturating_sub(1).max(1)
+        } else {
+            current
+        }
+    }
+
     pub fn mining_reward(&self) -> u64 {
         self.mining_reward
     }
 
+    pub fn consensus(&self) -> ConsensusMode {
+        self.consensus
+    }
+
+    pub fn tie_breaker(&self) -> ForkTieBreaker {
+        self.tie_breaker
+    }
+
+    pub fn deploy_fee_per_byte(&self) -> u64 {
+        self.deploy_fee_per_byte
+    }
+
+    pub fn require_signatures(&self) -> bool {
+        self.require_signatures
+    }
+
+    pub fn timestamp_tolerance_secs(&self) -> u64 {
+        self.timestamp_tolerance_secs
+    }
+
+    pub fn max_supply(&self) -> Option<u64> {
+        self.max_supply
+    }
+
+    pub fn mempool_capacity(&self) -> usize {
+        self.max_mempool
+    }
+
     pub fn latest_block(&self) -> &Block {
         self.chain.last().expect("Chain must have at least genesis block")
     }
@@ -62,195 +509,2136 @@ impl Blockchain {
         self.chain.len() as u64
     }
 
-    pub fn add_transaction(&mut self, tx: Transaction) -> CoreResult<()> {
-        if tx.sender != "system" {
-            tx.verify()?;
-
-            if tx.tx_type == TransactionType::Transfer {
-                let balance = self.state.get_balance(&tx.sender);
-                if balance < tx.amount {
-                    return Err(CoreError::InsufficientBalance {
-                        account: tx.sender.clone(),
-                        balance,
-                        required: tx.amount,
-                    });
-                }
-            }
+    /// Looks up a transaction by id, confirmed or pending. Confirmed ones
+    /// come with the index of the block that holds them.
+    pub fn find_transaction(&self, id: &str) -> Option<(&Transaction, Option<u64>)> {
+        if let Some(&(index, position)) = self.tx_index.get(id) {
+            let tx = &self.chain[index as usize].transactions[position];
+            return Some((tx, Some(index)));
         }
+        self.pending_transactions
+            .iter()
+            .find(|tx| tx.id == id)
+            .map(|tx| (tx, None))
+    }
 
-        self.pending_transactions.push(tx);
-        Ok(())
+    fn push_block(&mut self, block: Block) {
+        let index = block.header.index;
+        for (position, tx) in block.transactions.iter().enumerate() {
+            self.tx_index.insert(tx.id.clone(), (index, position));
+        }
+        self.chain.push(block);
     }
 
-    pub fn mine_pending(&mut self, miner_address: &str) -> CoreResult<Block> {
-        let reward_tx = Transaction::new_transfer(
-            "system".into(),
-            miner_address.into(),
-            self.mining_reward,
-        );
+    fn set_chain(&mut self, blocks: Vec<Block>) {
+        self.chain.clear();
+        self.tx_index.clear();
+        for block in blocks {
+            self.push_block(block);
+        }
+    }
 
-        let mut transactions = self.pending_transactions.drain(..).collect::<Vec<_>>();
-        transactions.push(reward_tx);
+    /// Merkle root over every block hash; identical chains produce the
+    /// same value, diverged chains do not.
+    pub fn chain_hash(&self) -> String {
+        MerkleTree::root_from_hashes(self.chain.iter().map(|b| b.hash.clone()).collect())
+    }
+
+    /// Admits a newly submitted or gossiped transaction to the mempool.
+    pub fn add_transaction(&mut self, tx: Transaction) -> CoreResult<()> {
+        self.admit_transaction(tx, true)
+    }
+
+    /// `check_timestamp` applies the receive-time window, which only makes
+    /// sense for transactions arriving now; restored ones rely on expiry.
+    fn admit_transaction(&mut self, tx: Transaction, check_timestamp: bool) -> CoreResult<()> {
+        if self.seen_tx_ids.contains(&tx.id) || self.tx_index.contains_key(&tx.id) {
+            return Err(CoreError::DuplicateTransaction(tx.id));
+        }
+
+        if tx.sender != address::SYSTEM {
+            self.validate_addresses(&tx)?;
+            if check_timestamp {
+                self.validate_timestamp(&tx)?;
+            }
+            if tx.is_expired(Utc::now()) {
+                return Err(CoreError::InvalidTransaction(format!("Tx {} has expired", tx.id)));
+            }
+
+            let expected = self.next_nonce(&tx.sender);
+            if tx.nonce != expected {
+                return Err(CoreError::InvalidNonce {
+                    expected,
+                    got: tx.nonce,
+                });
+            }
+
+            if self.require_signatures {
+                tx.verify(self.chain_id)?;
+                Self::authorize(&self.state, &tx, self.chain_id)
+                    .map_err(CoreError::InvalidSignature)?;
+            } else {
+                tracing::debug!(tx_id = %tx.id, "Skipping signature check (insecure mode)");
+            }
 
-        // Apply state transitions
-        for tx in &transactions {
             match tx.tx_type {
                 TransactionType::Transfer => {
-                    if tx.sender == "system" {
-                        self.state.credit(&tx.recipient, tx.amount);
-                    } else {
-                        if !self.state.transfer(&tx.sender, &tx.recipient, tx.amount) {
-                            tracing::warn!(
-                                "Skipping tx {}: insufficient balance",
-                                tx.id
-                            );
-                        }
+                    if tx.amount == 0 {
+                        return Err(CoreError::InvalidTransaction(
+                            "Transfer amount must be greater than zero".into(),
+                        ));
                     }
+                    self.ensure_balance(&tx.sender, tx.amount.saturating_add(tx.fee))?
                 }
-                TransactionType::ContractDeploy | TransactionType::ContractCall => {
-                    // Handled by VM integration layer
+                TransactionType::RegisterName => {
+                    self.validate_name_registration(&tx)?;
+                    self.ensure_balance(&tx.sender, tx.fee)?
+                }
+                TransactionType::SetMultisig => {
+                    if !tx.multisig_config().is_some_and(|c| c.is_none_or(|c| c.is_valid())) {
+                        return Err(CoreError::InvalidTransaction(
+                            "Multisig config needs distinct valid owners and a threshold \
+                             between 1 and their count"
+                                .into(),
+                        ));
+                    }
+                    self.ensure_balance(&tx.sender, tx.fee)?
                 }
+                TransactionType::ContractDeploy => self.validate_deploy(&tx)?,
+                TransactionType::ContractCall => self.validate_call(&tx)?,
             }
         }
 
-        let previous_hash = self.latest_block().hash.clone();
-        let index = self.height();
-        let mut block = Block::new(index, previous_hash, transactions, self.difficulty);
-        block.mine();
-
-        self.chain.push(block.clone());
-        tracing::info!("Block {} added to chain", index);
+        if self.pending_transactions.len() >= self.max_mempool {
+            self.make_room_for(&tx)?;
+        }
 
-        Ok(block)
+        self.seen_tx_ids.insert(tx.id.clone());
+        self.pending_transactions.push(tx);
+        Ok(())
     }
 
-    pub fn is_chain_valid(&self) -> bool {
-        for i in 1..self.chain.len() {
-            let current = &self.chain[i];
-            let previous = &self.chain[i - 1];
-
-            if !current.is_valid() {
-                tracing::error!("Block {} has invalid hash", current.header.index);
-                return false;
-            }
+    /// Evicts the lowest-fee pending transaction if `tx` pays more, along
+    /// with the same sender's later transactions, which could no longer be
+    /// mined. Otherwise rejects `tx`.
+    fn make_room_for(&mut self, tx: &Transaction) -> CoreResult<()> {
+        // Among equal fees, evict the newest arrival
+        let lowest = self
+            .pending_transactions
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, p)| (p.fee, Reverse(*i)))
+            .map(|(_, p)| (p.fee, p.sender.clone(), p.nonce));
 
-            if current.header.previous_hash != previous.hash {
-                tracing::error!(
-                    "Block {} has mismatched previous_hash",
-                    current.header.index
+        match lowest {
+            Some((fee, sender, nonce)) if tx.fee > fee => {
+                let before = self.pending_transactions.len();
+                self.retain_pending(|p| !(p.sender == sender && p.nonce >= nonce));
+                tracing::debug!(
+                    "Mempool full: evicted {} tx(s) from {} paying fee {}",
+                    before - self.pending_transactions.len(),
+                    sender,
+                    fee
                 );
-                return false;
+                Ok(())
             }
+            _ => Err(CoreError::InvalidTransaction(format!(
+                "Mempool full ({} transactions) and fee {} is too low to replace any",
+                self.max_mempool, tx.fee
+            ))),
         }
-        true
     }
 
-    pub fn replace_chain(&mut self, new_chain: Vec<Block>) -> CoreResult<()> {
-        if new_chain.len() <= self.chain.len() {
-            return Err(CoreError::InvalidChain(
-                "Incoming chain is not longer than current chain".into(),
-            ));
+    /// Re-admits `orphaned` transactions, then the mempool, against the
+    /// state after adopting a block or chain. Ones already mined, with a
+    /// stale nonce or otherwise no longer valid are dropped.
+    fn revalidate_pending(&mut self, orphaned: Vec<Transaction>) {
+        let pending = std::mem::take(&mut self.pending_transactions);
+        self.seen_tx_ids.clear();
+        for tx in orphaned.into_iter().chain(pending) {
+            let id = tx.id.clone();
+            if let Err(e) = self.admit_transaction(tx, false) {
+                tracing::debug!("Dropping pending tx {}: {}", id, e);
+            }
         }
+    }
 
-        let temp = Blockchain {
-            chain: new_chain.clone(),
-            pending_transactions: vec![],
-            difficulty: self.difficulty,
-            mining_reward: self.mining_reward,
-            state: WorldState::new(),
-        };
+    /// Drops pending transactions that fail `keep`, forgetting their ids.
+    fn retain_pending(&mut self, mut keep: impl FnMut(&Transaction) -> bool) {
+        let seen = &mut self.seen_tx_ids;
+        self.pending_transactions.retain(|tx| {
+            let kept = keep(tx);
+            if !kept {
+                seen.remove(&tx.id);
+            }
+            kept
+        });
+    }
 
-        if !temp.is_chain_valid() {
-            return Err(CoreError::InvalidChain(
-                "Incoming chain is not valid".into(),
-            ));
-        }
+    /// Orders transactions highest fee first, except that each sender's
+    /// transactions stay in nonce order. Transactions whose nonce can never
+    /// become current are appended last (and skipped when applied).
+    fn order_by_fee(&self, mut transactions: Vec<Transaction>) -> Vec<Transaction> {
+        transactions.sort_by_key(|tx| Reverse(tx.fee));
 
-        tracing::info!(
-            "Replacing chain: {} blocks -> {} blocks",
-            self.chain.len(),
-            new_chain.len()
-        );
+        // Positions in fee order of each sender's transactions, by nonce;
+        // the heap holds the position of every sender's next-nonce head
+        let mut by_nonce: HashMap<(&str, u64), Vec<usize>> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        for (position, tx) in transactions.iter().enumerate() {
+            if tx.sender == "system" {
+                heap.push(Reverse(position));
+            } else {
+                by_nonce.entry((tx.sender.as_str(), tx.nonce)).or_default().push(position);
+            }
+        }
+        let mut next_nonce: HashMap<&str, u64> = HashMap::new();
+        for tx in &transactions {
+            if tx.sender == "system" || next_nonce.contains_key(tx.sender.as_str()) {
+                continue;
+            }
+            let nonce = self.state.get_account(&tx.sender).map_or(0, |a| a.nonce);
+            next_nonce.insert(&tx.sender, nonce);
+            if let Some(positions) = by_nonce.get(&(tx.sender.as_str(), nonce)) {
+                heap.push(Reverse(positions[0]));
+            }
+        }
 
-        // Rebuild state from new chain
-        let mut state = WorldState::new();
-        for block in &new_chain {
-            for tx in &block.transactions {
-                if tx.tx_type == TransactionType::Transfer {
-                    if tx.sender == "system" {
-                        state.credit(&tx.recipient, tx.amount);
-                    } else {
-                        state.transfer(&tx.sender, &tx.recipient, tx.amount);
-                    }
+        let mut order = Vec::with_capacity(transactions.len());
+        let mut taken = vec![false; transactions.len()];
+        while let Some(Reverse(position)) = heap.pop() {
+            order.push(position);
+            taken[position] = true;
+            let sender = transactions[position].sender.as_str();
+            if let Some(nonce) = next_nonce.get_mut(sender) {
+                *nonce += 1;
+                if let Some(positions) = by_nonce.get(&(sender, *nonce)) {
+                    heap.push(Reverse(positions[0]));
                 }
             }
         }
+        order.extend((0..transactions.len()).filter(|&position| !taken[position]));
 
-        self.chain = new_chain;
-        self.state = state;
-        Ok(())
+        let mut slots: Vec<Option<Transaction>> = transactions.into_iter().map(Some).collect();
+        order.into_iter().filter_map(|position| slots[position].take()).collect()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::wallet::Wallet;
+    /// Nonce the sender's next transaction must carry: the account nonce
+    /// plus however many of its transactions are already pending.
+    pub fn next_nonce(&self, sender: &str) -> u64 {
+        let confirmed = self.state.get_account(sender).map_or(0, |a| a.nonce);
+        let pending = self
+            .pending_transactions
+            .iter()
+            .filter(|tx| tx.sender == sender)
+            .count() as u64;
+        confirmed + pending
+    }
 
-    #[test]
-    fn test_new_blockchain() {
-        let bc = Blockchain::new(1, 50);
-        assert_eq!(bc.chain().len(), 1);
-        assert_eq!(bc.height(), 1);
+    fn ensure_balance(&self, account: &str, required: u64) -> CoreResult<()> {
+        let balance = self.state.get_balance(account);
+        if balance < required {
+            return Err(CoreError::InsufficientBalance {
+                account: account.to_string(),
+                balance,
+                required,
+            });
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_mine_block() {
-        let mut bc = Blockchain::new(1, 50);
-        let block = bc.mine_pending("miner").unwrap();
-        assert_eq!(block.header.index, 1);
-        assert_eq!(bc.chain().len(), 2);
-        assert!(bc.is_chain_valid());
+    fn validate_deploy(&self, tx: &Transaction) -> CoreResult<()> {
+        if tx.data.is_empty() {
+            return Err(CoreError::InvalidTransaction("Deploy has no bytecode".into()));
+        }
+        if tx.data.len() > MAX_BYTECODE_SIZE {
+            return Err(CoreError::InvalidTransaction(format!(
+                "Bytecode is {} bytes (max {})",
+                tx.data.len(),
+                MAX_BYTECODE_SIZE
+            )));
+        }
+        self.ensure_balance(&tx.sender, self.rules().deploy_fee(tx).saturating_add(tx.fee))
     }
 
-    #[test]
-    fn test_mine_with_transactions() {
-        let mut bc = Blockchain::new(1, 50);
+    fn validate_call(&self, tx: &Transaction) -> CoreResult<()> {
+        // Contracts deployed earlier in the mempool are not callable yet
+        if self.state.get_contract(&tx.recipient).is_none() {
+            return Err(CoreError::ContractNotFound(tx.recipient.clone()));
+        }
+        // Calls carry no gas price yet, so only the value sent and the
+        // miner fee are reserved
+        self.ensure_balance(&tx.sender, tx.amount.saturating_add(tx.fee))
+    }
 
-        // First mine to give miner some coins
-        bc.mine_pending("miner").unwrap();
-        assert_eq!(bc.state().get_balance("miner"), 50);
+    /// Rejects malformed or mis-checksummed sender and transfer recipient
+    /// addresses. Contract addresses are recognised by lookup instead.
+    fn validate_addresses(&self, tx: &Transaction) -> CoreResult<()> {
+        if !address::is_valid(&tx.sender) {
+            return Err(CoreError::InvalidTransaction(format!(
+                "Invalid sender address: {}",
+                tx.sender
+            )));
+        }
+        let recipient = tx.recipient.as_str();
+        if tx.tx_type == TransactionType::Transfer
+            && recipient != address::SYSTEM
+            && !address::is_valid(recipient)
+            && self.state.get_contract(recipient).is_none()
+        {
+            return Err(CoreError::InvalidTransaction(format!(
+                "Invalid recipient address: {}",
+                recipient
+            )));
+        }
+        Ok(())
+    }
 
-        // Create and sign transfer
-        let wallet = Wallet::new();
-        bc.state_mut().credit(&wallet.address, 1000);
+    fn validate_timestamp(&self, tx: &Transaction) -> CoreResult<()> {
+        let skew = (tx.timestamp - Utc::now()).num_seconds();
+        if skew.unsigned_abs() > self.timestamp_tolerance_secs {
+            let direction = if skew > 0 { "in the future" } else { "in the past" };
+            return Err(CoreError::InvalidTransaction(format!(
+                "Timestamp of tx {} is {}s {} (tolerance {}s)",
+                tx.id,
+                skew.unsigned_abs(),
+                direction,
+                self.timestamp_tolerance_secs
+            )));
+        }
+        Ok(())
+    }
 
-        let mut tx = Transaction::new_transfer(
-            wallet.address.clone(),
-            "bob".into(),
-            100,
-        );
-        tx.sign(wallet.signing_key());
-        bc.add_transaction(tx).unwrap();
+    /// Checks that `tx`'s signers may act for its sender. A multisig
+    /// account needs its threshold of owner signatures; any other account
+    /// must be among the signers of a multisig transaction. Single-key
+    /// signatures are already bound to the sender by `verify`.
+    fn authorize(state: &WorldState, tx: &Transaction, chain_id: u64) -> Result<(), String> {
+        let config = state
+            .get_account(&tx.sender)
+            .and_then(|account| account.multisig.as_ref());
+        match config {
+            Some(config) => {
+                let signers = tx.cosigner_addresses(chain_id);
+                let approvals =
+                    config.owners.iter().filter(|owner| signers.contains(*owner)).count();
+                if approvals < config.threshold as usize {
+                    return Err(format!(
+                        "Account {} needs {} owner signatures, got {}",
+                        tx.sender, config.threshold, approvals
+                    ));
+                }
+            }
+            None if tx.multisig.is_some()
+                && !tx.cosigner_addresses(chain_id).contains(&tx.sender) =>
+            {
+                return Err(format!("Multisig signatures do not include sender {}", tx.sender));
+            }
+            None => {}
+        }
+        Ok(())
+    }
 
-        bc.mine_pending("miner").unwrap();
-        assert_eq!(bc.state().get_balance("bob"), 100);
-        assert_eq!(bc.state().get_balance(&wallet.address), 900);
+    fn validate_name_registration(&self, tx: &Transaction) -> CoreResult<()> {
+        let name = tx
+            .name()
+            .ok_or_else(|| CoreError::InvalidTransaction("Name is not valid UTF-8".into()))?;
+        if !WorldState::is_valid_name(name) {
+            return Err(CoreError::InvalidTransaction(format!(
+                "Invalid name '{}': use 1-32 lowercase letters, digits, '-' or '_'",
+                name
+            )));
+        }
+
+        let pending = self
+            .pending_transactions
+            .iter()
+            .any(|p| p.tx_type == TransactionType::RegisterName && p.name() == Some(name));
+        if pending || self.state.resolve_name(name).is_some() {
+            return Err(CoreError::InvalidTransaction(format!(
+                "Name '{}' is already registered",
+                name
+            )));
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_insufficient_balance() {
-        let mut bc = Blockchain::new(1, 50);
-        let wallet = Wallet::new();
+    pub fn mine_pending(&mut self, miner_address: &str) -> CoreResult<Block> {
+        // Checked before touching the mempool or state so a bad config
+        // can't leave them half-applied
+        let difficulty = self.next_difficulty();
+        if difficulty > MAX_DIFFICULTY {
+            return Err(CoreError::MiningError(format!(
+                "Difficulty {} exceeds maximum of {}",
+                difficulty, MAX_DIFFICULTY
+            )));
+        }
 
-        let mut tx = Transaction::new_transfer(
+        let forging_key = match self.consensus {
+            ConsensusMode::Pow => None,
+            ConsensusMode::Pos => Some(self.forging_key()?),
+        };
+
+        let reward = Self::issuable(&self.state, self.mining_reward, self.max_supply);
+        if reward < self.mining_reward {
+            tracing::info!("Block reward clamped to {} by max supply", reward);
+        }
+
+        let mut pending = std::mem::take(&mut self.pending_transactions);
+        let now = Utc::now();
+        pending.retain(|tx| {
+            let expired = tx.is_expired(now);
+            if expired {
+                tracing::debug!(tx_id = %tx.id, "Dropping expired tx");
+            }
+            !expired
+        });
+        let mut ordered = self.order_by_fee(pending).into_iter();
+
+        // Apply state transitions; transactions that fail are left out of
+        // the block so peers validating it see only applicable ones. Once
+        // the block is full the rest stay in the mempool.
+        let rules = self.rules();
+        let index = self.height();
+        let mut transactions = Vec::new();
+        while transactions.len() < self.max_block_transactions {
+            let Some(tx) = ordered.next() else { break };
+            match Self::apply_transaction(&mut self.state, &tx, &rules) {
+                Ok(outcome) => {
+                    let receipt = TransactionReceipt::new(tx.id.clone(), index, outcome);
+                    self.receipts.insert(tx.id.clone(), receipt);
+                    transactions.push(tx);
+                }
+                Err(reason) => tracing::warn!(tx_id = %tx.id, %reason, "Skipping tx"),
+            }
+        }
+        self.pending_transactions = ordered.collect();
+        // Mined and skipped transactions both leave the mempool
+        self.seen_tx_ids = self.pending_transactions.iter().map(|tx| tx.id.clone()).collect();
+
+        // Fees were debited from senders above; the miner collects them on
+        // top of the (possibly clamped) block reward
+        let fees = Self::total_fees(&transactions).unwrap_or(u64::MAX);
+        let reward_tx = Transaction::new_transfer(
+            "system".into(),
+            miner_address.into(),
+            reward.saturating_add(fees),
+        );
+        match Self::apply_transaction(&mut self.state, &reward_tx, &rules) {
+            Ok(_) => {
+                let receipt = TransactionReceipt::new(reward_tx.id.clone(), index, None);
+                self.receipts.insert(reward_tx.id.clone(), receipt);
+            }
+            Err(reason) => tracing::warn!(tx_id = %reward_tx.id, %reason, "Skipping reward tx"),
+        }
+        transactions.push(reward_tx);
+
+        let previous_hash = self.latest_block().hash.clone();
+        let mut block = Block::new(index, previous_hash, transactions, difficulty);
+        match forging_key {
+            Some(key) => block.forge(&key),
+            None => {
+                block.mine_parallel(self.mining_threads)?;
+                if let Some(key) = &self.producer_key {
+                    block.sign(key);
+                }
+            }
+        }
+
+        self.push_block(block.clone());
+        self.persist_contract_storage();
+        tracing::info!(block_index = index, "Block added to chain");
+
+        Ok(block)
+    }
+
+    /// Stake-weighted producer of the block after `previous_hash`. Each
+    /// account is weighted by its balance and a draw seeded by the hash
+    /// picks one, so every node holding `state` agrees on the result.
+    /// Only key-derived addresses stake: contracts and other addresses
+    /// nobody holds a key for could never forge their slot. `None` when no
+    /// such account holds a balance.
+    pub fn select_producer(state: &WorldState, previous_hash: &str) -> Option<String> {
+        let mut stakers: Vec<(&String, u64)> = state
+            .accounts()
+            .iter()
+            .filter(|(address, account)| {
+                account.balance > 0
+                    && address::is_valid(address)
+                    && !state.contracts().contains_key(*address)
+            })
+            .map(|(address, account)| (address, account.balance))
+            .collect();
+        stakers.sort();
+        let total: u128 = stakers.iter().map(|&(_, stake)| stake as u128).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let seed = Sha256::digest(previous_hash.as_bytes());
+        let mut draw = u128::from_be_bytes(seed[..16].try_into().unwrap()) % total;
+        for (address, stake) in stakers {
+            if draw < stake as u128 {
+                return Some(address.clone());
+            }
+            draw -= stake as u128;
+        }
+        unreachable!("draw is below the total stake")
+    }
+
+    /// Producer of the next block under proof of stake.
+    pub fn next_producer(&self) -> Option<String> {
+        Self::select_producer(&self.state, &self.latest_block().hash)
+    }
+
+    /// Key to forge the next block with, if this node holds the selected
+    /// producer's.
+    fn forging_key(&self) -> CoreResult<SigningKey> {
+        let producer = self
+            .next_producer()
+            .ok_or_else(|| CoreError::MiningError("No account holds stake".into()))?;
+        self.validator_keys.get(&producer).cloned().ok_or_else(|| {
+            CoreError::MiningError(format!(
+                "Selected producer {} is not a validator on this node",
+                producer
+            ))
+        })
+    }
+
+    /// Checks that `block` was forged by the producer `state` selects for
+    /// it. Only applies under proof of stake.
+    fn check_producer(
+        &self,
+        state: &WorldState,
+        block: &Block,
+        previous_hash: &str,
+    ) -> CoreResult<()> {
+        if self.consensus != ConsensusMode::Pos {
+            return Ok(());
+        }
+        let expected = Self::select_producer(state, previous_hash);
+        if block.header.producer.is_none() || block.header.producer != expected {
+            return Err(CoreError::InvalidBlock(format!(
+                "Block {} producer {:?} is not the selected producer {:?}",
+                block.header.index, block.header.producer, expected
+            )));
+        }
+        if !block.is_signed_by_producer() {
+            return Err(CoreError::InvalidBlock(format!(
+                "Block {} is not signed by its producer",
+                block.header.index
+            )));
+        }
+        Ok(())
+    }
+
+    /// Appends a block built elsewhere (e.g. by an external miner) if it
+    /// extends the current tip and every transaction in it is valid.
+    /// Transactions it includes are dropped from the mempool.
+    pub fn try_append_block(&mut self, block: Block) -> CoreResult<()> {
+        let mut state = self.state.clone();
+        let receipts = self.apply_block(&mut state, &self.chain, &block)?;
+
+        self.state = state;
+        self.receipts.extend(receipts);
+        tracing::info!(block_index = block.header.index, "Block appended to chain");
+        self.push_block(block);
+        self.revalidate_pending(Vec::new());
+        self.persist_contract_storage();
+        Ok(())
+    }
+
+    /// Validates `block` as the successor of `chain` and applies it to
+    /// `state`, the state after `chain`. Every path that accepts blocks
+    /// goes through here, so appended, synced and restored blocks are held
+    /// to the same rules. On error `state` may be partly updated.
+    fn apply_block(
+        &self,
+        state: &mut WorldState,
+        chain: &[Block],
+        block: &Block,
+    ) -> CoreResult<Vec<(String, TransactionReceipt)>> {
+        let parent = chain.last().expect("Chain must have at least genesis block");
+        if block.header.index != chain.len() as u64 {
+            return Err(CoreError::InvalidBlock(format!(
+                "Block index {} does not extend chain height {}",
+                block.header.index,
+                chain.len()
+            )));
+        }
+        if block.header.previous_hash != parent.hash {
+            return Err(CoreError::UnknownParent(format!(
+                "Block {} previous_hash does not match the current tip",
+                block.header.index
+            )));
+        }
+        if block.header.timestamp < parent.header.timestamp {
+            return Err(CoreError::InvalidBlock(format!(
+                "Block {} timestamp {} is before its parent's {}",
+                block.header.index, block.header.timestamp, parent.header.timestamp
+            )));
+        }
+        let ahead = (block.header.timestamp - Utc::now()).num_seconds();
+        if ahead > MAX_BLOCK_FUTURE_SECS {
+            return Err(CoreError::InvalidBlock(format!(
+                "Block {} timestamp is {}s in the future (max {}s)",
+                block.header.index, ahead, MAX_BLOCK_FUTURE_SECS
+            )));
+        }
+        let required = self.difficulty_after(chain);
+        if block.header.difficulty != required {
+            return Err(CoreError::InvalidBlock(format!(
+                "Block difficulty {} does not match required {}",
+                block.header.difficulty, required
+            )));
+        }
+        if !self.validated.is_valid(block) {
+            return Err(CoreError::InvalidBlock(format!(
+                "Block {} has an invalid hash or proof of work",
+                block.header.index
+            )));
+        }
+        self.check_producer(state, block, &parent.hash)?;
+
+        let carried = block.transactions.iter().filter(|tx| tx.sender != "system").count();
+        if carried > self.max_block_transactions {
+            return Err(CoreError::InvalidBlock(format!(
+                "Block {} carries {} transactions (max {})",
+                block.header.index, carried, self.max_block_transactions
+            )));
+        }
+
+        if self.require_signatures {
+            Transaction::verify_batch(&block.transactions, self.chain_id).map_err(|e| {
+                CoreError::InvalidBlock(format!("Block {}: {}", block.header.index, e))
+            })?;
+        }
+
+        let rules = self.rules();
+        let mut receipts = Vec::new();
+        let mut rewarded = false;
+        let allowed = Self::total_fees(&block.transactions)
+            .and_then(|fees| {
+                Self::issuable(state, self.mining_reward, self.max_supply).checked_add(fees)
+            })
+            .ok_or_else(|| {
+                CoreError::InvalidBlock(format!("Block {}: fees overflow", block.header.index))
+            })?;
+        for tx in &block.transactions {
+            if tx.sender == "system" {
+                if rewarded || tx.tx_type != TransactionType::Transfer || tx.amount > allowed {
+                    return Err(CoreError::InvalidBlock(format!(
+                        "Invalid reward transaction {}",
+                        tx.id
+                    )));
+                }
+                rewarded = true;
+            }
+            let outcome = Self::apply_transaction(state, tx, &rules).map_err(|reason| {
+                CoreError::InvalidBlock(format!("Transaction {} cannot be applied: {}", tx.id, reason))
+            })?;
+            let receipt = TransactionReceipt::new(tx.id.clone(), block.header.index, outcome);
+            receipts.push((tx.id.clone(), receipt));
+        }
+        Ok(receipts)
+    }
+
+    pub fn is_chain_valid(&self) -> bool {
+        self.blocks_valid(&self.chain)
+    }
+
+    /// Checks blocks from `from_index` to the tip, each against the block
+    /// before it. Blocks unchanged since they last passed are not hashed
+    /// or verified again, so repeated checks cost little.
+    pub fn validate_suffix(&self, from_index: u64) -> bool {
+        self.suffix_valid(&self.chain, from_index)
+    }
+
+    /// Validates `chain` from genesis. Blocks it shares with ours are
+    /// answered from the validation cache.
+    fn blocks_valid(&self, chain: &[Block]) -> bool {
+        if !chain.first().is_some_and(Block::is_valid_genesis) {
+            tracing::error!("Chain has a missing or invalid genesis block");
+            return false;
+        }
+        self.suffix_valid(chain, 1)
+    }
+
+    fn suffix_valid(&self, chain: &[Block], from_index: u64) -> bool {
+        for i in (from_index as usize).max(1)..chain.len() {
+            let current = &chain[i];
+            let previous = &chain[i - 1];
+
+            if !self.validated.is_valid(current) {
+                if !current.has_valid_merkle_root() {
+                    tracing::error!(
+                        "Block {} transactions do not match its merkle root",
+                        current.header.index
+                    );
+                } else {
+                    tracing::error!("Block {} has invalid hash", current.header.index);
+                }
+                return false;
+            }
+
+            if current.header.previous_hash != previous.hash {
+                tracing::error!(
+                    "Block {} has mismatched previous_hash",
+                    current.header.index
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Replaces the local chain with `new_chain` if it has more total work,
+    /// or if it has the same work and wins the configured `ForkTieBreaker`.
+    pub fn replace_chain(&mut self, new_chain: Vec<Block>) -> CoreResult<()> {
+        match new_chain.first() {
+            None => return Err(CoreError::InvalidChain("Incoming chain is empty".into())),
+            Some(genesis) if genesis.hash != self.chain[0].hash => {
+                return Err(CoreError::InvalidChain(format!(
+                    "Incoming chain has genesis {}, expected {}",
+                    genesis.hash, self.chain[0].hash
+                )));
+            }
+            Some(_) => {}
+        }
+
+        if !self.prefers(&new_chain) {
+            return Err(CoreError::InvalidChain(
+                "Incoming chain is not preferred over current chain".into(),
+            ));
+        }
+
+        // Replaying validates every block past the shared genesis
+        let (state, receipts) = self
+            .rebuild_state(&new_chain)
+            .map_err(|e| CoreError::InvalidChain(format!("Incoming chain is not valid: {}", e)))?;
+
+        tracing::info!(
+            "Replacing chain: {} blocks -> {} blocks",
+            self.chain.len(),
+            new_chain.len()
+        );
+        let fork = self
+            .chain
+            .iter()
+            .zip(&new_chain)
+            .take_while(|(ours, theirs)| ours.hash == theirs.hash)
+            .count();
+        let orphaned: Vec<Transaction> = self.chain[fork..]
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .filter(|tx| tx.sender != address::SYSTEM)
+            .cloned()
+            .collect();
+        self.replace_state(state, receipts);
+        self.set_chain(new_chain);
+        self.revalidate_pending(orphaned);
+        Ok(())
+    }
+
+    /// Loads a previously persisted chain, replacing the current one
+    /// regardless of length. The chain must still be valid.
+    pub fn restore_chain(&mut self, blocks: Vec<Block>) -> CoreResult<()> {
+        let Some(genesis) = blocks.first() else {
+            return Err(CoreError::InvalidChain("Persisted chain is empty".into()));
+        };
+        if genesis.hash != self.chain[0].hash {
+            return Err(CoreError::InvalidChain(format!(
+                "Persisted chain has genesis {}, expected {}",
+                genesis.hash, self.chain[0].hash
+            )));
+        }
+        let (mut state, receipts) = self
+            .rebuild_state(&blocks)
+            .map_err(|e| CoreError::InvalidChain(format!("Persisted chain is not valid: {}", e)))?;
+        if let Some(backend) = &self.contract_storage {
+            // Without a runtime the replay can't recompute storage
+            state.load_contract_storage(backend.as_ref(), self.contract_runtime.is_none())?;
+        }
+        self.replace_state(state, receipts);
+        self.set_chain(blocks);
+        Ok(())
+    }
+
+    /// Re-submits persisted mempool transactions against the current state,
+    /// discarding any that are no longer valid or have expired; how long
+    /// they sat on disk doesn't matter. Returns how many were kept.
+    pub fn restore_pending(&mut self, transactions: Vec<Transaction>) -> usize {
+        let mut accepted = 0;
+        for tx in transactions {
+            let id = tx.id.clone();
+            match self.admit_transaction(tx, false) {
+                Ok(()) => accepted += 1,
+                Err(e) => tracing::warn!("Discarding persisted tx {}: {}", id, e),
+            }
+        }
+        accepted
+    }
+
+    /// Replays `chain` from empty state, returning the state and the
+    /// receipts of its transactions. Genesis must already be known good;
+    /// every later block goes through `apply_block`, so one invalid block
+    /// or transaction fails the whole chain.
+    fn rebuild_state(
+        &self,
+        chain: &[Block],
+    ) -> CoreResult<(WorldState, HashMap<String, TransactionReceipt>)> {
+        let Some(genesis) = chain.first() else {
+            return Err(CoreError::InvalidChain("Chain is empty".into()));
+        };
+        let rules = self.rules();
+        let mut state = WorldState::new();
+        let mut receipts = HashMap::new();
+        for tx in &genesis.transactions {
+            let outcome = Self::apply_transaction(&mut state, tx, &rules).map_err(|reason| {
+                CoreError::InvalidBlock(format!("Genesis transaction {} failed: {}", tx.id, reason))
+            })?;
+            receipts.insert(tx.id.clone(), TransactionReceipt::new(tx.id.clone(), 0, outcome));
+        }
+        for i in 1..chain.len() {
+            receipts.extend(self.apply_block(&mut state, &chain[..i], &chain[i])?);
+        }
+        Ok((state, receipts))
+    }
+
+    fn rules(&self) -> ApplyRules {
+        ApplyRules {
+            require_signatures: self.require_signatures,
+            chain_id: self.chain_id,
+            max_supply: self.max_supply,
+            deploy_fee_per_byte: self.deploy_fee_per_byte,
+            contract_runtime: self.contract_runtime.clone(),
+        }
+    }
+
+    /// Applies one mined transaction to `state`, returning why it was
+    /// skipped if it could not be applied. Contract calls run by the
+    /// runtime yield their outcome.
+    fn apply_transaction(
+        state: &mut WorldState,
+        tx: &Transaction,
+        rules: &ApplyRules,
+    ) -> Result<Option<CallOutcome>, String> {
+        let max_supply = rules.max_supply;
+        if tx.sender != "system" {
+            let expected = state.get_account(&tx.sender).map_or(0, |a| a.nonce);
+            if tx.nonce != expected {
+                return Err(format!("nonce {} does not match expected {}", tx.nonce, expected));
+            }
+            if rules.require_signatures {
+                Self::authorize(state, tx, rules.chain_id)?;
+            }
+        }
+
+        if tx.sender != "system" {
+            let value = match tx.tx_type {
+                TransactionType::Transfer | TransactionType::ContractCall => tx.amount,
+                TransactionType::ContractDeploy => rules.deploy_fee(tx),
+                _ => 0,
+            };
+            let cost = value.checked_add(tx.fee).ok_or("amount plus fee overflows")?;
+            if state.get_balance(&tx.sender) < cost {
+                return Err("insufficient balance for amount plus fee".into());
+            }
+        }
+
+        let mut outcome = None;
+        match tx.tx_type {
+            TransactionType::Transfer => {
+                if tx.sender == "system" {
+                    let amount = Self::issuable(state, tx.amount, max_supply);
+                    if !state.credit(&tx.recipient, amount) {
+                        return Err("credit overflows recipient balance".into());
+                    }
+                } else if !state.transfer(&tx.sender, &tx.recipient, tx.amount) {
+                    // The sender's balance was checked above
+                    return Err("recipient balance would overflow".into());
+                }
+            }
+            TransactionType::RegisterName => {
+                let name = tx.name().ok_or("name is not valid UTF-8")?;
+                if !state.register_name(name, &tx.sender) {
+                    return Err(format!("name '{}' already registered", name));
+                }
+                state.get_or_create_account(&tx.sender).nonce += 1;
+            }
+            TransactionType::SetMultisig => {
+                let config = tx
+                    .multisig_config()
+                    .filter(|c| c.as_ref().is_none_or(MultisigConfig::is_valid))
+                    .ok_or("invalid multisig config")?;
+                state.set_multisig(&tx.sender, config);
+                state.get_or_create_account(&tx.sender).nonce += 1;
+            }
+            TransactionType::ContractDeploy => {
+                // Deploying only stores bytecode; no execution happens here.
+                // The per-byte deploy fee is burned, not paid to the miner
+                if !state.burn(&tx.sender, rules.deploy_fee(tx)) {
+                    return Err("insufficient balance for deploy fee".into());
+                }
+                state.deploy_contract_from(&tx.sender, tx.data.clone());
+            }
+            TransactionType::ContractCall => {
+                if state.get_contract(&tx.recipient).is_none() {
+                    return Err(format!("contract {} not found", tx.recipient));
+                }
+                // The value reaches the contract before it runs, so the
+                // call can spend it; this also bumps the sender's nonce
+                if !state.transfer(&tx.sender, &tx.recipient, tx.amount) {
+                    return Err("contract balance would overflow".into());
+                }
+                if let Some(runtime) = &rules.contract_runtime {
+                    let result = runtime.call(state, &tx.sender, &tx.recipient, &tx.data);
+                    if result.error.is_some() {
+                        // A failed call left state untouched, so the value
+                        // is still on the contract to hand back
+                        state.get_or_create_account(&tx.recipient).balance -= tx.amount;
+                        state.credit(&tx.sender, tx.amount);
+                    }
+                    outcome = Some(result);
+                }
+            }
+        }
+
+        // Balance covering the fee was checked above; the miner is credited
+        // through the block's reward transaction
+        if tx.sender != "system" && tx.fee > 0 {
+            state.get_or_create_account(&tx.sender).balance -= tx.fee;
+        }
+        Ok(outcome)
+    }
+
+    /// `None` if the fees overflow, which only a forged block can make
+    /// them do.
+    fn total_fees(transactions: &[Transaction]) -> Option<u64> {
+        transactions
+            .iter()
+            .filter(|tx| tx.sender != "system")
+            .try_fold(0u64, |total, tx| total.checked_add(tx.fee))
+    }
+
+    /// How much of `amount` can be newly issued without everything ever
+    /// issued exceeding `max_supply`; burned coins don't free up room.
+    fn issuable(state: &WorldState, amount: u64, max_supply: Option<u64>) -> u64 {
+        match max_supply {
+            Some(cap) => amount.min(cap.saturating_sub(state.total_issued())),
+            None => amount,
+        }
+    }
+
+    /// Accumulated proof-of-work: each block counts the expected number of
+    /// hashes to meet its difficulty (16^difficulty, saturating).
+    pub fn total_work(&self) -> u128 {
+        Self::chain_work(&self.chain)
+    }
+
+    fn chain_work(chain: &[Block]) -> u128 {
+        chain
+            .iter()
+            .map(|block| {
+                1u128
+                    .checked_shl(4 * block.header.difficulty)
+                    .unwrap_or(u128::MAX)
+            })
+            .fold(0, u128::saturating_add)
+    }
+
+    fn prefers(&self, new_chain: &[Block]) -> bool {
+        let (incoming, local) = (Self::chain_work(new_chain), self.total_work());
+        if incoming != local {
+            return incoming > local;
+        }
+        match (self.tie_breaker, new_chain.last()) {
+            (ForkTieBreaker::LowestHash, Some(tip)) => tip.hash < self.latest_block().hash,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::test_address;
+    use crate::receipt::ReceiptStatus;
+    use crate::wallet::Wallet;
+
+    #[test]
+    fn test_new_blockchain() {
+        let bc = Blockchain::new(1, 50);
+        assert_eq!(bc.chain().len(), 1);
+        assert_eq!(bc.height(), 1);
+    }
+
+    #[test]
+    fn test_mine_block() {
+        let mut bc = Blockchain::new(1, 50);
+        let block = bc.mine_pending("miner").unwrap();
+        assert_eq!(block.header.index, 1);
+        assert_eq!(bc.chain().len(), 2);
+        assert!(bc.is_chain_valid());
+    }
+
+    #[test]
+    fn test_fast_blocks_raise_difficulty() {
+        let mut bc = Blockchain::new(1, 50).with_target_block_time(Duration::from_secs(60));
+        for _ in 0..RETARGET_WINDOW {
+            assert_eq!(bc.next_difficulty(), 1);
+            bc.mine_pending("miner").unwrap();
+        }
+
+        assert_eq!(bc.next_difficulty(), 2);
+        let block = bc.mine_pending("miner").unwrap();
+        assert_eq!(block.header.difficulty, 2);
+        assert!(bc.is_chain_valid());
+    }
+
+    /// Spaces the mined blocks `gap` apart, ending now.
+    fn space_blocks(bc: &mut Blockchain, gap: chrono::Duration) {
+        let now = Utc::now();
+        let len = bc.chain.len();
+        for (i, block) in bc.chain.iter_mut().enumerate().skip(1) {
+            block.header.timestamp = now - gap * (len - 1 - i) as i32;
+        }
+    }
+
+    #[test]
+    fn test_slow_blocks_lower_difficulty_to_floor() {
+        let target = Duration::from_secs(60);
+        let mut bc = Blockchain::new(2, 50).with_target_block_time(target);
+        for _ in 0..RETARGET_WINDOW {
+            bc.mine_pending("miner").unwrap();
+        }
+        // On-target spacing keeps the current difficulty
+        space_blocks(&mut bc, chrono::Duration::seconds(60));
+        assert_eq!(bc.next_difficulty(), 2);
+        space_blocks(&mut bc, chrono::Duration::minutes(10));
+        assert_eq!(bc.next_difficulty(), 1);
+
+        let mut bc = Blockchain::new(1, 50).with_target_block_time(target);
+        for _ in 0..RETARGET_WINDOW {
+            bc.mine_pending("miner").unwrap();
+        }
+        space_blocks(&mut bc, chrono::Duration::minutes(10));
+        assert_eq!(bc.next_difficulty(), 1);
+    }
+
+    #[test]
+    fn test_difficulty_fixed_without_target() {
+        let mut bc = Blockchain::new(1, 50);
+        for _ in 0..=RETARGET_WINDOW {
+            bc.mine_pending("miner").unwrap();
+        }
+        assert_eq!(bc.next_difficulty(), 1);
+    }
+
+    #[test]
+    fn test_mine_with_transactions() {
+        let mut bc = Blockchain::new(1, 50);
+
+        // First mine to give miner some coins
+        bc.mine_pending("miner").unwrap();
+        assert_eq!(bc.state().get_balance("miner"), 50);
+
+        // Create and sign transfer
+        let wallet = Wallet::new();
+        bc.state_mut().credit(&wallet.address, 1000);
+
+        let mut tx = Transaction::new_transfer(
+            wallet.address.clone(),
+            test_address("bob"),
+            100,
+        );
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+
+        bc.mine_pending("miner").unwrap();
+        assert_eq!(bc.state().get_balance(&test_address("bob")), 100);
+        assert_eq!(bc.state().get_balance(&wallet.address), 900);
+    }
+
+    #[test]
+    fn test_find_transaction() {
+        let allocations = HashMap::from([(test_address("alice"), 100)]);
+        let mut bc = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_genesis_allocations(allocations.clone());
+        let tx = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10);
+        let id = tx.id.clone();
+        bc.add_transaction(tx).unwrap();
+        assert_eq!(bc.find_transaction(&id).unwrap().1, None);
+
+        bc.mine_pending("miner").unwrap();
+        let (found, index) = bc.find_transaction(&id).unwrap();
+        assert_eq!(found.id, id);
+        assert_eq!(index, Some(1));
+        assert!(bc.find_transaction("missing").is_none());
+
+        let mut other = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_genesis_allocations(allocations);
+        other.replace_chain(bc.chain().to_vec()).unwrap();
+        assert_eq!(other.find_transaction(&id).unwrap().1, Some(1));
+    }
+
+    #[test]
+    fn test_insufficient_balance() {
+        let mut bc = Blockchain::new(1, 50);
+        let wallet = Wallet::new();
+
+        let mut tx = Transaction::new_transfer(
             wallet.address.clone(),
-            "bob".into(),
+            test_address("bob"),
             100,
         );
-        tx.sign(wallet.signing_key());
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        let result = bc.add_transaction(tx);
+        assert!(matches!(result, Err(CoreError::InsufficientBalance { .. })));
+    }
+
+    #[test]
+    fn test_zero_amount_transfer_rejected() {
+        let mut bc = Blockchain::new(1, 50);
+        let wallet = Wallet::new();
+        bc.state_mut().credit(&wallet.address, 100);
+
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), Wallet::new().address, 0)
+            .with_fee(1);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        let result = bc.add_transaction(tx);
+        assert!(matches!(result, Err(CoreError::InvalidTransaction(_))));
+        assert!(bc.pending_transactions().is_empty());
+    }
+
+    #[test]
+    fn test_equal_length_fork_converges_on_lowest_tip_hash() {
+        let mut base = Blockchain::new(1, 50);
+        base.mine_pending("miner").unwrap();
+
+        let mut node_a = base.clone();
+        let mut node_b = base.clone();
+        node_a.mine_pending("miner-a").unwrap();
+        node_b.mine_pending("miner-b").unwrap();
+
+        let chain_a = node_a.chain().to_vec();
+        let chain_b = node_b.chain().to_vec();
+        let expected_tip = std::cmp::min(
+            chain_a.last().unwrap().hash.clone(),
+            chain_b.last().unwrap().hash.clone(),
+        );
+
+        // Exactly one side switches; the other keeps its (already lower) tip.
+        let a_switched = node_a.replace_chain(chain_b).is_ok();
+        let b_switched = node_b.replace_chain(chain_a).is_ok();
+        assert!(a_switched ^ b_switched);
+
+        assert_eq!(node_a.latest_block().hash, expected_tip);
+        assert_eq!(node_b.latest_block().hash, expected_tip);
+    }
+
+    #[test]
+    fn test_keep_local_tie_breaker_rejects_equal_length() {
+        let mut base = Blockchain::new(1, 50).with_tie_breaker(ForkTieBreaker::KeepLocal);
+        base.mine_pending("miner").unwrap();
+
+        let mut node_a = base.clone();
+        let mut node_b = base.clone();
+        node_a.mine_pending("miner-a").unwrap();
+        node_b.mine_pending("miner-b").unwrap();
+
+        assert!(node_a.replace_chain(node_b.chain().to_vec()).is_err());
+        assert!(node_b.replace_chain(node_a.chain().to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_chain_hash_matches_for_identical_chains() {
+        let mut bc = Blockchain::new(1, 50);
+        bc.mine_pending("miner").unwrap();
+        let mut synced = bc.clone();
+        assert_eq!(bc.chain_hash(), synced.chain_hash());
+
+        bc.mine_pending("miner-a").unwrap();
+        synced.mine_pending("miner-b").unwrap();
+        assert_ne!(bc.chain_hash(), synced.chain_hash());
+    }
+
+    #[test]
+    fn test_unsigned_transfer_rejected_in_strict_mode() {
+        let mut bc = Blockchain::new(1, 50);
+        bc.state_mut().credit(&test_address("alice"), 100);
+
+        let tx = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10);
+        let result = bc.add_transaction(tx);
+        assert!(matches!(result, Err(CoreError::InvalidSignature(_))));
+        assert!(bc.pending_transactions().is_empty());
+    }
+
+    #[test]
+    fn test_unsigned_transfer_accepted_in_lax_mode() {
+        let mut bc = Blockchain::new(1, 50).with_require_signatures(false);
+        bc.state_mut().credit(&test_address("alice"), 100);
+
+        let tx = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10);
+        bc.add_transaction(tx).unwrap();
+        assert_eq!(bc.pending_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_transaction_signed_for_other_chain_rejected() {
+        let wallet = Wallet::new();
+        let mut mainnet = Blockchain::new(1, 50).with_chain_id(1);
+        let mut testnet = Blockchain::new(1, 50).with_chain_id(2);
+        mainnet.state_mut().credit(&wallet.address, 100);
+        testnet.state_mut().credit(&wallet.address, 100);
+
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 10);
+        tx.sign(wallet.signing_key(), 1);
+        let result = testnet.add_transaction(tx.clone());
+        assert!(matches!(result, Err(CoreError::InvalidSignature(_))));
+
+        mainnet.add_transaction(tx).unwrap();
+        mainnet.mine_pending("miner").unwrap();
+        let result = testnet.try_append_block(mainnet.latest_block().clone());
+        assert!(matches!(result, Err(CoreError::InvalidBlock(_))));
+    }
+
+    #[test]
+    fn test_strict_node_rejects_chain_with_unsigned_transaction() {
+        let mut lax = Blockchain::new(1, 50).with_require_signatures(false);
+        lax.state_mut().credit(&test_address("alice"), 100);
+        let tx = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10);
+        lax.add_transaction(tx).unwrap();
+        lax.mine_pending("miner").unwrap();
+
+        let mut strict = Blockchain::new(1, 50);
+        let result = strict.replace_chain(lax.chain().to_vec());
+        assert!(matches!(result, Err(CoreError::InvalidChain(_))));
+        let result = strict.try_append_block(lax.latest_block().clone());
+        assert!(matches!(result, Err(CoreError::InvalidBlock(_))));
+        assert_eq!(strict.height(), 1);
+    }
+
+    #[test]
+    fn test_future_dated_transaction_rejected() {
+        let mut bc = Blockchain::new(1, 50).with_require_signatures(false);
+        bc.state_mut().credit(&test_address("alice"), 100);
+
+        let mut tx = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10);
+        tx.timestamp = Utc::now() + chrono::Duration::hours(1);
+        let result = bc.add_transaction(tx);
+        let future = |msg: &str| msg.contains("in the future");
+        assert!(matches!(result, Err(CoreError::InvalidTransaction(ref msg)) if future(msg)));
+        assert!(bc.pending_transactions().is_empty());
+    }
+
+    #[test]
+    fn test_stale_transaction_rejected() {
+        let mut bc = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_timestamp_tolerance_secs(60);
+        bc.state_mut().credit(&test_address("alice"), 100);
+
+        let mut tx = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10);
+        tx.timestamp = Utc::now() - chrono::Duration::minutes(5);
+        let result = bc.add_transaction(tx);
+        let past = |msg: &str| msg.contains("in the past");
+        assert!(matches!(result, Err(CoreError::InvalidTransaction(ref msg)) if past(msg)));
+
+        // System transactions are exempt from the window
+        let mut reward = Transaction::new_transfer("system".into(), "miner".into(), 50);
+        reward.timestamp = Utc::now() - chrono::Duration::days(1);
+        bc.add_transaction(reward).unwrap();
+        assert_eq!(bc.pending_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_adopted_block_drops_stale_pending_nonce() {
+        let allocations = HashMap::from([(test_address("alice"), 100)]);
+        let mut local = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_genesis_allocations(allocations);
+        let mut peer = local.clone();
+
+        let ours = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10);
+        local.add_transaction(ours).unwrap();
+        let theirs = Transaction::new_transfer(test_address("alice"), test_address("carol"), 5);
+        peer.add_transaction(theirs).unwrap();
+        local.try_append_block(peer.mine_pending("miner").unwrap()).unwrap();
+
+        assert!(local.pending_transactions().is_empty());
+        assert_eq!(local.next_nonce(&test_address("alice")), 1);
+    }
+
+    #[test]
+    fn test_reorg_requeues_orphaned_transactions() {
+        let allocations = HashMap::from([(test_address("alice"), 100)]);
+        let mut local = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_genesis_allocations(allocations);
+        let mut peer = local.clone();
+
+        let tx = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10);
+        local.add_transaction(tx.clone()).unwrap();
+        local.mine_pending("local").unwrap();
+        peer.mine_pending("peer").unwrap();
+        peer.mine_pending("peer").unwrap();
+
+        local.replace_chain(peer.chain().to_vec()).unwrap();
+        let pending = local.pending_transactions();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, tx.id);
+        assert_eq!(local.next_nonce(&test_address("alice")), 1);
+    }
+
+    #[test]
+    fn test_restored_transaction_skips_receive_window() {
+        let mut bc = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_timestamp_tolerance_secs(60);
+        bc.state_mut().credit(&test_address("alice"), 100);
+
+        let mut tx = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10);
+        tx.timestamp = Utc::now() - chrono::Duration::hours(1);
+        assert!(bc.add_transaction(tx.clone()).is_err());
+        assert_eq!(bc.restore_pending(vec![tx]), 1);
+
+        // Expiry still applies
+        let mut expired = Transaction::new_transfer(test_address("alice"), test_address("bob"), 10)
+            .with_nonce(1)
+            .with_valid_until(Utc::now() - chrono::Duration::minutes(1));
+        expired.timestamp = Utc::now() - chrono::Duration::hours(1);
+        assert_eq!(bc.restore_pending(vec![expired]), 0);
+    }
+
+    fn register_name_tx(wallet: &Wallet, name: &str) -> Transaction {
+        let mut tx = Transaction::new_register_name(wallet.address.clone(), name.into());
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        tx
+    }
+
+    #[test]
+    fn test_register_and_resolve_name() {
+        let mut bc = Blockchain::new(1, 50);
+        let wallet = Wallet::new();
+
+        bc.add_transaction(register_name_tx(&wallet, "alice")).unwrap();
+        assert_eq!(bc.state().resolve_name("alice"), None);
+
+        bc.mine_pending("miner").unwrap();
+        assert_eq!(bc.state().resolve_name("alice"), Some(wallet.address.as_str()));
+    }
+
+    #[test]
+    fn test_duplicate_name_rejected() {
+        let mut bc = Blockchain::new(1, 50);
+        let first = Wallet::new();
+        let second = Wallet::new();
+
+        bc.add_transaction(register_name_tx(&first, "alice")).unwrap();
+        // Still pending, but the name is already claimed
+        assert!(bc.add_transaction(register_name_tx(&second, "alice")).is_err());
+
+        bc.mine_pending("miner").unwrap();
+        let result = bc.add_transaction(register_name_tx(&second, "alice"));
+        assert!(matches!(result, Err(CoreError::InvalidTransaction(_))));
+        assert_eq!(bc.state().resolve_name("alice"), Some(first.address.as_str()));
+    }
+
+    #[test]
+    fn test_name_must_be_signed_by_owner() {
+        let mut bc = Blockchain::new(1, 50);
+        let owner = Wallet::new();
+        let impostor = Wallet::new();
+
+        let mut tx = Transaction::new_register_name(owner.address.clone(), "alice".into());
+        tx.sign(impostor.signing_key(), DEFAULT_CHAIN_ID);
+        let result = bc.add_transaction(tx);
+        assert!(matches!(result, Err(CoreError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_replayed_deploys_keep_their_addresses() {
+        let mut bc = Blockchain::new(1, 50).with_require_signatures(false);
+        let alice = test_address("alice");
+        for (nonce, code) in [vec![0x01], vec![0x02], vec![0x01]].into_iter().enumerate() {
+            let tx = Transaction::new_contract_deploy(alice.clone(), code).with_nonce(nonce as u64);
+            bc.add_transaction(tx).unwrap();
+        }
+        bc.mine_pending("miner").unwrap();
+        bc.add_transaction(Transaction::new_contract_deploy(test_address("bob"), vec![0x03]))
+            .unwrap();
+        bc.mine_pending("miner").unwrap();
+
+        let mut addresses: Vec<_> = bc.state().contracts().keys().cloned().collect();
+        addresses.sort();
+        assert_eq!(addresses.len(), 4);
+        assert!(addresses.contains(&WorldState::contract_address(&alice, 2)));
+
+        let mut other = Blockchain::new(1, 50).with_require_signatures(false);
+        other.replace_chain(bc.chain().to_vec()).unwrap();
+        let mut replayed: Vec<_> = other.state().contracts().keys().cloned().collect();
+        replayed.sort();
+        assert_eq!(replayed, addresses);
+        for address in &addresses {
+            assert_eq!(
+                other.state().get_contract(address).unwrap().bytecode,
+                bc.state().get_contract(address).unwrap().bytecode
+            );
+        }
+    }
+
+    #[test]
+    fn test_longer_chain_with_foreign_genesis_rejected() {
+        let mut bc = Blockchain::new(1, 50);
+
+        let mut genesis = Block::genesis();
+        genesis.header.timestamp = Utc::now();
+        genesis.hash = Block::calculate_hash(&genesis.header);
+        let mut foreign = vec![genesis];
+        for index in 1..3 {
+            let mut block = Block::new(index, foreign.last().unwrap().hash.clone(), vec![], 1);
+            block.mine().unwrap();
+            foreign.push(block);
+        }
+        assert!(bc.blocks_valid(&foreign));
+
+        let result = bc.replace_chain(foreign);
+        assert!(matches!(result, Err(CoreError::InvalidChain(_))));
+        assert_eq!(bc.height(), 1);
+        assert!(bc.replace_chain(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_genesis_is_stable_and_carries_allocations() {
+        assert_eq!(Blockchain::new(1, 50).chain()[0].hash, Blockchain::new(3, 10).chain()[0].hash);
+
+        let allocations: HashMap<String, u64> =
+            [("alice".to_string(), 1_000), ("bob".to_string(), 250)].into();
+        let mut bc = Blockchain::new(1, 50).with_genesis_allocations(allocations.clone());
+        assert_eq!(bc.state().get_balance("alice"), 1_000);
+        assert_eq!(bc.state().get_balance("bob"), 250);
+        assert!(bc.is_chain_valid());
+        let twin = Blockchain::new(1, 50).with_genesis_allocations(allocations);
+        assert_eq!(bc.chain()[0].hash, twin.chain()[0].hash);
+
+        // A node with a different genesis rejects the chain outright
+        bc.mine_pending("miner").unwrap();
+        let mut plain = Blockchain::new(1, 50);
+        let err = plain.replace_chain(bc.chain().to_vec()).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidChain(ref msg) if msg.contains("genesis")));
+        let err = plain.restore_chain(bc.chain().to_vec()).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidChain(_)));
+    }
+
+    #[test]
+    fn test_tampered_genesis_invalidates_chain() {
+        let mut bc = Blockchain::new(1, 50);
+        bc.mine_pending("miner").unwrap();
+        assert!(bc.is_chain_valid());
+
+        bc.chain[0].header.previous_hash = "1".repeat(64);
+        assert!(!bc.is_chain_valid());
+    }
+
+    #[test]
+    fn test_rewards_stop_at_max_supply() {
+        let mut bc = Blockchain::new(1, 50).with_max_supply(120);
+        bc.mine_pending("miner").unwrap();
+        bc.mine_pending("miner").unwrap();
+        assert_eq!(bc.state().total_supply(), 100);
+
+        let block = bc.mine_pending("miner").unwrap();
+        assert_eq!(block.transactions.last().unwrap().amount, 20);
+        let block = bc.mine_pending("miner").unwrap();
+        assert_eq!(block.transactions.last().unwrap().amount, 0);
+        assert_eq!(bc.state().total_supply(), 120);
+
+        // Replay under the same cap reaches the same supply
+        let mut other = Blockchain::new(1, 50).with_max_supply(120);
+        other.replace_chain(bc.chain().to_vec()).unwrap();
+        assert_eq!(other.state().get_balance("miner"), 120);
+    }
+
+    #[test]
+    fn test_mined_transfer_gets_success_receipt() {
+        let mut bc = Blockchain::new(1, 50).with_require_signatures(false);
+        let alice = test_address("alice");
+        bc.mine_pending(&alice).unwrap();
+        let tx = Transaction::new_transfer(alice, test_address("bob"), 10);
+        bc.add_transaction(tx.clone()).unwrap();
+        assert!(bc.receipt(&tx.id).is_none());
+
+        let block = bc.mine_pending("miner").unwrap();
+        let receipt = bc.receipt(&tx.id).unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Success);
+        assert_eq!(receipt.block_index, 2);
+        assert_eq!((receipt.gas_used, receipt.return_value), (0, None));
+        let reward = block.transactions.last().unwrap();
+        assert!(bc.receipt(&reward.id).is_some());
+
+        let mut replica = Blockchain::new(1, 50).with_require_signatures(false);
+        replica.replace_chain(bc.chain().to_vec()).unwrap();
+        assert_eq!(replica.receipt(&tx.id), Some(receipt));
+    }
+
+    #[test]
+    fn test_mined_deploy_creates_contract() {
+        let mut bc = Blockchain::new(1, 50).with_deploy_fee_per_byte(10);
+        let wallet = Wallet::new();
+        bc.state_mut().credit(&wallet.address, 100);
+
+        let mut tx = Transaction::new_contract_deploy(wallet.address.clone(), vec![0x3F; 3])
+            .with_fee(5);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+        let address = WorldState::contract_address(&wallet.address, 0);
+        assert!(bc.state().get_contract(&address).is_none());
+
+        bc.mine_pending("miner").unwrap();
+        let contract = bc.state().get_contract(&address).unwrap();
+        assert_eq!(contract.bytecode, vec![0x3F; 3]);
+        assert_eq!(contract.owner, wallet.address);
+        // 30 deploy fee burned, 5 paid to the miner
+        assert_eq!(bc.state().get_balance(&wallet.address), 65);
+        assert_eq!(bc.state().get_balance("miner"), 55);
+
+        let mut replica = Blockchain::new(1, 50).with_deploy_fee_per_byte(10);
+        replica.state_mut().credit(&wallet.address, 100);
+        replica.try_append_block(bc.latest_block().clone()).unwrap();
+        assert!(replica.state().get_contract(&address).is_some());
+        assert_eq!(replica.state().get_balance(&wallet.address), 65);
+    }
+
+    #[test]
+    fn test_burned_deploy_fees_are_not_reissued() {
+        let wallet = Wallet::new();
+        let allocations = HashMap::from([(wallet.address.clone(), 100)]);
+        let mut bc = Blockchain::new(1, 50)
+            .with_deploy_fee_per_byte(10)
+            .with_max_supply(150)
+            .with_genesis_allocations(allocations);
+
+        let mut tx = Transaction::new_contract_deploy(wallet.address.clone(), vec![0x3F; 3]);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+        bc.mine_pending("miner").unwrap();
+        assert_eq!(bc.state().total_supply(), 120);
+        assert_eq!(bc.state().total_issued(), 150);
+
+        let block = bc.mine_pending("miner").unwrap();
+        assert_eq!(block.transactions.last().unwrap().amount, 0);
+        assert_eq!(bc.state().total_supply(), 120);
+    }
+
+    #[test]
+    fn test_underfunded_deploy_rejected() {
+        let mut bc = Blockchain::new(1, 50).with_deploy_fee_per_byte(10);
+        let wallet = Wallet::new();
+        bc.state_mut().credit(&wallet.address, 25);
+
+        let mut tx = Transaction::new_contract_deploy(wallet.address.clone(), vec![0u8; 3]);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        let result = bc.add_transaction(tx);
+        assert!(matches!(
+            result,
+            Err(CoreError::InsufficientBalance { balance: 25, required: 30, .. })
+        ));
+
+        let mut tx = Transaction::new_contract_deploy(wallet.address.clone(), vec![0u8; 2]);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+        assert_eq!(bc.pending_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_deploy_and_call_shape_checks() {
+        let mut bc = Blockchain::new(1, 50).with_require_signatures(false);
+
+        let empty = Transaction::new_contract_deploy(test_address("alice"), Vec::new());
+        assert!(matches!(bc.add_transaction(empty), Err(CoreError::InvalidTransaction(_))));
+
+        let huge =
+            Transaction::new_contract_deploy(test_address("alice"), vec![0u8; MAX_BYTECODE_SIZE + 1]);
+        assert!(matches!(bc.add_transaction(huge), Err(CoreError::InvalidTransaction(_))));
+
+        let call =
+            Transaction::new_contract_call(test_address("alice"), "0xcmissing".into(), vec![]);
+        assert!(matches!(bc.add_transaction(call), Err(CoreError::ContractNotFound(_))));
+    }
+
+    #[test]
+    fn test_duplicate_transaction_rejected() {
+        let mut bc = Blockchain::new(1, 50);
+        let wallet = Wallet::new();
+        bc.state_mut().credit(&wallet.address, 100);
+
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 10);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx.clone()).unwrap();
+
+        // Resubmitted while still pending
+        let result = bc.add_transaction(tx.clone());
+        assert!(matches!(result, Err(CoreError::DuplicateTransaction(id)) if id == tx.id));
+
+        // Replayed after it was mined
+        bc.mine_pending("miner").unwrap();
+        assert!(bc.seen_tx_ids.is_empty());
+        assert_eq!(bc.state().get_account(&wallet.address).unwrap().nonce, 1);
+        let result = bc.add_transaction(tx.clone());
+        assert!(matches!(result, Err(CoreError::DuplicateTransaction(id)) if id == tx.id));
+        assert_eq!(bc.state().get_balance(&test_address("bob")), 10);
+
+        let mut next =
+            Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 5).with_nonce(1);
+        next.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(next).unwrap();
+    }
+
+    #[test]
+    fn test_expired_transactions_rejected_and_dropped() {
+        let mut bc = Blockchain::new(1, 50).with_require_signatures(false);
+        let alice = test_address("alice");
+        bc.state_mut().credit(&alice, 100);
+
+        let past = Utc::now() - chrono::Duration::seconds(1);
+        let expired = Transaction::new_transfer(alice.clone(), test_address("bob"), 10)
+            .with_valid_until(past);
+        let result = bc.add_transaction(expired);
+        assert!(matches!(result, Err(CoreError::InvalidTransaction(_))));
+
+        let future = Utc::now() + chrono::Duration::minutes(5);
+        let tx = Transaction::new_transfer(alice.clone(), test_address("bob"), 10)
+            .with_valid_until(future);
+        bc.add_transaction(tx).unwrap();
+
+        // Expires while waiting in the mempool
+        bc.pending_transactions[0].valid_until = Some(past);
+        let block = bc.mine_pending("miner").unwrap();
+        assert_eq!(block.transactions.len(), 1);
+        assert!(bc.pending_transactions().is_empty());
+        assert_eq!(bc.state().get_balance(&test_address("bob")), 0);
+    }
+
+    #[test]
+    fn test_multisig_account_needs_owner_signatures() {
+        let vault = Wallet::new();
+        let owners: Vec<_> = (0..3).map(|_| Wallet::new()).collect();
+        let outsider = Wallet::new();
+        let allocations = HashMap::from([(vault.address.clone(), 100)]);
+        let mut bc = Blockchain::new(1, 50).with_genesis_allocations(allocations.clone());
+
+        let config = MultisigConfig {
+            owners: owners.iter().map(|w| w.address.clone()).collect(),
+            threshold: 2,
+        };
+        let mut tx = Transaction::new_set_multisig(vault.address.clone(), Some(&config));
+        tx.sign(vault.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+        bc.mine_pending("miner").unwrap();
+
+        // The vault's own key is not enough
+        let mut tx = Transaction::new_transfer(vault.address.clone(), test_address("bob"), 10)
+            .with_nonce(1);
+        tx.sign(vault.signing_key(), DEFAULT_CHAIN_ID);
+        assert!(matches!(bc.add_transaction(tx), Err(CoreError::InvalidSignature(_))));
+
+        // Two valid signatures, but only one from an owner
+        let mut tx = Transaction::new_transfer(vault.address.clone(), test_address("bob"), 10)
+            .with_nonce(1)
+            .with_multisig(2);
+        tx.cosign(owners[0].signing_key(), DEFAULT_CHAIN_ID);
+        tx.cosign(outsider.signing_key(), DEFAULT_CHAIN_ID);
+        assert!(matches!(bc.add_transaction(tx), Err(CoreError::InvalidSignature(_))));
+
+        // The config is chain state, so a replica replaying the chain has it
+        // and rejects a block carrying an under-signed vault transaction
+        let mut lax = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_genesis_allocations(allocations);
+        lax.replace_chain(bc.chain().to_vec()).unwrap();
+        assert_eq!(lax.state().get_account(&vault.address).unwrap().multisig, Some(config));
+        let mut tx = Transaction::new_transfer(vault.address.clone(), test_address("bob"), 10)
+            .with_nonce(1);
+        tx.sign(vault.signing_key(), DEFAULT_CHAIN_ID);
+        lax.add_transaction(tx).unwrap();
+        let block = lax.mine_pending("miner").unwrap();
+        assert!(matches!(bc.try_append_block(block), Err(CoreError::InvalidBlock(_))));
+
+        let mut tx = Transaction::new_transfer(vault.address.clone(), test_address("bob"), 10)
+            .with_nonce(1)
+            .with_multisig(2);
+        tx.cosign(owners[0].signing_key(), DEFAULT_CHAIN_ID);
+        tx.cosign(owners[2].signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+        bc.mine_pending("miner").unwrap();
+        assert_eq!(bc.state().get_balance(&test_address("bob")), 10);
+    }
+
+    #[test]
+    fn test_multisig_from_plain_account_must_include_sender() {
+        let mut bc = Blockchain::new(1, 50);
+        let wallet = Wallet::new();
+        let other = Wallet::new();
+        bc.state_mut().credit(&wallet.address, 100);
+
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 10)
+            .with_multisig(1);
+        tx.cosign(other.signing_key(), DEFAULT_CHAIN_ID);
+        assert!(matches!(bc.add_transaction(tx), Err(CoreError::InvalidSignature(_))));
+
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 10)
+            .with_multisig(1);
+        tx.cosign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_multisig_config_rejected() {
+        let mut bc = Blockchain::new(1, 50);
+        let wallet = Wallet::new();
+        let owner = test_address("owner");
+        for config in [
+            MultisigConfig { owners: vec![owner.clone()], threshold: 2 },
+            MultisigConfig { owners: vec![owner.clone(), owner], threshold: 1 },
+            MultisigConfig { owners: vec!["nope".into()], threshold: 1 },
+        ] {
+            let mut tx = Transaction::new_set_multisig(wallet.address.clone(), Some(&config));
+            tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+            assert!(matches!(bc.add_transaction(tx), Err(CoreError::InvalidTransaction(_))));
+        }
+    }
+
+    #[test]
+    fn test_replayed_nonce_with_fresh_id_rejected_by_nonce() {
+        let mut bc = Blockchain::new(1, 50);
+        let wallet = Wallet::new();
+        bc.state_mut().credit(&wallet.address, 100);
+
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 10);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+
+        // A different transaction reusing the nonce gets past the id check
+        let mut reuse = Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 7);
+        reuse.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        let result = bc.add_transaction(reuse);
+        assert!(matches!(result, Err(CoreError::InvalidNonce { expected: 1, got: 0 })));
+    }
+
+    #[test]
+    fn test_miner_collects_fees() {
+        let mut bc = Blockchain::new(1, 50);
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        bc.state_mut().credit(&alice.address, 100);
+        bc.state_mut().credit(&bob.address, 100);
+
+        let mut tx =
+            Transaction::new_transfer(alice.address.clone(), test_address("carol"), 10).with_fee(3);
+        tx.sign(alice.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+        let mut tx =
+            Transaction::new_transfer(bob.address.clone(), test_address("carol"), 20).with_fee(5);
+        tx.sign(bob.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx).unwrap();
+
+        let block = bc.mine_pending("miner").unwrap();
+        assert_eq!(bc.state().get_balance("miner"), 50 + 8);
+        assert_eq!(block.transactions.last().unwrap().amount, 58);
+        assert_eq!(bc.state().get_balance(&alice.address), 87);
+        assert_eq!(bc.state().get_balance(&bob.address), 75);
+        assert_eq!(bc.state().get_balance(&test_address("carol")), 30);
+
+        let mut other = Blockchain::new(1, 50);
+        other.state_mut().credit(&alice.address, 100);
+        other.state_mut().credit(&bob.address, 100);
+        other.try_append_block(block).unwrap();
+        assert_eq!(other.state().get_balance("miner"), 58);
+    }
+
+    #[test]
+    fn test_append_block_extends_tip() {
+        let mut source = Blockchain::new(1, 50);
+        let block = source.mine_pending("miner").unwrap();
+
+        let mut bc = Blockchain::new(1, 50);
+        bc.try_append_block(block.clone()).unwrap();
+        assert_eq!(bc.height(), 2);
+        assert_eq!(bc.latest_block().hash, block.hash);
+        assert_eq!(bc.state().get_balance("miner"), 50);
+    }
+
+    #[test]
+    fn test_append_block_with_wrong_previous_hash() {
+        let mut source = Blockchain::new(1, 50);
+        let mut fork = Blockchain::new(1, 50);
+        let mut bc = Blockchain::new(1, 50);
+        bc.try_append_block(source.mine_pending("miner").unwrap()).unwrap();
+        fork.mine_pending("other").unwrap();
+
+        // Not invalid in itself, just built on a block we don't have
+        let result = bc.try_append_block(fork.mine_pending("other").unwrap());
+        assert!(matches!(result, Err(CoreError::UnknownParent(_))));
+        assert_eq!(bc.height(), 2);
+        assert_eq!(bc.state().get_balance("other"), 0);
+    }
+
+    #[test]
+    fn test_mined_blocks_signed_with_producer_key() {
+        let wallet = Wallet::new();
+        let mut source = Blockchain::new(1, 50).with_producer_key(wallet.signing_key().clone());
+        let block = source.mine_pending("miner").unwrap();
+        assert!(block.producer_signature.is_some());
+        let expected = wallet.signing_key().verifying_key().to_bytes().to_vec();
+        assert_eq!(block.producer_pubkey, Some(expected));
+
+        let mut bc = Blockchain::new(1, 50);
+        let mut tampered = block.clone();
+        tampered.producer_signature.as_mut().unwrap()[5] ^= 0x01;
+        let result = bc.try_append_block(tampered);
+        assert!(matches!(result, Err(CoreError::InvalidBlock(_))));
+        bc.try_append_block(block).unwrap();
+        assert!(bc.is_chain_valid());
+    }
+
+    #[test]
+    fn test_append_block_out_of_order_index() {
+        let mut source = Blockchain::new(1, 50);
+        let first = source.mine_pending("miner").unwrap();
+        let second = source.mine_pending("miner").unwrap();
+
+        let mut bc = Blockchain::new(1, 50);
+        let result = bc.try_append_block(second.clone());
+        assert!(matches!(result, Err(CoreError::InvalidBlock(_))));
+        assert_eq!(bc.height(), 1);
+
+        bc.try_append_block(first.clone()).unwrap();
+        bc.try_append_block(second).unwrap();
+        assert!(bc.try_append_block(first).is_err());
+        assert_eq!(bc.height(), 3);
+    }
+
+    #[test]
+    fn test_higher_stake_selected_more_often() {
+        let (whale_address, minnow) = (test_address("whale"), test_address("minnow"));
+        let mut state = WorldState::new();
+        state.credit(&whale_address, 900);
+        state.credit(&minnow, 100);
+
+        let mut whale = 0;
+        for i in 0..1_000 {
+            let previous_hash = Block::new(i, "0".repeat(64), vec![], 0).hash;
+            match Blockchain::select_producer(&state, &previous_hash) {
+                Some(producer) if producer == whale_address => whale += 1,
+                Some(producer) if producer == minnow => {}
+                other => panic!("unexpected producer {:?}", other),
+            }
+        }
+        assert!((800..=980).contains(&whale), "whale selected {} times", whale);
+        assert_eq!(Blockchain::select_producer(&WorldState::new(), "00"), None);
+    }
+
+    #[test]
+    fn test_keyless_accounts_hold_no_stake() {
+        let mut state = WorldState::new();
+        state.credit("miner", 1_000);
+        let contract = state.deploy_contract_from(&test_address("alice"), vec![0]);
+        state.credit(&contract, 1_000);
+        assert_eq!(Blockchain::select_producer(&state, "00"), None);
+
+        state.credit(&test_address("alice"), 1);
+        assert_eq!(Blockchain::select_producer(&state, "00"), Some(test_address("alice")));
+    }
+
+    fn pos_chain(validators: &[&Wallet]) -> Blockchain {
+        let allocations = validators
+            .iter()
+            .enumerate()
+            .map(|(i, wallet)| (wallet.address.clone(), 100 * (i as u64 + 1)))
+            .collect();
+        Blockchain::new(1, 50)
+            .with_consensus(ConsensusMode::Pos)
+            .with_genesis_allocations(allocations)
+    }
+
+    #[test]
+    fn test_pos_block_validity_checks_producer() {
+        let (alice, bob) = (Wallet::new(), Wallet::new());
+        let mut producer_node = pos_chain(&[&alice, &bob])
+            .with_validator_key(alice.signing_key().clone())
+            .with_validator_key(bob.signing_key().clone());
+        let mut replica = pos_chain(&[&alice, &bob]);
+        assert!(matches!(replica.mine_pending("miner"), Err(CoreError::MiningError(_))));
+
+        let block = producer_node.mine_pending("miner").unwrap();
+        assert_eq!(block.header.difficulty, 0);
+        assert_eq!(block.header.producer, replica.next_producer());
+        replica.try_append_block(block).unwrap();
+
+        // A block forged by anyone but the selected producer is rejected
+        let selected = replica.next_producer().unwrap();
+        let imposter = if selected == alice.address { &bob } else { &alice };
+        let reward = Transaction::new_transfer("system".into(), "miner".into(), 50);
+        let mut block =
+            Block::new(replica.height(), replica.latest_block().hash.clone(), vec![reward], 0);
+        block.forge(imposter.signing_key());
+        let result = replica.try_append_block(block.clone());
+        let rejected = |msg: &str| msg.contains("not the selected producer");
+        assert!(matches!(result, Err(CoreError::InvalidBlock(ref msg)) if rejected(msg)));
+
+        // So is a block that names the right producer without its signature
+        block.header.producer = Some(selected);
+        block.hash = Block::calculate_hash(&block.header);
+        (block.producer_signature, block.producer_pubkey) = (None, None);
+        let result = replica.try_append_block(block.clone());
+        assert!(matches!(result, Err(CoreError::InvalidBlock(ref msg)) if msg.contains("signed")));
+
+        // Replaying a chain checks every producer too
+        let mut chain = replica.chain().to_vec();
+        chain.push(block);
+        let mut fresh = pos_chain(&[&alice, &bob]);
+        assert!(matches!(fresh.replace_chain(chain), Err(CoreError::InvalidChain(_))));
+        fresh.replace_chain(replica.chain().to_vec()).unwrap();
+        assert_eq!(fresh.height(), 2);
+    }
+
+    #[test]
+    fn test_block_transaction_cap_leaves_rest_pending() {
+        let mut bc = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_max_block_transactions(4);
+        let alice = test_address("alice");
+        bc.state_mut().credit(&alice, 1_000);
+        for nonce in 0..10 {
+            let tx = Transaction::new_transfer(alice.clone(), test_address("bob"), 1)
+                .with_fee(nonce)
+                .with_nonce(nonce);
+            bc.add_transaction(tx).unwrap();
+        }
+
+        let block = bc.mine_pending("miner").unwrap();
+        assert_eq!(block.transactions.len(), 4 + 1);
+        assert_eq!(bc.pending_transactions().len(), 6);
+        let nonces: Vec<u64> = block.transactions[..4].iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![0, 1, 2, 3]);
+
+        let block = bc.mine_pending("miner").unwrap();
+        assert_eq!(block.transactions.len(), 4 + 1);
+        assert_eq!(bc.pending_transactions().len(), 2);
+        assert_eq!(bc.state().get_balance(&test_address("bob")), 8);
+    }
+
+    #[test]
+    fn test_block_timestamps_must_not_go_back_or_run_ahead() {
+        let mut miner = Blockchain::new(1, 50);
+        miner.mine_pending("miner").unwrap();
+        let parent_time = miner.latest_block().header.timestamp;
+
+        for timestamp in [
+            parent_time - chrono::Duration::seconds(1),
+            Utc::now() + chrono::Duration::hours(1),
+        ] {
+            let mut block = Block::new(2, miner.latest_block().hash.clone(), Vec::new(), 1);
+            block.header.timestamp = timestamp;
+            block.mine().unwrap();
+            let result = miner.clone().try_append_block(block);
+            assert!(matches!(result, Err(CoreError::InvalidBlock(_))));
+        }
+
+        let mut block = Block::new(2, miner.latest_block().hash.clone(), Vec::new(), 1);
+        block.header.timestamp = parent_time;
+        block.mine().unwrap();
+        miner.try_append_block(block).unwrap();
+    }
+
+    #[test]
+    fn test_oversized_block_rejected() {
+        let allocations = HashMap::from([(test_address("alice"), 1_000)]);
+        let mut large = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_genesis_allocations(allocations.clone());
+        for nonce in 0..5 {
+            let tx = Transaction::new_transfer(test_address("alice"), test_address("bob"), 1)
+                .with_nonce(nonce);
+            large.add_transaction(tx).unwrap();
+        }
+        let block = large.mine_pending("miner").unwrap();
+
+        let mut small = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_max_block_transactions(4)
+            .with_genesis_allocations(allocations);
+        assert!(matches!(small.try_append_block(block), Err(CoreError::InvalidBlock(_))));
+        assert!(small.replace_chain(large.chain().to_vec()).is_err());
+        assert_eq!(small.height(), 1);
+    }
+
+    #[test]
+    fn test_overflowing_fees_rejected() {
+        let mut bc = Blockchain::new(1, 50).with_require_signatures(false);
+        let mut transactions: Vec<Transaction> = (0..2)
+            .map(|nonce| {
+                Transaction::new_transfer(test_address("alice"), test_address("bob"), 1)
+                    .with_fee(u64::MAX / 2 + 1)
+                    .with_nonce(nonce)
+            })
+            .collect();
+        transactions.push(Transaction::new_transfer("system".into(), "miner".into(), 50));
+        let mut block = Block::new(1, bc.latest_block().hash.clone(), transactions, 1);
+        block.mine().unwrap();
+
+        let result = bc.try_append_block(block);
+        let overflow = |msg: &str| msg.contains("overflow");
+        assert!(matches!(result, Err(CoreError::InvalidBlock(ref msg)) if overflow(msg)));
+    }
+
+    #[test]
+    fn test_fee_counts_toward_required_balance() {
+        let mut bc = Blockchain::new(1, 50);
+        let wallet = Wallet::new();
+        bc.state_mut().credit(&wallet.address, 10);
+
+        let mut tx =
+            Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 10).with_fee(1);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
         let result = bc.add_transaction(tx);
-        assert!(result.is_err());
+        assert!(matches!(
+            result,
+            Err(CoreError::InsufficientBalance { balance: 10, required: 11, .. })
+        ));
+    }
+
+    fn fee_tx(wallet: &Wallet, nonce: u64, fee: u64) -> Transaction {
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 1)
+            .with_nonce(nonce)
+            .with_fee(fee);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        tx
+    }
+
+    #[test]
+    fn test_full_mempool_evicts_lowest_fee() {
+        let mut bc = Blockchain::new(1, 50).with_max_mempool(2);
+        assert_eq!(bc.mempool_capacity(), 2);
+        let (a, b, c) = (Wallet::new(), Wallet::new(), Wallet::new());
+        for w in [&a, &b, &c] {
+            bc.state_mut().credit(&w.address, 100);
+        }
+
+        bc.add_transaction(fee_tx(&a, 0, 5)).unwrap();
+        bc.add_transaction(fee_tx(&b, 0, 1)).unwrap();
+
+        // Pays less than everything pending: rejected
+        let result = bc.add_transaction(fee_tx(&c, 0, 1));
+        assert!(matches!(result, Err(CoreError::InvalidTransaction(_))));
+
+        // Pays more than b: b is evicted
+        bc.add_transaction(fee_tx(&c, 0, 3)).unwrap();
+        let senders: Vec<_> = bc.pending_transactions().iter().map(|tx| tx.sender.clone()).collect();
+        assert_eq!(senders, vec![a.address.clone(), c.address.clone()]);
+    }
+
+    #[test]
+    fn test_mining_prefers_higher_fees_within_nonce_order() {
+        let mut bc = Blockchain::new(1, 50);
+        let (a, b) = (Wallet::new(), Wallet::new());
+        bc.state_mut().credit(&a.address, 100);
+        bc.state_mut().credit(&b.address, 100);
+
+        bc.add_transaction(fee_tx(&a, 0, 1)).unwrap();
+        bc.add_transaction(fee_tx(&a, 1, 9)).unwrap();
+        bc.add_transaction(fee_tx(&b, 0, 4)).unwrap();
+
+        let block = bc.mine_pending("miner").unwrap();
+        let order: Vec<_> = block.transactions.iter().map(|tx| (tx.sender.clone(), tx.fee)).collect();
+        assert_eq!(
+            order,
+            vec![
+                (b.address.clone(), 4),
+                (a.address.clone(), 1),
+                (a.address.clone(), 9),
+                ("system".to_string(), 0),
+            ]
+        );
+        assert_eq!(block.transactions.last().unwrap().amount, 50 + 14);
+    }
+
+    #[test]
+    fn test_heavier_shorter_chain_wins() {
+        let mut heavy = Blockchain::new(3, 50);
+        heavy.mine_pending("heavy").unwrap();
+        heavy.mine_pending("heavy").unwrap();
+
+        let mut light = Blockchain::new(1, 50);
+        for _ in 0..3 {
+            light.mine_pending("light").unwrap();
+        }
+        assert!(heavy.height() < light.height());
+        assert!(heavy.total_work() > light.total_work());
+
+        let light_chain = light.chain().to_vec();
+        assert!(heavy.replace_chain(light_chain).is_err());
+        assert_eq!(heavy.height(), 3);
+
+        // Preferred on work, but mined at a difficulty the light node's
+        // rules don't allow
+        assert!(light.prefers(heavy.chain()));
+        assert!(light.replace_chain(heavy.chain().to_vec()).is_err());
+        assert_eq!(light.height(), 4);
+    }
+
+    #[test]
+    fn test_chain_with_invalid_block_rejected() {
+        let mut honest = Blockchain::new(1, 50);
+        honest.mine_pending("miner").unwrap();
+        honest.mine_pending("miner").unwrap();
+
+        // A reward above the allowed amount, re-mined so hash and proof of
+        // work still check out
+        let mut forged = honest.chain().to_vec();
+        let block = &mut forged[2];
+        block.transactions[0].amount = 1_000;
+        *block = Block::new(
+            2,
+            block.header.previous_hash.clone(),
+            block.transactions.clone(),
+            block.header.difficulty,
+        );
+        block.mine().unwrap();
+
+        let mut replica = Blockchain::new(1, 50);
+        assert!(matches!(
+            replica.replace_chain(forged.clone()),
+            Err(CoreError::InvalidChain(_))
+        ));
+        assert!(replica.restore_chain(forged).is_err());
+        assert_eq!(replica.height(), 1);
+    }
+
+    #[test]
+    fn test_revalidation_skips_unchanged_blocks() {
+        let mut bc = Blockchain::new(1, 50);
+        for _ in 0..10 {
+            bc.mine_pending("miner").unwrap();
+        }
+        assert!(bc.is_chain_valid());
+        let checked = bc.validated.full_checks();
+        assert!(bc.is_chain_valid());
+        assert_eq!(bc.validated.full_checks(), checked);
+
+        // A changed block misses the cache and is checked in full
+        bc.chain[5].header.nonce += 1;
+        assert!(!bc.is_chain_valid());
+        assert_eq!(bc.validated.full_checks(), checked + 1);
+        assert!(bc.validate_suffix(6));
+        assert_eq!(bc.validated.full_checks(), checked + 1);
+        assert!(!bc.validate_suffix(5));
+        bc.chain[5].header.nonce -= 1;
+        assert!(bc.is_chain_valid());
+        assert_eq!(bc.validated.full_checks(), checked + 2);
     }
 
     #[test]
@@ -259,5 +2647,8 @@ mod tests {
         bc.mine_pending("miner1").unwrap();
         bc.mine_pending("miner2").unwrap();
         assert!(bc.is_chain_valid());
+
+        bc.chain[1].transactions[0].amount += 1;
+        assert!(!bc.is_chain_valid());
     }
 }
@@ -11,6 +11,11 @@ pub enum CoreError {
     #[error("Invalid chain: {0}")]
     InvalidChain(String),
 
+    /// The block builds on a block this node doesn't have, e.g. the tip of
+    /// a fork; the block itself may be fine.
+    #[error("Unknown parent: {0}")]
+    UnknownParent(String),
+
     #[error("Insufficient balance: account {account} has {balance}, needs {required}")]
     InsufficientBalance {
         account: String,
@@ -18,9 +23,18 @@ pub enum CoreError {
         required: u64,
     },
 
+    #[error("Invalid nonce: expected {expected}, got {got}")]
+    InvalidNonce { expected: u64, got: u64 },
+
     #[error("Invalid signature: {0}")]
     InvalidSignature(String),
 
+    #[error("Invalid mnemonic: {0}")]
+    InvalidMnemonic(String),
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
     #[error("Duplicate transaction: {0}")]
     DuplicateTransaction(String),
 
@@ -33,6 +47,9 @@ pub enum CoreError {
     #[error("Serialization error: {0}")]
     Serialization(String),
 
+    #[error("Storage error: {0}")]
+    Storage(String),
+
     #[error("Mining error: {0}")]
     MiningError(String),
 }
@@ -1,7 +1,11 @@
+pub mod address;
 pub mod block;
 pub mod chain;
 pub mod errors;
 pub mod merkle;
+pub mod peer;
+pub mod receipt;
 pub mod state;
+pub mod storage;
 pub mod transaction;
 pub mod wallet;
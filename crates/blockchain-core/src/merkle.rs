@@ -6,14 +6,17 @@ pub struct MerkleTree;
 
 impl MerkleTree {
     pub fn root(transactions: &[Transaction]) -> String {
-        if transactions.is_empty() {
+        Self::root_from_hashes(transactions.iter().map(|tx| tx.hash()).collect())
+    }
+
+    /// Merkle root over arbitrary leaf hashes.
+    pub fn root_from_hashes(mut hashes: Vec<String>) -> String {
+        if hashes.is_empty() {
             return Self::hash_pair("", "");
         }
 
-        let mut hashes: Vec<String> = transactions.iter().map(|tx| tx.hash()).collect();
-
         while hashes.len() > 1 {
-            if hashes.len() % 2 != 0 {
+            if !hashes.len().is_multiple_of(2) {
                 let last = hashes.last().unwrap().clone();
                 hashes.push(last);
             }
@@ -28,6 +31,52 @@ impl MerkleTree {
         hashes.into_iter().next().unwrap()
     }
 
+    /// Inclusion proof for `transactions[index]`: the sibling hash at each
+    /// level, flagged `true` when the sibling sits on the left. Empty if
+    /// `index` is out of range (or there is a single transaction).
+    pub fn proof(transactions: &[Transaction], index: usize) -> Vec<(String, bool)> {
+        Self::proof_from_hashes(transactions.iter().map(|tx| tx.hash()).collect(), index)
+    }
+
+    pub fn proof_from_hashes(mut hashes: Vec<String>, mut index: usize) -> Vec<(String, bool)> {
+        let mut proof = Vec::new();
+        if index >= hashes.len() {
+            return proof;
+        }
+
+        while hashes.len() > 1 {
+            // Same odd-node duplication as `root_from_hashes`
+            if !hashes.len().is_multiple_of(2) {
+                let last = hashes.last().unwrap().clone();
+                hashes.push(last);
+            }
+
+            let sibling_is_left = index % 2 == 1;
+            let sibling = if sibling_is_left { index - 1 } else { index + 1 };
+            proof.push((hashes[sibling].clone(), sibling_is_left));
+
+            hashes = hashes
+                .chunks(2)
+                .map(|chunk| Self::hash_pair(&chunk[0], &chunk[1]))
+                .collect();
+            index /= 2;
+        }
+
+        proof
+    }
+
+    /// Checks that `tx_hash` combined with `proof` hashes up to `root`.
+    pub fn verify_proof(tx_hash: &str, proof: &[(String, bool)], root: &str) -> bool {
+        let computed = proof.iter().fold(tx_hash.to_string(), |acc, (sibling, is_left)| {
+            if *is_left {
+                Self::hash_pair(sibling, &acc)
+            } else {
+                Self::hash_pair(&acc, sibling)
+            }
+        });
+        computed == root
+    }
+
     fn hash_pair(left: &str, right: &str) -> String {
         let mut hasher = Sha256::new();
         hasher.update(format!("{}{}", left, right).as_bytes());
@@ -54,6 +103,24 @@ mod tests {
         assert_eq!(root.len(), 64);
     }
 
+    #[test]
+    fn test_every_transaction_has_a_valid_proof() {
+        let txs: Vec<_> = (0..5)
+            .map(|i| Transaction::new_transfer("a".into(), "b".into(), i))
+            .collect();
+        let root = MerkleTree::root(&txs);
+
+        for (i, tx) in txs.iter().enumerate() {
+            let proof = MerkleTree::proof(&txs, i);
+            assert_eq!(proof.len(), 3);
+            assert!(MerkleTree::verify_proof(&tx.hash(), &proof, &root), "tx {}", i);
+        }
+
+        // A proof does not transfer to a different transaction
+        let proof = MerkleTree::proof(&txs, 0);
+        assert!(!MerkleTree::verify_proof(&txs[1].hash(), &proof, &root));
+    }
+
     #[test]
     fn test_merkle_deterministic() {
         let txs = vec![
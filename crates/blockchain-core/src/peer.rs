@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// Metadata a peer advertised over identify, plus the highest block
+/// index we've seen it gossip. Shared by the network layer, which fills
+/// it in, and the API, which reports it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub agent_version: String,
+    pub protocol_version: String,
+    pub protocols: Vec<String>,
+    pub height: Option<u64>,
+}
+
+impl PeerInfo {
+    /// Records a gossiped block index, keeping the highest one seen.
+    pub fn observe_height(&mut self, height: u64) {
+        self.height = Some(self.height.map_or(height, |h| h.max(height)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observe_height_keeps_highest() {
+        let mut info = PeerInfo::default();
+        info.observe_height(5);
+        info.observe_height(3);
+        assert_eq!(info.height, Some(5));
+        info.observe_height(9);
+        assert_eq!(info.height, Some(9));
+    }
+}
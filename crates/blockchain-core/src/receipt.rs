@@ -0,0 +1,73 @@
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::WorldState;
+
+/// Runs `ContractCall` transactions while blocks are applied. The VM lives
+/// in a crate that depends on this one, so it is plugged in with
+/// `Blockchain::with_contract_runtime`.
+pub trait ContractRuntime: Debug + Send + Sync {
+    /// Calls `contract` on behalf of `caller`. A failed call must leave
+    /// `state` untouched.
+    fn call(
+        &self,
+        state: &mut WorldState,
+        caller: &str,
+        contract: &str,
+        call_data: &[u8],
+    ) -> CallOutcome;
+}
+
+/// What a contract call did; `error` is set if it failed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CallOutcome {
+    pub return_value: Option<i64>,
+    pub logs: Vec<i64>,
+    pub gas_used: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiptStatus {
+    Success,
+    Failed,
+}
+
+/// Result of a mined transaction. Only contract calls can fail; a failed
+/// call is still included in its block, consuming the nonce and fee and
+/// nothing else.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionReceipt {
+    pub tx_id: String,
+    pub block_index: u64,
+    pub status: ReceiptStatus,
+    pub gas_used: u64,
+    pub logs: Vec<i64>,
+    pub return_value: Option<i64>,
+    /// Why a failed call failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TransactionReceipt {
+    /// Receipt for `tx_id` mined in `block_index`; `outcome` is the
+    /// contract call's, absent for every other transaction.
+    pub fn new(tx_id: String, block_index: u64, outcome: Option<CallOutcome>) -> Self {
+        let outcome = outcome.unwrap_or_default();
+        Self {
+            tx_id,
+            block_index,
+            status: if outcome.error.is_none() {
+                ReceiptStatus::Success
+            } else {
+                ReceiptStatus::Failed
+            },
+            gas_used: outcome.gas_used,
+            logs: outcome.logs,
+            return_value: outcome.return_value,
+            error: outcome.error,
+        }
+    }
+}
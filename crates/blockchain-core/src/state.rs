@@ -1,30 +1,97 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::errors::CoreResult;
+use crate::storage::StorageBackend;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccountState {
     pub balance: u64,
     pub nonce: u64,
+    /// When set, transactions from this account need `threshold` valid
+    /// multisig signatures from `owners`.
+    #[serde(default)]
+    pub multisig: Option<MultisigConfig>,
 }
 
 impl AccountState {
     pub fn new(balance: u64) -> Self {
-        Self { balance, nonce: 0 }
+        Self {
+            balance,
+            nonce: 0,
+            multisig: None,
+        }
+    }
+}
+
+/// Owner addresses of a multisig account and how many must sign.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultisigConfig {
+    pub owners: Vec<String>,
+    pub threshold: u8,
+}
+
+impl MultisigConfig {
+    /// Distinct, well-formed owners and a threshold between 1 and their
+    /// count.
+    pub fn is_valid(&self) -> bool {
+        let distinct: HashSet<&String> = self.owners.iter().collect();
+        distinct.len() == self.owners.len()
+            && self.owners.iter().all(|owner| crate::address::is_valid(owner))
+            && (1..=self.owners.len()).contains(&(self.threshold as usize))
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContractState {
     pub bytecode: Vec<u8>,
+    /// Lives in the `StorageBackend`, not in chain snapshots; see
+    /// `WorldState::load_contract_storage`.
+    #[serde(skip)]
     pub storage: HashMap<u64, i64>,
     pub owner: String,
+    /// Most recent events emitted by successful calls, oldest first; at
+    /// most `MAX_CONTRACT_EVENTS` are kept.
+    #[serde(default)]
+    pub events: Vec<ContractEvent>,
+}
+
+/// Events a contract keeps before the oldest are dropped.
+pub const MAX_CONTRACT_EVENTS: usize = 1_000;
+
+/// A structured event emitted by a contract; `topic` lets clients filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractEvent {
+    pub topic: i64,
+    pub data: i64,
+}
+
+impl ContractState {
+    /// Appends `events`, dropping the oldest beyond `MAX_CONTRACT_EVENTS`.
+    pub fn record_events(&mut self, events: &[ContractEvent]) {
+        self.events.extend_from_slice(events);
+        let excess = self.events.len().saturating_sub(MAX_CONTRACT_EVENTS);
+        self.events.drain(..excess);
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorldState {
     accounts: HashMap<String, AccountState>,
     contracts: HashMap<String, ContractState>,
+    /// Registered human-readable names, name -> address.
+    #[serde(default)]
+    names: HashMap<String, String>,
+    /// Total ever burned (e.g. deploy fees); together with the current
+    /// supply it is everything ever issued.
+    #[serde(default)]
+    burned: u64,
+    /// Contracts deployed, removed or given new storage since the last
+    /// `persist_contract_storage`.
+    #[serde(skip)]
+    dirty_contracts: HashSet<String>,
 }
 
 impl WorldState {
@@ -32,6 +99,9 @@ impl WorldState {
         Self {
             accounts: HashMap::new(),
             contracts: HashMap::new(),
+            names: HashMap::new(),
+            burned: 0,
+            dirty_contracts: HashSet::new(),
         }
     }
 
@@ -52,25 +122,58 @@ impl WorldState {
             .or_insert_with(|| AccountState::new(0))
     }
 
-    pub fn credit(&mut self, address: &str, amount: u64) {
+    /// Puts `address` under multisig control; `None` reverts it to a
+    /// single-key account.
+    pub fn set_multisig(&mut self, address: &str, config: Option<MultisigConfig>) {
+        self.get_or_create_account(address).multisig = config;
+    }
+
+    /// Adds `amount` to the balance; returns false, changing nothing, if
+    /// that would overflow.
+    pub fn credit(&mut self, address: &str, amount: u64) -> bool {
         let account = self.get_or_create_account(address);
-        account.balance += amount;
+        match account.balance.checked_add(amount) {
+            Some(balance) => {
+                account.balance = balance;
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn debit(&mut self, address: &str, amount: u64) -> bool {
         let account = self.get_or_create_account(address);
-        if account.balance >= amount {
-            account.balance -= amount;
-            account.nonce += 1;
-            true
-        } else {
-            false
+        match account.balance.checked_sub(amount) {
+            Some(balance) => {
+                account.balance = balance;
+                account.nonce += 1;
+                true
+            }
+            None => false,
         }
     }
 
+    /// Destroys `amount` of `address`'s balance; returns false, changing
+    /// nothing, if it cannot cover it. Burned coins are never reissued.
+    pub fn burn(&mut self, address: &str, amount: u64) -> bool {
+        let account = self.get_or_create_account(address);
+        match account.balance.checked_sub(amount) {
+            Some(balance) => {
+                account.balance = balance;
+                self.burned = self.burned.saturating_add(amount);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves `amount` between accounts; returns false, changing nothing,
+    /// if `from` cannot cover it or `to` would overflow.
     pub fn transfer(&mut self, from: &str, to: &str, amount: u64) -> bool {
-        let from_balance = self.get_balance(from);
-        if from_balance < amount {
+        if self.get_balance(from) < amount {
+            return false;
+        }
+        if from != to && self.get_balance(to).checked_add(amount).is_none() {
             return false;
         }
         self.debit(from, amount);
@@ -84,16 +187,105 @@ impl WorldState {
         bytecode: Vec<u8>,
         owner: String,
     ) {
+        self.dirty_contracts.insert(address.clone());
         self.contracts.insert(
             address,
             ContractState {
                 bytecode,
                 storage: HashMap::new(),
                 owner,
+                events: Vec::new(),
             },
         );
     }
 
+    /// Address of the contract `sender` deploys when its nonce is `nonce`.
+    pub fn contract_address(sender: &str, nonce: u64) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(sender.as_bytes());
+        hasher.update(nonce.to_le_bytes());
+        let hash = hex::encode(hasher.finalize());
+        format!("0xc{}", &hash[..39])
+    }
+
+    /// Deploys `bytecode` at the address derived from `sender`'s current
+    /// nonce, then bumps the nonce. Replaying the same deploys in the same
+    /// order therefore reproduces the same addresses.
+    pub fn deploy_contract_from(&mut self, sender: &str, bytecode: Vec<u8>) -> String {
+        let account = self.get_or_create_account(sender);
+        let address = Self::contract_address(sender, account.nonce);
+        account.nonce += 1;
+        self.deploy_contract(address.clone(), bytecode, sender.to_string());
+        address
+    }
+
+    pub fn remove_contract(&mut self, address: &str) -> Option<ContractState> {
+        self.dirty_contracts.insert(address.to_string());
+        self.contracts.remove(address)
+    }
+
+    /// Replaces a contract's storage; returns false if there is no contract
+    /// at `address`. Use this rather than writing `storage` through
+    /// `get_contract_mut`, which `persist_contract_storage` wouldn't see.
+    pub fn set_contract_storage(&mut self, address: &str, storage: HashMap<u64, i64>) -> bool {
+        let Some(contract) = self.contracts.get_mut(address) else {
+            return false;
+        };
+        if contract.storage != storage {
+            contract.storage = storage;
+            self.dirty_contracts.insert(address.to_string());
+        }
+        true
+    }
+
+    /// Writes the storage of every contract changed since the last call to
+    /// `backend`, and removes contracts that no longer exist. Returns how
+    /// many contracts were written or removed; on error the rest stay
+    /// pending for the next call.
+    pub fn persist_contract_storage(&mut self, backend: &dyn StorageBackend) -> CoreResult<usize> {
+        let mut dirty: Vec<String> = self.dirty_contracts.iter().cloned().collect();
+        dirty.sort();
+        for address in &dirty {
+            match self.contracts.get(address) {
+                Some(contract) => backend.store(address, &contract.storage)?,
+                None => backend.remove(address)?,
+            }
+            self.dirty_contracts.remove(address);
+        }
+        Ok(dirty.len())
+    }
+
+    /// Reads each contract's storage back from `backend`. Storage that
+    /// already matches is no longer pending; otherwise the backend's copy is
+    /// taken only when `adopt` is set, since replayed storage is
+    /// authoritative when a runtime recomputed it. Returns how many
+    /// contracts took the backend's copy.
+    pub fn load_contract_storage(
+        &mut self,
+        backend: &dyn StorageBackend,
+        adopt: bool,
+    ) -> CoreResult<usize> {
+        let mut loaded = 0;
+        for (address, contract) in self.contracts.iter_mut() {
+            let Some(stored) = backend.load(address)? else { continue };
+            if stored != contract.storage {
+                if !adopt {
+                    continue;
+                }
+                contract.storage = stored;
+                loaded += 1;
+            }
+            self.dirty_contracts.remove(address);
+        }
+        Ok(loaded)
+    }
+
+    /// Marks `addresses` for `persist_contract_storage`, e.g. contracts of
+    /// a state this one replaced.
+    pub(crate) fn mark_contracts_dirty(&mut self, addresses: impl IntoIterator<Item = String>) {
+        self.dirty_contracts.extend(addresses);
+    }
+
     pub fn get_contract(&self, address: &str) -> Option<&ContractState> {
         self.contracts.get(address)
     }
@@ -102,6 +294,47 @@ impl WorldState {
         self.contracts.get_mut(address)
     }
 
+    /// Sum of all account balances.
+    pub fn total_supply(&self) -> u64 {
+        self.accounts
+            .values()
+            .fold(0u64, |total, a| total.saturating_add(a.balance))
+    }
+
+    /// Everything ever issued: the current supply plus what was burned.
+    pub fn total_issued(&self) -> u64 {
+        self.total_supply().saturating_add(self.burned)
+    }
+
+    /// Drops accounts that hold no balance and have never sent a
+    /// transaction. Returns how many were removed.
+    pub fn prune_empty_accounts(&mut self) -> usize {
+        let before = self.accounts.len();
+        self.accounts
+            .retain(|_, account| account.balance != 0 || account.nonce != 0);
+        before - self.accounts.len()
+    }
+
+    pub fn is_valid_name(name: &str) -> bool {
+        (1..=32).contains(&name.len())
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+    }
+
+    /// Claims `name` for `address`. Returns false if it is already taken.
+    pub fn register_name(&mut self, name: &str, address: &str) -> bool {
+        if self.names.contains_key(name) {
+            return false;
+        }
+        self.names.insert(name.to_string(), address.to_string());
+        true
+    }
+
+    pub fn resolve_name(&self, name: &str) -> Option<&str> {
+        self.names.get(name).map(String::as_str)
+    }
+
     pub fn accounts(&self) -> &HashMap<String, AccountState> {
         &self.accounts
     }
@@ -145,6 +378,32 @@ mod tests {
         assert_eq!(state.get_balance("alice"), 100);
     }
 
+    #[test]
+    fn test_overflowing_credit_is_refused() {
+        let mut state = WorldState::new();
+        assert!(state.credit("alice", u64::MAX - 1));
+        assert!(!state.credit("alice", 2));
+        assert_eq!(state.get_balance("alice"), u64::MAX - 1);
+
+        state.credit("bob", 10);
+        assert!(!state.transfer("bob", "alice", 5));
+        assert_eq!(state.get_balance("bob"), 10);
+        assert_eq!(state.total_supply(), u64::MAX);
+    }
+
+    #[test]
+    fn test_prune_empty_accounts() {
+        let mut state = WorldState::new();
+        state.credit("alice", 500);
+        state.get_or_create_account("ghost");
+        let supply = state.total_supply();
+
+        assert_eq!(state.prune_empty_accounts(), 1);
+        assert!(state.get_account("ghost").is_none());
+        assert_eq!(state.get_balance("alice"), 500);
+        assert_eq!(state.total_supply(), supply);
+    }
+
     #[test]
     fn test_deploy_contract() {
         let mut state = WorldState::new();
@@ -153,4 +412,17 @@ mod tests {
         assert_eq!(contract.bytecode, vec![1, 2, 3]);
         assert_eq!(contract.owner, "alice");
     }
+
+    #[test]
+    fn test_contract_events_are_capped() {
+        let mut state = WorldState::new();
+        state.deploy_contract("contract1".into(), vec![], "alice".into());
+        let contract = state.get_contract_mut("contract1").unwrap();
+        let events: Vec<ContractEvent> = (0..MAX_CONTRACT_EVENTS as i64 + 5)
+            .map(|data| ContractEvent { topic: 1, data })
+            .collect();
+        contract.record_events(&events);
+        assert_eq!(contract.events.len(), MAX_CONTRACT_EVENTS);
+        assert_eq!(contract.events[0].data, 5);
+    }
 }
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::block::Block;
+use crate::chain::Blockchain;
+use crate::errors::{CoreError, CoreResult};
+use crate::transaction::Transaction;
+
+const CHAIN_FILE: &str = "chain.json";
+const MEMPOOL_FILE: &str = "mempool.json";
+/// Blocks appended since the last chain snapshot, one JSON block per line.
+const WAL_FILE: &str = "chain.wal";
+
+/// JSON files in a data directory holding the chain and the mempool.
+///
+/// The full chain is rewritten at most every `flush_interval` blocks; the
+/// blocks in between are appended to a write-ahead log so a crash loses
+/// nothing that was saved.
+#[derive(Debug)]
+pub struct ChainStore {
+    dir: PathBuf,
+    flush_interval: u64,
+    persisted: Mutex<Persisted>,
+}
+
+/// What is currently on disk: `snapshot_height` blocks in the chain file,
+/// `height` blocks in total once the WAL is replayed.
+#[derive(Debug, Default)]
+struct Persisted {
+    snapshot_height: u64,
+    height: u64,
+    tip_hash: String,
+}
+
+impl ChainStore {
+    pub fn open(dir: impl Into<PathBuf>) -> CoreResult<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(Self {
+            dir,
+            flush_interval: 1,
+            persisted: Mutex::new(Persisted::default()),
+        })
+    }
+
+    /// Rewrites the chain file only once this many blocks have accumulated
+    /// in the WAL. 1 (the default) snapshots on every save.
+    pub fn with_flush_interval(mut self, blocks: u64) -> Self {
+        self.flush_interval = blocks.max(1);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn flush_interval(&self) -> u64 {
+        self.flush_interval
+    }
+
+    /// Writes the pending transactions and any new blocks. New blocks go to
+    /// the WAL until `flush_interval` of them are pending, then the whole
+    /// chain is snapshotted. A reorg always forces a snapshot.
+    pub fn save(&self, blockchain: &Blockchain) -> CoreResult<()> {
+        let blocks = blockchain.chain();
+        let mut persisted = self.persisted.lock().unwrap();
+
+        let extends_persisted = persisted.height > 0
+            && persisted.height <= blocks.len() as u64
+            && blocks[persisted.height as usize - 1].hash == persisted.tip_hash;
+
+        if !extends_persisted
+            || blocks.len() as u64 - persisted.snapshot_height >= self.flush_interval
+        {
+            self.snapshot(blocks, &mut persisted)?;
+        } else if (persisted.height as usize) < blocks.len() {
+            self.append_wal(&blocks[persisted.height as usize..])?;
+            persisted.height = blocks.len() as u64;
+            persisted.tip_hash = blocks[blocks.len() - 1].hash.clone();
+        }
+        drop(persisted);
+
+        self.save_mempool(blockchain.pending_transactions())
+    }
+
+    /// Snapshots the full chain and clears the WAL regardless of the flush
+    /// interval, e.g. on shutdown.
+    pub fn flush(&self, blockchain: &Blockchain) -> CoreResult<()> {
+        let mut persisted = self.persisted.lock().unwrap();
+        self.snapshot(blockchain.chain(), &mut persisted)?;
+        drop(persisted);
+        self.save_mempool(blockchain.pending_transactions())
+    }
+
+    pub fn save_mempool(&self, transactions: &[Transaction]) -> CoreResult<()> {
+        self.write(MEMPOOL_FILE, &transactions)
+    }
+
+    /// Returns the persisted blocks (snapshot plus WAL), or `None` on a
+    /// fresh data directory.
+    pub fn load_chain(&self) -> CoreResult<Option<Vec<Block>>> {
+        let snapshot: Option<Vec<Block>> = self.read(CHAIN_FILE)?;
+        let snapshot_height = snapshot.as_ref().map_or(0, Vec::len) as u64;
+        let (wal, torn) = self.read_wal()?;
+        if snapshot.is_none() && wal.is_empty() {
+            return Ok(None);
+        }
+
+        let mut blocks = snapshot.unwrap_or_default();
+        for block in wal {
+            // Entries already covered by the snapshot are skipped
+            if block.header.index == blocks.len() as u64 {
+                blocks.push(block);
+            }
+        }
+
+        let mut persisted = self.persisted.lock().unwrap();
+        persisted.snapshot_height = snapshot_height;
+        persisted.height = blocks.len() as u64;
+        persisted.tip_hash = blocks.last().map(|b| b.hash.clone()).unwrap_or_default();
+        if torn {
+            // Appending after a torn line would hide the new entries, so
+            // make the next save write a fresh snapshot instead
+            persisted.height = 0;
+        }
+        Ok(Some(blocks))
+    }
+
+    pub fn load_mempool(&self) -> CoreResult<Vec<Transaction>> {
+        Ok(self.read(MEMPOOL_FILE)?.unwrap_or_default())
+    }
+
+    /// Restores chain and mempool from disk into `blockchain`. Pending
+    /// transactions are re-validated against the restored state.
+    pub fn load_into(&self, blockchain: &mut Blockchain) -> CoreResult<()> {
+        if let Some(blocks) = self.load_chain()? {
+            blockchain.restore_chain(blocks)?;
+            tracing::info!("Loaded {} blocks from {}", blockchain.height(), self.dir.display());
+        }
+
+        let pending = self.load_mempool()?;
+        let total = pending.len();
+        let accepted = blockchain.restore_pending(pending);
+        if total > 0 {
+            tracing::info!(
+                "Restored {} of {} pending transactions",
+                accepted,
+                total
+            );
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self, blocks: &[Block], persisted: &mut Persisted) -> CoreResult<()> {
+        self.write(CHAIN_FILE, blocks)?;
+        fs::write(self.dir.join(WAL_FILE), b"").map_err(|e| CoreError::Storage(e.to_string()))?;
+        persisted.snapshot_height = blocks.len() as u64;
+        persisted.height = blocks.len() as u64;
+        persisted.tip_hash = blocks.last().map(|b| b.hash.clone()).unwrap_or_default();
+        Ok(())
+    }
+
+    fn append_wal(&self, blocks: &[Block]) -> CoreResult<()> {
+        let mut data = Vec::new();
+        for block in blocks {
+            serde_json::to_writer(&mut data, block)
+                .map_err(|e| CoreError::Serialization(e.to_string()))?;
+            data.push(b'\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(WAL_FILE))
+            .map_err(|e| CoreError::Storage(e.to_string()))?;
+        file.write_all(&data)
+            .and_then(|_| file.sync_data())
+            .map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    /// Returns the readable WAL entries and whether a torn entry was found.
+    fn read_wal(&self) -> CoreResult<(Vec<Block>, bool)> {
+        let path = self.dir.join(WAL_FILE);
+        if !path.exists() {
+            return Ok((Vec::new(), false));
+        }
+        let data = fs::read_to_string(&path).map_err(|e| CoreError::Storage(e.to_string()))?;
+        let mut blocks = Vec::new();
+        for line in data.lines().filter(|l| !l.is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(block) => blocks.push(block),
+                Err(e) => {
+                    // A crash mid-append can leave a torn final line
+                    tracing::warn!("Ignoring unreadable WAL entry: {}", e);
+                    return Ok((blocks, true));
+                }
+            }
+        }
+        Ok((blocks, false))
+    }
+
+    fn write<T: Serialize + ?Sized>(&self, name: &str, value: &T) -> CoreResult<()> {
+        let data = serde_json::to_vec(value)
+            .map_err(|e| CoreError::Serialization(e.to_string()))?;
+        // Write to a temp file first so a crash never leaves a torn file behind
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        fs::write(&tmp, data).map_err(|e| CoreError::Storage(e.to_string()))?;
+        fs::rename(&tmp, self.dir.join(name)).map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    fn read<T: DeserializeOwned>(&self, name: &str) -> CoreResult<Option<T>> {
+        let path = self.dir.join(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).map_err(|e| CoreError::Storage(e.to_string()))?;
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| CoreError::Serialization(e.to_string()))
+    }
+}
+
+/// Where contract storage is persisted, one contract at a time, so saving
+/// a contract never rewrites the others. See
+/// `WorldState::persist_contract_storage`.
+pub trait StorageBackend: std::fmt::Debug + Send + Sync {
+    /// Storage last persisted for `address`, if any.
+    fn load(&self, address: &str) -> CoreResult<Option<HashMap<u64, i64>>>;
+    fn store(&self, address: &str, storage: &HashMap<u64, i64>) -> CoreResult<()>;
+    /// Forgets `address`; removing one that was never stored is not an error.
+    fn remove(&self, address: &str) -> CoreResult<()>;
+}
+
+/// Keeps contract storage in memory only; the default when no data
+/// directory is configured.
+#[derive(Debug, Default)]
+pub struct MemoryStorageBackend {
+    contracts: Mutex<HashMap<String, HashMap<u64, i64>>>,
+}
+
+impl StorageBackend for MemoryStorageBackend {
+    fn load(&self, address: &str) -> CoreResult<Option<HashMap<u64, i64>>> {
+        Ok(self.contracts.lock().unwrap().get(address).cloned())
+    }
+
+    fn store(&self, address: &str, storage: &HashMap<u64, i64>) -> CoreResult<()> {
+        self.contracts
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), storage.clone());
+        Ok(())
+    }
+
+    fn remove(&self, address: &str) -> CoreResult<()> {
+        self.contracts.lock().unwrap().remove(address);
+        Ok(())
+    }
+}
+
+/// One JSON file per contract in a directory, named after its address.
+#[derive(Debug)]
+pub struct FileStorageBackend {
+    dir: PathBuf,
+}
+
+impl FileStorageBackend {
+    pub fn open(dir: impl Into<PathBuf>) -> CoreResult<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| CoreError::Storage(e.to_string()))?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// File for `address`; anything but ASCII letters and digits is refused
+    /// so an address can't point outside the directory.
+    fn path(&self, address: &str) -> CoreResult<PathBuf> {
+        if address.is_empty() || !address.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(CoreError::Storage(format!(
+                "Invalid contract address for storage: {}",
+                address
+            )));
+        }
+        Ok(self.dir.join(format!("{}.json", address)))
+    }
+}
+
+impl StorageBackend for FileStorageBackend {
+    fn load(&self, address: &str) -> CoreResult<Option<HashMap<u64, i64>>> {
+        let path = self.path(address)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).map_err(|e| CoreError::Storage(e.to_string()))?;
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| CoreError::Serialization(e.to_string()))
+    }
+
+    fn store(&self, address: &str, storage: &HashMap<u64, i64>) -> CoreResult<()> {
+        let path = self.path(address)?;
+        let data =
+            serde_json::to_vec(storage).map_err(|e| CoreError::Serialization(e.to_string()))?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, data).map_err(|e| CoreError::Storage(e.to_string()))?;
+        fs::rename(&tmp, &path).map_err(|e| CoreError::Storage(e.to_string()))
+    }
+
+    fn remove(&self, address: &str) -> CoreResult<()> {
+        match fs::remove_file(self.path(address)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(CoreError::Storage(e.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::test_address;
+    use crate::wallet::Wallet;
+    use crate::transaction::DEFAULT_CHAIN_ID;
+
+    fn temp_store() -> ChainStore {
+        let dir = std::env::temp_dir().join(format!("chain-store-{}", uuid::Uuid::new_v4()));
+        ChainStore::open(dir).unwrap()
+    }
+
+    #[test]
+    fn test_pending_transaction_survives_restart() {
+        let store = temp_store();
+        let wallet = Wallet::new();
+
+        let mut bc = Blockchain::new(1, 50);
+        bc.mine_pending(&wallet.address).unwrap();
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 10);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx.clone()).unwrap();
+        store.save(&bc).unwrap();
+
+        let mut restored = Blockchain::new(1, 50);
+        store.load_into(&mut restored).unwrap();
+
+        assert_eq!(restored.height(), 2);
+        assert_eq!(restored.state().get_balance(&wallet.address), 50);
+        assert_eq!(restored.pending_transactions().len(), 1);
+        assert_eq!(restored.pending_transactions()[0].id, tx.id);
+
+        fs::remove_dir_all(store.dir()).ok();
+    }
+
+    #[test]
+    fn test_chain_snapshot_waits_for_flush_interval() {
+        let store = temp_store().with_flush_interval(3);
+        let mut bc = Blockchain::new(1, 50);
+        store.save(&bc).unwrap();
+
+        let snapshot_len = |store: &ChainStore| {
+            store.read::<Vec<Block>>(CHAIN_FILE).unwrap().unwrap().len()
+        };
+        assert_eq!(snapshot_len(&store), 1);
+
+        for _ in 0..2 {
+            bc.mine_pending("miner").unwrap();
+            store.save(&bc).unwrap();
+            assert_eq!(snapshot_len(&store), 1);
+        }
+        bc.mine_pending("miner").unwrap();
+        store.save(&bc).unwrap();
+        assert_eq!(snapshot_len(&store), 4);
+        assert!(store.read_wal().unwrap().0.is_empty());
+
+        fs::remove_dir_all(store.dir()).ok();
+    }
+
+    #[test]
+    fn test_blocks_in_wal_survive_crash() {
+        let store = temp_store().with_flush_interval(10);
+        let mut bc = Blockchain::new(1, 50);
+        store.save(&bc).unwrap();
+        for _ in 0..3 {
+            bc.mine_pending("miner").unwrap();
+            store.save(&bc).unwrap();
+        }
+        assert_eq!(store.read_wal().unwrap().0.len(), 3);
+
+        // Simulate a crash: no flush, a torn trailing write, then reopen
+        let dir = store.dir().to_path_buf();
+        drop(store);
+        let mut wal = OpenOptions::new().append(true).open(dir.join(WAL_FILE)).unwrap();
+        wal.write_all(b"{\"header\":").unwrap();
+
+        let reopened = ChainStore::open(&dir).unwrap().with_flush_interval(10);
+        let mut restored = Blockchain::new(1, 50);
+        reopened.load_into(&mut restored).unwrap();
+        assert_eq!(restored.height(), 4);
+        assert_eq!(restored.latest_block().hash, bc.latest_block().hash);
+        assert_eq!(restored.state().get_balance("miner"), 150);
+
+        // The torn tail is replaced by a snapshot on the next save
+        restored.mine_pending("miner").unwrap();
+        reopened.save(&restored).unwrap();
+        assert_eq!(reopened.load_chain().unwrap().unwrap().len(), 5);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_file_storage_backend_round_trip() {
+        let dir = std::env::temp_dir().join(format!("contract-store-{}", uuid::Uuid::new_v4()));
+        let backend = FileStorageBackend::open(&dir).unwrap();
+        let storage = HashMap::from([(7, 42), (8, -1)]);
+
+        assert_eq!(backend.load("0xcabc").unwrap(), None);
+        backend.store("0xcabc", &storage).unwrap();
+        assert_eq!(backend.load("0xcabc").unwrap(), Some(storage));
+        backend.remove("0xcabc").unwrap();
+        backend.remove("0xcabc").unwrap();
+        assert_eq!(backend.load("0xcabc").unwrap(), None);
+
+        assert!(backend.store("../escape", &HashMap::new()).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_invalid_pending_transaction_is_discarded_on_load() {
+        let store = temp_store();
+        let wallet = Wallet::new();
+
+        // Never funded, so the transfer fails revalidation
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), test_address("bob"), 10);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        let result = Blockchain::new(1, 50).add_transaction(tx.clone());
+        assert!(matches!(result, Err(CoreError::InsufficientBalance { .. })));
+        store.save_mempool(&[tx]).unwrap();
+
+        let mut restored = Blockchain::new(1, 50);
+        store.load_into(&mut restored).unwrap();
+        assert!(restored.pending_transactions().is_empty());
+
+        fs::remove_dir_all(store.dir()).ok();
+    }
+}
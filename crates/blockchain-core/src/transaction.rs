@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use chrono::{DateTime, Utc};
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use serde::{Deserialize, Serialize};
@@ -5,12 +7,31 @@ use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 use crate::errors::{CoreError, CoreResult};
+use crate::state::MultisigConfig;
+use crate::wallet::Wallet;
+
+/// Network id signatures are bound to unless a node configures another.
+pub const DEFAULT_CHAIN_ID: u64 = 1;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum TransactionType {
     Transfer,
     ContractDeploy,
     ContractCall,
+    /// Claims the UTF-8 name in `data` for `sender`.
+    RegisterName,
+    /// Puts `sender` under the JSON `MultisigConfig` in `data`; empty
+    /// `data` reverts it to a single-key account.
+    SetMultisig,
+}
+
+/// Signatures from several keys, at least `required` of which must be
+/// valid for the transaction to verify.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Hash)]
+pub struct MultiSig {
+    pub required: u8,
+    /// (public key, signature) pairs over the same payload as `signature`.
+    pub signatures: Vec<(Vec<u8>, Vec<u8>)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,7 +42,21 @@ pub struct Transaction {
     pub amount: u64,
     pub data: Vec<u8>,
     pub tx_type: TransactionType,
+    /// Sender's account nonce this transaction consumes; replaying an
+    /// already-applied transaction fails the nonce check.
+    #[serde(default)]
+    pub nonce: u64,
+    /// Paid by the sender to the miner of the block that includes it.
+    #[serde(default)]
+    pub fee: u64,
     pub timestamp: DateTime<Utc>,
+    /// Past this instant the transaction is rejected and dropped from the
+    /// mempool instead of mined.
+    #[serde(default)]
+    pub valid_until: Option<DateTime<Utc>>,
+    /// When set, `verify` checks these signatures instead of `signature`.
+    #[serde(default)]
+    pub multisig: Option<MultiSig>,
     pub signature: Option<Vec<u8>>,
     pub public_key: Option<Vec<u8>>,
 }
@@ -41,7 +76,11 @@ impl Transaction {
             amount,
             data,
             tx_type,
+            nonce: 0,
+            fee: 0,
             timestamp: Utc::now(),
+            valid_until: None,
+            multisig: None,
             signature: None,
             public_key: None,
         }
@@ -71,38 +110,247 @@ impl Transaction {
         )
     }
 
+    pub fn new_register_name(sender: String, name: String) -> Self {
+        Self::new(
+            sender,
+            String::new(),
+            0,
+            name.into_bytes(),
+            TransactionType::RegisterName,
+        )
+    }
+
+    /// Puts `sender` under `config`, or back under its own key with `None`.
+    pub fn new_set_multisig(sender: String, config: Option<&MultisigConfig>) -> Self {
+        let data = config
+            .map(|config| serde_json::to_vec(config).expect("config serializes"))
+            .unwrap_or_default();
+        Self::new(sender, String::new(), 0, data, TransactionType::SetMultisig)
+    }
+
+    /// Sets the nonce; must be called before signing.
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    /// Sets the miner fee; must be called before signing.
+    pub fn with_fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Sets the expiry; must be called before signing.
+    pub fn with_valid_until(mut self, valid_until: DateTime<Utc>) -> Self {
+        self.valid_until = Some(valid_until);
+        self
+    }
+
+    /// Makes this a multisig transaction needing `required` signatures
+    /// added with `cosign`; must be called before signing.
+    pub fn with_multisig(mut self, required: u8) -> Self {
+        self.multisig = Some(MultiSig {
+            required,
+            signatures: Vec::new(),
+        });
+        self
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.valid_until.is_some_and(|until| until < now)
+    }
+
+    /// The name claimed by a `RegisterName` transaction.
+    pub fn name(&self) -> Option<&str> {
+        if self.tx_type != TransactionType::RegisterName {
+            return None;
+        }
+        std::str::from_utf8(&self.data).ok()
+    }
+
+    /// The config a `SetMultisig` transaction installs, where `Some(None)`
+    /// reverts the sender to a single key. `None` for other types or
+    /// malformed data.
+    pub fn multisig_config(&self) -> Option<Option<MultisigConfig>> {
+        if self.tx_type != TransactionType::SetMultisig {
+            return None;
+        }
+        if self.data.is_empty() {
+            return Some(None);
+        }
+        serde_json::from_slice(&self.data).ok().map(Some)
+    }
+
+    /// Address derived from the attached public key, if any.
+    pub fn signer_address(&self) -> Option<String> {
+        let bytes: [u8; 32] = self.public_key.as_deref()?.try_into().ok()?;
+        let key = VerifyingKey::from_bytes(&bytes).ok()?;
+        Some(Wallet::address_from_public_key(&key))
+    }
+
+    /// Fields are `|`-separated so no two transactions share a preimage,
+    /// e.g. by shifting digits from `amount` into `fee`.
     pub fn hash(&self) -> String {
-        let data = format!(
-            "{}{}{}{}{}{:?}{:?}",
-            self.id, self.sender, self.recipient, self.amount, self.timestamp, self.data, self.tx_type
+        let mut data = format!(
+            "{}|{}|{}|{}|{}|{}|{}|{:?}|{:?}",
+            self.id,
+            self.sender,
+            self.recipient,
+            self.amount,
+            self.fee,
+            self.nonce,
+            self.timestamp,
+            self.data,
+            self.tx_type
         );
+        data.push_str(&self.expiry_suffix());
+        data.push_str(&self.multisig_suffix());
         let mut hasher = Sha256::new();
         hasher.update(data.as_bytes());
         hex::encode(hasher.finalize())
     }
 
-    fn signable_bytes(&self) -> Vec<u8> {
-        let data = format!(
-            "{}{}{}{}{}",
-            self.id, self.sender, self.recipient, self.amount, self.timestamp
+    /// Digest a signature covers. `chain_id` leads the payload so a
+    /// signature made for one network never verifies on another; the type
+    /// and a hash of `data` are included so neither can be swapped. Fields
+    /// are `|`-separated, as in `hash`.
+    ///
+    /// Migration: signatures made before the chain id was part of the
+    /// payload, or before fields were separated, no longer verify anywhere;
+    /// such transactions (e.g. preload files) have to be re-signed for the
+    /// target chain.
+    fn signable_bytes(&self, chain_id: u64) -> Vec<u8> {
+        let mut data = format!(
+            "{}:{}|{}|{}|{}|{}|{}|{}|{:?}|{}",
+            chain_id,
+            self.id,
+            self.sender,
+            self.recipient,
+            self.amount,
+            self.fee,
+            self.nonce,
+            self.timestamp,
+            self.tx_type,
+            hex::encode(Sha256::digest(&self.data))
         );
+        data.push_str(&self.expiry_suffix());
+        data.push_str(&self.multisig_suffix());
         let mut hasher = Sha256::new();
         hasher.update(data.as_bytes());
         hasher.finalize().to_vec()
     }
 
-    pub fn sign(&mut self, signing_key: &SigningKey) {
-        let message = self.signable_bytes();
+    /// Empty without an expiry, so older hashes and signatures still match.
+    fn expiry_suffix(&self) -> String {
+        self.valid_until.map_or_else(String::new, |until| format!(":{}", until))
+    }
+
+    /// Empty for single-signature transactions; otherwise binds the
+    /// threshold so it can't be lowered after signing.
+    fn multisig_suffix(&self) -> String {
+        self.multisig
+            .as_ref()
+            .map_or_else(String::new, |multisig| format!(":ms{}", multisig.required))
+    }
+
+    pub fn sign(&mut self, signing_key: &SigningKey, chain_id: u64) {
+        let message = self.signable_bytes(chain_id);
         let signature = signing_key.sign(&message);
         self.signature = Some(signature.to_bytes().to_vec());
         self.public_key = Some(signing_key.verifying_key().to_bytes().to_vec());
     }
 
-    pub fn verify(&self) -> CoreResult<bool> {
+    /// Adds a multisig signature; a no-op unless `with_multisig` was called.
+    pub fn cosign(&mut self, signing_key: &SigningKey, chain_id: u64) {
+        let message = self.signable_bytes(chain_id);
+        if let Some(multisig) = self.multisig.as_mut() {
+            let signature = signing_key.sign(&message);
+            multisig.signatures.push((
+                signing_key.verifying_key().to_bytes().to_vec(),
+                signature.to_bytes().to_vec(),
+            ));
+        }
+    }
+
+    /// Addresses of the keys whose multisig signatures are valid for
+    /// `chain_id`. Malformed or invalid pairs are ignored.
+    pub fn cosigner_addresses(&self, chain_id: u64) -> HashSet<String> {
+        let Some(multisig) = &self.multisig else {
+            return HashSet::new();
+        };
+        let message = self.signable_bytes(chain_id);
+        multisig
+            .signatures
+            .iter()
+            .filter_map(|(public_key, signature)| {
+                let (key, signature) = Self::parse_signature(public_key, signature).ok()?;
+                key.verify(&message, &signature).ok()?;
+                Some(Wallet::address_from_public_key(&key))
+            })
+            .collect()
+    }
+
+    /// Checks the signature against `chain_id`; one made for a different
+    /// chain, or by a key that isn't the sender's, fails like any other bad
+    /// signature. Multisig transactions need
+    /// `required` valid signatures from distinct keys.
+    pub fn verify(&self, chain_id: u64) -> CoreResult<bool> {
         if self.sender == "system" {
             return Ok(true);
         }
 
+        if let Some(multisig) = &self.multisig {
+            let valid = self.cosigner_addresses(chain_id).len();
+            if multisig.required == 0 || valid < multisig.required as usize {
+                return Err(CoreError::InvalidSignature(format!(
+                    "{} of {} required signatures are valid",
+                    valid, multisig.required
+                )));
+            }
+            return Ok(true);
+        }
+
+        let (verifying_key, signature) = self.signature_parts()?;
+        let message = self.signable_bytes(chain_id);
+        verifying_key
+            .verify(&message, &signature)
+            .map_err(|e| CoreError::InvalidSignature(e.to_string()))?;
+
+        Ok(true)
+    }
+
+    /// Verifies every non-system transaction in `txs` with a single ed25519
+    /// batch check. The error doesn't say which signature failed; fall back
+    /// to `verify` per transaction if that's needed.
+    pub fn verify_batch(txs: &[Transaction], chain_id: u64) -> CoreResult<()> {
+        let mut messages = Vec::new();
+        let mut signatures = Vec::new();
+        let mut verifying_keys = Vec::new();
+        for tx in txs.iter().filter(|tx| tx.sender != "system") {
+            if tx.multisig.is_some() {
+                tx.verify(chain_id).map_err(|e| {
+                    CoreError::InvalidSignature(format!("Transaction {}: {}", tx.id, e))
+                })?;
+                continue;
+            }
+            let (verifying_key, signature) = tx.signature_parts().map_err(|e| {
+                CoreError::InvalidSignature(format!("Transaction {}: {}", tx.id, e))
+            })?;
+            messages.push(tx.signable_bytes(chain_id));
+            signatures.push(signature);
+            verifying_keys.push(verifying_key);
+        }
+        if signatures.is_empty() {
+            return Ok(());
+        }
+
+        let messages: Vec<&[u8]> = messages.iter().map(Vec::as_slice).collect();
+        ed25519_dalek::verify_batch(&messages, &signatures, &verifying_keys).map_err(|e| {
+            CoreError::InvalidSignature(format!("Batch verification failed: {}", e))
+        })
+    }
+
+    fn signature_parts(&self) -> CoreResult<(VerifyingKey, Signature)> {
         let signature_bytes = self
             .signature
             .as_ref()
@@ -113,25 +361,32 @@ impl Transaction {
             .as_ref()
             .ok_or_else(|| CoreError::InvalidSignature("Missing public key".into()))?;
 
+        let (verifying_key, signature) =
+            Self::parse_signature(public_key_bytes, signature_bytes)?;
+        if Wallet::address_from_public_key(&verifying_key) != self.sender {
+            return Err(CoreError::InvalidSignature(
+                "Public key does not match sender".into(),
+            ));
+        }
+        Ok((verifying_key, signature))
+    }
+
+    fn parse_signature(
+        public_key_bytes: &[u8],
+        signature_bytes: &[u8],
+    ) -> CoreResult<(VerifyingKey, Signature)> {
         let sig_array: [u8; 64] = signature_bytes
-            .as_slice()
             .try_into()
             .map_err(|_| CoreError::InvalidSignature("Invalid signature length".into()))?;
         let signature = Signature::from_bytes(&sig_array);
 
         let pk_array: [u8; 32] = public_key_bytes
-            .as_slice()
             .try_into()
             .map_err(|_| CoreError::InvalidSignature("Invalid public key length".into()))?;
         let verifying_key = VerifyingKey::from_bytes(&pk_array)
             .map_err(|e| CoreError::InvalidSignature(e.to_string()))?;
 
-        let message = self.signable_bytes();
-        verifying_key
-            .verify(&message, &signature)
-            .map_err(|e| CoreError::InvalidSignature(e.to_string()))?;
-
-        Ok(true)
+        Ok((verifying_key, signature))
     }
 }
 
@@ -157,7 +412,11 @@ mod tests {
             amount: 100,
             data: vec![],
             tx_type: TransactionType::Transfer,
+            nonce: 0,
+            fee: 0,
             timestamp: Utc::now(),
+            valid_until: None,
+            multisig: None,
             signature: None,
             public_key: None,
         };
@@ -168,17 +427,156 @@ mod tests {
 
     #[test]
     fn test_sign_and_verify() {
-        let signing_key = SigningKey::generate(&mut rand::thread_rng());
-        let mut tx = Transaction::new_transfer("alice".into(), "bob".into(), 50);
-        tx.sign(&signing_key);
+        let wallet = Wallet::new();
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), "bob".into(), 50);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
 
         assert!(tx.signature.is_some());
-        assert!(tx.verify().unwrap());
+        assert!(tx.verify(DEFAULT_CHAIN_ID).unwrap());
+    }
+
+    #[test]
+    fn test_signer_must_match_sender() {
+        let alice = Wallet::new();
+        let mallory = Wallet::new();
+        let mut tx = Transaction::new_transfer(alice.address.clone(), "bob".into(), 50);
+        tx.sign(mallory.signing_key(), DEFAULT_CHAIN_ID);
+
+        assert!(matches!(tx.verify(DEFAULT_CHAIN_ID), Err(CoreError::InvalidSignature(_))));
+        assert!(Transaction::verify_batch(std::slice::from_ref(&tx), DEFAULT_CHAIN_ID).is_err());
+    }
+
+    #[test]
+    fn test_nonce_is_signed() {
+        let wallet = Wallet::new();
+        let mut tx =
+            Transaction::new_transfer(wallet.address.clone(), "bob".into(), 50).with_nonce(3);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+
+        tx.nonce = 4;
+        assert!(tx.verify(DEFAULT_CHAIN_ID).is_err());
+    }
+
+    #[test]
+    fn test_data_and_type_are_signed() {
+        let wallet = Wallet::new();
+        let mut tx =
+            Transaction::new_contract_call(wallet.address.clone(), "0xcontract".into(), vec![1]);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        assert!(tx.verify(DEFAULT_CHAIN_ID).unwrap());
+
+        let mut swapped_data = tx.clone();
+        swapped_data.data = vec![2];
+        assert!(swapped_data.verify(DEFAULT_CHAIN_ID).is_err());
+
+        let mut swapped_type = tx;
+        swapped_type.tx_type = TransactionType::Transfer;
+        assert!(swapped_type.verify(DEFAULT_CHAIN_ID).is_err());
+    }
+
+    #[test]
+    fn test_amount_cannot_be_moved_into_fee() {
+        let wallet = Wallet::new();
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), Wallet::new().address, 51)
+            .with_fee(0);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        let hash = tx.hash();
+
+        let mut shifted = tx;
+        shifted.amount = 5;
+        shifted.fee = 10;
+        assert!(shifted.verify(DEFAULT_CHAIN_ID).is_err());
+        assert_ne!(shifted.hash(), hash);
+    }
+
+    #[test]
+    fn test_expiry_is_signed() {
+        let wallet = Wallet::new();
+        let until = Utc::now() + chrono::Duration::minutes(5);
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), "bob".into(), 50)
+            .with_valid_until(until);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        assert!(tx.verify(DEFAULT_CHAIN_ID).unwrap());
+
+        tx.valid_until = Some(until + chrono::Duration::days(1));
+        assert!(tx.verify(DEFAULT_CHAIN_ID).is_err());
+        tx.valid_until = None;
+        assert!(tx.verify(DEFAULT_CHAIN_ID).is_err());
+    }
+
+    #[test]
+    fn test_multisig_two_of_three() {
+        let keys: Vec<_> =
+            (0..3).map(|_| SigningKey::generate(&mut rand::thread_rng())).collect();
+        let mut tx =
+            Transaction::new_transfer("vault".into(), "bob".into(), 50).with_multisig(2);
+
+        tx.cosign(&keys[0], DEFAULT_CHAIN_ID);
+        assert!(matches!(tx.verify(DEFAULT_CHAIN_ID), Err(CoreError::InvalidSignature(_))));
+
+        // The same key twice still counts once
+        tx.cosign(&keys[0], DEFAULT_CHAIN_ID);
+        assert!(tx.verify(DEFAULT_CHAIN_ID).is_err());
+
+        tx.cosign(&keys[2], DEFAULT_CHAIN_ID);
+        assert!(tx.verify(DEFAULT_CHAIN_ID).unwrap());
+        assert!(Transaction::verify_batch(std::slice::from_ref(&tx), DEFAULT_CHAIN_ID).is_ok());
+
+        // Lowering the threshold breaks every signature
+        tx.multisig.as_mut().unwrap().required = 1;
+        assert!(tx.verify(DEFAULT_CHAIN_ID).is_err());
+    }
+
+    #[test]
+    fn test_signature_bound_to_chain_id() {
+        let wallet = Wallet::new();
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), "bob".into(), 50);
+        tx.sign(wallet.signing_key(), 1);
+
+        assert!(tx.verify(1).unwrap());
+        assert!(matches!(tx.verify(2), Err(CoreError::InvalidSignature(_))));
+        assert!(Transaction::verify_batch(std::slice::from_ref(&tx), 2).is_err());
     }
 
     #[test]
     fn test_system_transaction_no_signature() {
         let tx = Transaction::new_transfer("system".into(), "miner".into(), 50);
-        assert!(tx.verify().unwrap());
+        assert!(tx.verify(DEFAULT_CHAIN_ID).unwrap());
+    }
+
+    fn signed_block(count: u64) -> Vec<Transaction> {
+        let wallet = Wallet::new();
+        let mut txs: Vec<Transaction> = (0..count)
+            .map(|nonce| {
+                let mut tx =
+                    Transaction::new_transfer(wallet.address.clone(), "0xrecipient".into(), 1)
+                        .with_nonce(nonce);
+                tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+                tx
+            })
+            .collect();
+        txs.push(Transaction::new_transfer("system".into(), "miner".into(), 50));
+        txs
+    }
+
+    #[test]
+    fn test_verify_batch_block_of_100() {
+        let txs = signed_block(100);
+        assert!(Transaction::verify_batch(&txs, DEFAULT_CHAIN_ID).is_ok());
+        assert!(Transaction::verify_batch(&[], DEFAULT_CHAIN_ID).is_ok());
+    }
+
+    #[test]
+    fn test_verify_batch_rejects_tampered_signature() {
+        let mut txs = signed_block(100);
+        txs[42].signature.as_mut().unwrap()[0] ^= 0xff;
+        assert!(matches!(
+            Transaction::verify_batch(&txs, DEFAULT_CHAIN_ID),
+            Err(CoreError::InvalidSignature(_))
+        ));
+
+        let mut txs = signed_block(3);
+        txs[1].signature = None;
+        assert!(Transaction::verify_batch(&txs, DEFAULT_CHAIN_ID).is_err());
     }
 }
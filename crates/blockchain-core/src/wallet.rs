@@ -1,6 +1,19 @@
-use ed25519_dalek::SigningKey;
+use bip39::Mnemonic;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::address;
+use crate::errors::{CoreError, CoreResult};
+
+/// Words in a mnemonic from `Wallet::generate_mnemonic`.
+pub const MNEMONIC_WORDS: usize = 24;
+
+/// Hardened SLIP-0010 path prefix, `m/44'/1'`; the wallet index is the
+/// final hardened level.
+const DERIVATION_PATH: [u32; 2] = [44, 1];
+const HARDENED: u32 = 0x8000_0000;
 
 #[derive(Debug)]
 pub struct Wallet {
@@ -24,6 +37,75 @@ impl Wallet {
         }
     }
 
+    /// A fresh BIP39 English mnemonic for `from_mnemonic`.
+    pub fn generate_mnemonic() -> String {
+        Mnemonic::generate(MNEMONIC_WORDS)
+            .expect("supported mnemonic word count")
+            .to_string()
+    }
+
+    /// Deterministically derives wallet `index` from a BIP39 mnemonic,
+    /// using SLIP-0010 ed25519 derivation along `m/44'/1'/index'`.
+    pub fn from_mnemonic(phrase: &str, index: u32) -> CoreResult<Self> {
+        let mnemonic = Mnemonic::parse(phrase)
+            .map_err(|e| CoreError::InvalidMnemonic(e.to_string()))?;
+        if index >= HARDENED {
+            return Err(CoreError::InvalidMnemonic(format!(
+                "Wallet index {} out of range",
+                index
+            )));
+        }
+        let seed = mnemonic.to_seed("");
+
+        let (mut key, mut chain_code) = Self::slip10_step(b"ed25519 seed", &[&seed]);
+        for level in DERIVATION_PATH.into_iter().chain([index]) {
+            let child = (level | HARDENED).to_be_bytes();
+            (key, chain_code) = Self::slip10_step(&chain_code, &[&[0], &key, &child]);
+        }
+
+        let signing_key = SigningKey::from_bytes(&key);
+        let address = Self::derive_address(&signing_key);
+        Ok(Self {
+            signing_key,
+            address,
+        })
+    }
+
+    /// One HMAC-SHA512 step, split into (key, chain code).
+    fn slip10_step(hmac_key: &[u8], data: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+        let mut mac =
+            Hmac::<Sha512>::new_from_slice(hmac_key).expect("HMAC accepts any key length");
+        for part in data {
+            mac.update(part);
+        }
+        let out = mac.finalize().into_bytes();
+        (out[..32].try_into().unwrap(), out[32..].try_into().unwrap())
+    }
+
+    /// Restores a wallet from a hex-encoded 32-byte private key, as
+    /// printed by `private_key_hex`.
+    pub fn from_private_key_hex(private_key: &str) -> CoreResult<Self> {
+        let bytes: [u8; 32] = Self::decode_key(private_key)?;
+        let signing_key = SigningKey::from_bytes(&bytes);
+        let address = Self::derive_address(&signing_key);
+        Ok(Self {
+            signing_key,
+            address,
+        })
+    }
+
+    pub fn private_key_hex(&self) -> String {
+        hex::encode(self.signing_key.to_bytes())
+    }
+
+    fn decode_key(key: &str) -> CoreResult<[u8; 32]> {
+        let bytes = hex::decode(key.trim_start_matches("0x"))
+            .map_err(|e| CoreError::InvalidKey(e.to_string()))?;
+        bytes
+            .try_into()
+            .map_err(|_| CoreError::InvalidKey("expected 32 bytes".into()))
+    }
+
     pub fn signing_key(&self) -> &SigningKey {
         &self.signing_key
     }
@@ -39,12 +121,29 @@ impl Wallet {
         }
     }
 
-    fn derive_address(signing_key: &SigningKey) -> String {
-        let public_key = signing_key.verifying_key();
+    /// Checksummed address for `signing_key`'s public key.
+    pub fn derive_address(signing_key: &SigningKey) -> String {
+        Self::address_from_public_key(&signing_key.verifying_key())
+    }
+
+    /// Address for a hex-encoded ed25519 public key.
+    pub fn address_from_public_key_hex(public_key: &str) -> CoreResult<String> {
+        let key = VerifyingKey::from_bytes(&Self::decode_key(public_key)?)
+            .map_err(|e| CoreError::InvalidKey(e.to_string()))?;
+        Ok(Self::address_from_public_key(&key))
+    }
+
+    pub fn address_from_public_key(public_key: &VerifyingKey) -> String {
         let mut hasher = Sha256::new();
         hasher.update(public_key.as_bytes());
         let hash = hex::encode(hasher.finalize());
-        format!("0x{}", &hash[..40])
+        address::to_checksum(&hash[..40])
+    }
+
+    /// Whether `address` is a well-formed, correctly checksummed wallet
+    /// address.
+    pub fn is_valid_address(address: &str) -> bool {
+        address::is_valid(address)
     }
 }
 
@@ -56,7 +155,7 @@ impl Default for Wallet {
 
 #[cfg(test)]
 mod tests {
-    use crate::transaction::Transaction;
+    use crate::transaction::{Transaction, DEFAULT_CHAIN_ID};
 
     use super::*;
 
@@ -67,6 +166,68 @@ mod tests {
         assert_eq!(wallet.address.len(), 42);
     }
 
+    #[test]
+    fn test_is_valid_address() {
+        assert!(Wallet::is_valid_address(&Wallet::new().address));
+        assert!(!Wallet::is_valid_address("miner-node"));
+        assert!(!Wallet::is_valid_address("0x1234"));
+        assert!(!Wallet::is_valid_address(&format!("0x{}", "g".repeat(40))));
+    }
+
+    #[test]
+    fn test_mnemonic_derivation_is_deterministic() {
+        let phrase = Wallet::generate_mnemonic();
+        assert_eq!(phrase.split_whitespace().count(), MNEMONIC_WORDS);
+
+        let first = Wallet::from_mnemonic(&phrase, 0).unwrap();
+        let again = Wallet::from_mnemonic(&phrase, 0).unwrap();
+        let second = Wallet::from_mnemonic(&phrase, 1).unwrap();
+        assert_eq!(first.address, again.address);
+        assert_eq!(first.public_key_hex(), again.public_key_hex());
+        assert_ne!(first.address, second.address);
+        assert!(Wallet::is_valid_address(&first.address));
+
+        let other = Wallet::from_mnemonic(&Wallet::generate_mnemonic(), 0).unwrap();
+        assert_ne!(first.address, other.address);
+    }
+
+    #[test]
+    fn test_slip10_master_key_vector() {
+        // SLIP-0010 ed25519 test vector 1, chain m
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let (key, chain_code) = Wallet::slip10_step(b"ed25519 seed", &[&seed]);
+        assert_eq!(
+            hex::encode(key),
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+        );
+        assert_eq!(
+            hex::encode(chain_code),
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"
+        );
+    }
+
+    #[test]
+    fn test_private_key_round_trip() {
+        let wallet = Wallet::new();
+        let restored = Wallet::from_private_key_hex(&wallet.private_key_hex()).unwrap();
+        assert_eq!(restored.address, wallet.address);
+        assert_eq!(
+            Wallet::address_from_public_key_hex(&wallet.public_key_hex()).unwrap(),
+            wallet.address
+        );
+        assert!(matches!(Wallet::from_private_key_hex("abcd"), Err(CoreError::InvalidKey(_))));
+        assert!(matches!(
+            Wallet::address_from_public_key_hex("zz"),
+            Err(CoreError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_mnemonic_rejected() {
+        let result = Wallet::from_mnemonic("not a real mnemonic phrase", 0);
+        assert!(matches!(result, Err(CoreError::InvalidMnemonic(_))));
+    }
+
     #[test]
     fn test_unique_addresses() {
         let w1 = Wallet::new();
@@ -82,7 +243,7 @@ mod tests {
             "0xrecipient".into(),
             50,
         );
-        tx.sign(wallet.signing_key());
-        assert!(tx.verify().unwrap());
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        assert!(tx.verify(DEFAULT_CHAIN_ID).unwrap());
     }
 }
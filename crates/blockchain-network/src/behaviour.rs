@@ -1,9 +1,16 @@
 use libp2p::gossipsub;
+use libp2p::identify;
 use libp2p::mdns;
+use libp2p::request_response;
 use libp2p::swarm::NetworkBehaviour;
 
+use crate::messages::{SyncRequest, SyncResponse};
+
 #[derive(NetworkBehaviour)]
 pub struct BlockchainBehaviour {
     pub gossipsub: gossipsub::Behaviour,
     pub mdns: mdns::tokio::Behaviour,
+    pub identify: identify::Behaviour,
+    /// Direct block-range sync with a single peer.
+    pub sync: request_response::json::Behaviour<SyncRequest, SyncResponse>,
 }
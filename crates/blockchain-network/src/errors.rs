@@ -13,6 +13,9 @@ pub enum NetworkError {
 
     #[error("Channel error: {0}")]
     Channel(String),
+
+    #[error("Identity error: {0}")]
+    Identity(String),
 }
 
 pub type NetworkResult<T> = Result<T, NetworkError>;
@@ -1,12 +1,40 @@
+use std::collections::{HashSet, VecDeque};
+
 use blockchain_core::block::Block;
 use blockchain_core::transaction::Transaction;
 
+use crate::messages::{MessageOrigin, NetworkMessage, SyncRequest};
+use crate::peers::PeerInfo;
+
+/// Most blocks sent in reply to a single `GetBlocks` request.
+pub const MAX_BLOCKS_PER_RESPONSE: u64 = 500;
+
+/// Chain responses remembered by `ChainResponseFilter`.
+pub const RECENT_CHAIN_RESPONSES: usize = 256;
+
+/// Largest gossip message accepted or published unless configured
+/// otherwise.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Longest chain accepted in a `ChainResponse`.
+pub const MAX_CHAIN_RESPONSE_BLOCKS: usize = 50_000;
+
 /// Commands sent from the application to the network layer.
 #[derive(Debug, Clone)]
 pub enum NetworkCommand {
     BroadcastTransaction(Transaction),
     BroadcastBlock(Block),
+    /// Asks the connected peer with the highest known height for its whole
+    /// chain over request-response; the reply arrives as a `ChainResponse`.
     RequestChain,
+    RequestBlocks { from_index: u64, to_index: u64 },
+    SendBlocks(Vec<Block>),
+    /// Sends the whole chain, stamped with this node's next sequence number.
+    SendChain(Vec<Block>),
+    /// Answers the `NetworkEvent::SyncRequest` with id `request_id`.
+    SendSyncResponse { request_id: u64, blocks: Vec<Block> },
+    /// Stops `NetworkNode::run`, dropping the swarm and its connections.
+    Shutdown,
 }
 
 /// Events emitted from the network layer to the application.
@@ -15,7 +43,336 @@ pub enum NetworkEvent {
     NewTransaction(Transaction),
     NewBlock(Block),
     ChainRequest { peer: String },
-    ChainResponse(Vec<Block>),
+    /// `peer` is the connection the chain arrived on, unlike
+    /// `origin.peer`, which the sender may claim freely.
+    ChainResponse {
+        peer: String,
+        origin: MessageOrigin,
+        chain: Vec<Block>,
+    },
+    BlocksRequest { peer: String, from_index: u64, to_index: u64 },
+    Blocks(Vec<Block>),
+    /// A peer asked us directly for blocks; answer with
+    /// `NetworkCommand::SendSyncResponse` and the same `request_id`.
+    SyncRequest {
+        request_id: u64,
+        peer: String,
+        from_index: u64,
+        to_index: u64,
+    },
     PeerConnected(String),
     PeerDisconnected(String),
+    PeerIdentified(PeerInfo),
+    PeerHeight { peer: String, height: u64 },
+}
+
+impl NetworkCommand {
+    /// The gossip message that carries this command; `None` for commands
+    /// that are handled locally or sent over request-response rather than
+    /// gossip. Responses take their origin from `sequencer`.
+    pub fn into_message(self, sequencer: &mut Sequencer) -> Option<NetworkMessage> {
+        let message = match self {
+            Self::BroadcastTransaction(tx) => NetworkMessage::NewTransaction(tx),
+            Self::BroadcastBlock(block) => NetworkMessage::NewBlock(block),
+            Self::RequestBlocks {
+                from_index,
+                to_index,
+            } => NetworkMessage::GetBlocks {
+                from_index,
+                to_index,
+            },
+            Self::SendBlocks(blocks) => NetworkMessage::Blocks(blocks),
+            Self::SendChain(chain) => NetworkMessage::ChainResponse {
+                origin: sequencer.next_origin(),
+                chain,
+            },
+            Self::RequestChain | Self::SendSyncResponse { .. } | Self::Shutdown => return None,
+        };
+        Some(message)
+    }
+}
+
+impl NetworkEvent {
+    /// The event raised for a message authored by `peer` and delivered by
+    /// `propagation_source`.
+    pub fn from_message(
+        message: NetworkMessage,
+        peer: Option<String>,
+        propagation_source: String,
+    ) -> Self {
+        match message {
+            NetworkMessage::NewTransaction(tx) => Self::NewTransaction(tx),
+            NetworkMessage::NewBlock(block) => Self::NewBlock(block),
+            NetworkMessage::ChainRequest => Self::ChainRequest {
+                peer: peer.unwrap_or_default(),
+            },
+            NetworkMessage::ChainResponse { origin, chain } => Self::ChainResponse {
+                peer: propagation_source,
+                origin,
+                chain,
+            },
+            NetworkMessage::GetBlocks {
+                from_index,
+                to_index,
+            } => Self::BlocksRequest {
+                peer: peer.unwrap_or_default(),
+                from_index,
+                to_index,
+            },
+            NetworkMessage::Blocks(blocks) => Self::Blocks(blocks),
+        }
+    }
+}
+
+/// Parses gossip `data`, dropping (and logging) messages over `max_size`
+/// bytes before deserializing them, malformed ones, and chain responses
+/// over `MAX_CHAIN_RESPONSE_BLOCKS`.
+pub fn decode_message(data: &[u8], max_size: usize) -> Option<NetworkMessage> {
+    if data.len() > max_size {
+        tracing::warn!(size = data.len(), max_size, "Dropped oversized gossip message");
+        return None;
+    }
+    let message = match serde_json::from_slice::<NetworkMessage>(data) {
+        Ok(message) => message,
+        Err(e) => {
+            tracing::debug!("Dropped malformed gossip message: {}", e);
+            return None;
+        }
+    };
+    if let NetworkMessage::ChainResponse { chain, .. } = &message {
+        if chain.len() > MAX_CHAIN_RESPONSE_BLOCKS {
+            tracing::warn!(blocks = chain.len(), "Dropped oversized chain response");
+            return None;
+        }
+    }
+    Some(message)
+}
+
+/// Stamps outgoing messages with this node's peer id and a sequence
+/// number that increases by one per message.
+#[derive(Debug)]
+pub struct Sequencer {
+    peer: String,
+    next_seq: u64,
+}
+
+impl Sequencer {
+    /// Starting from a fresh `first_seq` on every run (e.g. the current
+    /// time) keeps new messages distinct from ones sent before a restart.
+    pub fn new(peer: impl Into<String>, first_seq: u64) -> Self {
+        Self {
+            peer: peer.into(),
+            next_seq: first_seq,
+        }
+    }
+
+    pub fn next_origin(&mut self) -> MessageOrigin {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        MessageOrigin {
+            peer: self.peer.clone(),
+            seq,
+        }
+    }
+}
+
+/// Screens chain responses before the costly `replace_chain`, dropping
+/// replays of a recently seen one. Responses are keyed on the peer that
+/// delivered them, so a forged `origin.peer` can't suppress another
+/// node's responses; whether a chain is worth adopting is left to
+/// `Blockchain::prefers`.
+#[derive(Debug, Default)]
+pub struct ChainResponseFilter {
+    seen: HashSet<(String, u64)>,
+    /// Least recently seen first.
+    order: VecDeque<(String, u64)>,
+}
+
+impl ChainResponseFilter {
+    /// Whether a chain response delivered by `peer` with `origin` is new.
+    pub fn accept(&mut self, peer: &str, origin: &MessageOrigin) -> bool {
+        let key = (peer.to_string(), origin.seq);
+        if self.seen.contains(&key) {
+            if let Some(pos) = self.order.iter().position(|seen| *seen == key) {
+                let seen = self.order.remove(pos).unwrap();
+                self.order.push_back(seen);
+            }
+            tracing::debug!(peer, seq = origin.seq, "Dropped replayed chain response");
+            return false;
+        }
+        if self.order.len() >= RECENT_CHAIN_RESPONSES {
+            if let Some(evicted) = self.order.pop_front() {
+                self.seen.remove(&evicted);
+            }
+        }
+        self.seen.insert(key.clone());
+        self.order.push_back(key);
+        true
+    }
+}
+
+/// The blocks to send for a `from_index..=to_index` request, clamped to
+/// the chain and to `MAX_BLOCKS_PER_RESPONSE`.
+pub fn blocks_in_range(chain: &[Block], from_index: u64, to_index: u64) -> Vec<Block> {
+    clamped_range(chain, from_index, to_index, MAX_BLOCKS_PER_RESPONSE)
+}
+
+/// The blocks to answer a `SyncRequest` with. Syncs may span the whole
+/// chain, so only `MAX_CHAIN_RESPONSE_BLOCKS` limits them.
+pub fn sync_blocks(chain: &[Block], request: &SyncRequest) -> Vec<Block> {
+    let max = MAX_CHAIN_RESPONSE_BLOCKS as u64;
+    clamped_range(chain, request.from_index, request.to_index, max)
+}
+
+fn clamped_range(chain: &[Block], from_index: u64, to_index: u64, max: u64) -> Vec<Block> {
+    let last = to_index
+        .min(from_index.saturating_add(max - 1))
+        .min((chain.len() as u64).saturating_sub(1));
+    if chain.is_empty() || from_index > last {
+        return Vec::new();
+    }
+    chain[from_index as usize..=last as usize].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain_core::chain::Blockchain;
+    use tokio::sync::mpsc;
+
+    /// Carries a command from one node to the other as serialized gossip.
+    fn deliver(cmd: NetworkCommand, from: &str) -> NetworkEvent {
+        let mut sequencer = Sequencer::new(from, 0);
+        let data = serde_json::to_vec(&cmd.into_message(&mut sequencer).unwrap()).unwrap();
+        let message = serde_json::from_slice(&data).unwrap();
+        NetworkEvent::from_message(message, Some(from.to_string()), from.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_range_sync_between_two_nodes() {
+        let mut ahead = Blockchain::new(1, 50);
+        for _ in 0..5 {
+            ahead.mine_pending("miner").unwrap();
+        }
+        let mut behind = Blockchain::new(1, 50);
+        behind.try_append_block(ahead.chain()[1].clone()).unwrap();
+
+        // behind -> ahead: request, ahead -> behind: response
+        let (to_ahead, mut ahead_rx) = mpsc::channel(8);
+        let (to_behind, mut behind_rx) = mpsc::channel(8);
+
+        to_ahead
+            .send(deliver(
+                NetworkCommand::RequestBlocks {
+                    from_index: behind.height(),
+                    to_index: 4,
+                },
+                "behind",
+            ))
+            .await
+            .unwrap();
+
+        let Some(NetworkEvent::BlocksRequest {
+            peer,
+            from_index,
+            to_index,
+        }) = ahead_rx.recv().await
+        else {
+            panic!("expected a blocks request");
+        };
+        assert_eq!((peer.as_str(), from_index, to_index), ("behind", 2, 4));
+        let reply = blocks_in_range(ahead.chain(), from_index, to_index);
+        to_behind
+            .send(deliver(NetworkCommand::SendBlocks(reply), "ahead"))
+            .await
+            .unwrap();
+
+        let Some(NetworkEvent::Blocks(blocks)) = behind_rx.recv().await else {
+            panic!("expected blocks");
+        };
+        let indices: Vec<_> = blocks.iter().map(|b| b.header.index).collect();
+        assert_eq!(indices, vec![2, 3, 4]);
+        for block in blocks {
+            behind.try_append_block(block).unwrap();
+        }
+        assert_eq!(behind.height(), 5);
+        assert_eq!(behind.latest_block().hash, ahead.chain()[4].hash);
+    }
+
+    #[test]
+    fn test_replayed_chain_response_is_tried_once() {
+        let mut ahead = Blockchain::new(1, 50);
+        ahead.mine_pending("miner").unwrap();
+        let mut local = Blockchain::new(1, 50);
+
+        let mut sequencer = Sequencer::new("ahead", 7);
+        let message = NetworkCommand::SendChain(ahead.chain().to_vec())
+            .into_message(&mut sequencer)
+            .unwrap();
+        let data = serde_json::to_vec(&message).unwrap();
+
+        let mut filter = ChainResponseFilter::default();
+        let mut attempts = 0;
+        for _ in 0..2 {
+            let message = serde_json::from_slice(&data).unwrap();
+            let NetworkEvent::ChainResponse { peer, origin, chain } =
+                NetworkEvent::from_message(message, Some("ahead".into()), "relay".into())
+            else {
+                panic!("expected a chain response");
+            };
+            assert_eq!(peer, "relay");
+            assert_eq!(origin, MessageOrigin { peer: "ahead".into(), seq: 7 });
+            if filter.accept(&peer, &origin) {
+                attempts += 1;
+                local.replace_chain(chain).unwrap();
+            }
+        }
+        assert_eq!(attempts, 1);
+        assert_eq!(local.height(), 2);
+
+        // The same origin delivered by another peer isn't a replay of it
+        let origin = MessageOrigin { peer: "ahead".into(), seq: 7 };
+        assert!(filter.accept("other", &origin));
+        assert!(filter.accept("relay", &sequencer.next_origin()));
+    }
+
+    #[test]
+    fn test_chain_response_filter_forgets_oldest() {
+        let mut filter = ChainResponseFilter::default();
+        let mut sequencer = Sequencer::new("peer", 0);
+        let first = sequencer.next_origin();
+        assert!(filter.accept("peer", &first));
+        for _ in 0..RECENT_CHAIN_RESPONSES {
+            assert!(filter.accept("peer", &sequencer.next_origin()));
+        }
+        assert!(filter.accept("peer", &first));
+    }
+
+    #[test]
+    fn test_oversized_chain_response_rejected() {
+        let mut bc = Blockchain::new(1, 50);
+        for _ in 0..5 {
+            bc.mine_pending("miner").unwrap();
+        }
+        let message = NetworkCommand::SendChain(bc.chain().to_vec())
+            .into_message(&mut Sequencer::new("peer-a", 0))
+            .unwrap();
+        let data = serde_json::to_vec(&message).unwrap();
+
+        assert!(decode_message(&data, data.len() - 1).is_none());
+        assert!(matches!(
+            decode_message(&data, data.len()),
+            Some(NetworkMessage::ChainResponse { chain, .. }) if chain.len() == 6
+        ));
+        assert!(decode_message(b"{not json", DEFAULT_MAX_MESSAGE_SIZE).is_none());
+    }
+
+    #[test]
+    fn test_blocks_in_range_clamps() {
+        let mut bc = Blockchain::new(1, 50);
+        bc.mine_pending("miner").unwrap();
+        assert_eq!(blocks_in_range(bc.chain(), 0, 100).len(), 2);
+        assert!(blocks_in_range(bc.chain(), 5, 10).is_empty());
+        assert!(blocks_in_range(bc.chain(), 1, 0).is_empty());
+    }
 }
@@ -0,0 +1,74 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use libp2p::identity::Keypair;
+
+use crate::errors::{NetworkError, NetworkResult};
+
+/// Loads the node's ed25519 keypair from `path`, or generates one and
+/// saves it there so the PeerId stays the same across restarts. The file
+/// holds libp2p's protobuf keypair encoding.
+pub fn load_or_generate_keypair(path: &Path) -> NetworkResult<Keypair> {
+    if path.exists() {
+        let bytes = fs::read(path).map_err(|e| {
+            NetworkError::Identity(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        return Keypair::from_protobuf_encoding(&bytes).map_err(|e| {
+            NetworkError::Identity(format!("Invalid keypair in {}: {}", path.display(), e))
+        });
+    }
+
+    let keypair = Keypair::generate_ed25519();
+    let bytes = keypair
+        .to_protobuf_encoding()
+        .map_err(|e| NetworkError::Identity(e.to_string()))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| NetworkError::Identity(e.to_string()))?;
+    }
+    write_private(path, &bytes).map_err(|e| {
+        NetworkError::Identity(format!("Failed to write {}: {}", path.display(), e))
+    })?;
+    tracing::info!("Generated new network identity at {}", path.display());
+    Ok(keypair)
+}
+
+/// Creates `path` readable and writable by its owner only, since it
+/// holds the private key.
+fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_file_yields_same_peer_id() {
+        let path = std::env::temp_dir()
+            .join(format!("p2p-identity-{}", std::process::id()))
+            .join("node.key");
+        let _ = fs::remove_file(&path);
+
+        let first = load_or_generate_keypair(&path).unwrap();
+        let second = load_or_generate_keypair(&path).unwrap();
+        assert_eq!(first.public().to_peer_id(), second.public().to_peer_id());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::write(&path, b"garbage").unwrap();
+        assert!(matches!(
+            load_or_generate_keypair(&path),
+            Err(NetworkError::Identity(_))
+        ));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,5 +1,7 @@
 pub mod behaviour;
 pub mod errors;
 pub mod handler;
+pub mod identity;
 pub mod messages;
 pub mod node;
+pub mod peers;
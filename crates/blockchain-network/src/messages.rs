@@ -7,5 +7,33 @@ pub enum NetworkMessage {
     NewTransaction(Transaction),
     NewBlock(Block),
     ChainRequest,
-    ChainResponse(Vec<Block>),
+    ChainResponse {
+        origin: MessageOrigin,
+        chain: Vec<Block>,
+    },
+    /// Asks peers for blocks `from_index..=to_index`.
+    GetBlocks { from_index: u64, to_index: u64 },
+    Blocks(Vec<Block>),
+}
+
+/// Asks one peer directly, over the sync request-response protocol, for
+/// its blocks `from_index..=to_index`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncRequest {
+    pub from_index: u64,
+    pub to_index: u64,
+}
+
+/// Reply to a `SyncRequest`: the requested blocks the peer has, in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncResponse {
+    pub blocks: Vec<Block>,
+}
+
+/// Identifies a message by the node that sent it and that node's sequence
+/// number, which increases with every message it stamps.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MessageOrigin {
+    pub peer: String,
+    pub seq: u64,
 }
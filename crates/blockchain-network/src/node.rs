@@ -1,26 +1,78 @@
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::path::Path;
 use std::time::Duration;
 
+use blockchain_core::block::Block;
 use futures::StreamExt;
 use libp2p::gossipsub::{self, IdentTopic, MessageAuthenticity};
+use libp2p::identify;
 use libp2p::identity::Keypair;
 use libp2p::mdns;
+use libp2p::request_response::{self, ProtocolSupport, ResponseChannel};
 use libp2p::swarm::SwarmEvent;
-use libp2p::{noise, tcp, yamux, Multiaddr, SwarmBuilder};
+use libp2p::{noise, tcp, yamux, Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder};
 use tokio::sync::mpsc;
 
 use crate::behaviour::{BlockchainBehaviour, BlockchainBehaviourEvent};
-use crate::handler::{NetworkCommand, NetworkEvent};
-use crate::messages::NetworkMessage;
+use crate::errors::NetworkResult;
+use crate::handler::{
+    decode_message, NetworkCommand, NetworkEvent, Sequencer, DEFAULT_MAX_MESSAGE_SIZE,
+    MAX_CHAIN_RESPONSE_BLOCKS,
+};
+use crate::identity::load_or_generate_keypair;
+use crate::messages::{MessageOrigin, NetworkMessage, SyncRequest, SyncResponse};
+use crate::peers;
 
+/// Version of the peer-to-peer protocol; peers advertise it via identify.
+pub const PROTOCOL_VERSION: &str = "1.0.0";
 const BLOCKS_TOPIC: &str = "blockchain-blocks";
 const TRANSACTIONS_TOPIC: &str = "blockchain-transactions";
+/// Prefix of the protocol version advertised over identify.
+const IDENTIFY_PREFIX: &str = "/blockchain/";
+
+/// Gossip topic names for `protocol_version`, blocks first. They carry the
+/// major version, so incompatible nodes never share a topic.
+pub fn gossip_topics(protocol_version: &str) -> [String; 2] {
+    let major = major_version(protocol_version);
+    [
+        format!("{}/v{}", BLOCKS_TOPIC, major),
+        format!("{}/v{}", TRANSACTIONS_TOPIC, major),
+    ]
+}
+
+fn major_version(version: &str) -> &str {
+    let version = version.strip_prefix(IDENTIFY_PREFIX).unwrap_or(version);
+    version.split('.').next().unwrap_or(version)
+}
+
+/// Whether a peer advertising `theirs` over identify speaks our protocol.
+fn is_compatible(ours: &str, theirs: &str) -> bool {
+    theirs.starts_with(IDENTIFY_PREFIX) && major_version(theirs) == major_version(ours)
+}
+const SYNC_PROTOCOL: &str = "/blockchain/sync/1.0.0";
 
 pub struct NetworkNode {
     command_rx: mpsc::Receiver<NetworkCommand>,
     event_tx: mpsc::Sender<NetworkEvent>,
     listen_port: u16,
+    /// Node identity; random per run unless loaded with `with_keypair_path`.
+    keypair: Keypair,
+    /// Gossip messages larger than this are neither sent nor parsed.
+    max_message_size: usize,
+    /// Advertised over identify; its major version picks the gossip topics.
+    protocol_version: String,
+    /// Protocol version each identified peer advertised.
+    peer_versions: HashMap<PeerId, String>,
+    /// Inbound sync requests awaiting `SendSyncResponse`, by request id.
+    pending_sync: HashMap<u64, ResponseChannel<SyncResponse>>,
+    next_sync_id: u64,
+    /// Sync responses received so far; numbers their `ChainResponse`.
+    sync_responses: u64,
+    /// Highest block index each peer has gossiped, for picking whom to
+    /// sync from.
+    peer_heights: HashMap<PeerId, u64>,
 }
 
 impl NetworkNode {
@@ -33,52 +85,47 @@ impl NetworkNode {
             command_rx,
             event_tx,
             listen_port,
+            keypair: Keypair::generate_ed25519(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            protocol_version: PROTOCOL_VERSION.to_string(),
+            peer_versions: HashMap::new(),
+            pending_sync: HashMap::new(),
+            next_sync_id: 0,
+            sync_responses: 0,
+            peer_heights: HashMap::new(),
         }
     }
 
-    pub async fn run(mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let local_key = Keypair::generate_ed25519();
-
-        let mut swarm = SwarmBuilder::with_existing_identity(local_key)
-            .with_tokio()
-            .with_tcp(
-                tcp::Config::default(),
-                noise::Config::new,
-                yamux::Config::default,
-            )?
-            .with_behaviour(|key| {
-                let message_id_fn = |message: &gossipsub::Message| {
-                    let mut s = DefaultHasher::new();
-                    message.data.hash(&mut s);
-                    gossipsub::MessageId::from(s.finish().to_string())
-                };
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
 
-                let gossipsub_config = gossipsub::ConfigBuilder::default()
-                    .heartbeat_interval(Duration::from_secs(10))
-                    .validation_mode(gossipsub::ValidationMode::Strict)
-                    .message_id_fn(message_id_fn)
-                    .build()
-                    .expect("Valid gossipsub config");
+    /// Overrides `PROTOCOL_VERSION`, e.g. to run a node that must not
+    /// gossip with the current network.
+    pub fn with_protocol_version(mut self, protocol_version: impl Into<String>) -> Self {
+        self.protocol_version = protocol_version.into();
+        self
+    }
 
-                let gossipsub = gossipsub::Behaviour::new(
-                    MessageAuthenticity::Signed(key.clone()),
-                    gossipsub_config,
-                )
-                .expect("Valid gossipsub behaviour");
+    /// Uses the keypair stored at `path`, creating it on first run, so
+    /// the PeerId is stable across restarts.
+    pub fn with_keypair_path(mut self, path: &Path) -> NetworkResult<Self> {
+        self.keypair = load_or_generate_keypair(path)?;
+        Ok(self)
+    }
 
-                let mdns = mdns::tokio::Behaviour::new(
-                    mdns::Config::default(),
-                    key.public().to_peer_id(),
-                )
-                .expect("Valid mDNS behaviour");
+    pub fn peer_id(&self) -> PeerId {
+        self.keypair.public().to_peer_id()
+    }
 
-                BlockchainBehaviour { gossipsub, mdns }
-            })?
-            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
-            .build();
+    pub async fn run(mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        tracing::info!("Local peer id: {}", self.peer_id());
+        let mut swarm =
+            build_swarm(self.keypair.clone(), self.max_message_size, &self.protocol_version)?;
 
-        let blocks_topic = IdentTopic::new(BLOCKS_TOPIC);
-        let transactions_topic = IdentTopic::new(TRANSACTIONS_TOPIC);
+        let [blocks_topic, transactions_topic] =
+            gossip_topics(&self.protocol_version).map(IdentTopic::new);
 
         swarm.behaviour_mut().gossipsub.subscribe(&blocks_topic)?;
         swarm
@@ -91,10 +138,24 @@ impl NetworkNode {
 
         tracing::info!("Network node listening on port {}", self.listen_port);
 
+        // Millisecond start keeps sequence numbers increasing across restarts
+        let first_seq = std::time::UNIX_EPOCH.elapsed().map_or(0, |t| t.as_millis() as u64);
+        let mut sequencer = Sequencer::new(self.peer_id().to_string(), first_seq);
+
         loop {
             tokio::select! {
                 Some(cmd) = self.command_rx.recv() => {
-                    self.handle_command(&mut swarm, &blocks_topic, &transactions_topic, cmd);
+                    if matches!(cmd, NetworkCommand::Shutdown) {
+                        tracing::info!("Network node shutting down");
+                        return Ok(());
+                    }
+                    self.handle_command(
+                        &mut swarm,
+                        &blocks_topic,
+                        &transactions_topic,
+                        &mut sequencer,
+                        cmd,
+                    );
                 }
                 event = swarm.select_next_some() => {
                     self.handle_swarm_event(&mut swarm, event).await;
@@ -104,114 +165,262 @@ impl NetworkNode {
     }
 
     fn handle_command(
-        &self,
+        &mut self,
         swarm: &mut libp2p::Swarm<BlockchainBehaviour>,
         blocks_topic: &IdentTopic,
         transactions_topic: &IdentTopic,
+        sequencer: &mut Sequencer,
         cmd: NetworkCommand,
     ) {
-        match cmd {
-            NetworkCommand::BroadcastTransaction(tx) => {
-                if let Ok(data) = serde_json::to_vec(&NetworkMessage::NewTransaction(tx)) {
-                    if let Err(e) = swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .publish(transactions_topic.clone(), data)
-                    {
-                        tracing::warn!("Failed to publish transaction: {}", e);
-                    }
-                }
+        let (topic, what) = match &cmd {
+            NetworkCommand::BroadcastTransaction(_) => (transactions_topic, "transaction"),
+            NetworkCommand::BroadcastBlock(_) => (blocks_topic, "block"),
+            NetworkCommand::RequestChain => {
+                self.request_chain(swarm);
+                return;
             }
-            NetworkCommand::BroadcastBlock(block) => {
-                if let Ok(data) = serde_json::to_vec(&NetworkMessage::NewBlock(block)) {
-                    if let Err(e) = swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .publish(blocks_topic.clone(), data)
-                    {
-                        tracing::warn!("Failed to publish block: {}", e);
-                    }
-                }
+            NetworkCommand::SendSyncResponse { request_id, blocks } => {
+                self.send_sync_response(swarm, *request_id, blocks.clone());
+                return;
             }
-            NetworkCommand::RequestChain => {
-                if let Ok(data) = serde_json::to_vec(&NetworkMessage::ChainRequest) {
-                    if let Err(e) = swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .publish(blocks_topic.clone(), data)
-                    {
-                        tracing::warn!("Failed to request chain: {}", e);
-                    }
+            NetworkCommand::RequestBlocks { .. } => (blocks_topic, "blocks request"),
+            NetworkCommand::SendBlocks(_) => (blocks_topic, "blocks"),
+            NetworkCommand::SendChain(_) => (blocks_topic, "chain"),
+            NetworkCommand::Shutdown => (blocks_topic, "shutdown"),
+        };
+        let Some(message) = cmd.into_message(sequencer) else { return };
+        if let Ok(data) = serde_json::to_vec(&message) {
+            if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+                tracing::warn!("Failed to publish {}: {}", what, e);
+            }
+        }
+    }
+
+    /// Sends a whole-chain `SyncRequest` to the connected peer with the
+    /// highest gossiped height, or any connected peer if none has gossiped.
+    /// Peers known to speak an incompatible protocol are skipped.
+    fn request_chain(&self, swarm: &mut Swarm<BlockchainBehaviour>) {
+        let Some(peer) = swarm
+            .connected_peers()
+            .filter(|peer| {
+                self.peer_versions
+                    .get(peer)
+                    .is_none_or(|version| is_compatible(&self.protocol_version, version))
+            })
+            .max_by_key(|peer| self.peer_heights.get(peer).copied())
+            .copied()
+        else {
+            tracing::debug!("No connected peer to sync from");
+            return;
+        };
+        let request = SyncRequest {
+            from_index: 0,
+            to_index: u64::MAX,
+        };
+        tracing::debug!(peer_id = %peer, "Requesting chain");
+        swarm.behaviour_mut().sync.send_request(&peer, request);
+    }
+
+    fn send_sync_response(
+        &mut self,
+        swarm: &mut Swarm<BlockchainBehaviour>,
+        request_id: u64,
+        blocks: Vec<Block>,
+    ) {
+        // Requests whose peer has gone away are not worth keeping
+        self.pending_sync.retain(|_, channel| channel.is_open());
+        let Some(channel) = self.pending_sync.remove(&request_id) else {
+            tracing::debug!(request_id, "Sync request no longer pending");
+            return;
+        };
+        let response = SyncResponse { blocks };
+        if swarm
+            .behaviour_mut()
+            .sync
+            .send_response(channel, response)
+            .is_err()
+        {
+            tracing::warn!(request_id, "Failed to send sync response");
+        }
+    }
+
+    async fn handle_sync_event(
+        &mut self,
+        event: request_response::Event<SyncRequest, SyncResponse>,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                let request_id = self.next_sync_id;
+                self.next_sync_id += 1;
+                self.pending_sync.insert(request_id, channel);
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::SyncRequest {
+                        request_id,
+                        peer: peer.to_string(),
+                        from_index: request.from_index,
+                        to_index: request.to_index,
+                    })
+                    .await;
+            }
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { response, .. },
+            } => {
+                let blocks = response.blocks;
+                if blocks.len() > MAX_CHAIN_RESPONSE_BLOCKS {
+                    tracing::warn!(peer_id = %peer, "Dropped oversized sync response");
+                    return;
                 }
+                let event = if blocks.first().is_some_and(|b| b.header.index == 0) {
+                    self.sync_responses += 1;
+                    NetworkEvent::ChainResponse {
+                        peer: peer.to_string(),
+                        origin: MessageOrigin {
+                            peer: peer.to_string(),
+                            seq: self.sync_responses,
+                        },
+                        chain: blocks,
+                    }
+                } else {
+                    NetworkEvent::Blocks(blocks)
+                };
+                let _ = self.event_tx.send(event).await;
+            }
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                tracing::warn!(peer_id = %peer, "Sync request failed: {}", error);
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                tracing::warn!(peer_id = %peer, "Sync response failed: {}", error);
             }
+            request_response::Event::ResponseSent { .. } => {}
         }
     }
 
     async fn handle_swarm_event(
-        &self,
+        &mut self,
         swarm: &mut libp2p::Swarm<BlockchainBehaviour>,
         event: SwarmEvent<BlockchainBehaviourEvent>,
     ) {
         match event {
             SwarmEvent::Behaviour(BlockchainBehaviourEvent::Gossipsub(
                 gossipsub::Event::Message {
-                    message, ..
+                    propagation_source,
+                    message,
+                    ..
                 },
             )) => {
-                if let Ok(net_msg) = serde_json::from_slice::<NetworkMessage>(&message.data) {
-                    let event = match net_msg {
-                        NetworkMessage::NewTransaction(tx) => {
-                            Some(NetworkEvent::NewTransaction(tx))
-                        }
-                        NetworkMessage::NewBlock(block) => {
-                            Some(NetworkEvent::NewBlock(block))
-                        }
-                        NetworkMessage::ChainRequest => {
-                            let peer = message
-                                .source
-                                .map(|p| p.to_string())
-                                .unwrap_or_default();
-                            Some(NetworkEvent::ChainRequest { peer })
-                        }
-                        NetworkMessage::ChainResponse(chain) => {
-                            Some(NetworkEvent::ChainResponse(chain))
-                        }
-                    };
-                    if let Some(ev) = event {
-                        let _ = self.event_tx.send(ev).await;
+                if let Some(net_msg) = decode_message(&message.data, self.max_message_size) {
+                    if let (NetworkMessage::NewBlock(block), Some(peer)) =
+                        (&net_msg, message.source)
+                    {
+                        let height = self.peer_heights.entry(peer).or_default();
+                        *height = (*height).max(block.header.index);
+                        let _ = self
+                            .event_tx
+                            .send(NetworkEvent::PeerHeight {
+                                peer: peer.to_string(),
+                                height: block.header.index,
+                            })
+                            .await;
                     }
+                    let source = message.source.map(|p| p.to_string());
+                    let _ = self
+                        .event_tx
+                        .send(NetworkEvent::from_message(
+                            net_msg,
+                            source,
+                            propagation_source.to_string(),
+                        ))
+                        .await;
                 }
             }
+            SwarmEvent::Behaviour(BlockchainBehaviourEvent::Sync(event)) => {
+                self.handle_sync_event(event).await;
+            }
+            // Discovered peers become explicit gossip peers only once
+            // identify shows they speak our protocol version
             SwarmEvent::Behaviour(BlockchainBehaviourEvent::Mdns(
                 mdns::Event::Discovered(peers),
             )) => {
-                for (peer_id, _addr) in peers {
-                    tracing::info!("mDNS discovered peer: {}", peer_id);
-                    swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .add_explicit_peer(&peer_id);
-                    let _ = self
-                        .event_tx
-                        .send(NetworkEvent::PeerConnected(peer_id.to_string()))
-                        .await;
+                for (peer_id, addr) in peers {
+                    tracing::info!(%peer_id, "mDNS discovered peer");
+                    if !swarm.is_connected(&peer_id) {
+                        if let Err(e) = swarm.dial(addr) {
+                            tracing::debug!(%peer_id, "Failed to dial discovered peer: {}", e);
+                        }
+                    }
                 }
             }
             SwarmEvent::Behaviour(BlockchainBehaviourEvent::Mdns(
                 mdns::Event::Expired(peers),
             )) => {
                 for (peer_id, _addr) in peers {
-                    tracing::info!("mDNS peer expired: {}", peer_id);
+                    tracing::info!(%peer_id, "mDNS peer expired");
                     swarm
                         .behaviour_mut()
                         .gossipsub
                         .remove_explicit_peer(&peer_id);
-                    let _ = self
-                        .event_tx
-                        .send(NetworkEvent::PeerDisconnected(peer_id.to_string()))
-                        .await;
                 }
             }
+            // Peer membership follows live connections rather than mDNS,
+            // which reports each address separately
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                num_established,
+                ..
+            } if num_established.get() == 1 => {
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::PeerConnected(peer_id.to_string()))
+                    .await;
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                self.peer_heights.remove(&peer_id);
+                self.peer_versions.remove(&peer_id);
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::PeerDisconnected(peer_id.to_string()))
+                    .await;
+            }
+            SwarmEvent::Behaviour(BlockchainBehaviourEvent::Identify(
+                identify::Event::Received { peer_id, info, .. },
+            )) => {
+                tracing::info!(
+                    %peer_id,
+                    agent_version = %info.agent_version,
+                    protocol_version = %info.protocol_version,
+                    "Identified peer"
+                );
+                self.peer_versions.insert(peer_id, info.protocol_version.clone());
+                if is_compatible(&self.protocol_version, &info.protocol_version) {
+                    swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                } else {
+                    tracing::warn!(
+                        %peer_id,
+                        theirs = %info.protocol_version,
+                        ours = %self.protocol_version,
+                        "Peer speaks an incompatible protocol version; not gossiping with it"
+                    );
+                    swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
+                }
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::PeerIdentified(peers::from_identify(
+                        &peer_id, &info,
+                    )))
+                    .await;
+            }
             SwarmEvent::NewListenAddr { address, .. } => {
                 tracing::info!("Listening on {}", address);
             }
@@ -219,3 +428,246 @@ impl NetworkNode {
         }
     }
 }
+
+/// Swarm with every protocol the node speaks: gossipsub for blocks and
+/// transactions, mDNS discovery, identify, and request-response sync.
+pub(crate) fn build_swarm(
+    keypair: Keypair,
+    max_message_size: usize,
+    protocol_version: &str,
+) -> Result<Swarm<BlockchainBehaviour>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(SwarmBuilder::with_existing_identity(keypair)
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )?
+        .with_behaviour(|key| {
+            let message_id_fn = |message: &gossipsub::Message| {
+                let mut s = DefaultHasher::new();
+                message.data.hash(&mut s);
+                gossipsub::MessageId::from(s.finish().to_string())
+            };
+
+            let gossipsub_config = gossipsub::ConfigBuilder::default()
+                .heartbeat_interval(Duration::from_secs(10))
+                .validation_mode(gossipsub::ValidationMode::Strict)
+                .message_id_fn(message_id_fn)
+                .max_transmit_size(max_message_size)
+                .build()
+                .expect("Valid gossipsub config");
+
+            let gossipsub = gossipsub::Behaviour::new(
+                MessageAuthenticity::Signed(key.clone()),
+                gossipsub_config,
+            )
+            .expect("Valid gossipsub behaviour");
+
+            let mdns =
+                mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().to_peer_id())
+                    .expect("Valid mDNS behaviour");
+
+            let identify = identify::Behaviour::new(
+                identify::Config::new(
+                    format!("{}{}", IDENTIFY_PREFIX, protocol_version),
+                    key.public(),
+                )
+                .with_agent_version(format!("blockchain-node/{}", env!("CARGO_PKG_VERSION"))),
+            );
+
+            let sync = request_response::json::Behaviour::new(
+                [(StreamProtocol::new(SYNC_PROTOCOL), ProtocolSupport::Full)],
+                request_response::Config::default(),
+            );
+
+            BlockchainBehaviour {
+                gossipsub,
+                mdns,
+                identify,
+                sync,
+            }
+        })?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::sync_blocks;
+    use blockchain_core::chain::Blockchain;
+
+    struct TestPeer {
+        node: NetworkNode,
+        swarm: Swarm<BlockchainBehaviour>,
+        events: mpsc::Receiver<NetworkEvent>,
+        sequencer: Sequencer,
+        topics: [IdentTopic; 2],
+    }
+
+    impl TestPeer {
+        fn new() -> Self {
+            Self::with_version(PROTOCOL_VERSION)
+        }
+
+        fn with_version(protocol_version: &str) -> Self {
+            let (_, command_rx) = mpsc::channel(1);
+            let (event_tx, events) = mpsc::channel(64);
+            let node =
+                NetworkNode::new(command_rx, event_tx, 0).with_protocol_version(protocol_version);
+            let mut swarm =
+                build_swarm(node.keypair.clone(), node.max_message_size, protocol_version).unwrap();
+            let topics = gossip_topics(protocol_version).map(IdentTopic::new);
+            for topic in &topics {
+                swarm.behaviour_mut().gossipsub.subscribe(topic).unwrap();
+            }
+            let sequencer = Sequencer::new(node.peer_id().to_string(), 0);
+            Self {
+                node,
+                swarm,
+                events,
+                sequencer,
+                topics,
+            }
+        }
+
+        async fn listen(&mut self) -> Multiaddr {
+            self.swarm
+                .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .unwrap();
+            loop {
+                if let SwarmEvent::NewListenAddr { address, .. } =
+                    self.swarm.select_next_some().await
+                {
+                    return address;
+                }
+            }
+        }
+
+        fn command(&mut self, cmd: NetworkCommand) {
+            let [blocks, transactions] = &self.topics;
+            self.node
+                .handle_command(&mut self.swarm, blocks, transactions, &mut self.sequencer, cmd);
+        }
+    }
+
+    #[test]
+    fn test_topics_carry_major_version() {
+        assert_eq!(
+            gossip_topics("1.4.2"),
+            ["blockchain-blocks/v1".to_string(), "blockchain-transactions/v1".to_string()]
+        );
+        assert!(is_compatible("1.0.0", "/blockchain/1.3.0"));
+        assert!(!is_compatible("1.0.0", "/blockchain/2.0.0"));
+        assert!(!is_compatible("1.0.0", "/ipfs/0.1.0"));
+    }
+
+    #[tokio::test]
+    async fn test_gossip_only_reaches_same_version_peers() {
+        let mut hub = TestPeer::new();
+        let mut same = TestPeer::new();
+        let mut other = TestPeer::with_version("2.0.0");
+        let address = hub.listen().await;
+        same.swarm.dial(address.clone()).unwrap();
+        other.swarm.dial(address).unwrap();
+        let other_id = other.node.peer_id();
+        let block = Blockchain::new(1, 50).latest_block().clone();
+
+        let mut publish = tokio::time::interval(Duration::from_millis(200));
+        let exchange = async {
+            let mut delivered = false;
+            loop {
+                tokio::select! {
+                    event = hub.swarm.select_next_some() => {
+                        hub.node.handle_swarm_event(&mut hub.swarm, event).await;
+                    }
+                    event = same.swarm.select_next_some() => {
+                        same.node.handle_swarm_event(&mut same.swarm, event).await;
+                    }
+                    event = other.swarm.select_next_some() => {
+                        other.node.handle_swarm_event(&mut other.swarm, event).await;
+                    }
+                    _ = publish.tick() => {
+                        hub.command(NetworkCommand::BroadcastBlock(block.clone()));
+                    }
+                }
+                while let Ok(event) = other.events.try_recv() {
+                    assert!(!matches!(event, NetworkEvent::NewBlock(_)), "crossed versions");
+                }
+                while let Ok(event) = same.events.try_recv() {
+                    delivered |= matches!(event, NetworkEvent::NewBlock(_));
+                }
+                if delivered && hub.node.peer_versions.contains_key(&other_id) {
+                    return;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), exchange)
+            .await
+            .expect("gossip between same-version peers timed out");
+
+        assert_eq!(hub.node.peer_versions[&other_id], "/blockchain/2.0.0");
+        assert!(other.events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chain_sync_over_request_response() {
+        let mut chain = Blockchain::new(1, 50);
+        for _ in 0..3 {
+            chain.mine_pending("miner").unwrap();
+        }
+        let mut behind = TestPeer::new();
+        let mut ahead = TestPeer::new();
+
+        let address = ahead.listen().await;
+        behind.swarm.dial(address).unwrap();
+
+        let round_trip = async {
+            loop {
+                tokio::select! {
+                    event = behind.swarm.select_next_some() => {
+                        behind.node.handle_swarm_event(&mut behind.swarm, event).await;
+                    }
+                    event = ahead.swarm.select_next_some() => {
+                        ahead.node.handle_swarm_event(&mut ahead.swarm, event).await;
+                    }
+                }
+                while let Ok(event) = ahead.events.try_recv() {
+                    if let NetworkEvent::SyncRequest {
+                        request_id,
+                        from_index,
+                        to_index,
+                        ..
+                    } = event
+                    {
+                        let request = SyncRequest {
+                            from_index,
+                            to_index,
+                        };
+                        let blocks = sync_blocks(chain.chain(), &request);
+                        ahead.command(NetworkCommand::SendSyncResponse { request_id, blocks });
+                    }
+                }
+                while let Ok(event) = behind.events.try_recv() {
+                    match event {
+                        NetworkEvent::PeerConnected(_) => {
+                            behind.command(NetworkCommand::RequestChain)
+                        }
+                        NetworkEvent::ChainResponse { origin, chain, .. } => {
+                            return (origin, chain)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        };
+        let (origin, synced) = tokio::time::timeout(Duration::from_secs(10), round_trip)
+            .await
+            .expect("chain sync timed out");
+
+        assert_eq!(origin.peer, ahead.node.peer_id().to_string());
+        assert_eq!(synced.len(), 4);
+        assert_eq!(synced.last().unwrap().hash, chain.latest_block().hash);
+    }
+}
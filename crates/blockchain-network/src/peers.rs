@@ -0,0 +1,46 @@
+use libp2p::identify;
+use libp2p::PeerId;
+
+pub use blockchain_core::peer::PeerInfo;
+
+/// The `PeerInfo` for what `peer_id` advertised over identify.
+pub fn from_identify(peer_id: &PeerId, info: &identify::Info) -> PeerInfo {
+    PeerInfo {
+        peer_id: peer_id.to_string(),
+        agent_version: info.agent_version.clone(),
+        protocol_version: info.protocol_version.clone(),
+        protocols: info.protocols.iter().map(|p| p.to_string()).collect(),
+        height: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+    use libp2p::StreamProtocol;
+
+    fn sample_info(key: &Keypair) -> identify::Info {
+        identify::Info {
+            public_key: key.public(),
+            protocol_version: "/blockchain/1.0.0".into(),
+            agent_version: "blockchain-node/0.1.0".into(),
+            listen_addrs: vec![],
+            protocols: vec![StreamProtocol::new("/meshsub/1.1.0")],
+            observed_addr: "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_peer_info_from_identify() {
+        let key = Keypair::generate_ed25519();
+        let peer_id = key.public().to_peer_id();
+        let info = from_identify(&peer_id, &sample_info(&key));
+
+        assert_eq!(info.peer_id, peer_id.to_string());
+        assert_eq!(info.agent_version, "blockchain-node/0.1.0");
+        assert_eq!(info.protocol_version, "/blockchain/1.0.0");
+        assert_eq!(info.protocols, vec!["/meshsub/1.1.0".to_string()]);
+        assert_eq!(info.height, None);
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+
+use blockchain_api::state::{AppState, NetworkCommand};
+use blockchain_core::block::Block;
+
+/// Mines the mempool every `interval`, crediting the node's miner address.
+/// Empty mempools are skipped unless `mine_empty` is set.
+pub async fn run(state: AppState, interval: Duration, mine_empty: bool) {
+    let mut ticker = tokio::time::interval(interval);
+    // A slow proof of work should not be followed by a burst of blocks
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        mine_once(&state, mine_empty).await;
+    }
+}
+
+/// One auto-mine round: mines, persists, publishes and broadcasts a block,
+/// returning it, or `None` if nothing was mined.
+pub async fn mine_once(state: &AppState, mine_empty: bool) -> Option<Block> {
+    let block = {
+        let mut bc = state.blockchain.lock().await;
+        if bc.pending_transactions().is_empty() && !mine_empty {
+            return None;
+        }
+        match bc.mine_pending(&state.miner_address) {
+            Ok(block) => {
+                state.persist(&bc);
+                block
+            }
+            Err(e) => {
+                tracing::warn!("Auto-mine failed: {}", e);
+                return None;
+            }
+        }
+    };
+    tracing::info!(
+        block_index = block.header.index,
+        transactions = block.transactions.len(),
+        "Auto-mined block"
+    );
+    state.publish_block(&block);
+    if let Some(tx) = &state.network_tx {
+        let _ = tx.send(NetworkCommand::BroadcastBlock(block.clone())).await;
+    }
+    Some(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain_core::chain::Blockchain;
+    use blockchain_core::transaction::{Transaction, DEFAULT_CHAIN_ID};
+    use blockchain_core::wallet::Wallet;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_auto_mine_seals_pending_transactions() {
+        let wallet = Wallet::new();
+        let mut bc = Blockchain::new(1, 50);
+        bc.mine_pending(&wallet.address).unwrap();
+        let mut tx = Transaction::new_transfer(wallet.address.clone(), Wallet::new().address, 10);
+        tx.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        bc.add_transaction(tx.clone()).unwrap();
+
+        let (network_tx, mut network_rx) = mpsc::channel(4);
+        let state = AppState::new(bc).with_network(network_tx);
+        let task = tokio::spawn(run(state.clone(), Duration::from_millis(10), false));
+
+        let broadcast = tokio::time::timeout(Duration::from_secs(5), network_rx.recv())
+            .await
+            .unwrap();
+        task.abort();
+        let Some(NetworkCommand::BroadcastBlock(block)) = broadcast else {
+            panic!("expected a block broadcast, got {:?}", broadcast);
+        };
+        assert!(block.transactions.iter().any(|t| t.id == tx.id));
+
+        {
+            let bc = state.blockchain.lock().await;
+            assert!(bc.pending_transactions().is_empty());
+            assert_eq!(bc.height(), 3);
+        }
+        // Nothing left to mine, so only the override seals a block
+        assert!(mine_once(&state, false).await.is_none());
+        assert!(mine_once(&state, true).await.is_some());
+        assert_eq!(state.blockchain.lock().await.height(), 4);
+    }
+}
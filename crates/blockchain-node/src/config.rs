@@ -1,22 +1,587 @@
-use clap::Parser;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use clap::builder::RangedU64ValueParser;
+use clap::parser::ValueSource;
+use clap::{
+    ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
+use serde::Deserialize;
+
+use blockchain_core::block::MAX_DIFFICULTY;
+use blockchain_core::chain::{
+    ConsensusMode, ForkTieBreaker, DEFAULT_MAX_BLOCK_TRANSACTIONS, DEFAULT_MAX_MEMPOOL,
+    DEFAULT_TIMESTAMP_TOLERANCE_SECS,
+};
+use blockchain_core::transaction::DEFAULT_CHAIN_ID;
+use blockchain_core::wallet::Wallet;
+use blockchain_network::handler::DEFAULT_MAX_MESSAGE_SIZE;
+
+use crate::wallet::WalletCommand;
+
+/// Smallest accepted `max_message_size`; below this even a single block
+/// may not fit.
+const MIN_MESSAGE_SIZE: usize = 1024;
 
 #[derive(Parser, Debug)]
 #[command(name = "blockchain-node")]
 #[command(about = "A Rust blockchain node")]
 pub struct Config {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// TOML file with settings to use instead of the defaults; flags and
+    /// environment variables still take precedence
+    #[arg(long = "config", env = "CONFIG_FILE")]
+    pub config_file: Option<PathBuf>,
+
+    /// Log output: `text` for people, `json` (one object per line) for
+    /// log pipelines
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
     /// Port for the REST API
     #[arg(long, env = "API_PORT", default_value_t = 8080)]
     pub api_port: u16,
 
+    /// Serve mutating/admin routes on this port; API_PORT then only
+    /// serves read-only routes
+    #[arg(long, env = "INTERNAL_API_PORT")]
+    pub internal_api_port: Option<u16>,
+
+    /// Address the internal API binds to; loopback unless set
+    #[arg(long, env = "INTERNAL_API_HOST", default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    pub internal_api_host: IpAddr,
+
+    /// Bearer token required on every mutating route; unset leaves them open
+    #[arg(long, env = "API_TOKEN", hide_env_values = true)]
+    pub api_token: Option<String>,
+
+    /// Let `POST /api/chain/import` replace the chain with a snapshot
+    #[arg(long, env = "ALLOW_CHAIN_IMPORT", default_value_t = false, action = ArgAction::Set)]
+    pub allow_chain_import: bool,
+
+    /// Requests per second each client IP may make to the API; 0 disables
+    /// the limit
+    #[arg(long, env = "RATE_LIMIT", default_value_t = 50)]
+    pub rate_limit: u32,
+
+    /// Requests a client may make in a burst before `rate_limit` applies
+    #[arg(
+        long,
+        env = "RATE_LIMIT_BURST",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub rate_limit_burst: u32,
+
+    /// Like `rate_limit`, but only for `/api/blocks/mine`
+    #[arg(long, env = "MINE_RATE_LIMIT", default_value_t = 1)]
+    pub mine_rate_limit: u32,
+
+    /// Burst size for `mine_rate_limit`
+    #[arg(
+        long,
+        env = "MINE_RATE_LIMIT_BURST",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub mine_rate_limit_burst: u32,
+
+    /// Browser origin allowed to call the API (repeatable, or
+    /// comma-separated in the env var); `*` allows any. None by default,
+    /// which sends no CORS headers
+    #[arg(long = "cors-origin", env = "CORS_ORIGINS", value_delimiter = ',')]
+    pub cors_origins: Vec<String>,
+
+    /// HTTP methods allowed for cross-origin requests
+    #[arg(
+        long = "cors-method",
+        env = "CORS_METHODS",
+        value_delimiter = ',',
+        default_values_t = ["GET".to_string(), "POST".to_string()]
+    )]
+    pub cors_methods: Vec<String>,
+
+    /// Request headers allowed for cross-origin requests
+    #[arg(
+        long = "cors-header",
+        env = "CORS_HEADERS",
+        value_delimiter = ',',
+        default_values_t = ["content-type".to_string(), "x-request-id".to_string()]
+    )]
+    pub cors_headers: Vec<String>,
+
     /// Port for P2P networking
     #[arg(long, env = "P2P_PORT", default_value_t = 0)]
     pub p2p_port: u16,
 
+    /// Largest gossip message in bytes; bigger ones are dropped unparsed
+    #[arg(
+        long,
+        env = "MAX_MESSAGE_SIZE",
+        default_value_t = DEFAULT_MAX_MESSAGE_SIZE,
+        value_parser = RangedU64ValueParser::<usize>::new().range(MIN_MESSAGE_SIZE as u64..)
+    )]
+    pub max_message_size: usize,
+
     /// Mining difficulty (number of leading zeros)
-    #[arg(long, env = "DIFFICULTY", default_value_t = 2)]
+    #[arg(
+        long,
+        env = "DIFFICULTY",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u32).range(1..=MAX_DIFFICULTY as i64)
+    )]
     pub difficulty: u32,
 
+    /// Target seconds between blocks; enables difficulty retargeting
+    #[arg(long, env = "TARGET_BLOCK_TIME", value_parser = clap::value_parser!(u64).range(1..))]
+    pub target_block_time: Option<u64>,
+
+    /// Mine pending transactions every N seconds without waiting for
+    /// `POST /api/blocks/mine`
+    #[arg(long, env = "AUTO_MINE_INTERVAL", value_parser = clap::value_parser!(u64).range(1..))]
+    pub auto_mine_interval: Option<u64>,
+
+    /// Let the auto-miner seal blocks even when the mempool is empty
+    #[arg(long, env = "AUTO_MINE_EMPTY", default_value_t = false, action = ArgAction::Set)]
+    pub auto_mine_empty: bool,
+
     /// Mining reward amount
     #[arg(long, env = "MINING_REWARD", default_value_t = 50)]
     pub mining_reward: u64,
+
+    /// Address credited with block rewards (`0x` + 40 hex characters)
+    #[arg(long, env = "MINER_ADDRESS", value_parser = parse_miner_address)]
+    pub miner_address: Option<String>,
+
+    /// Balance credited in the genesis block, as `ADDRESS=AMOUNT`
+    /// (repeatable, or comma-separated in the env var). Every node on a
+    /// network must use the same allocations
+    #[arg(
+        long = "genesis-alloc",
+        env = "GENESIS_ALLOCATIONS",
+        value_delimiter = ',',
+        value_parser = parse_allocation
+    )]
+    pub genesis_allocations: Vec<(String, u64)>,
+
+    /// Cap on total coin supply; block rewards stop once it is reached
+    #[arg(long, env = "MAX_SUPPLY")]
+    pub max_supply: Option<u64>,
+
+    /// Fee charged per bytecode byte when deploying a contract
+    #[arg(long, env = "DEPLOY_FEE_PER_BYTE", default_value_t = 1)]
+    pub deploy_fee_per_byte: u64,
+
+    /// How blocks are sealed: `pow` mines, `pos` forges by stake
+    #[arg(long, env = "CONSENSUS", value_enum, default_value = "pow")]
+    pub consensus: ConsensusArg,
+
+    /// BIP39 mnemonic of this node's proof-of-stake validator (wallet 0);
+    /// without it a `pos` node only follows the chain
+    #[arg(long, env = "VALIDATOR_MNEMONIC", hide_env_values = true)]
+    pub validator_mnemonic: Option<String>,
+
+    /// Hex-encoded private key to sign mined blocks with, attributing
+    /// them to this node
+    #[arg(long, env = "PRODUCER_KEY", hide_env_values = true)]
+    pub producer_key: Option<String>,
+
+    /// How to choose between two valid forks of equal length
+    #[arg(long, env = "FORK_TIE_BREAKER", value_enum, default_value = "lowest-hash")]
+    pub fork_tie_breaker: TieBreakerArg,
+
+    /// Network id transaction signatures are bound to; nodes on the same
+    /// network must agree on it
+    #[arg(long, env = "CHAIN_ID", default_value_t = DEFAULT_CHAIN_ID)]
+    pub chain_id: u64,
+
+    /// Verify transaction signatures (set to false only for load testing)
+    #[arg(long, env = "REQUIRE_SIGNATURES", default_value_t = true, action = ArgAction::Set)]
+    pub require_signatures: bool,
+
+    /// Max seconds a transaction timestamp may differ from the local clock
+    #[arg(long, env = "TX_TIMESTAMP_TOLERANCE", default_value_t = DEFAULT_TIMESTAMP_TOLERANCE_SECS)]
+    pub tx_timestamp_tolerance: u64,
+
+    /// Most pending transactions kept; lowest-fee ones are evicted first
+    #[arg(long, env = "MAX_MEMPOOL", default_value_t = DEFAULT_MAX_MEMPOOL)]
+    pub max_mempool: usize,
+
+    /// Most transactions per block (plus the reward), mined or accepted
+    #[arg(long, env = "MAX_BLOCK_TRANSACTIONS", default_value_t = DEFAULT_MAX_BLOCK_TRANSACTIONS)]
+    pub max_block_transactions: usize,
+
+    /// Threads searching for a proof-of-work nonce when mining
+    #[arg(long, env = "MINING_THREADS", default_value_t = 1)]
+    pub mining_threads: usize,
+
+    /// Directory for persisting the chain and mempool across restarts
+    #[arg(long, env = "DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Snapshot the chain to disk every N blocks; blocks in between are
+    /// kept in a write-ahead log
+    #[arg(
+        long,
+        env = "FLUSH_INTERVAL",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub flush_interval: u64,
+
+    /// File holding the libp2p identity keypair, created if missing.
+    /// Defaults to `p2p.key` in DATA_DIR; without either the PeerId
+    /// changes on every start
+    #[arg(long, env = "P2P_KEY_FILE")]
+    pub p2p_key_file: Option<PathBuf>,
+
+    /// JSON file of transactions to submit to the mempool on startup
+    #[arg(long, env = "PRELOAD_TRANSACTIONS")]
+    pub preload_transactions: Option<PathBuf>,
+}
+
+fn parse_miner_address(address: &str) -> Result<String, String> {
+    if Wallet::is_valid_address(address) {
+        Ok(address.to_string())
+    } else {
+        Err("expected 0x followed by 40 hex characters".into())
+    }
+}
+
+fn parse_allocation(allocation: &str) -> Result<(String, u64), String> {
+    let (address, amount) = allocation
+        .split_once('=')
+        .ok_or("expected ADDRESS=AMOUNT")?;
+    let address = parse_miner_address(address)?;
+    let amount = amount.parse().map_err(|e| format!("invalid amount: {}", e))?;
+    Ok((address, amount))
+}
+
+impl Config {
+    /// Parses flags and environment variables, exiting with usage on
+    /// error, then fills in anything left at its default from the
+    /// `--config` file.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        Self::from_matches(&Self::command().get_matches())
+    }
+
+    /// Like `load`, for an explicit argument list.
+    #[cfg(test)]
+    pub fn load_from<I, T>(args: I) -> Result<Self, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        Self::from_matches(&Self::command().try_get_matches_from(args)?)
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Result<Self, Box<dyn Error>> {
+        let mut config = Self::from_arg_matches(matches)?;
+        if let Some(path) = config.config_file.clone() {
+            config.merge(FileConfig::read(&path)?, matches);
+        }
+        Ok(config)
+    }
+
+    /// Takes each value set in `file` unless the flag or its environment
+    /// variable set it too.
+    fn merge(&mut self, file: FileConfig, matches: &ArgMatches) {
+        let unset = |id: &str| {
+            matches!(matches.value_source(id), None | Some(ValueSource::DefaultValue))
+        };
+        macro_rules! merge {
+            ($($field:ident),* ; $($optional:ident),*) => {
+                $(if let Some(value) = file.$field.filter(|_| unset(stringify!($field))) {
+                    self.$field = value;
+                })*
+                $(if let Some(value) = file.$optional.filter(|_| unset(stringify!($optional))) {
+                    self.$optional = Some(value);
+                })*
+            };
+        }
+        merge!(
+            log_format, api_port, internal_api_host, allow_chain_import, rate_limit,
+            rate_limit_burst, mine_rate_limit, mine_rate_limit_burst, cors_origins, cors_methods,
+            cors_headers, p2p_port,
+            max_message_size, difficulty, auto_mine_empty, mining_reward, deploy_fee_per_byte,
+            consensus, fork_tie_breaker, chain_id, require_signatures, tx_timestamp_tolerance,
+            max_mempool, max_block_transactions, mining_threads, flush_interval;
+            internal_api_port, api_token, target_block_time, auto_mine_interval, miner_address,
+            max_supply, validator_mnemonic, producer_key, data_dir, p2p_key_file,
+            preload_transactions
+        );
+        if let Some(allocations) = file.genesis_allocations {
+            if unset("genesis_allocations") {
+                self.genesis_allocations = allocations.into_iter().collect();
+            }
+        }
+    }
+
+    /// Genesis allocations keyed by address; a repeated address keeps the
+    /// last amount given.
+    pub fn genesis_allocations(&self) -> HashMap<String, u64> {
+        self.genesis_allocations.iter().cloned().collect()
+    }
+
+    /// Where the network identity is kept, if anywhere.
+    pub fn p2p_key_path(&self) -> Option<PathBuf> {
+        self.p2p_key_file
+            .clone()
+            .or_else(|| self.data_dir.as_ref().map(|dir| dir.join("p2p.key")))
+    }
+}
+
+/// Settings read from a `--config` TOML file. Keys are the `Config` field
+/// names; every key is optional and unknown keys are rejected.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub log_format: Option<LogFormat>,
+    pub api_port: Option<u16>,
+    pub internal_api_port: Option<u16>,
+    pub internal_api_host: Option<IpAddr>,
+    pub api_token: Option<String>,
+    pub allow_chain_import: Option<bool>,
+    pub rate_limit: Option<u32>,
+    pub rate_limit_burst: Option<u32>,
+    pub mine_rate_limit: Option<u32>,
+    pub mine_rate_limit_burst: Option<u32>,
+    pub cors_origins: Option<Vec<String>>,
+    pub cors_methods: Option<Vec<String>>,
+    pub cors_headers: Option<Vec<String>>,
+    pub p2p_port: Option<u16>,
+    pub max_message_size: Option<usize>,
+    pub difficulty: Option<u32>,
+    pub target_block_time: Option<u64>,
+    pub auto_mine_interval: Option<u64>,
+    pub auto_mine_empty: Option<bool>,
+    pub mining_reward: Option<u64>,
+    pub miner_address: Option<String>,
+    pub max_supply: Option<u64>,
+    /// `address = amount` table.
+    pub genesis_allocations: Option<HashMap<String, u64>>,
+    pub deploy_fee_per_byte: Option<u64>,
+    pub consensus: Option<ConsensusArg>,
+    pub validator_mnemonic: Option<String>,
+    pub producer_key: Option<String>,
+    pub fork_tie_breaker: Option<TieBreakerArg>,
+    pub chain_id: Option<u64>,
+    pub require_signatures: Option<bool>,
+    pub tx_timestamp_tolerance: Option<u64>,
+    pub max_mempool: Option<usize>,
+    pub max_block_transactions: Option<usize>,
+    pub mining_threads: Option<usize>,
+    pub data_dir: Option<PathBuf>,
+    pub flush_interval: Option<u64>,
+    pub p2p_key_file: Option<PathBuf>,
+    pub preload_transactions: Option<PathBuf>,
+}
+
+impl FileConfig {
+    /// Reads and validates a config file, applying the same limits as the
+    /// corresponding flags.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read config file {}: {}", path.display(), e))?;
+        let file: Self = toml::from_str(&data)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+        file.validate()
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+        Ok(file)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(difficulty) = self.difficulty {
+            if !(1..=MAX_DIFFICULTY).contains(&difficulty) {
+                return Err(format!("difficulty must be between 1 and {}", MAX_DIFFICULTY));
+            }
+        }
+        if self.target_block_time == Some(0) {
+            return Err("target_block_time must be at least 1".into());
+        }
+        if self.auto_mine_interval == Some(0) {
+            return Err("auto_mine_interval must be at least 1".into());
+        }
+        if self.rate_limit_burst == Some(0) || self.mine_rate_limit_burst == Some(0) {
+            return Err("rate limit bursts must be at least 1".into());
+        }
+        if self.max_message_size.is_some_and(|size| size < MIN_MESSAGE_SIZE) {
+            return Err(format!("max_message_size must be at least {}", MIN_MESSAGE_SIZE));
+        }
+        if self.flush_interval == Some(0) {
+            return Err("flush_interval must be at least 1".into());
+        }
+        if let Some(address) = &self.miner_address {
+            parse_miner_address(address).map_err(|e| format!("miner_address: {}", e))?;
+        }
+        for address in self.genesis_allocations.iter().flat_map(HashMap::keys) {
+            parse_miner_address(address)
+                .map_err(|e| format!("genesis_allocations: {}: {}", address, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Tools run instead of the node.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Create keys and sign transactions offline
+    #[command(subcommand)]
+    Wallet(WalletCommand),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// CLI spelling of `ConsensusMode`.
+#[derive(Debug, Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConsensusArg {
+    Pow,
+    Pos,
+}
+
+impl From<ConsensusArg> for ConsensusMode {
+    fn from(arg: ConsensusArg) -> Self {
+        match arg {
+            ConsensusArg::Pow => ConsensusMode::Pow,
+            ConsensusArg::Pos => ConsensusMode::Pos,
+        }
+    }
+}
+
+/// CLI spelling of `ForkTieBreaker`.
+#[derive(Debug, Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TieBreakerArg {
+    LowestHash,
+    KeepLocal,
+}
+
+impl From<TieBreakerArg> for ForkTieBreaker {
+    fn from(arg: TieBreakerArg) -> Self {
+        match arg {
+            TieBreakerArg::LowestHash => ForkTieBreaker::LowestHash,
+            TieBreakerArg::KeepLocal => ForkTieBreaker::KeepLocal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let nanos = std::time::UNIX_EPOCH.elapsed().unwrap().as_nanos();
+        let path = std::env::temp_dir().join(format!("{}-{}.toml", name, nanos));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_flags_override_config_file() {
+        let path = write_config(
+            "node-config",
+            r#"
+            api_port = 9000
+            internal_api_host = "0.0.0.0"
+            difficulty = 4
+            mining_reward = 75
+            fork_tie_breaker = "keep-local"
+            data_dir = "/var/lib/node"
+            "#,
+        );
+        let config = Config::load_from([
+            "blockchain-node".as_ref(),
+            "--config".as_ref(),
+            path.as_os_str(),
+            "--api-port".as_ref(),
+            "9100".as_ref(),
+        ]);
+        fs::remove_file(&path).ok();
+        let config = config.unwrap();
+
+        assert_eq!(config.api_port, 9100);
+        assert_eq!(config.internal_api_host, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(config.difficulty, 4);
+        assert_eq!(config.mining_reward, 75);
+        assert!(matches!(config.fork_tie_breaker, TieBreakerArg::KeepLocal));
+        assert_eq!(config.data_dir, Some(PathBuf::from("/var/lib/node")));
+        assert_eq!(config.flush_interval, 1);
+    }
+
+    #[test]
+    fn test_cors_origin_is_repeatable() {
+        let config = Config::load_from([
+            "blockchain-node",
+            "--cors-origin",
+            "https://a.example",
+            "--cors-origin",
+            "https://b.example",
+        ])
+        .unwrap();
+        assert_eq!(config.cors_origins, ["https://a.example", "https://b.example"]);
+        assert_eq!(config.cors_methods, ["GET", "POST"]);
+
+        let config = Config::load_from(["blockchain-node"]).unwrap();
+        assert!(config.cors_origins.is_empty());
+        assert_eq!(config.internal_api_host, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn test_genesis_allocations_from_flags_and_file() {
+        let alice = Wallet::new().address;
+        let bob = Wallet::new().address;
+        let config = Config::load_from([
+            "blockchain-node".to_string(),
+            "--genesis-alloc".into(),
+            format!("{}=100", alice),
+            "--genesis-alloc".into(),
+            format!("{}=7", bob),
+        ])
+        .unwrap();
+        assert_eq!(config.genesis_allocations(), [(alice.clone(), 100), (bob, 7)].into());
+
+        let path = write_config(
+            "genesis",
+            &format!("[genesis_allocations]\n\"{}\" = 5000\n", alice),
+        );
+        let config = Config::load_from([
+            "blockchain-node".as_ref(),
+            "--config".as_ref(),
+            path.as_os_str(),
+        ]);
+        fs::remove_file(&path).ok();
+        assert_eq!(config.unwrap().genesis_allocations(), [(alice, 5000)].into());
+
+        assert!(Config::load_from(["blockchain-node", "--genesis-alloc", "alice=5"]).is_err());
+    }
+
+    #[test]
+    fn test_malformed_config_file_rejected() {
+        for (name, contents) in [
+            ("malformed", "difficulty = "),
+            ("unknown-key", "difficulty = 3\nfavourite_colour = \"blue\""),
+            ("out-of-range", "difficulty = 0"),
+        ] {
+            let path = write_config(name, contents);
+            let result = Config::load_from([
+                "blockchain-node".as_ref(),
+                "--config".as_ref(),
+                path.as_os_str(),
+            ]);
+            fs::remove_file(&path).ok();
+            let err = result.unwrap_err().to_string();
+            assert!(err.starts_with("Invalid config file"), "{}: {}", name, err);
+        }
+    }
 }
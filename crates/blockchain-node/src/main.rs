@@ -1,31 +1,48 @@
+mod auto_mine;
 mod config;
+mod logging;
+mod preload;
+mod shutdown;
+mod wallet;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
 
-use clap::Parser;
 use tokio::sync::mpsc;
-use tracing_subscriber::EnvFilter;
 
-use blockchain_api::routes::create_router;
+use blockchain_api::middleware::{CorsConfig, RateLimiter};
+use blockchain_api::routes::{create_public_router, create_router};
 use blockchain_api::state::AppState;
 use blockchain_core::chain::Blockchain;
-use blockchain_network::handler::{NetworkCommand, NetworkEvent};
-use blockchain_network::node::NetworkNode;
+use blockchain_core::errors::CoreError;
+use blockchain_core::peer::PeerInfo;
+use blockchain_core::storage::{ChainStore, FileStorageBackend};
+use blockchain_core::wallet::Wallet;
+use blockchain_network::handler::{
+    blocks_in_range, sync_blocks, ChainResponseFilter, NetworkCommand, NetworkEvent,
+};
+use blockchain_network::messages::SyncRequest;
+use blockchain_network::node::{gossip_topics, NetworkNode, PROTOCOL_VERSION};
+use blockchain_vm::contract::ContractExecutor;
 
-use crate::config::Config;
+use crate::config::{Command, Config};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load .env file (ignore if missing)
     dotenvy::dotenv().ok();
 
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .init();
+    let config = Config::load()?;
+    if let Some(Command::Wallet(command)) = &config.command {
+        println!("{}", wallet::run(command)?);
+        return Ok(());
+    }
 
-    let config = Config::parse();
+    // Initialize logging
+    tracing::subscriber::set_global_default(logging::subscriber(
+        config.log_format,
+        std::io::stdout,
+    ))?;
 
     tracing::info!(
         "Starting blockchain node (difficulty={}, reward={})",
@@ -34,7 +51,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     // Create blockchain
-    let blockchain = Blockchain::new(config.difficulty, config.mining_reward);
+    let mut blockchain = Blockchain::new(config.difficulty, config.mining_reward)
+        .with_chain_id(config.chain_id)
+        .with_contract_runtime(Arc::new(ContractExecutor))
+        .with_consensus(config.consensus.into())
+        .with_tie_breaker(config.fork_tie_breaker.into())
+        .with_deploy_fee_per_byte(config.deploy_fee_per_byte)
+        .with_require_signatures(config.require_signatures)
+        .with_timestamp_tolerance_secs(config.tx_timestamp_tolerance)
+        .with_max_mempool(config.max_mempool)
+        .with_max_block_transactions(config.max_block_transactions)
+        .with_mining_threads(config.mining_threads);
+    if let Some(max_supply) = config.max_supply {
+        blockchain = blockchain.with_max_supply(max_supply);
+    }
+    if !config.genesis_allocations.is_empty() {
+        blockchain = blockchain.with_genesis_allocations(config.genesis_allocations());
+    }
+    if let Some(phrase) = &config.validator_mnemonic {
+        let validator = Wallet::from_mnemonic(phrase, 0)?;
+        tracing::info!("Forging as validator {}", validator.address);
+        blockchain = blockchain.with_validator_key(validator.signing_key().clone());
+    }
+    if let Some(key) = &config.producer_key {
+        let producer = Wallet::from_private_key_hex(key)?;
+        tracing::info!("Signing mined blocks as {}", producer.address);
+        blockchain = blockchain.with_producer_key(producer.signing_key().clone());
+    }
+    if let Some(secs) = config.target_block_time {
+        blockchain = blockchain.with_target_block_time(std::time::Duration::from_secs(secs));
+    }
+
+    let store = match &config.data_dir {
+        Some(dir) => {
+            let contracts = FileStorageBackend::open(dir.join("contracts"))?;
+            blockchain = blockchain.with_contract_storage(Arc::new(contracts));
+            let store = ChainStore::open(dir)?.with_flush_interval(config.flush_interval);
+            store.load_into(&mut blockchain)?;
+            Some(store)
+        }
+        None => None,
+    };
+
+    if let Some(path) = &config.preload_transactions {
+        preload::preload_transactions(&mut blockchain, path)?;
+    }
 
     // Create channels for network communication
     let (net_cmd_tx, net_cmd_rx) = mpsc::channel::<NetworkCommand>(256);
@@ -44,13 +105,50 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Map NetworkCommand from network crate to API crate's NetworkCommand
     let (api_cmd_tx, mut api_cmd_rx) =
         mpsc::channel::<blockchain_api::state::NetworkCommand>(256);
-    let app_state = AppState::new(blockchain).with_network(api_cmd_tx);
+    let mut network_node = NetworkNode::new(net_cmd_rx, net_event_tx, config.p2p_port)
+        .with_max_message_size(config.max_message_size);
+    if let Some(path) = config.p2p_key_path() {
+        network_node = network_node.with_keypair_path(&path)?;
+    }
+    let [blocks_topic, transactions_topic] = gossip_topics(PROTOCOL_VERSION);
+    let mut app_state = AppState::new(blockchain)
+        .with_network(api_cmd_tx)
+        .with_peer_id(network_node.peer_id().to_string())
+        .with_protocol(PROTOCOL_VERSION, &[&blocks_topic, &transactions_topic])
+        .with_chain_import(config.allow_chain_import);
+    if let Some(token) = &config.api_token {
+        app_state = app_state.with_api_token(token.clone());
+    }
+    if let Some(store) = store {
+        app_state = app_state.with_store(store);
+    }
+    if let Some(address) = &config.miner_address {
+        app_state = app_state.with_miner_address(address);
+    }
+    if config.rate_limit > 0 {
+        let limiter = RateLimiter::new(config.rate_limit, config.rate_limit_burst);
+        app_state = app_state.with_rate_limit(limiter);
+    }
+    if config.mine_rate_limit > 0 {
+        let limiter = RateLimiter::new(config.mine_rate_limit, config.mine_rate_limit_burst);
+        app_state = app_state.with_mining_rate_limit(limiter);
+    }
+    let cors = CorsConfig {
+        origins: config.cors_origins.clone(),
+        methods: config.cors_methods.clone(),
+        headers: config.cors_headers.clone(),
+    };
+    if let Some(cors) = cors.layer()? {
+        app_state = app_state.with_cors(cors);
+    }
+    let shutdown_state = app_state.clone();
+    let event_state = app_state.clone();
     let shared_blockchain = app_state.blockchain.clone();
-    let shared_peer_count = app_state.peer_count.clone();
+    let shared_peers = app_state.peers.clone();
 
     // Forward API commands to network commands
     let net_cmd_tx_clone = net_cmd_tx.clone();
-    tokio::spawn(async move {
+    let forward_handle = tokio::spawn(async move {
         while let Some(cmd) = api_cmd_rx.recv().await {
             let net_cmd = match cmd {
                 blockchain_api::state::NetworkCommand::BroadcastTransaction(tx) => {
@@ -68,46 +166,112 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     // Start network node
-    let network_node = NetworkNode::new(net_cmd_rx, net_event_tx, config.p2p_port);
-    tokio::spawn(async move {
+    let network_handle = tokio::spawn(async move {
         if let Err(e) = network_node.run().await {
             tracing::error!("Network node error: {}", e);
         }
     });
 
-    // Start API server
-    let router = create_router(app_state);
+    let mut tasks = vec![forward_handle];
+    // Seal pending transactions without waiting for POST /api/blocks/mine
+    if let Some(secs) = config.auto_mine_interval {
+        tracing::info!("Auto-mining every {}s", secs);
+        let interval = std::time::Duration::from_secs(secs);
+        tasks.push(tokio::spawn(auto_mine::run(
+            app_state.clone(),
+            interval,
+            config.auto_mine_empty,
+        )));
+    }
+
+    // Start API server(s); with an internal port the public one is read-only
+    let router = match config.internal_api_port {
+        Some(internal_port) => {
+            let internal_addr = SocketAddr::new(config.internal_api_host, internal_port);
+            tracing::info!("Internal API server starting on http://{}", internal_addr);
+            let listener = tokio::net::TcpListener::bind(&internal_addr).await?;
+            let internal_router = create_router(app_state.clone())
+                .into_make_service_with_connect_info::<SocketAddr>();
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, internal_router).await {
+                    tracing::error!("Internal API server error: {}", e);
+                }
+            }));
+            create_public_router(app_state)
+        }
+        None => create_router(app_state),
+    };
     let api_addr = format!("0.0.0.0:{}", config.api_port);
     tracing::info!("API server starting on http://{}", api_addr);
 
     let listener = tokio::net::TcpListener::bind(&api_addr).await?;
-    let api_handle = tokio::spawn(async move {
-        if let Err(e) = axum::serve(listener, router).await {
+    let mut api_handle = tokio::spawn(async move {
+        let service = router.into_make_service_with_connect_info::<SocketAddr>();
+        if let Err(e) = axum::serve(listener, service).await {
             tracing::error!("API server error: {}", e);
         }
     });
 
     // Main event loop: process network events
-    let event_loop = tokio::spawn(async move {
+    let shutdown_net_cmd_tx = net_cmd_tx.clone();
+    let mut event_loop = tokio::spawn(async move {
+        let mut chain_responses = ChainResponseFilter::default();
         while let Some(event) = net_event_rx.recv().await {
             match event {
                 NetworkEvent::NewTransaction(tx) => {
-                    tracing::info!("Received transaction from network: {}", tx.id);
+                    tracing::info!(tx_id = %tx.id, "Received transaction from network");
+                    let tx_id = tx.id.clone();
                     let mut bc = shared_blockchain.lock().await;
-                    if let Err(e) = bc.add_transaction(tx) {
-                        tracing::warn!("Failed to add network transaction: {}", e);
+                    match bc.add_transaction(tx) {
+                        Ok(()) => event_state.persist_mempool(&bc),
+                        Err(e) => tracing::warn!(
+                            tx_id = %tx_id,
+                            error = %e,
+                            "Failed to add network transaction"
+                        ),
                     }
                 }
                 NetworkEvent::NewBlock(block) => {
                     tracing::info!(
-                        "Received block from network: index={}",
-                        block.header.index
+                        block_index = block.header.index,
+                        "Received block from network"
                     );
-                    // For simplicity, request full chain sync
-                    let _ = net_cmd_tx.send(NetworkCommand::RequestChain).await;
+                    let mut bc = shared_blockchain.lock().await;
+                    let height = bc.height();
+                    if block.header.index > height {
+                        // We are behind; fetch the missing range first
+                        let _ = net_cmd_tx
+                            .send(NetworkCommand::RequestBlocks {
+                                from_index: height,
+                                to_index: block.header.index,
+                            })
+                            .await;
+                    } else if block.header.index == height {
+                        match bc.try_append_block(block.clone()) {
+                            Ok(()) => {
+                                event_state.persist(&bc);
+                                event_state.publish_block(&block);
+                            }
+                            Err(e @ CoreError::UnknownParent(_)) => {
+                                // Tip of a competing fork; let fork choice
+                                // settle it on the full chain
+                                tracing::info!(
+                                    block_index = block.header.index,
+                                    error = %e,
+                                    "Network block is on another fork"
+                                );
+                                let _ = net_cmd_tx.send(NetworkCommand::RequestChain).await;
+                            }
+                            Err(e) => tracing::warn!(
+                                block_index = block.header.index,
+                                error = %e,
+                                "Rejected invalid network block"
+                            ),
+                        }
+                    }
                 }
                 NetworkEvent::ChainRequest { peer } => {
-                    tracing::info!("Chain requested by peer: {}", peer);
+                    tracing::info!(peer_id = %peer, "Chain requested by peer");
                     let bc = shared_blockchain.lock().await;
                     let chain = bc.chain().to_vec();
                     let msg = NetworkCommand::BroadcastBlock(
@@ -115,39 +279,123 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     );
                     let _ = net_cmd_tx.send(msg).await;
                 }
-                NetworkEvent::ChainResponse(chain) => {
+                NetworkEvent::ChainResponse { peer, origin, chain } => {
                     tracing::info!(
-                        "Received chain response: {} blocks",
-                        chain.len()
+                        blocks = chain.len(),
+                        peer_id = %peer,
+                        origin = %origin.peer,
+                        seq = origin.seq,
+                        "Received chain response"
                     );
+                    if !chain_responses.accept(&peer, &origin) {
+                        continue;
+                    }
+                    // Rejections are logged and recorded for /api/sync/status
+                    if event_state.apply_chain_response(chain).await.is_ok() {
+                        let tip = shared_blockchain.lock().await.latest_block().clone();
+                        event_state.publish_block(&tip);
+                    }
+                }
+                NetworkEvent::BlocksRequest {
+                    peer,
+                    from_index,
+                    to_index,
+                } => {
+                    tracing::info!(
+                        from_index,
+                        to_index,
+                        peer_id = %peer,
+                        "Blocks requested by peer"
+                    );
+                    let blocks = {
+                        let bc = shared_blockchain.lock().await;
+                        blocks_in_range(bc.chain(), from_index, to_index)
+                    };
+                    if !blocks.is_empty() {
+                        let _ = net_cmd_tx.send(NetworkCommand::SendBlocks(blocks)).await;
+                    }
+                }
+                NetworkEvent::SyncRequest {
+                    request_id,
+                    peer,
+                    from_index,
+                    to_index,
+                } => {
+                    tracing::info!(from_index, to_index, peer_id = %peer, "Sync requested by peer");
+                    let request = SyncRequest {
+                        from_index,
+                        to_index,
+                    };
+                    let blocks = sync_blocks(shared_blockchain.lock().await.chain(), &request);
+                    let reply = NetworkCommand::SendSyncResponse { request_id, blocks };
+                    let _ = net_cmd_tx.send(reply).await;
+                }
+                NetworkEvent::Blocks(blocks) => {
+                    tracing::info!(blocks = blocks.len(), "Received blocks from network");
                     let mut bc = shared_blockchain.lock().await;
-                    if let Err(e) = bc.replace_chain(chain) {
-                        tracing::debug!("Chain replacement rejected: {}", e);
+                    let mut appended = 0;
+                    for block in blocks {
+                        // Skip blocks we already have (replies are broadcast)
+                        if block.header.index < bc.height() {
+                            continue;
+                        }
+                        if let Err(e) = bc.try_append_block(block.clone()) {
+                            tracing::warn!(
+                                block_index = block.header.index,
+                                error = %e,
+                                "Stopped applying synced blocks"
+                            );
+                            break;
+                        }
+                        event_state.publish_block(&block);
+                        appended += 1;
+                    }
+                    if appended > 0 {
+                        event_state.persist(&bc);
                     }
                 }
                 NetworkEvent::PeerConnected(peer) => {
-                    tracing::info!("Peer connected: {}", peer);
-                    let mut count = shared_peer_count.lock().await;
-                    *count += 1;
+                    tracing::info!(peer_id = %peer, "Peer connected");
+                    event_state.peer_connected(peer).await;
                 }
                 NetworkEvent::PeerDisconnected(peer) => {
-                    tracing::info!("Peer disconnected: {}", peer);
-                    let mut count = shared_peer_count.lock().await;
-                    *count = count.saturating_sub(1);
+                    tracing::info!(peer_id = %peer, "Peer disconnected");
+                    event_state.peer_disconnected(&peer).await;
+                }
+                NetworkEvent::PeerIdentified(info) => {
+                    let mut peers = shared_peers.lock().await;
+                    let entry = peers.entry(info.peer_id.clone()).or_default();
+                    entry.peer_id = info.peer_id;
+                    entry.agent_version = info.agent_version;
+                    entry.protocol_version = info.protocol_version;
+                    entry.protocols = info.protocols;
+                }
+                NetworkEvent::PeerHeight { peer, height } => {
+                    let mut peers = shared_peers.lock().await;
+                    let entry = peers.entry(peer.clone()).or_insert_with(|| PeerInfo {
+                        peer_id: peer,
+                        ..Default::default()
+                    });
+                    entry.observe_height(height);
                 }
             }
         }
     });
 
-    // Wait for API server (event loop runs indefinitely alongside it)
+    // Run until the API server or event loop stops, or Ctrl-C
     tokio::select! {
-        _ = api_handle => {
+        _ = &mut api_handle => {
             tracing::info!("API server stopped");
         }
-        _ = event_loop => {
+        _ = &mut event_loop => {
             tracing::info!("Event loop stopped");
         }
+        _ = shutdown::wait_for_shutdown() => {}
     }
 
+    tasks.push(api_handle);
+    tasks.push(event_loop);
+    shutdown::shut_down(&shutdown_state, &shutdown_net_cmd_tx, network_handle, tasks).await;
+
     Ok(())
 }
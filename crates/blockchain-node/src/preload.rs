@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::Path;
+
+use blockchain_core::chain::Blockchain;
+use blockchain_core::errors::{CoreError, CoreResult};
+use blockchain_core::transaction::Transaction;
+
+/// Feeds a JSON array of transactions through `add_transaction`, returning
+/// `(accepted, rejected)` counts.
+pub fn preload_transactions(
+    blockchain: &mut Blockchain,
+    path: &Path,
+) -> CoreResult<(usize, usize)> {
+    let data = fs::read(path).map_err(|e| CoreError::Storage(e.to_string()))?;
+    let transactions: Vec<Transaction> =
+        serde_json::from_slice(&data).map_err(|e| CoreError::Serialization(e.to_string()))?;
+
+    let total = transactions.len();
+    let accepted = blockchain.restore_pending(transactions);
+    let rejected = total - accepted;
+    tracing::info!(
+        "Preloaded transactions from {}: {} accepted, {} rejected",
+        path.display(),
+        accepted,
+        rejected
+    );
+    Ok((accepted, rejected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain_core::wallet::Wallet;
+    use blockchain_core::transaction::DEFAULT_CHAIN_ID;
+
+    #[test]
+    fn test_preloaded_transaction_is_pending() {
+        let wallet = Wallet::new();
+        let mut bc = Blockchain::new(1, 50);
+        bc.mine_pending(&wallet.address).unwrap();
+
+        let recipient = Wallet::new().address;
+        let mut valid = Transaction::new_transfer(wallet.address.clone(), recipient.clone(), 10);
+        valid.sign(wallet.signing_key(), DEFAULT_CHAIN_ID);
+        let unsigned = Transaction::new_transfer(wallet.address.clone(), recipient, 5);
+
+        let path = std::env::temp_dir().join(format!("preload-{}.json", valid.id));
+        fs::write(&path, serde_json::to_vec(&vec![valid.clone(), unsigned]).unwrap()).unwrap();
+
+        let (accepted, rejected) = preload_transactions(&mut bc, &path).unwrap();
+        fs::remove_file(&path).ok();
+
+        assert_eq!((accepted, rejected), (1, 1));
+        assert_eq!(bc.pending_transactions().len(), 1);
+        assert_eq!(bc.pending_transactions()[0].id, valid.id);
+    }
+}
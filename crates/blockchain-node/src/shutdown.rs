@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use blockchain_api::state::AppState;
+use blockchain_network::handler::NetworkCommand;
+
+/// How long the network task gets to stop on its own before it is aborted.
+const NETWORK_STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Resolves on Ctrl-C. If the signal can't be listened for, never
+/// resolves, leaving the node to stop with its API server or event loop.
+pub async fn wait_for_shutdown() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!("Failed to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+    tracing::info!("Received Ctrl-C");
+}
+
+/// Snapshots the chain and mempool, stops the network task and aborts
+/// the remaining tasks (API servers, event loop).
+pub async fn shut_down(
+    state: &AppState,
+    network_cmd: &mpsc::Sender<NetworkCommand>,
+    mut network: JoinHandle<()>,
+    tasks: Vec<JoinHandle<()>>,
+) {
+    for task in &tasks {
+        task.abort();
+    }
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    if let Some(store) = &state.store {
+        let bc = state.blockchain.lock().await;
+        match store.flush(&bc) {
+            Ok(()) => tracing::info!("Chain flushed to disk at height {}", bc.height()),
+            Err(e) => tracing::error!("Failed to flush chain on shutdown: {}", e),
+        }
+    }
+
+    let _ = network_cmd.send(NetworkCommand::Shutdown).await;
+    if tokio::time::timeout(NETWORK_STOP_TIMEOUT, &mut network).await.is_err() {
+        tracing::warn!("Network node did not stop in time; aborting");
+        network.abort();
+    }
+    tracing::info!("Node shut down cleanly");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain_core::chain::Blockchain;
+    use blockchain_core::storage::ChainStore;
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_and_stops_tasks() {
+        let mut bc = Blockchain::new(1, 50);
+        let nanos = std::time::UNIX_EPOCH.elapsed().unwrap().as_nanos();
+        let dir = std::env::temp_dir().join(format!("shutdown-{}", nanos));
+        // Large enough that the mined block only reaches the WAL
+        let store = ChainStore::open(&dir).unwrap().with_flush_interval(100);
+        store.save(&bc).unwrap();
+        bc.mine_pending("miner").unwrap();
+        store.save(&bc).unwrap();
+        let state = AppState::new(bc).with_store(store);
+
+        // Stand-ins for the API server and the network node
+        let (alive_tx, mut alive_rx) = mpsc::channel::<()>(1);
+        let api = tokio::spawn(async move {
+            let _alive = alive_tx;
+            std::future::pending::<()>().await;
+        });
+        let (network_cmd, mut network_rx) = mpsc::channel(1);
+        let network = tokio::spawn(async move {
+            while let Some(cmd) = network_rx.recv().await {
+                if matches!(cmd, NetworkCommand::Shutdown) {
+                    break;
+                }
+            }
+        });
+
+        shut_down(&state, &network_cmd, network, vec![api]).await;
+
+        assert!(alive_rx.recv().await.is_none());
+        let snapshot: Vec<serde_json::Value> =
+            serde_json::from_slice(&std::fs::read(dir.join("chain.json")).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(snapshot.len(), 2);
+    }
+}
@@ -0,0 +1,133 @@
+use clap::Subcommand;
+use serde_json::json;
+
+use blockchain_core::errors::{CoreError, CoreResult};
+use blockchain_core::transaction::{Transaction, DEFAULT_CHAIN_ID};
+use blockchain_core::wallet::Wallet;
+
+/// `blockchain-node wallet ...`: key and transaction tools that need no
+/// running node.
+#[derive(Subcommand, Debug)]
+pub enum WalletCommand {
+    /// Generate a wallet and print its address, keys and mnemonic
+    New,
+    /// Print the address for a hex-encoded public key
+    Address {
+        #[arg(long)]
+        public_key: String,
+    },
+    /// Print a signed transfer, ready to POST to /api/transactions/raw
+    Sign {
+        /// Hex-encoded private key of the sender
+        #[arg(long)]
+        key: String,
+        #[arg(long)]
+        sender: String,
+        #[arg(long)]
+        recipient: String,
+        #[arg(long)]
+        amount: u64,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        /// The sender's next nonce (see GET /api/accounts/:address)
+        #[arg(long, default_value_t = 0)]
+        nonce: u64,
+        #[arg(long, default_value_t = DEFAULT_CHAIN_ID)]
+        chain_id: u64,
+    },
+}
+
+/// Runs `command`, returning what to print.
+pub fn run(command: &WalletCommand) -> CoreResult<String> {
+    let output = match command {
+        WalletCommand::New => {
+            let mnemonic = Wallet::generate_mnemonic();
+            let wallet = Wallet::from_mnemonic(&mnemonic, 0)?;
+            json!({
+                "address": wallet.address,
+                "public_key": wallet.public_key_hex(),
+                "private_key": wallet.private_key_hex(),
+                "mnemonic": mnemonic,
+            })
+        }
+        WalletCommand::Address { public_key } => {
+            return Wallet::address_from_public_key_hex(public_key);
+        }
+        WalletCommand::Sign {
+            key,
+            sender,
+            recipient,
+            amount,
+            fee,
+            nonce,
+            chain_id,
+        } => {
+            let wallet = Wallet::from_private_key_hex(key)?;
+            if wallet.address != *sender {
+                return Err(CoreError::InvalidKey(format!(
+                    "Key belongs to {}, not sender {}",
+                    wallet.address, sender
+                )));
+            }
+            let mut tx = Transaction::new_transfer(sender.clone(), recipient.clone(), *amount)
+                .with_fee(*fee)
+                .with_nonce(*nonce);
+            tx.sign(wallet.signing_key(), *chain_id);
+            serde_json::to_value(&tx).map_err(|e| CoreError::Serialization(e.to_string()))?
+        }
+    };
+    serde_json::to_string_pretty(&output).map_err(|e| CoreError::Serialization(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Command, Config};
+
+    #[test]
+    fn test_new_wallet_keys_agree() {
+        let output: serde_json::Value =
+            serde_json::from_str(&run(&WalletCommand::New).unwrap()).unwrap();
+        let address = output["address"].as_str().unwrap();
+        let public_key = output["public_key"].as_str().unwrap().to_string();
+        let from_mnemonic = Wallet::from_mnemonic(output["mnemonic"].as_str().unwrap(), 0);
+        let from_key = Wallet::from_private_key_hex(output["private_key"].as_str().unwrap());
+
+        assert_eq!(from_mnemonic.unwrap().address, address);
+        assert_eq!(from_key.unwrap().address, address);
+        let command = WalletCommand::Address {
+            public_key: public_key.clone(),
+        };
+        assert_eq!(run(&command).unwrap(), address);
+
+        let args = ["blockchain-node", "wallet", "address", "--public-key", &public_key];
+        let Some(Command::Wallet(command)) = Config::load_from(args).unwrap().command else {
+            panic!("expected the wallet subcommand");
+        };
+        assert_eq!(run(&command).unwrap(), address);
+    }
+
+    #[test]
+    fn test_signed_transfer_verifies() {
+        let wallet = Wallet::new();
+        let sign = |sender: &str| {
+            run(&WalletCommand::Sign {
+                key: wallet.private_key_hex(),
+                sender: sender.to_string(),
+                recipient: Wallet::new().address,
+                amount: 25,
+                fee: 2,
+                nonce: 3,
+                chain_id: 7,
+            })
+        };
+
+        let tx: Transaction = serde_json::from_str(&sign(&wallet.address).unwrap()).unwrap();
+        assert_eq!((tx.amount, tx.fee, tx.nonce), (25, 2, 3));
+        assert!(tx.verify(7).unwrap());
+        assert!(tx.verify(DEFAULT_CHAIN_ID).is_err());
+
+        // A key that doesn't belong to the sender is refused up front
+        assert!(matches!(sign(&Wallet::new().address), Err(CoreError::InvalidKey(_))));
+    }
+}
@@ -1,5 +1,8 @@
+use std::collections::HashMap;
+
 use crate::errors::{VmError, VmResult};
 use crate::opcodes::OpCode;
+use crate::vm::{divide, remainder};
 
 /// Compiles text assembly into bytecode.
 ///
@@ -10,12 +13,41 @@ use crate::opcodes::OpCode;
 ///   SWAP
 ///   ADD / SUB / MUL / DIV / MOD
 ///   EQ / LT / GT / NOT
-///   JUMP / JUMPIF
+///   JUMP / JUMPIF (target from the stack)
+///   JUMP <label> / JUMPIF <label> (pushes the label's offset first)
 ///   STORE / LOAD
+///   MSTORE / MLOAD (scratch memory by word index; cleared every execution)
+///   TRANSFER (pays from the contract's balance: recipient handle, amount)
+///   EXTLOAD (reads another contract's storage: handle, key)
+///   SELFDESTRUCT (beneficiary handle, 0 = owner; stops execution)
 ///   LOG
+///   CALLER (pushes the caller's address handle)
+///   CALL (runs another contract read-only: handle; pushes its return value)
 ///   HALT
+///   <label>: (on its own line, marks the next instruction's offset)
+///   .def <NAME> <i64> (names a constant usable as a PUSH operand, even
+///   before its definition)
 pub fn compile(source: &str) -> VmResult<Vec<u8>> {
+    assemble(source, false)
+}
+
+/// `compile`, but `PUSH a` `PUSH b` followed by ADD, SUB, MUL, DIV or MOD
+/// becomes a single `PUSH` of the result, repeatedly, saving gas. Folds
+/// never span a label, so label jumps still land where written; jumps to
+/// hard-coded offsets are not adjusted. A literal division by zero is a
+/// compile error.
+pub fn compile_optimized(source: &str) -> VmResult<Vec<u8>> {
+    assemble(source, true)
+}
+
+fn assemble(source: &str, fold: bool) -> VmResult<Vec<u8>> {
+    let constants = definitions(source)?;
     let mut bytecode = Vec::new();
+    let mut labels: HashMap<&str, (usize, usize)> = HashMap::new();
+    // (immediate offset, label, line) patched once every label is known
+    let mut fixups: Vec<(usize, &str, usize)> = Vec::new();
+    // (offset, value) of the literal PUSHes ending the bytecode so far
+    let mut literals: Vec<(usize, i64)> = Vec::new();
 
     for (line_num, line) in source.lines().enumerate() {
         let line = line.trim();
@@ -24,29 +56,71 @@ pub fn compile(source: &str) -> VmResult<Vec<u8>> {
         }
 
         let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.is_empty() {
+        // Empty, or a `.def` already collected by `definitions`
+        if parts.is_empty() || parts[0] == ".def" {
+            continue;
+        }
+
+        if let Some(label) = parts[0].strip_suffix(':') {
+            if label.is_empty() || parts.len() > 1 {
+                return Err(VmError::CompileError(format!(
+                    "Line {}: a label must be alone on its line",
+                    line_num + 1
+                )));
+            }
+            if let Some((_, first)) = labels.insert(label, (bytecode.len(), line_num + 1)) {
+                return Err(VmError::CompileError(format!(
+                    "Line {}: duplicate label '{}' (first defined on line {})",
+                    line_num + 1,
+                    label,
+                    first
+                )));
+            }
+            literals.clear();
             continue;
         }
 
         let instruction = parts[0].to_uppercase();
+        let target = parts.get(1).filter(|p| !p.starts_with(';') && !p.starts_with('#'));
+        if let ("JUMP" | "JUMPIF", Some(label)) = (instruction.as_str(), target) {
+            bytecode.push(OpCode::Push as u8);
+            fixups.push((bytecode.len(), label, line_num + 1));
+            bytecode.extend_from_slice(&0i64.to_le_bytes());
+        }
+        if let (true, [.., (offset, a), (_, b)]) = (fold, literals.as_slice()) {
+            if let Some(value) = fold_binary(&instruction, *a, *b, line_num + 1)? {
+                let offset = *offset;
+                literals.truncate(literals.len() - 2);
+                literals.push((offset, value));
+                bytecode.truncate(offset);
+                bytecode.push(OpCode::Push as u8);
+                bytecode.extend_from_slice(&value.to_le_bytes());
+                continue;
+            }
+        }
+        if instruction != "PUSH" {
+            literals.clear();
+        }
         match instruction.as_str() {
             "PUSH" => {
-                let value: i64 = parts
-                    .get(1)
-                    .ok_or_else(|| {
-                        VmError::CompileError(format!(
-                            "Line {}: PUSH requires a value",
-                            line_num + 1
-                        ))
-                    })?
-                    .parse()
-                    .map_err(|e| {
-                        VmError::CompileError(format!(
-                            "Line {}: invalid number: {}",
+                let operand = parts.get(1).ok_or_else(|| {
+                    VmError::CompileError(format!(
+                        "Line {}: PUSH requires a value",
+                        line_num + 1
+                    ))
+                })?;
+                let value = match constants.get(operand) {
+                    Some(&(value, _)) => value,
+                    None if is_constant_name(operand) => {
+                        return Err(VmError::CompileError(format!(
+                            "Line {}: undefined constant '{}'",
                             line_num + 1,
-                            e
-                        ))
-                    })?;
+                            operand
+                        )));
+                    }
+                    None => parse_number(operand, line_num + 1)?,
+                };
+                literals.push((bytecode.len(), value));
                 bytecode.push(OpCode::Push as u8);
                 bytecode.extend_from_slice(&value.to_le_bytes());
             }
@@ -58,16 +132,29 @@ pub fn compile(source: &str) -> VmResult<Vec<u8>> {
             "MUL" => bytecode.push(OpCode::Mul as u8),
             "DIV" => bytecode.push(OpCode::Div as u8),
             "MOD" => bytecode.push(OpCode::Mod as u8),
+            "DIVMOD" => bytecode.push(OpCode::DivMod as u8),
             "EQ" => bytecode.push(OpCode::Eq as u8),
             "LT" => bytecode.push(OpCode::Lt as u8),
             "GT" => bytecode.push(OpCode::Gt as u8),
             "NOT" => bytecode.push(OpCode::Not as u8),
             "JUMP" => bytecode.push(OpCode::Jump as u8),
             "JUMPIF" => bytecode.push(OpCode::JumpIf as u8),
+            "RETURN" => bytecode.push(OpCode::Return as u8),
+            "REVERT" => bytecode.push(OpCode::Revert as u8),
             "HALT" => bytecode.push(OpCode::Halt as u8),
             "STORE" => bytecode.push(OpCode::Store as u8),
             "LOAD" => bytecode.push(OpCode::Load as u8),
+            "MSTORE" => bytecode.push(OpCode::MStore as u8),
+            "MLOAD" => bytecode.push(OpCode::MLoad as u8),
+            "TRANSFER" => bytecode.push(OpCode::Transfer as u8),
+            "EXTLOAD" => bytecode.push(OpCode::ExtLoad as u8),
+            "SELFDESTRUCT" => bytecode.push(OpCode::SelfDestruct as u8),
             "LOG" => bytecode.push(OpCode::Log as u8),
+            "EMIT" => bytecode.push(OpCode::Emit as u8),
+            "CALLER" => bytecode.push(OpCode::Caller as u8),
+            "CALL" => bytecode.push(OpCode::CallContract as u8),
+            "CALLDATALOAD" => bytecode.push(OpCode::CallDataLoad as u8),
+            "CALLDATASIZE" => bytecode.push(OpCode::CallDataSize as u8),
             _ => {
                 return Err(VmError::CompileError(format!(
                     "Line {}: unknown instruction '{}'",
@@ -78,9 +165,115 @@ pub fn compile(source: &str) -> VmResult<Vec<u8>> {
         }
     }
 
+    for (at, label, line) in fixups {
+        let (offset, _) = labels.get(label).ok_or_else(|| {
+            VmError::CompileError(format!("Line {}: undefined label '{}'", line, label))
+        })?;
+        bytecode[at..at + 8].copy_from_slice(&(*offset as i64).to_le_bytes());
+    }
+
     Ok(bytecode)
 }
 
+/// What `instruction` computes from the literals `a` and `b`, as the VM
+/// would. `None` when it isn't foldable arithmetic, or overflows in a way
+/// best left to fail at run time.
+fn fold_binary(instruction: &str, a: i64, b: i64, line: usize) -> VmResult<Option<i64>> {
+    let result = match instruction {
+        "ADD" => Ok(a.wrapping_add(b)),
+        "SUB" => Ok(a.wrapping_sub(b)),
+        "MUL" => Ok(a.wrapping_mul(b)),
+        "DIV" => divide(a, b),
+        "MOD" => remainder(a, b),
+        _ => return Ok(None),
+    };
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(VmError::DivisionByZero) => Err(VmError::CompileError(format!(
+            "Line {}: division by zero",
+            line
+        ))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// First pass: every `.def NAME value` in `source`, as name -> (value,
+/// line).
+fn definitions(source: &str) -> VmResult<HashMap<&str, (i64, usize)>> {
+    let mut constants = HashMap::new();
+    for (line_num, line) in source.lines().enumerate() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.first() != Some(&".def") {
+            continue;
+        }
+        let comment = parts.get(3).filter(|p| !p.starts_with(';') && !p.starts_with('#'));
+        let (Some(&name), Some(&value), None) = (parts.get(1), parts.get(2), comment) else {
+            return Err(VmError::CompileError(format!(
+                "Line {}: expected .def <NAME> <value>",
+                line_num + 1
+            )));
+        };
+        if !is_constant_name(name) {
+            return Err(VmError::CompileError(format!(
+                "Line {}: invalid constant name '{}'",
+                line_num + 1,
+                name
+            )));
+        }
+        let value = parse_number(value, line_num + 1)?;
+        if let Some((_, first)) = constants.insert(name, (value, line_num + 1)) {
+            return Err(VmError::CompileError(format!(
+                "Line {}: constant '{}' redefined (first defined on line {})",
+                line_num + 1,
+                name,
+                first
+            )));
+        }
+    }
+    Ok(constants)
+}
+
+/// Constant names start with a letter or `_`, so they never look like
+/// numbers.
+fn is_constant_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_number(text: &str, line: usize) -> VmResult<i64> {
+    text.parse().map_err(|e| {
+        VmError::CompileError(format!("Line {}: invalid number: {}", line, e))
+    })
+}
+
+/// Turns bytecode back into assembly `compile` accepts, one instruction
+/// per line. Jump targets come out as plain `PUSH <offset>` immediates.
+pub fn disassemble(bytecode: &[u8]) -> VmResult<String> {
+    let mut lines = Vec::new();
+    let mut pc = 0;
+
+    while pc < bytecode.len() {
+        let byte = bytecode[pc];
+        let opcode = OpCode::from_byte(byte).ok_or(VmError::InvalidOpcode(byte))?;
+        pc += 1;
+        if opcode == OpCode::Push {
+            let bytes: [u8; 8] = bytecode
+                .get(pc..pc + 8)
+                .and_then(|imm| imm.try_into().ok())
+                .ok_or(VmError::PcOutOfBounds {
+                    pc,
+                    len: bytecode.len(),
+                })?;
+            lines.push(format!("PUSH {}", i64::from_le_bytes(bytes)));
+            pc += 8;
+        } else {
+            lines.push(opcode.mnemonic().to_string());
+        }
+    }
+
+    Ok(lines.join("\n"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,6 +325,156 @@ mod tests {
         assert_eq!(result.stack, vec![42]);
     }
 
+    #[test]
+    fn test_compile_mstore_mload() {
+        let source = r#"
+            PUSH 5
+            PUSH 7
+            MSTORE
+            PUSH 5
+            MLOAD
+            HALT
+        "#;
+        let bytecode = compile(source).unwrap();
+        let mut vm = VM::new();
+        let result = vm.execute(&bytecode).unwrap();
+        assert_eq!(result.stack, vec![7]);
+    }
+
+    #[test]
+    fn test_compile_countdown_loop_with_label() {
+        let source = r#"
+            PUSH 3
+            loop:
+            DUP
+            LOG
+            PUSH 1
+            SUB
+            DUP
+            JUMPIF loop
+            HALT
+        "#;
+        let bytecode = compile(source).unwrap();
+        let result = VM::new().execute(&bytecode).unwrap();
+        assert_eq!(result.logs, vec![3, 2, 1]);
+        assert_eq!(result.stack, vec![0]);
+    }
+
+    #[test]
+    fn test_compile_label_errors() {
+        let err = compile("PUSH 1\nJUMP nowhere").unwrap_err();
+        assert!(err.to_string().contains("Line 2: undefined label 'nowhere'"));
+
+        let err = compile("a:\nPUSH 1\na:\nHALT").unwrap_err();
+        assert!(err.to_string().contains("Line 3: duplicate label 'a'"));
+    }
+
+    #[test]
+    fn test_disassemble_round_trip() {
+        let source = r#"
+            PUSH -7
+            loop:
+            PUSH 0
+            LOAD
+            CALLER
+            EXTLOAD
+            LOG
+            JUMPIF loop
+            PUSH 3
+            REVERT
+            RETURN
+            HALT
+        "#;
+        let bytecode = compile(source).unwrap();
+        let text = disassemble(&bytecode).unwrap();
+        assert!(text.starts_with("PUSH -7\nPUSH 0\nLOAD"));
+        assert_eq!(compile(&text).unwrap(), bytecode);
+    }
+
+    #[test]
+    fn test_disassemble_errors() {
+        assert!(matches!(disassemble(&[0xFF]), Err(VmError::InvalidOpcode(0xFF))));
+
+        let truncated = [OpCode::Push as u8, 1, 2, 3];
+        assert!(matches!(
+            disassemble(&truncated),
+            Err(VmError::PcOutOfBounds { pc: 1, len: 4 })
+        ));
+    }
+
+    #[test]
+    fn test_compile_constants() {
+        let with_constants = r#"
+            PUSH SLOT
+            PUSH 42
+            STORE
+            .def SLOT 3 ; storage slot
+            .def _neg -1
+            PUSH _neg
+        "#;
+        let plain = "PUSH 3\nPUSH 42\nSTORE\nPUSH -1";
+        assert_eq!(compile(with_constants).unwrap(), compile(plain).unwrap());
+
+        let err = compile(".def SLOT 3\n.def SLOT 4").unwrap_err();
+        assert!(err.to_string().contains("Line 2: constant 'SLOT' redefined"));
+        let err = compile("PUSH SLOT").unwrap_err();
+        assert!(err.to_string().contains("Line 1: undefined constant 'SLOT'"));
+        // Values are plain numbers, not other constants or expressions
+        assert!(compile(".def A 1\n.def B A").is_err());
+        assert!(compile(".def C 1+1").is_err());
+    }
+
+    #[test]
+    fn test_optimized_compile_folds_constants() {
+        let source = r#"
+            PUSH 2
+            PUSH 3
+            ADD
+            PUSH 4
+            MUL      ; (2 + 3) * 4
+            PUSH -7
+            PUSH 2
+            MOD
+            SUB
+            LOG
+            PUSH 5
+            PUSH 1
+            JUMPIF skip
+            PUSH 100
+            skip:
+            PUSH 9   ; a label between the pushes keeps them apart
+            ADD
+            HALT
+        "#;
+        let plain = compile(source).unwrap();
+        let folded = compile_optimized(source).unwrap();
+        assert!(folded.len() < plain.len());
+        assert!(disassemble(&folded).unwrap().starts_with("PUSH 21\nLOG"));
+
+        let mut vm = VM::new();
+        let expected = vm.execute(&plain).unwrap();
+        let plain_gas = vm.gas_used();
+        let mut vm = VM::new();
+        let result = vm.execute(&folded).unwrap();
+        assert_eq!(result.stack, vec![14]);
+        assert_eq!((result.stack, result.logs), (expected.stack, expected.logs));
+        assert!(vm.gas_used() < plain_gas);
+    }
+
+    #[test]
+    fn test_optimized_compile_rejects_literal_division_by_zero() {
+        let err = compile_optimized("PUSH 1\nPUSH 0\nDIV").unwrap_err();
+        assert!(err.to_string().contains("Line 3: division by zero"));
+        assert!(compile_optimized("PUSH 1\nPUSH 0\nMOD").is_err());
+        // Unoptimized, it only fails when run
+        assert!(compile("PUSH 1\nPUSH 0\nDIV").is_ok());
+        // A divisor that isn't a literal is left alone
+        assert!(compile_optimized("PUSH 1\nPUSH 0\nLOAD\nDIV").is_ok());
+        // Overflow keeps failing at run time
+        let source = format!("PUSH {}\nPUSH -1\nDIV", i64::MIN);
+        assert_eq!(compile_optimized(&source).unwrap(), compile(&source).unwrap());
+    }
+
     #[test]
     fn test_compile_unknown_instruction() {
         let source = "UNKNOWN 42";
@@ -1,49 +1,364 @@
+use serde::Serialize;
 use sha2::{Digest, Sha256};
 
+use blockchain_core::receipt::{CallOutcome, ContractRuntime};
 use blockchain_core::state::WorldState;
 
 use crate::errors::{VmError, VmResult};
-use crate::vm::VM;
+use crate::vm::{CallHandler, Event, ExecutionResult, NestedCall, TraceEntry, VM};
 
 #[derive(Debug)]
 pub struct ContractResult {
     pub logs: Vec<i64>,
+    /// Events recorded with EMIT; also kept on the contract.
+    pub events: Vec<Event>,
+    /// Whatever was left on top of the stack; see `return_value` for what
+    /// the contract explicitly returned.
     pub stack_top: Option<i64>,
+    /// Value passed to RETURN, if the call ended with one.
+    pub return_value: Option<i64>,
     pub steps_used: u64,
+    /// Includes the gas of contracts invoked with CALL.
+    pub gas_used: u64,
+    /// Contracts invoked with CALL, in order.
+    pub calls: Vec<CallFrame>,
+    /// Frame tree for the call, populated only by `call_traced`.
+    pub call_trace: Option<Vec<CallFrame>>,
 }
 
+/// One contract invocation in a traced call. Nested invocations made by
+/// the contract are recorded in `calls`, in execution order.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CallFrame {
+    pub address: String,
+    /// Call data of the invocation; always empty for nested frames, as
+    /// CALL passes no input.
+    pub input: Vec<u8>,
+    pub steps_used: u64,
+    pub result: Option<i64>,
+    /// Why the invocation failed. Any failure fails the whole call, so
+    /// frames handed back by a successful `call_traced` never set it.
+    pub error: Option<String>,
+    pub calls: Vec<CallFrame>,
+}
+
+/// Maps a contract address to the i64 word contracts use to refer to it
+/// on the stack (first 8 bytes of its SHA-256).
+pub fn address_handle(address: &str) -> i64 {
+    let digest = Sha256::digest(address.as_bytes());
+    i64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Runs CALL targets against `state`. Nested calls are read-only: one
+/// that writes storage, emits events, transfers or self-destructs fails,
+/// and with it the whole call.
+struct NestedCalls<'s> {
+    state: &'s WorldState,
+}
+
+impl CallHandler for NestedCalls<'_> {
+    fn call(
+        &self,
+        caller: &str,
+        handle: i64,
+        depth: usize,
+        gas_limit: u64,
+    ) -> VmResult<NestedCall> {
+        let (address, contract) = self
+            .state
+            .contracts()
+            .iter()
+            .find(|(address, _)| address_handle(address) == handle)
+            .ok_or_else(|| VmError::ContractError(format!("Unknown contract handle: {}", handle)))?;
+
+        let mut vm = VM::new()
+            .with_storage(contract.storage.clone())
+            .with_world(self.state)
+            .with_contract_address(address)
+            .with_context(address_handle(caller))
+            .with_call_handler(self)
+            .with_depth(depth)
+            .with_gas_limit(gas_limit);
+        let result = vm.execute(&contract.bytecode)?;
+        if result.storage != contract.storage
+            || !result.events.is_empty()
+            || !result.transfers.is_empty()
+            || result.self_destruct.is_some()
+        {
+            return Err(VmError::ContractError(format!(
+                "Contract {} may not change state when invoked with CALL",
+                address
+            )));
+        }
+
+        Ok(NestedCall {
+            return_value: result.return_value,
+            gas_used: result.gas_used,
+            frame: CallFrame {
+                address: address.clone(),
+                input: Vec::new(),
+                steps_used: result.steps_used,
+                result: result.stack.last().copied(),
+                error: None,
+                calls: result.calls,
+            },
+        })
+    }
+}
+
+/// A finished execution whose effects have been checked but not applied.
+struct PreparedCall {
+    result: ExecutionResult,
+    transfers: Vec<(String, u64)>,
+    /// Beneficiary, if the contract self-destructed.
+    destruct_to: Option<String>,
+    /// Instructions run, when the call was traced.
+    trace: Vec<TraceEntry>,
+}
+
+#[derive(Debug)]
 pub struct ContractExecutor;
 
 impl ContractExecutor {
+    /// Deploys `bytecode` on behalf of `sender`, charging
+    /// `fee_per_byte * bytecode.len()` from the sender's balance.
     pub fn deploy(
         state: &mut WorldState,
         sender: &str,
         bytecode: Vec<u8>,
+        fee_per_byte: u64,
     ) -> VmResult<String> {
-        // Generate contract address from sender + bytecode hash
-        let mut hasher = Sha256::new();
-        hasher.update(sender.as_bytes());
-        hasher.update(&bytecode);
-        hasher.update(
-            chrono::Utc::now()
-                .timestamp_nanos_opt()
-                .unwrap_or(0)
-                .to_le_bytes(),
+        let fee = Self::deploy_fee(&bytecode, fee_per_byte);
+        let balance = state.get_balance(sender);
+        if balance < fee {
+            return Err(VmError::InsufficientFunds {
+                required: fee,
+                available: balance,
+            });
+        }
+        // Address comes from the sender's nonce, so the fee is taken
+        // directly rather than via `debit` to bump the nonce only once
+        let address = state.deploy_contract_from(sender, bytecode);
+        state.get_or_create_account(sender).balance -= fee;
+
+        tracing::info!(
+            "Contract deployed at {} by {} (fee: {})",
+            address,
+            sender,
+            fee
         );
-        let hash = hex::encode(hasher.finalize());
-        let address = format!("0xc{}", &hash[..39]);
+        Ok(address)
+    }
 
-        state.deploy_contract(address.clone(), bytecode, sender.to_string());
+    /// Finds the account or contract address whose handle is `handle`.
+    fn resolve_account(state: &WorldState, handle: i64) -> Option<String> {
+        state
+            .accounts()
+            .keys()
+            .chain(state.contracts().keys())
+            .find(|address| address_handle(address) == handle)
+            .cloned()
+    }
 
-        tracing::info!("Contract deployed at {} by {}", address, sender);
-        Ok(address)
+    fn self_destruct(state: &mut WorldState, contract_address: &str, beneficiary: &str) {
+        state.remove_contract(contract_address);
+        let balance = state.get_balance(contract_address);
+        if balance > 0 {
+            state.transfer(contract_address, beneficiary, balance);
+        }
+        tracing::info!(
+            contract = contract_address,
+            "Contract self-destructed, {} sent to {}",
+            balance,
+            beneficiary
+        );
     }
 
+    /// Resolves TRANSFER recipients and checks the contract can cover
+    /// every payment, so nothing is applied unless all of them succeed.
+    fn resolve_transfers(
+        state: &WorldState,
+        contract_address: &str,
+        transfers: &[(i64, i64)],
+    ) -> VmResult<Vec<(String, u64)>> {
+        let resolved = transfers
+            .iter()
+            .map(|&(handle, amount)| {
+                let recipient = Self::resolve_account(state, handle).ok_or_else(|| {
+                    VmError::ContractError(format!("Unknown transfer recipient handle: {}", handle))
+                })?;
+                Ok((recipient, amount as u64))
+            })
+            .collect::<VmResult<Vec<_>>>()?;
+
+        let required = resolved
+            .iter()
+            .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+            .unwrap_or(u64::MAX);
+        let available = state.get_balance(contract_address);
+        if required > available {
+            return Err(VmError::InsufficientFunds {
+                required,
+                available,
+            });
+        }
+        Ok(resolved)
+    }
+
+    /// Pays out `transfers` from the contract. If one fails (a recipient's
+    /// balance would overflow), those already made are undone and the call
+    /// fails with nothing applied.
+    fn apply_transfers(
+        state: &mut WorldState,
+        contract_address: &str,
+        transfers: &[(String, u64)],
+    ) -> VmResult<()> {
+        let nonce = state.get_account(contract_address).map_or(0, |a| a.nonce);
+        for (done, (recipient, amount)) in transfers.iter().enumerate() {
+            if state.transfer(contract_address, recipient, *amount) {
+                continue;
+            }
+            for (paid, refund) in transfers[..done].iter().rev() {
+                state.get_or_create_account(paid).balance -= refund;
+                state.credit(contract_address, *refund);
+            }
+            state.get_or_create_account(contract_address).nonce = nonce;
+            return Err(VmError::ContractError(format!(
+                "Transfer of {} to {} failed: recipient balance would overflow",
+                amount, recipient
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn deploy_fee(bytecode: &[u8], fee_per_byte: u64) -> u64 {
+        (bytecode.len() as u64).saturating_mul(fee_per_byte)
+    }
+
+    /// Runs the contract at `contract_address` on behalf of `caller`.
     pub fn call(
         state: &mut WorldState,
+        caller: &str,
         contract_address: &str,
-        _call_data: &[u8],
+        call_data: &[u8],
     ) -> VmResult<ContractResult> {
+        Self::execute_call(state, caller, contract_address, call_data, None)
+    }
+
+    /// Like `call`, but also returns the call-stack as a `CallFrame` tree.
+    /// A failure anywhere, including in a contract invoked with CALL,
+    /// fails the whole call and returns that error instead of a tree.
+    pub fn call_traced(
+        state: &mut WorldState,
+        caller: &str,
+        contract_address: &str,
+        call_data: &[u8],
+    ) -> VmResult<ContractResult> {
+        let mut frames = Vec::new();
+        let mut result =
+            Self::execute_call(state, caller, contract_address, call_data, Some(&mut frames))?;
+        result.call_trace = Some(frames);
+        Ok(result)
+    }
+
+    fn execute_call(
+        state: &mut WorldState,
+        caller: &str,
+        contract_address: &str,
+        call_data: &[u8],
+        trace: Option<&mut Vec<CallFrame>>,
+    ) -> VmResult<ContractResult> {
+        let outcome = Self::run_contract(state, caller, contract_address, call_data);
+
+        if let Some(frames) = trace {
+            frames.push(CallFrame {
+                address: contract_address.to_string(),
+                input: call_data.to_vec(),
+                steps_used: outcome.as_ref().map(|r| r.steps_used).unwrap_or(0),
+                result: outcome.as_ref().ok().and_then(|r| r.stack_top),
+                error: outcome.as_ref().err().map(|e| e.to_string()),
+                calls: outcome.as_ref().map(|r| r.calls.clone()).unwrap_or_default(),
+            });
+        }
+
+        outcome
+    }
+
+    /// Executes the contract at `contract_address` against `state` without
+    /// writing anything back. Nothing is charged or persisted, so the
+    /// result is an estimate of what `call` would do right now.
+    pub fn simulate(
+        state: &WorldState,
+        caller: &str,
+        contract_address: &str,
+        call_data: &[u8],
+    ) -> VmResult<ContractResult> {
+        let prepared = Self::prepare_call(state, caller, contract_address, call_data, false)?;
+        Ok(Self::into_result(prepared.result))
+    }
+
+    /// Like `simulate`, but also returns every instruction the contract
+    /// ran. Instructions of contracts it invokes with CALL are not included.
+    pub fn simulate_traced(
+        state: &WorldState,
+        caller: &str,
+        contract_address: &str,
+        call_data: &[u8],
+    ) -> VmResult<(ContractResult, Vec<TraceEntry>)> {
+        let prepared = Self::prepare_call(state, caller, contract_address, call_data, true)?;
+        Ok((Self::into_result(prepared.result), prepared.trace))
+    }
+
+    /// Runs `bytecode` as if just deployed by `caller`, with empty storage,
+    /// and returns every instruction it ran. Nothing is deployed.
+    pub fn trace_bytecode(
+        state: &WorldState,
+        caller: &str,
+        bytecode: &[u8],
+    ) -> VmResult<(ContractResult, Vec<TraceEntry>)> {
+        let nested = NestedCalls { state };
+        let (result, trace) = VM::new()
+            .with_world(state)
+            .with_context(address_handle(caller))
+            .with_call_handler(&nested)
+            .execute_traced(bytecode)?;
+        Ok((Self::into_result(result), trace))
+    }
+
+    fn run_contract(
+        state: &mut WorldState,
+        caller: &str,
+        contract_address: &str,
+        call_data: &[u8],
+    ) -> VmResult<ContractResult> {
+        let PreparedCall {
+            mut result,
+            transfers,
+            destruct_to,
+            ..
+        } = Self::prepare_call(state, caller, contract_address, call_data, false)?;
+
+        Self::apply_transfers(state, contract_address, &transfers)?;
+        if let Some(beneficiary) = destruct_to {
+            Self::self_destruct(state, contract_address, &beneficiary);
+            return Ok(Self::into_result(result));
+        }
+        state.set_contract_storage(contract_address, std::mem::take(&mut result.storage));
+        if let Some(contract) = state.get_contract_mut(contract_address) {
+            contract.record_events(&result.events);
+        }
+        Ok(Self::into_result(result))
+    }
+
+    /// Runs the contract and checks everything its effects depend on, so
+    /// `run_contract` can apply them without failing halfway.
+    fn prepare_call(
+        state: &WorldState,
+        caller: &str,
+        contract_address: &str,
+        call_data: &[u8],
+        traced: bool,
+    ) -> VmResult<PreparedCall> {
         let contract = state
             .get_contract(contract_address)
             .ok_or_else(|| {
@@ -55,20 +370,100 @@ impl ContractExecutor {
 
         let bytecode = contract.bytecode.clone();
         let storage = contract.storage.clone();
+        let owner = contract.owner.clone();
 
-        let mut vm = VM::new().with_storage(storage);
-        let result = vm.execute(&bytecode)?;
+        // The VM works on a copy of the storage, so a trap leaves the
+        // contract's on-chain state untouched
+        let nested = NestedCalls { state };
+        let mut vm = VM::new()
+            .with_storage(storage)
+            .with_world(state)
+            .with_contract_address(contract_address)
+            .with_context(address_handle(caller))
+            .with_call_data(call_data)
+            .with_call_handler(&nested);
+        let executed = if traced {
+            vm.execute_traced(&bytecode)
+        } else {
+            vm.execute(&bytecode).map(|result| (result, Vec::new()))
+        };
+        let (result, trace) = executed.map_err(|reason| {
+            tracing::info!(
+                contract = contract_address,
+                gas_used = vm.gas_used(),
+                "Call reverted: {}",
+                reason
+            );
+            VmError::Reverted {
+                reason: Box::new(reason),
+                gas_used: vm.gas_used(),
+            }
+        })?;
 
-        // Update contract storage
-        if let Some(contract) = state.get_contract_mut(contract_address) {
-            contract.storage = result.storage;
-        }
+        let transfers = Self::resolve_transfers(state, contract_address, &result.transfers)?;
+
+        let destruct_to = match result.self_destruct {
+            None => None,
+            Some(_) if caller != owner => {
+                return Err(VmError::ContractError(format!(
+                    "Only the owner {} may self-destruct {}",
+                    owner, contract_address
+                )));
+            }
+            Some(0) => Some(owner),
+            Some(beneficiary) => Some(Self::resolve_account(state, beneficiary).ok_or_else(
+                || VmError::ContractError(format!("Unknown beneficiary handle: {}", beneficiary)),
+            )?),
+        };
 
-        Ok(ContractResult {
+        Ok(PreparedCall {
+            result,
+            transfers,
+            destruct_to,
+            trace,
+        })
+    }
+
+    fn into_result(result: ExecutionResult) -> ContractResult {
+        ContractResult {
             stack_top: result.stack.last().copied(),
+            return_value: result.return_value,
             logs: result.logs,
+            events: result.events,
             steps_used: result.steps_used,
-        })
+            gas_used: result.gas_used,
+            calls: result.calls,
+            call_trace: None,
+        }
+    }
+}
+
+/// Lets the chain run mined `ContractCall` transactions with `call`.
+impl ContractRuntime for ContractExecutor {
+    fn call(
+        &self,
+        state: &mut WorldState,
+        caller: &str,
+        contract: &str,
+        call_data: &[u8],
+    ) -> CallOutcome {
+        match Self::call(state, caller, contract, call_data) {
+            Ok(result) => CallOutcome {
+                return_value: result.return_value,
+                logs: result.logs,
+                gas_used: result.gas_used,
+                error: None,
+            },
+            Err(VmError::Reverted { reason, gas_used }) => CallOutcome {
+                gas_used,
+                error: Some(reason.to_string()),
+                ..CallOutcome::default()
+            },
+            Err(e) => CallOutcome {
+                error: Some(e.to_string()),
+                ..CallOutcome::default()
+            },
+        }
     }
 }
 
@@ -76,6 +471,12 @@ impl ContractExecutor {
 mod tests {
     use super::*;
     use crate::compiler::compile;
+    use std::collections::HashMap;
+    use blockchain_core::chain::Blockchain;
+    use blockchain_core::receipt::ReceiptStatus;
+    use blockchain_core::storage::{MemoryStorageBackend, StorageBackend};
+    use blockchain_core::transaction::Transaction;
+    use blockchain_core::wallet::Wallet;
 
     #[test]
     fn test_deploy_and_call() {
@@ -94,22 +495,609 @@ mod tests {
         let bytecode = compile(source).unwrap();
 
         let address =
-            ContractExecutor::deploy(&mut state, "alice", bytecode).unwrap();
+            ContractExecutor::deploy(&mut state, "alice", bytecode, 0).unwrap();
         assert!(address.starts_with("0xc"));
 
         let result =
-            ContractExecutor::call(&mut state, &address, &[]).unwrap();
+            ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
         assert_eq!(result.logs, vec![42]);
         assert_eq!(result.stack_top, Some(42));
     }
 
+    #[test]
+    fn test_caller_opcode_pushes_sender_handle() {
+        let mut state = WorldState::new();
+        let bytecode = compile("CALLER\nLOG\nHALT").unwrap();
+        let address = ContractExecutor::deploy(&mut state, "alice", bytecode, 0).unwrap();
+
+        let result = ContractExecutor::call(&mut state, "bob", &address, &[]).unwrap();
+        assert_eq!(result.logs, vec![address_handle("bob")]);
+    }
+
+    #[test]
+    fn test_transfer_pays_recipient_from_contract() {
+        let mut state = WorldState::new();
+        state.credit("bob", 0);
+        let source = format!("PUSH {}\nPUSH 30\nTRANSFER\nHALT", address_handle("bob"));
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(&source).unwrap(), 0).unwrap();
+        state.credit(&address, 100);
+
+        ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
+        assert_eq!(state.get_balance("bob"), 30);
+        assert_eq!(state.get_balance(&address), 70);
+    }
+
+    #[test]
+    fn test_failing_call_applies_no_transfers() {
+        let mut state = WorldState::new();
+        state.credit("bob", 0);
+        let bob = address_handle("bob");
+        // Transfer and store, then fail on division by zero
+        let source = format!(
+            "PUSH {}\nPUSH 30\nTRANSFER\nPUSH 0\nPUSH 1\nSTORE\nPUSH 1\nPUSH 0\nDIV",
+            bob
+        );
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(&source).unwrap(), 0).unwrap();
+        state.credit(&address, 100);
+
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]);
+        assert!(matches!(
+            result,
+            Err(VmError::Reverted { ref reason, .. }) if matches!(**reason, VmError::DivisionByZero)
+        ));
+        assert_eq!(state.get_balance("bob"), 0);
+        assert_eq!(state.get_balance(&address), 100);
+        assert!(state.get_contract(&address).unwrap().storage.is_empty());
+
+        // Overdrawing the contract also applies nothing
+        let source = format!("PUSH {}\nPUSH 60\nTRANSFER\nPUSH {}\nPUSH 60\nTRANSFER", bob, bob);
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(&source).unwrap(), 0).unwrap();
+        state.credit(&address, 100);
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]);
+        assert!(matches!(
+            result,
+            Err(VmError::InsufficientFunds { required: 120, available: 100 })
+        ));
+        assert_eq!(state.get_balance("bob"), 0);
+
+        // Negative amounts are rejected by the VM
+        let source = format!("PUSH {}\nPUSH -5\nTRANSFER", bob);
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(&source).unwrap(), 0).unwrap();
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]);
+        assert!(matches!(result, Err(VmError::Reverted { .. })));
+    }
+
+    #[test]
+    fn test_overflowing_transfer_undoes_earlier_ones() {
+        let mut state = WorldState::new();
+        state.credit("bob", 0);
+        state.credit("carol", u64::MAX - 10);
+        let source = format!(
+            "PUSH 0\nPUSH 1\nSTORE\nPUSH {}\nPUSH 5\nTRANSFER\nPUSH {}\nPUSH 20\nTRANSFER",
+            address_handle("bob"),
+            address_handle("carol")
+        );
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(&source).unwrap(), 0).unwrap();
+        state.credit(&address, 100);
+
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]);
+        assert!(matches!(result, Err(VmError::ContractError(_))));
+        assert_eq!(state.get_balance("bob"), 0);
+        assert_eq!(state.get_balance(&address), 100);
+        assert_eq!(state.get_account(&address).unwrap().nonce, 0);
+        assert!(state.get_contract(&address).unwrap().storage.is_empty());
+    }
+
+    #[test]
+    fn test_deploy_charges_fee_per_byte() {
+        let mut state = WorldState::new();
+        let bytecode = compile("PUSH 1\nHALT").unwrap();
+        let fee = ContractExecutor::deploy_fee(&bytecode, 2);
+        assert_eq!(fee, 20);
+
+        let result = ContractExecutor::deploy(&mut state, "alice", bytecode.clone(), 2);
+        assert!(matches!(result, Err(VmError::InsufficientFunds { required: 20, available: 0 })));
+        assert!(state.contracts().is_empty());
+
+        state.credit("alice", 100);
+        let address = ContractExecutor::deploy(&mut state, "alice", bytecode, 2).unwrap();
+        assert!(state.get_contract(&address).is_some());
+        assert_eq!(state.get_balance("alice"), 80);
+    }
+
+    #[test]
+    fn test_deploy_address_follows_sender_nonce() {
+        let mut state = WorldState::new();
+        let bytecode = compile("HALT").unwrap();
+        let first = ContractExecutor::deploy(&mut state, "alice", bytecode.clone(), 0).unwrap();
+        let second = ContractExecutor::deploy(&mut state, "alice", bytecode, 0).unwrap();
+
+        assert_eq!(first, WorldState::contract_address("alice", 0));
+        assert_eq!(second, WorldState::contract_address("alice", 1));
+        assert_eq!(state.get_account("alice").unwrap().nonce, 2);
+    }
+
+    fn chain_with_runtime() -> Blockchain {
+        Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_contract_runtime(std::sync::Arc::new(ContractExecutor))
+    }
+
+    #[test]
+    fn test_mined_call_updates_contract_storage() {
+        let mut bc = chain_with_runtime();
+        let alice = Wallet::new().address;
+        let bytecode = compile("PUSH 7\nPUSH 42\nSTORE\nPUSH 5\nRETURN").unwrap();
+        bc.add_transaction(Transaction::new_contract_deploy(alice.clone(), bytecode))
+            .unwrap();
+        bc.mine_pending(&alice).unwrap();
+        let address = WorldState::contract_address(&alice, 0);
+
+        let call = Transaction::new_contract_call(alice.clone(), address.clone(), vec![])
+            .with_nonce(1);
+        bc.add_transaction(call.clone()).unwrap();
+        assert!(bc.state().get_contract(&address).unwrap().storage.is_empty());
+        bc.mine_pending(&alice).unwrap();
+
+        assert_eq!(bc.state().get_contract(&address).unwrap().storage.get(&7), Some(&42));
+        let receipt = bc.receipt(&call.id).unwrap();
+        assert_eq!(receipt.block_index, 2);
+        assert_eq!(receipt.status, ReceiptStatus::Success);
+        assert_eq!(receipt.return_value, Some(5));
+
+        // Replaying the chain re-runs the call
+        let mut replica = chain_with_runtime();
+        replica.replace_chain(bc.chain().to_vec()).unwrap();
+        let contract = replica.state().get_contract(&address).unwrap();
+        assert_eq!(contract.storage.get(&7), Some(&42));
+        assert_eq!(replica.receipt(&call.id), Some(receipt));
+    }
+
+    #[test]
+    fn test_mined_call_reads_call_data() {
+        let mut bc = chain_with_runtime();
+        let alice = Wallet::new().address;
+        let bytecode = compile("PUSH 0\nCALLDATALOAD\nPUSH 1\nCALLDATALOAD\nADD\nRETURN").unwrap();
+        bc.add_transaction(Transaction::new_contract_deploy(alice.clone(), bytecode))
+            .unwrap();
+        bc.mine_pending(&alice).unwrap();
+        let address = WorldState::contract_address(&alice, 0);
+
+        let call_data = [5i64.to_le_bytes(), 7i64.to_le_bytes()].concat();
+        let call = Transaction::new_contract_call(alice.clone(), address.clone(), call_data)
+            .with_nonce(1);
+        bc.add_transaction(call.clone()).unwrap();
+        bc.mine_pending(&alice).unwrap();
+        assert_eq!(bc.receipt(&call.id).unwrap().return_value, Some(12));
+    }
+
+    #[test]
+    fn test_call_value_moves_to_contract_and_back_on_revert() {
+        let mut bc = chain_with_runtime();
+        let alice = Wallet::new().address;
+        for (nonce, source) in ["PUSH 1\nRETURN", "PUSH 3\nREVERT"].into_iter().enumerate() {
+            let deploy = Transaction::new_contract_deploy(alice.clone(), compile(source).unwrap())
+                .with_nonce(nonce as u64);
+            bc.add_transaction(deploy).unwrap();
+        }
+        bc.mine_pending(&alice).unwrap();
+        let accepting = WorldState::contract_address(&alice, 0);
+        let reverting = WorldState::contract_address(&alice, 1);
+        assert_eq!(bc.state().get_balance(&alice), 50);
+
+        for (nonce, contract) in [(2, &accepting), (3, &reverting)] {
+            let mut call = Transaction::new_contract_call(alice.clone(), contract.clone(), vec![])
+                .with_nonce(nonce);
+            call.amount = 20;
+            bc.add_transaction(call).unwrap();
+        }
+        bc.mine_pending("miner").unwrap();
+
+        assert_eq!(bc.state().get_balance(&accepting), 20);
+        assert_eq!(bc.state().get_balance(&reverting), 0);
+        assert_eq!(bc.state().get_balance(&alice), 30);
+        assert_eq!(bc.state().get_account(&alice).unwrap().nonce, 4);
+
+        // Value above the sender's balance is rejected up front
+        let mut call = Transaction::new_contract_call(alice.clone(), accepting, vec![])
+            .with_nonce(4);
+        call.amount = 31;
+        assert!(bc.add_transaction(call).is_err());
+    }
+
+    #[test]
+    fn test_failed_mined_call_keeps_state() {
+        let mut bc = chain_with_runtime();
+        let alice = Wallet::new().address;
+        let bytecode = compile("PUSH 7\nPUSH 42\nSTORE\nPUSH 3\nREVERT").unwrap();
+        bc.add_transaction(Transaction::new_contract_deploy(alice.clone(), bytecode))
+            .unwrap();
+        bc.mine_pending(&alice).unwrap();
+        let address = WorldState::contract_address(&alice, 0);
+
+        let call = Transaction::new_contract_call(alice.clone(), address.clone(), vec![])
+            .with_nonce(1);
+        bc.add_transaction(call.clone()).unwrap();
+        let block = bc.mine_pending(&alice).unwrap();
+
+        // Included, nonce consumed, storage untouched
+        assert!(block.transactions.iter().any(|tx| tx.id == call.id));
+        assert_eq!(bc.state().get_account(&alice).unwrap().nonce, 2);
+        assert!(bc.state().get_contract(&address).unwrap().storage.is_empty());
+        let receipt = bc.receipt(&call.id).unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Failed);
+        assert!(receipt.gas_used > 0);
+        assert!(receipt.error.as_deref().unwrap().contains("code 3"));
+    }
+
+    #[test]
+    fn test_vm_log_carries_contract_address() {
+        use std::io::Write;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let mut state = WorldState::new();
+        let bytecode = compile("PUSH 5\nLOG\nHALT").unwrap();
+        let address = ContractExecutor::deploy(&mut state, "alice", bytecode, 0).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
+        });
+
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let line = output.lines().find(|l| l.contains("VM LOG")).unwrap();
+        assert!(line.contains(&format!("contract=\"{}\"", address)), "{}", line);
+        assert!(line.contains("value=5"), "{}", line);
+    }
+
+    #[test]
+    fn test_self_destruct_removes_contract_and_pays_beneficiary() {
+        let mut state = WorldState::new();
+        state.credit("bob", 1);
+        let bytecode = compile(&format!("PUSH {}\nSELFDESTRUCT", address_handle("bob"))).unwrap();
+        let address = ContractExecutor::deploy(&mut state, "alice", bytecode, 0).unwrap();
+        state.credit(&address, 40);
+
+        // Only the owner may trigger it
+        let result = ContractExecutor::call(&mut state, "mallory", &address, &[]);
+        assert!(matches!(result, Err(VmError::ContractError(_))));
+        assert!(state.get_contract(&address).is_some());
+
+        ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
+        assert!(state.get_contract(&address).is_none());
+        assert_eq!(state.get_balance("bob"), 41);
+        assert_eq!(state.get_balance(&address), 0);
+
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]);
+        assert!(matches!(result, Err(VmError::ContractError(msg)) if msg.contains("not found")));
+    }
+
+    #[test]
+    fn test_only_changed_contract_storage_is_persisted() {
+        let mut state = WorldState::new();
+        let backend = MemoryStorageBackend::default();
+        let counter = compile("PUSH 0\nPUSH 0\nLOAD\nPUSH 1\nADD\nSTORE").unwrap();
+        let reader = compile("PUSH 0\nLOAD\nHALT").unwrap();
+        let a = ContractExecutor::deploy(&mut state, "alice", counter.clone(), 0).unwrap();
+        let b = ContractExecutor::deploy(&mut state, "alice", counter, 0).unwrap();
+        let c = ContractExecutor::deploy(&mut state, "alice", reader, 0).unwrap();
+        assert_eq!(state.persist_contract_storage(&backend).unwrap(), 3);
+
+        ContractExecutor::call(&mut state, "alice", &a, &[]).unwrap();
+        ContractExecutor::call(&mut state, "alice", &c, &[]).unwrap();
+        assert_eq!(state.persist_contract_storage(&backend).unwrap(), 1);
+        assert_eq!(backend.load(&a).unwrap(), Some(HashMap::from([(0, 1)])));
+        assert_eq!(backend.load(&b).unwrap(), Some(HashMap::new()));
+
+        // A failed call leaves nothing to persist
+        let result = ContractExecutor::call(&mut state, "alice", "0xcmissing", &[]);
+        assert!(result.is_err());
+        assert_eq!(state.persist_contract_storage(&backend).unwrap(), 0);
+
+        state.remove_contract(&b);
+        assert_eq!(state.persist_contract_storage(&backend).unwrap(), 1);
+        assert_eq!(backend.load(&b).unwrap(), None);
+    }
+
+    #[test]
+    fn test_contract_storage_survives_restart_outside_snapshot() {
+        let backend = std::sync::Arc::new(MemoryStorageBackend::default());
+        let mut bc = chain_with_runtime().with_contract_storage(backend.clone());
+        let alice = Wallet::new().address;
+        let bytecode = compile("PUSH 7\nPUSH 42\nSTORE\nHALT").unwrap();
+        bc.add_transaction(Transaction::new_contract_deploy(alice.clone(), bytecode))
+            .unwrap();
+        bc.mine_pending(&alice).unwrap();
+        let address = WorldState::contract_address(&alice, 0);
+        bc.add_transaction(
+            Transaction::new_contract_call(alice.clone(), address.clone(), vec![]).with_nonce(1),
+        )
+        .unwrap();
+        bc.mine_pending(&alice).unwrap();
+
+        let snapshot = serde_json::to_value(bc.state().get_contract(&address).unwrap()).unwrap();
+        assert!(snapshot.get("storage").is_none());
+
+        // A node without a runtime can't replay the call, so storage comes
+        // from the backend
+        let mut restarted = Blockchain::new(1, 50)
+            .with_require_signatures(false)
+            .with_contract_storage(backend.clone());
+        restarted.restore_chain(bc.chain().to_vec()).unwrap();
+        let contract = restarted.state().get_contract(&address).unwrap();
+        assert_eq!(contract.storage, HashMap::from([(7, 42)]));
+        assert_eq!(backend.load(&address).unwrap(), Some(HashMap::from([(7, 42)])));
+    }
+
     #[test]
     fn test_call_nonexistent_contract() {
         let mut state = WorldState::new();
-        let result = ContractExecutor::call(&mut state, "0xnotfound", &[]);
+        let result = ContractExecutor::call(&mut state, "alice", "0xnotfound", &[]);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_extload_reads_other_contract_storage() {
+        let mut state = WorldState::new();
+
+        let writer = compile("PUSH 1\nPUSH 99\nSTORE\nHALT").unwrap();
+        let a = ContractExecutor::deploy(&mut state, "alice", writer, 0).unwrap();
+        ContractExecutor::call(&mut state, "alice", &a, &[]).unwrap();
+
+        // Reads slot 1 (99) plus the never-written slot 2 (0)
+        let reader = compile(&format!(
+            "PUSH {}\nPUSH 1\nEXTLOAD\nPUSH {}\nPUSH 2\nEXTLOAD\nADD\nHALT",
+            address_handle(&a),
+            address_handle(&a)
+        ))
+        .unwrap();
+        let b = ContractExecutor::deploy(&mut state, "bob", reader, 0).unwrap();
+        let result = ContractExecutor::call(&mut state, "alice", &b, &[]).unwrap();
+
+        assert_eq!(result.stack_top, Some(99));
+        assert!(state.get_contract(&b).unwrap().storage.is_empty());
+        assert_eq!(state.get_contract(&a).unwrap().storage.get(&1), Some(&99));
+    }
+
+    #[test]
+    fn test_extload_unknown_handle() {
+        let mut state = WorldState::new();
+        let reader = compile("PUSH 12345\nPUSH 0\nEXTLOAD\nHALT").unwrap();
+        let address = ContractExecutor::deploy(&mut state, "bob", reader, 0).unwrap();
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]);
+        assert!(matches!(
+            result,
+            Err(VmError::Reverted { ref reason, .. }) if matches!(**reason, VmError::ContractError(_))
+        ));
+    }
+
+    #[test]
+    fn test_call_traced_records_root_frame() {
+        let mut state = WorldState::new();
+        let bytecode = compile("PUSH 7\nHALT").unwrap();
+        let address = ContractExecutor::deploy(&mut state, "alice", bytecode, 0).unwrap();
+
+        let plain = ContractExecutor::call(&mut state, "alice", &address, &[1]).unwrap();
+        assert!(plain.call_trace.is_none());
+
+        let traced = ContractExecutor::call_traced(&mut state, "alice", &address, &[1]).unwrap();
+        let trace = traced.call_trace.unwrap();
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0].address, address);
+        assert_eq!(trace[0].input, vec![1]);
+        assert_eq!(trace[0].result, Some(7));
+        assert_eq!(trace[0].steps_used, traced.steps_used);
+        assert!(trace[0].error.is_none());
+        assert!(trace[0].calls.is_empty());
+    }
+
+    #[test]
+    fn test_trap_reverts_storage_and_reports_gas() {
+        let mut state = WorldState::new();
+        let source = "PUSH 0\nPUSH 42\nSTORE\nPUSH 1\nPUSH 0\nDIV\nHALT";
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(source).unwrap(), 0).unwrap();
+
+        let err = ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap_err();
+        let VmError::Reverted { reason, gas_used } = err else {
+            panic!("expected a revert, got {:?}", err);
+        };
+        assert!(matches!(*reason, VmError::DivisionByZero));
+        // Four PUSHes, the STORE and the failing DIV are all charged
+        assert_eq!(gas_used, 4 * 3 + 100 + 5);
+        assert!(state.get_contract(&address).unwrap().storage.is_empty());
+    }
+
+    #[test]
+    fn test_return_value_is_distinct_from_stack_top() {
+        let mut state = WorldState::new();
+        let source = r#"
+            PUSH 99
+            PUSH 6
+            PUSH 7
+            MUL
+            RETURN
+            PUSH 1
+            HALT
+        "#;
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(source).unwrap(), 0).unwrap();
+
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
+        assert_eq!(result.return_value, Some(42));
+        assert_eq!(result.stack_top, Some(99));
+
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile("PUSH 5").unwrap(), 0).unwrap();
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
+        assert_eq!(result.return_value, None);
+        assert_eq!(result.stack_top, Some(5));
+    }
+
+    #[test]
+    fn test_revert_with_reason_code_keeps_storage() {
+        let mut state = WorldState::new();
+        // Writes a slot, then reverts with 7 unless slot 1 is already set
+        let source = r#"
+            PUSH 0
+            PUSH 42
+            STORE
+            PUSH 1
+            LOAD
+            JUMPIF done
+            PUSH 7
+            REVERT
+            done:
+            HALT
+        "#;
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(source).unwrap(), 0).unwrap();
+
+        let err = ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap_err();
+        let VmError::Reverted { reason, .. } = err else {
+            panic!("expected a revert, got {:?}", err);
+        };
+        let VmError::ContractError(message) = *reason else {
+            panic!("expected a contract error, got {:?}", reason);
+        };
+        assert!(message.contains("code 7"), "{}", message);
+        assert!(state.get_contract(&address).unwrap().storage.is_empty());
+    }
+
+    #[test]
+    fn test_emitted_events_are_kept_on_the_contract() {
+        let mut state = WorldState::new();
+        let source = "PUSH 9\nPUSH 1\nEMIT\nPUSH 5\nLOG\nHALT";
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(source).unwrap(), 0).unwrap();
+
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
+        assert_eq!(result.events, vec![Event { topic: 9, data: 1 }]);
+        assert_eq!(result.logs, vec![5]);
+        ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
+        assert_eq!(state.get_contract(&address).unwrap().events.len(), 2);
+
+        // A reverted call's events are dropped with the rest of its effects
+        let source = "PUSH 9\nPUSH 1\nEMIT\nPUSH 3\nREVERT";
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(source).unwrap(), 0).unwrap();
+        assert!(ContractExecutor::call(&mut state, "alice", &address, &[]).is_err());
+        assert!(state.get_contract(&address).unwrap().events.is_empty());
+    }
+
+    #[test]
+    fn test_contract_uses_return_value_of_called_contract() {
+        let mut state = WorldState::new();
+        let callee = compile("PUSH 21\nRETURN").unwrap();
+        let callee = ContractExecutor::deploy(&mut state, "alice", callee, 0).unwrap();
+        let source = format!(
+            "PUSH 0\nPUSH {}\nCALL\nPUSH 2\nMUL\nSTORE\nPUSH 0\nLOAD\nRETURN",
+            address_handle(&callee)
+        );
+        let caller = compile(&source).unwrap();
+        let caller = ContractExecutor::deploy(&mut state, "alice", caller, 0).unwrap();
+
+        let result = ContractExecutor::call_traced(&mut state, "alice", &caller, &[]).unwrap();
+        assert_eq!(result.return_value, Some(42));
+        assert_eq!(state.get_contract(&caller).unwrap().storage.get(&0), Some(&42));
+        // The callee's PUSH and RETURN are billed to the call
+        assert!(result.gas_used > 100 + 3 + 2);
+        let root = &result.call_trace.unwrap()[0];
+        assert_eq!(root.calls.len(), 1);
+        assert_eq!(root.calls[0].address, callee);
+        assert_eq!(root.calls[0].steps_used, 2);
+    }
+
+    #[test]
+    fn test_failing_nested_call_fails_traced_call() {
+        let mut state = WorldState::new();
+        let callee = compile("PUSH 1\nPUSH 0\nDIV\nRETURN").unwrap();
+        let callee = ContractExecutor::deploy(&mut state, "alice", callee, 0).unwrap();
+        let source = format!(
+            "PUSH 0\nPUSH 1\nSTORE\nPUSH {}\nCALL\nRETURN",
+            address_handle(&callee)
+        );
+        let caller =
+            ContractExecutor::deploy(&mut state, "alice", compile(&source).unwrap(), 0).unwrap();
+
+        let err = ContractExecutor::call_traced(&mut state, "alice", &caller, &[]).unwrap_err();
+        assert!(matches!(
+            err,
+            VmError::Reverted { ref reason, .. } if matches!(**reason, VmError::DivisionByZero)
+        ));
+        assert!(state.get_contract(&caller).unwrap().storage.is_empty());
+    }
+
+    #[test]
+    fn test_call_depth_is_limited() {
+        let mut state = WorldState::new();
+        // B calls back whoever called it, so A and B recurse until the limit
+        let b = compile("CALLER\nCALL\nRETURN").unwrap();
+        let b = ContractExecutor::deploy(&mut state, "alice", b, 0).unwrap();
+        let a = compile(&format!("PUSH {}\nCALL\nRETURN", address_handle(&b))).unwrap();
+        let a = ContractExecutor::deploy(&mut state, "alice", a, 0).unwrap();
+
+        let err = ContractExecutor::call(&mut state, "alice", &a, &[]).unwrap_err();
+        assert!(err.to_string().contains("Call depth limit of 8"), "{}", err);
+    }
+
+    #[test]
+    fn test_called_contract_may_not_write_state() {
+        let mut state = WorldState::new();
+        let writer = compile("PUSH 0\nPUSH 1\nSTORE\nHALT").unwrap();
+        let writer = ContractExecutor::deploy(&mut state, "alice", writer, 0).unwrap();
+        let caller = compile(&format!("PUSH {}\nCALL\nHALT", address_handle(&writer))).unwrap();
+        let caller = ContractExecutor::deploy(&mut state, "alice", caller, 0).unwrap();
+
+        let err = ContractExecutor::call(&mut state, "alice", &caller, &[]).unwrap_err();
+        assert!(err.to_string().contains("may not change state"), "{}", err);
+        assert!(state.get_contract(&writer).unwrap().storage.is_empty());
+    }
+
+    #[test]
+    fn test_simulate_matches_call_without_writing() {
+        let mut state = WorldState::new();
+        let source = "PUSH 0\nPUSH 42\nSTORE\nPUSH 3\nPUSH 4\nEMIT\nPUSH 1\nRETURN";
+        let address =
+            ContractExecutor::deploy(&mut state, "alice", compile(source).unwrap(), 0).unwrap();
+
+        let estimate = ContractExecutor::simulate(&state, "alice", &address, &[]).unwrap();
+        let contract = state.get_contract(&address).unwrap();
+        assert!(contract.storage.is_empty());
+        assert!(contract.events.is_empty());
+
+        let result = ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
+        assert_eq!(estimate.steps_used, result.steps_used);
+        assert_eq!(estimate.gas_used, result.gas_used);
+        assert_eq!(estimate.return_value, Some(1));
+        assert_eq!(state.get_contract(&address).unwrap().storage.get(&0), Some(&42));
+    }
+
     #[test]
     fn test_contract_storage_persists() {
         let mut state = WorldState::new();
@@ -122,9 +1110,9 @@ mod tests {
         "#;
         let bytecode = compile(source).unwrap();
         let address =
-            ContractExecutor::deploy(&mut state, "alice", bytecode).unwrap();
+            ContractExecutor::deploy(&mut state, "alice", bytecode, 0).unwrap();
 
-        ContractExecutor::call(&mut state, &address, &[]).unwrap();
+        ContractExecutor::call(&mut state, "alice", &address, &[]).unwrap();
 
         let contract = state.get_contract(&address).unwrap();
         assert_eq!(contract.storage.get(&0), Some(&100));
@@ -8,26 +8,50 @@ pub enum VmError {
     #[error("Stack underflow: needed {needed}, got {got}")]
     StackUnderflow { needed: usize, got: usize },
 
-    #[error("Gas limit exceeded: max {0} steps")]
+    #[error("Gas limit exceeded: max {0} gas")]
     GasLimitExceeded(u64),
 
+    #[error("Step limit exceeded: max {0} steps")]
+    StepLimitExceeded(u64),
+
     #[error("Invalid opcode: {0:#04x}")]
     InvalidOpcode(u8),
 
     #[error("Division by zero")]
     DivisionByZero,
 
-    #[error("Invalid jump target: {0}")]
+    /// The quotient doesn't fit in an `i64` (`i64::MIN / -1`).
+    #[error("Arithmetic overflow")]
+    ArithmeticOverflow,
+
+    #[error("Invalid jump target: {0} is out of range")]
     InvalidJump(usize),
 
+    /// The target is inside a PUSH's 8-byte immediate, not at an
+    /// instruction.
+    #[error("Invalid jump target: {0} is inside a PUSH immediate")]
+    JumpIntoImmediate(usize),
+
+    /// A memory index was negative or at or past the memory limit.
+    #[error("Memory index {index} outside limit of {limit} words")]
+    MemoryLimitExceeded { index: i64, limit: usize },
+
     #[error("Program counter out of bounds: {pc} >= {len}")]
     PcOutOfBounds { pc: usize, len: usize },
 
     #[error("Compilation error: {0}")]
     CompileError(String),
 
+    #[error("Insufficient funds: needs {required}, has {available}")]
+    InsufficientFunds { required: u64, available: u64 },
+
     #[error("Contract error: {0}")]
     ContractError(String),
+
+    /// A contract call trapped during execution. Nothing it did was
+    /// written to world state.
+    #[error("Contract reverted after {gas_used} gas: {reason}")]
+    Reverted { reason: Box<VmError>, gas_used: u64 },
 }
 
 pub type VmResult<T> = Result<T, VmError>;
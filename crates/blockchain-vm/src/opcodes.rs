@@ -13,8 +13,13 @@ pub enum OpCode {
     Add = 0x10,
     Sub = 0x11,
     Mul = 0x12,
+    /// Truncates toward zero, like Rust's `/`: `-7 / 2 == -3`.
     Div = 0x13,
+    /// Remainder of `Div`; takes the dividend's sign: `-7 % 2 == -1`.
     Mod = 0x14,
+    /// Pops a divisor, then a dividend, and pushes the `Div` quotient
+    /// followed by the `Mod` remainder.
+    DivMod = 0x15,
 
     // Comparison
     Eq = 0x20,
@@ -25,14 +30,42 @@ pub enum OpCode {
     // Control flow
     Jump = 0x30,
     JumpIf = 0x31,
+    /// Pops the call's return value and stops.
+    Return = 0x3D,
+    /// Pops a reason code and aborts the call; nothing it did is kept.
+    Revert = 0x3E,
     Halt = 0x3F,
 
     // Storage
     Store = 0x40,
     Load = 0x41,
+    Transfer = 0x42,
+    ExtLoad = 0x43,
+
+    // Memory: scratch words addressed by index, cleared every execution
+    /// Pops an index and pushes the memory word there (0 if never written).
+    MLoad = 0x44,
+    /// Pops a value, then an index, and writes the value there.
+    MStore = 0x45,
+
+    // Lifecycle
+    SelfDestruct = 0x49,
 
     // Logging
     Log = 0x50,
+    /// Pops a data word, then a topic, and records them as an event.
+    Emit = 0x53,
+
+    // Call context
+    Caller = 0x51,
+    /// Pops a contract address handle, runs that contract read-only and
+    /// pushes its RETURN value (0 if it returned none).
+    CallContract = 0x55,
+    /// Pops a word index and pushes that little-endian 8-byte word of the
+    /// call data, zero-padded past its end.
+    CallDataLoad = 0x56,
+    /// Pushes the length of the call data in bytes.
+    CallDataSize = 0x57,
 }
 
 impl OpCode {
@@ -47,17 +80,92 @@ impl OpCode {
             0x12 => Some(Self::Mul),
             0x13 => Some(Self::Div),
             0x14 => Some(Self::Mod),
+            0x15 => Some(Self::DivMod),
             0x20 => Some(Self::Eq),
             0x21 => Some(Self::Lt),
             0x22 => Some(Self::Gt),
             0x23 => Some(Self::Not),
             0x30 => Some(Self::Jump),
             0x31 => Some(Self::JumpIf),
+            0x3D => Some(Self::Return),
+            0x3E => Some(Self::Revert),
             0x3F => Some(Self::Halt),
             0x40 => Some(Self::Store),
             0x41 => Some(Self::Load),
+            0x42 => Some(Self::Transfer),
+            0x43 => Some(Self::ExtLoad),
+            0x44 => Some(Self::MLoad),
+            0x45 => Some(Self::MStore),
+            0x49 => Some(Self::SelfDestruct),
             0x50 => Some(Self::Log),
+            0x51 => Some(Self::Caller),
+            0x53 => Some(Self::Emit),
+            0x55 => Some(Self::CallContract),
+            0x56 => Some(Self::CallDataLoad),
+            0x57 => Some(Self::CallDataSize),
             _ => None,
         }
     }
+
+    /// Assembly mnemonic accepted by `compiler::compile`.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Push => "PUSH",
+            Self::Pop => "POP",
+            Self::Dup => "DUP",
+            Self::Swap => "SWAP",
+            Self::Add => "ADD",
+            Self::Sub => "SUB",
+            Self::Mul => "MUL",
+            Self::Div => "DIV",
+            Self::Mod => "MOD",
+            Self::DivMod => "DIVMOD",
+            Self::Eq => "EQ",
+            Self::Lt => "LT",
+            Self::Gt => "GT",
+            Self::Not => "NOT",
+            Self::Jump => "JUMP",
+            Self::JumpIf => "JUMPIF",
+            Self::Return => "RETURN",
+            Self::Revert => "REVERT",
+            Self::Halt => "HALT",
+            Self::Store => "STORE",
+            Self::Load => "LOAD",
+            Self::Transfer => "TRANSFER",
+            Self::ExtLoad => "EXTLOAD",
+            Self::MLoad => "MLOAD",
+            Self::MStore => "MSTORE",
+            Self::SelfDestruct => "SELFDESTRUCT",
+            Self::Log => "LOG",
+            Self::Caller => "CALLER",
+            Self::Emit => "EMIT",
+            Self::CallContract => "CALL",
+            Self::CallDataLoad => "CALLDATALOAD",
+            Self::CallDataSize => "CALLDATASIZE",
+        }
+    }
+}
+
+/// Gas charged for executing `op`. Storage access and jumps cost more
+/// than stack and arithmetic work.
+pub fn gas_cost(op: OpCode) -> u64 {
+    match op {
+        OpCode::Pop | OpCode::Dup | OpCode::Swap | OpCode::Halt | OpCode::Caller => 2,
+        OpCode::CallDataSize => 2,
+        OpCode::CallDataLoad => 3,
+        OpCode::Return | OpCode::Revert => 2,
+        OpCode::Push | OpCode::MLoad | OpCode::MStore => 3,
+        OpCode::Add | OpCode::Sub => 3,
+        OpCode::Eq | OpCode::Lt | OpCode::Gt | OpCode::Not => 3,
+        OpCode::Mul | OpCode::Div | OpCode::Mod | OpCode::DivMod => 5,
+        OpCode::Jump | OpCode::JumpIf => 8,
+        OpCode::Log => 10,
+        OpCode::Emit => 15,
+        OpCode::Load => 20,
+        OpCode::ExtLoad => 40,
+        // Plus whatever the callee uses
+        OpCode::CallContract => 100,
+        OpCode::Store | OpCode::Transfer => 100,
+        OpCode::SelfDestruct => 500,
+    }
 }
@@ -1,35 +1,175 @@
 use std::collections::HashMap;
 
+use blockchain_core::state::WorldState;
+
+use crate::contract::{address_handle, CallFrame};
 use crate::errors::{VmError, VmResult};
-use crate::opcodes::OpCode;
+use crate::opcodes::{gas_cost, OpCode};
+
+pub use blockchain_core::state::ContractEvent as Event;
 
-const MAX_STACK_SIZE: usize = 1024;
-const MAX_STEPS: u64 = 100_000;
+/// Stack depth unless overridden with `VM::with_stack_limit`.
+pub const DEFAULT_STACK_LIMIT: usize = 1024;
+/// Gas available to an execution unless overridden with `VM::with_gas_limit`.
+pub const DEFAULT_GAS_LIMIT: u64 = 1_000_000;
+/// Deepest chain of nested CALLs; the top-level call is depth 0.
+pub const MAX_CALL_DEPTH: usize = 8;
+/// Instructions an execution may run unless overridden with
+/// `VM::with_step_limit`. Every opcode costs at least 2 gas, so with the
+/// default gas limit this never binds first.
+pub const DEFAULT_STEP_LIMIT: u64 = 1_000_000;
+/// Memory words an execution may use unless overridden with
+/// `VM::with_memory_limit`.
+pub const DEFAULT_MEMORY_LIMIT: usize = 1024;
 
 #[derive(Debug)]
 pub struct ExecutionResult {
     pub stack: Vec<i64>,
     pub storage: HashMap<u64, i64>,
     pub logs: Vec<i64>,
+    /// Events recorded with EMIT, in order.
+    pub events: Vec<Event>,
     pub steps_used: u64,
+    /// Sum of `gas_cost` over every instruction executed.
+    pub gas_used: u64,
+    /// Sorted byte offsets of every instruction executed, when coverage
+    /// was enabled with `VM::with_coverage`.
+    pub coverage: Option<Vec<usize>>,
+    /// Beneficiary handle if the contract executed SELFDESTRUCT (0 means
+    /// the owner). Applied to world state by the executor.
+    pub self_destruct: Option<i64>,
+    /// `(recipient handle, amount)` payments requested with TRANSFER, in
+    /// order. Applied to world state by the executor.
+    pub transfers: Vec<(i64, i64)>,
+    /// Value popped by RETURN, if the program ended with one.
+    pub return_value: Option<i64>,
+    /// Contracts invoked with CALL, in order.
+    pub calls: Vec<CallFrame>,
+}
+
+/// Runs the contracts a VM invokes with CALL. `ContractExecutor` provides
+/// the implementation over world state.
+pub trait CallHandler {
+    /// Runs the contract whose address handle is `handle` on behalf of the
+    /// contract at `caller`, as the `depth`-th nested call, within
+    /// `gas_limit`.
+    fn call(&self, caller: &str, handle: i64, depth: usize, gas_limit: u64)
+        -> VmResult<NestedCall>;
+}
+
+/// What a CALL hands back to its caller.
+#[derive(Debug)]
+pub struct NestedCall {
+    pub return_value: Option<i64>,
+    pub gas_used: u64,
+    pub frame: CallFrame,
+}
+
+/// Where execution stands after a single `VM::step`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepOutcome {
+    /// True once the program halted, returned, self-destructed or ran off
+    /// the end of the bytecode; further steps do nothing.
+    pub halted: bool,
+    /// Offset of the next instruction to execute.
+    pub pc: usize,
+    pub stack_top: Option<i64>,
+    pub gas_used: u64,
 }
 
-pub struct VM {
+/// One instruction run by `VM::execute_traced`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Offset of the instruction.
+    pub pc: usize,
+    pub opcode: OpCode,
+    /// Stack depth before the instruction ran.
+    pub stack_depth: usize,
+    /// Gas used so far, including this instruction.
+    pub gas_used: u64,
+}
+
+/// Copy of the VM's machine state, for debuggers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmState {
+    pub stack: Vec<i64>,
+    pub storage: HashMap<u64, i64>,
+    pub pc: usize,
+}
+
+pub struct VM<'a> {
     stack: Vec<i64>,
     pc: usize,
     storage: HashMap<u64, i64>,
+    /// Scratch words for MLOAD/MSTORE; grows on access up to
+    /// `memory_limit` and is cleared by every `execute`.
+    memory: Vec<i64>,
+    memory_limit: usize,
     logs: Vec<i64>,
+    events: Vec<Event>,
     steps: u64,
+    gas_used: u64,
+    gas_limit: u64,
+    stack_limit: usize,
+    step_limit: u64,
+    /// Read-only view of world state for opcodes that inspect other contracts.
+    world: Option<&'a WorldState>,
+    /// Address of the executing contract, attached to log events.
+    contract_address: Option<&'a str>,
+    /// Address handle of whoever invoked the contract, pushed by CALLER.
+    caller: Option<i64>,
+    /// Input of the call, read with CALLDATALOAD and CALLDATASIZE.
+    call_data: &'a [u8],
+    /// One flag per bytecode offset, set when an instruction starts there.
+    coverage: Option<Vec<bool>>,
+    /// Instructions run so far; only recorded by `execute_traced`.
+    trace: Option<Vec<TraceEntry>>,
+    /// Valid jump targets of the bytecode being run, from
+    /// `instruction_boundaries`.
+    boundaries: Vec<bool>,
+    /// Address and length of the bytecode `boundaries` was computed for.
+    boundaries_of: (usize, usize),
+    /// Runs CALL targets; without one CALL traps.
+    call_handler: Option<&'a dyn CallHandler>,
+    /// Nesting level of this execution; 0 for a top-level call.
+    depth: usize,
+    calls: Vec<CallFrame>,
+    halted: bool,
+    self_destruct: Option<i64>,
+    transfers: Vec<(i64, i64)>,
+    return_value: Option<i64>,
 }
 
-impl VM {
+impl<'a> VM<'a> {
     pub fn new() -> Self {
         Self {
-            stack: Vec::with_capacity(MAX_STACK_SIZE),
+            stack: Vec::with_capacity(DEFAULT_STACK_LIMIT),
             pc: 0,
             storage: HashMap::new(),
+            memory: Vec::new(),
+            memory_limit: DEFAULT_MEMORY_LIMIT,
             logs: Vec::new(),
+            events: Vec::new(),
             steps: 0,
+            gas_used: 0,
+            gas_limit: DEFAULT_GAS_LIMIT,
+            stack_limit: DEFAULT_STACK_LIMIT,
+            step_limit: DEFAULT_STEP_LIMIT,
+            world: None,
+            contract_address: None,
+            caller: None,
+            call_data: &[],
+            coverage: None,
+            trace: None,
+            boundaries: Vec::new(),
+            boundaries_of: (0, 0),
+            call_handler: None,
+            depth: 0,
+            calls: Vec::new(),
+            halted: false,
+            self_destruct: None,
+            transfers: Vec::new(),
+            return_value: None,
         }
     }
 
@@ -38,140 +178,353 @@ impl VM {
         self
     }
 
-    pub fn execute(&mut self, bytecode: &[u8]) -> VmResult<ExecutionResult> {
-        self.pc = 0;
-        self.steps = 0;
+    pub fn with_world(mut self, world: &'a WorldState) -> Self {
+        self.world = Some(world);
+        self
+    }
 
-        while self.pc < bytecode.len() {
-            if self.steps >= MAX_STEPS {
-                return Err(VmError::GasLimitExceeded(MAX_STEPS));
-            }
-            self.steps += 1;
+    pub fn with_contract_address(mut self, address: &'a str) -> Self {
+        self.contract_address = Some(address);
+        self
+    }
 
-            let opcode_byte = bytecode[self.pc];
-            let opcode = OpCode::from_byte(opcode_byte)
-                .ok_or(VmError::InvalidOpcode(opcode_byte))?;
+    /// Sets the call context: `caller` is the invoker's address handle.
+    pub fn with_context(mut self, caller: i64) -> Self {
+        self.caller = Some(caller);
+        self
+    }
 
-            match opcode {
-                OpCode::Push => {
-                    self.pc += 1;
-                    let value = self.read_i64(bytecode)?;
-                    self.push(value)?;
-                }
-                OpCode::Pop => {
-                    self.pop()?;
-                }
-                OpCode::Dup => {
-                    let val = *self.peek()?;
-                    self.push(val)?;
-                }
-                OpCode::Swap => {
-                    let a = self.pop()?;
-                    let b = self.pop()?;
-                    self.push(a)?;
-                    self.push(b)?;
-                }
-                OpCode::Add => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(a.wrapping_add(b))?;
-                }
-                OpCode::Sub => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(a.wrapping_sub(b))?;
-                }
-                OpCode::Mul => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(a.wrapping_mul(b))?;
-                }
-                OpCode::Div => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    if b == 0 {
-                        return Err(VmError::DivisionByZero);
-                    }
-                    self.push(a / b)?;
-                }
-                OpCode::Mod => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    if b == 0 {
-                        return Err(VmError::DivisionByZero);
-                    }
-                    self.push(a % b)?;
-                }
-                OpCode::Eq => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(if a == b { 1 } else { 0 })?;
-                }
-                OpCode::Lt => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(if a < b { 1 } else { 0 })?;
-                }
-                OpCode::Gt => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(if a > b { 1 } else { 0 })?;
-                }
-                OpCode::Not => {
-                    let a = self.pop()?;
-                    self.push(if a == 0 { 1 } else { 0 })?;
-                }
-                OpCode::Jump => {
-                    let target = self.pop()? as usize;
-                    if target >= bytecode.len() {
-                        return Err(VmError::InvalidJump(target));
-                    }
-                    self.pc = target;
-                    continue;
-                }
-                OpCode::JumpIf => {
-                    let target = self.pop()? as usize;
-                    let condition = self.pop()?;
-                    if condition != 0 {
-                        if target >= bytecode.len() {
-                            return Err(VmError::InvalidJump(target));
-                        }
-                        self.pc = target;
-                        continue;
-                    }
-                }
-                OpCode::Halt => break,
-                OpCode::Store => {
-                    let value = self.pop()?;
-                    let key = self.pop()? as u64;
-                    self.storage.insert(key, value);
-                }
-                OpCode::Load => {
-                    let key = self.pop()? as u64;
-                    let value = self.storage.get(&key).copied().unwrap_or(0);
-                    self.push(value)?;
-                }
-                OpCode::Log => {
-                    let value = self.pop()?;
-                    self.logs.push(value);
-                    tracing::debug!("VM LOG: {}", value);
-                }
-            }
+    pub fn with_call_data(mut self, call_data: &'a [u8]) -> Self {
+        self.call_data = call_data;
+        self
+    }
 
-            self.pc += 1;
+    pub fn with_call_handler(mut self, handler: &'a dyn CallHandler) -> Self {
+        self.call_handler = Some(handler);
+        self
+    }
+
+    /// Marks this VM as running a nested call at `depth`.
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Caps the gas a single execution may use.
+    pub fn with_gas_limit(mut self, limit: u64) -> Self {
+        self.gas_limit = limit;
+        self
+    }
+
+    /// Caps how deep the stack may grow.
+    pub fn with_stack_limit(mut self, limit: usize) -> Self {
+        self.stack_limit = limit;
+        self
+    }
+
+    /// Caps the instructions a single execution may run, regardless of
+    /// gas.
+    pub fn with_step_limit(mut self, limit: u64) -> Self {
+        self.step_limit = limit;
+        self
+    }
+
+    /// Caps how many memory words MLOAD/MSTORE may address.
+    pub fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.memory_limit = limit;
+        self
+    }
+
+    /// Records which instruction offsets execute, for contract test tooling.
+    pub fn with_coverage(mut self) -> Self {
+        self.coverage = Some(Vec::new());
+        self
+    }
+
+    /// Gas consumed by the latest `execute`, including one that failed.
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    /// Capture of the stack, storage and program counter.
+    pub fn snapshot(&self) -> VmState {
+        VmState {
+            stack: self.stack.clone(),
+            storage: self.storage.clone(),
+            pc: self.pc,
         }
+    }
+
+    pub fn execute(&mut self, bytecode: &[u8]) -> VmResult<ExecutionResult> {
+        self.pc = 0;
+        self.steps = 0;
+        self.gas_used = 0;
+        self.halted = false;
+        self.self_destruct = None;
+        self.transfers.clear();
+        self.calls.clear();
+        self.return_value = None;
+        self.memory.clear();
+        if let Some(hits) = self.coverage.as_mut() {
+            hits.clear();
+        }
+        while !self.step(bytecode)?.halted {}
 
         Ok(ExecutionResult {
             stack: self.stack.clone(),
             storage: self.storage.clone(),
             logs: self.logs.clone(),
+            events: self.events.clone(),
             steps_used: self.steps,
+            gas_used: self.gas_used,
+            coverage: self.coverage.as_ref().map(|hits| {
+                hits.iter()
+                    .enumerate()
+                    .filter_map(|(offset, &hit)| hit.then_some(offset))
+                    .collect()
+            }),
+            self_destruct: self.self_destruct,
+            transfers: std::mem::take(&mut self.transfers),
+            return_value: self.return_value,
+            calls: std::mem::take(&mut self.calls),
         })
     }
 
+    /// Like `execute`, but also returns every instruction run, in order.
+    pub fn execute_traced(
+        &mut self,
+        bytecode: &[u8],
+    ) -> VmResult<(ExecutionResult, Vec<TraceEntry>)> {
+        self.trace = Some(Vec::new());
+        let result = self.execute(bytecode);
+        let trace = self.trace.take().unwrap_or_default();
+        Ok((result?, trace))
+    }
+
+    /// Executes exactly the instruction at the current `pc` (0 on a fresh
+    /// VM). `execute` is a loop over this. Jump targets are worked out from
+    /// `bytecode` on the first step of a run, so it must not be edited in
+    /// place until the run ends.
+    pub fn step(&mut self, bytecode: &[u8]) -> VmResult<StepOutcome> {
+        if self.pc >= bytecode.len() {
+            self.halted = true;
+        }
+        if self.halted {
+            return Ok(self.outcome());
+        }
+        if self.steps == 0 {
+            self.boundaries = instruction_boundaries(bytecode);
+            self.boundaries_of = (bytecode.as_ptr() as usize, bytecode.len());
+        }
+
+        let opcode_byte = bytecode[self.pc];
+        let opcode = OpCode::from_byte(opcode_byte)
+            .ok_or(VmError::InvalidOpcode(opcode_byte))?;
+
+        let gas = self.gas_used + gas_cost(opcode);
+        if gas > self.gas_limit {
+            return Err(VmError::GasLimitExceeded(self.gas_limit));
+        }
+        if self.steps >= self.step_limit {
+            return Err(VmError::StepLimitExceeded(self.step_limit));
+        }
+        self.gas_used = gas;
+        self.steps += 1;
+        if let Some(hits) = self.coverage.as_mut() {
+            if hits.len() < bytecode.len() {
+                hits.resize(bytecode.len(), false);
+            }
+            hits[self.pc] = true;
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(TraceEntry {
+                pc: self.pc,
+                opcode,
+                stack_depth: self.stack.len(),
+                gas_used: gas,
+            });
+        }
+
+        match opcode {
+            OpCode::Push => {
+                self.pc += 1;
+                let value = self.read_i64(bytecode)?;
+                self.push(value)?;
+            }
+            OpCode::Pop => {
+                self.pop()?;
+            }
+            OpCode::Dup => {
+                let val = *self.peek()?;
+                self.push(val)?;
+            }
+            OpCode::Swap => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.push(a)?;
+                self.push(b)?;
+            }
+            OpCode::Add => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(a.wrapping_add(b))?;
+            }
+            OpCode::Sub => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(a.wrapping_sub(b))?;
+            }
+            OpCode::Mul => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(a.wrapping_mul(b))?;
+            }
+            OpCode::Div => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(divide(a, b)?)?;
+            }
+            OpCode::Mod => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(remainder(a, b)?)?;
+            }
+            OpCode::DivMod => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let quotient = divide(a, b)?;
+                self.push(quotient)?;
+                self.push(remainder(a, b)?)?;
+            }
+            OpCode::Eq => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(if a == b { 1 } else { 0 })?;
+            }
+            OpCode::Lt => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(if a < b { 1 } else { 0 })?;
+            }
+            OpCode::Gt => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(if a > b { 1 } else { 0 })?;
+            }
+            OpCode::Not => {
+                let a = self.pop()?;
+                self.push(if a == 0 { 1 } else { 0 })?;
+            }
+            OpCode::Jump => {
+                let target = self.pop()? as usize;
+                return self.jump(bytecode, target);
+            }
+            OpCode::JumpIf => {
+                let target = self.pop()? as usize;
+                let condition = self.pop()?;
+                if condition != 0 {
+                    return self.jump(bytecode, target);
+                }
+            }
+            OpCode::Return => {
+                self.return_value = Some(self.pop()?);
+                self.halted = true;
+            }
+            OpCode::Revert => {
+                let code = self.pop()?;
+                return Err(VmError::ContractError(format!("Reverted with code {}", code)));
+            }
+            OpCode::Halt => self.halted = true,
+            OpCode::Store => {
+                let value = self.pop()?;
+                let key = self.pop()? as u64;
+                self.storage.insert(key, value);
+            }
+            OpCode::Load => {
+                let key = self.pop()? as u64;
+                let value = self.storage.get(&key).copied().unwrap_or(0);
+                self.push(value)?;
+            }
+            OpCode::MLoad => {
+                let index = self.pop()?;
+                let value = *self.memory_word(index)?;
+                self.push(value)?;
+            }
+            OpCode::MStore => {
+                let value = self.pop()?;
+                let index = self.pop()?;
+                *self.memory_word(index)? = value;
+            }
+            OpCode::Transfer => {
+                let amount = self.pop()?;
+                let recipient = self.pop()?;
+                if amount < 0 {
+                    return Err(VmError::ContractError(format!(
+                        "TRANSFER amount must be non-negative, got {}",
+                        amount
+                    )));
+                }
+                self.transfers.push((recipient, amount));
+            }
+            OpCode::ExtLoad => {
+                let key = self.pop()? as u64;
+                let handle = self.pop()?;
+                let value = self.ext_load(handle, key)?;
+                self.push(value)?;
+            }
+            OpCode::SelfDestruct => {
+                self.self_destruct = Some(self.pop()?);
+                self.halted = true;
+            }
+            OpCode::Log => {
+                let value = self.pop()?;
+                self.logs.push(value);
+                tracing::debug!(contract = self.contract_address, value, "VM LOG");
+            }
+            OpCode::Emit => {
+                let data = self.pop()?;
+                let topic = self.pop()?;
+                self.events.push(Event { topic, data });
+                tracing::debug!(contract = self.contract_address, topic, data, "VM EMIT");
+            }
+            OpCode::Caller => {
+                let caller = self.caller.ok_or_else(|| {
+                    VmError::ContractError("CALLER requires a call context".into())
+                })?;
+                self.push(caller)?;
+            }
+            OpCode::CallContract => {
+                let handle = self.pop()?;
+                let value = self.call_contract(handle)?;
+                self.push(value)?;
+            }
+            OpCode::CallDataLoad => {
+                let index = self.pop()?;
+                let value = self.call_data_word(index);
+                self.push(value)?;
+            }
+            OpCode::CallDataSize => self.push(self.call_data.len() as i64)?,
+        }
+
+        self.pc += 1;
+        if self.pc >= bytecode.len() {
+            self.halted = true;
+        }
+        Ok(self.outcome())
+    }
+
+    fn outcome(&self) -> StepOutcome {
+        StepOutcome {
+            halted: self.halted,
+            pc: self.pc,
+            stack_top: self.stack.last().copied(),
+            gas_used: self.gas_used,
+        }
+    }
+
     fn push(&mut self, value: i64) -> VmResult<()> {
-        if self.stack.len() >= MAX_STACK_SIZE {
-            return Err(VmError::StackOverflow(MAX_STACK_SIZE));
+        if self.stack.len() >= self.stack_limit {
+            return Err(VmError::StackOverflow(self.stack_limit));
         }
         self.stack.push(value);
         Ok(())
@@ -189,6 +542,65 @@ impl VM {
             .ok_or(VmError::StackUnderflow { needed: 1, got: 0 })
     }
 
+    /// Memory word `index`, growing memory to reach it.
+    fn memory_word(&mut self, index: i64) -> VmResult<&mut i64> {
+        let slot = usize::try_from(index)
+            .ok()
+            .filter(|&slot| slot < self.memory_limit)
+            .ok_or(VmError::MemoryLimitExceeded {
+                index,
+                limit: self.memory_limit,
+            })?;
+        if slot >= self.memory.len() {
+            self.memory.resize(slot + 1, 0);
+        }
+        Ok(&mut self.memory[slot])
+    }
+
+    /// Call data word `index`; bytes past the end read as zero, and so
+    /// does a negative index.
+    fn call_data_word(&self, index: i64) -> i64 {
+        let mut word = [0u8; 8];
+        let start = usize::try_from(index).ok().and_then(|i| i.checked_mul(8));
+        if let Some(chunk) = start.and_then(|start| self.call_data.get(start..)) {
+            let len = chunk.len().min(8);
+            word[..len].copy_from_slice(&chunk[..len]);
+        }
+        i64::from_le_bytes(word)
+    }
+
+    fn ext_load(&self, handle: i64, key: u64) -> VmResult<i64> {
+        let world = self.world.ok_or_else(|| {
+            VmError::ContractError("EXTLOAD requires world state".into())
+        })?;
+        let contract = world
+            .contracts()
+            .iter()
+            .find(|(address, _)| address_handle(address) == handle)
+            .map(|(_, contract)| contract)
+            .ok_or_else(|| {
+                VmError::ContractError(format!("Unknown contract handle: {}", handle))
+            })?;
+        Ok(contract.storage.get(&key).copied().unwrap_or(0))
+    }
+
+    fn call_contract(&mut self, handle: i64) -> VmResult<i64> {
+        let (handler, caller) = self
+            .call_handler
+            .zip(self.contract_address)
+            .ok_or_else(|| VmError::ContractError("CALL requires a contract executor".into()))?;
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(VmError::ContractError(format!(
+                "Call depth limit of {} exceeded",
+                MAX_CALL_DEPTH
+            )));
+        }
+        let call = handler.call(caller, handle, self.depth + 1, self.gas_limit - self.gas_used)?;
+        self.gas_used += call.gas_used;
+        self.calls.push(call.frame);
+        Ok(call.return_value.unwrap_or(0))
+    }
+
     fn read_i64(&mut self, bytecode: &[u8]) -> VmResult<i64> {
         if self.pc + 8 > bytecode.len() {
             return Err(VmError::PcOutOfBounds {
@@ -202,14 +614,66 @@ impl VM {
         self.pc += 7; // +1 will happen in main loop
         Ok(i64::from_le_bytes(bytes))
     }
+
+    /// Computes `boundaries` unless they are already for `bytecode`, e.g.
+    /// when a run switches buffers midway.
+    fn load_boundaries(&mut self, bytecode: &[u8]) {
+        let of = (bytecode.as_ptr() as usize, bytecode.len());
+        if self.boundaries_of != of || self.boundaries.len() != bytecode.len() {
+            self.boundaries = instruction_boundaries(bytecode);
+            self.boundaries_of = of;
+        }
+    }
+
+    fn jump(&mut self, bytecode: &[u8], target: usize) -> VmResult<StepOutcome> {
+        if target >= bytecode.len() {
+            return Err(VmError::InvalidJump(target));
+        }
+        // `step` may be driven directly, without `execute`'s pass
+        self.load_boundaries(bytecode);
+        if !self.boundaries[target] {
+            return Err(VmError::JumpIntoImmediate(target));
+        }
+        self.pc = target;
+        Ok(self.outcome())
+    }
 }
 
-impl Default for VM {
+/// One flag per offset of `bytecode`, set where an instruction starts;
+/// the 8 bytes after each PUSH are its immediate and never start one.
+pub fn instruction_boundaries(bytecode: &[u8]) -> Vec<bool> {
+    let mut boundaries = vec![false; bytecode.len()];
+    let mut offset = 0;
+    while offset < bytecode.len() {
+        boundaries[offset] = true;
+        offset += if bytecode[offset] == OpCode::Push as u8 { 9 } else { 1 };
+    }
+    boundaries
+}
+
+impl Default for VM<'_> {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// `a / b` truncated toward zero.
+pub(crate) fn divide(a: i64, b: i64) -> VmResult<i64> {
+    if b == 0 {
+        return Err(VmError::DivisionByZero);
+    }
+    a.checked_div(b).ok_or(VmError::ArithmeticOverflow)
+}
+
+/// `a % b` with the sign of `a`. `i64::MIN % -1` is 0 rather than an
+/// overflow: the remainder itself always fits.
+pub(crate) fn remainder(a: i64, b: i64) -> VmResult<i64> {
+    if b == 0 {
+        return Err(VmError::DivisionByZero);
+    }
+    Ok(a.wrapping_rem(b))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -263,6 +727,64 @@ mod tests {
         assert_eq!(result.storage.get(&0), Some(&42));
     }
 
+    #[test]
+    fn test_memory_store_and_load() {
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 5); // index
+        push_val(&mut bytecode, 99); // value
+        bytecode.push(OpCode::MStore as u8);
+        push_val(&mut bytecode, 5);
+        bytecode.push(OpCode::MLoad as u8);
+        push_val(&mut bytecode, 4); // grown but never written
+        bytecode.push(OpCode::MLoad as u8);
+        bytecode.push(OpCode::Halt as u8);
+
+        let mut vm = VM::new();
+        let result = vm.execute(&bytecode).unwrap();
+        assert_eq!(result.stack, vec![99, 0]);
+        assert!(result.storage.is_empty());
+
+        // Memory does not survive into the next execution
+        let mut reload = Vec::new();
+        push_val(&mut reload, 5);
+        reload.push(OpCode::MLoad as u8);
+        assert_eq!(vm.execute(&reload).unwrap().stack.last(), Some(&0));
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 8);
+        push_val(&mut bytecode, 1);
+        bytecode.push(OpCode::MStore as u8);
+
+        let mut vm = VM::new().with_memory_limit(8);
+        let result = vm.execute(&bytecode);
+        assert!(matches!(result, Err(VmError::MemoryLimitExceeded { index: 8, limit: 8 })));
+
+        let mut negative = Vec::new();
+        push_val(&mut negative, -1);
+        negative.push(OpCode::MLoad as u8);
+        let result = VM::new().execute(&negative);
+        assert!(matches!(result, Err(VmError::MemoryLimitExceeded { index: -1, .. })));
+    }
+
+    #[test]
+    fn test_call_data_words() {
+        let mut call_data = 7i64.to_le_bytes().to_vec();
+        call_data.extend_from_slice(&[1, 2]);
+        let mut bytecode = Vec::new();
+        for index in [0, 1, 2, -1] {
+            push_val(&mut bytecode, index);
+            bytecode.push(OpCode::CallDataLoad as u8);
+        }
+        bytecode.push(OpCode::CallDataSize as u8);
+
+        let result = VM::new().with_call_data(&call_data).execute(&bytecode).unwrap();
+        // The short second word is zero-padded; past the end reads zero
+        assert_eq!(result.stack, vec![7, 0x0201, 0, 0, 10]);
+    }
+
     #[test]
     fn test_comparison() {
         let mut bytecode = Vec::new();
@@ -300,10 +822,45 @@ mod tests {
         assert!(matches!(result, Err(VmError::DivisionByZero)));
     }
 
+    fn run_binary(op: OpCode, a: i64, b: i64) -> VmResult<Vec<i64>> {
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, a);
+        push_val(&mut bytecode, b);
+        bytecode.push(op as u8);
+        bytecode.push(OpCode::Halt as u8);
+        VM::new().execute(&bytecode).map(|result| result.stack)
+    }
+
+    #[test]
+    fn test_division_truncates_toward_zero() {
+        assert_eq!(run_binary(OpCode::Div, -7, 2).unwrap(), vec![-3]);
+        assert_eq!(run_binary(OpCode::Mod, -7, 2).unwrap(), vec![-1]);
+        assert_eq!(run_binary(OpCode::Div, 7, -2).unwrap(), vec![-3]);
+        assert_eq!(run_binary(OpCode::Mod, 7, -2).unwrap(), vec![1]);
+        assert_eq!(run_binary(OpCode::DivMod, -7, 2).unwrap(), vec![-3, -1]);
+        assert_eq!(run_binary(OpCode::DivMod, 17, 5).unwrap(), vec![3, 2]);
+        assert!(matches!(
+            run_binary(OpCode::DivMod, 1, 0),
+            Err(VmError::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn test_division_overflow() {
+        assert!(matches!(
+            run_binary(OpCode::Div, i64::MIN, -1),
+            Err(VmError::ArithmeticOverflow)
+        ));
+        assert!(matches!(
+            run_binary(OpCode::DivMod, i64::MIN, -1),
+            Err(VmError::ArithmeticOverflow)
+        ));
+        assert_eq!(run_binary(OpCode::Mod, i64::MIN, -1).unwrap(), vec![0]);
+    }
+
     #[test]
     fn test_stack_underflow() {
-        let mut bytecode = Vec::new();
-        bytecode.push(OpCode::Pop as u8);
+        let bytecode = vec![OpCode::Pop as u8];
 
         let mut vm = VM::new();
         let result = vm.execute(&bytecode);
@@ -323,6 +880,254 @@ mod tests {
         assert_eq!(result.stack, vec![10]);
     }
 
+    #[test]
+    fn test_coverage_reports_taken_branch_only() {
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 1); // 0
+        push_val(&mut bytecode, 29); // 9
+        bytecode.push(OpCode::JumpIf as u8); // 18
+        push_val(&mut bytecode, 111); // 19, skipped
+        bytecode.push(OpCode::Halt as u8); // 28, skipped
+        push_val(&mut bytecode, 222); // 29
+        bytecode.push(OpCode::Halt as u8); // 38
+
+        let result = VM::new().with_coverage().execute(&bytecode).unwrap();
+        assert_eq!(result.stack, vec![222]);
+        assert_eq!(result.coverage, Some(vec![0, 9, 18, 29, 38]));
+
+        let result = VM::new().execute(&bytecode).unwrap();
+        assert!(result.coverage.is_none());
+    }
+
+    #[test]
+    fn test_trace_records_each_instruction() {
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 2); // 0
+        push_val(&mut bytecode, 3); // 9
+        bytecode.push(OpCode::Add as u8); // 18
+        bytecode.push(OpCode::Halt as u8); // 19
+
+        let (result, trace) = VM::new().execute_traced(&bytecode).unwrap();
+        assert_eq!(trace.len() as u64, result.steps_used);
+        let steps: Vec<_> = trace.iter().map(|e| (e.pc, e.opcode, e.stack_depth)).collect();
+        assert_eq!(
+            steps,
+            vec![
+                (0, OpCode::Push, 0),
+                (9, OpCode::Push, 1),
+                (18, OpCode::Add, 2),
+                (19, OpCode::Halt, 1),
+            ]
+        );
+        assert_eq!(trace.last().unwrap().gas_used, result.gas_used);
+    }
+
+    #[test]
+    fn test_gas_used_sums_opcode_costs() {
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 2);
+        push_val(&mut bytecode, 3);
+        bytecode.push(OpCode::Mul as u8);
+        bytecode.push(OpCode::Halt as u8);
+
+        let result = VM::new().execute(&bytecode).unwrap();
+        let expected = 2 * gas_cost(OpCode::Push) + gas_cost(OpCode::Mul) + gas_cost(OpCode::Halt);
+        assert_eq!(result.gas_used, expected);
+        assert_eq!(result.steps_used, 4);
+    }
+
+    #[test]
+    fn test_store_heavy_program_exhausts_gas_first() {
+        // Same instruction count: PUSH PUSH <op> repeated
+        let mut stores = Vec::new();
+        let mut adds = Vec::new();
+        for i in 0..20 {
+            push_val(&mut stores, i);
+            push_val(&mut stores, i);
+            stores.push(OpCode::Store as u8);
+            push_val(&mut adds, i);
+            push_val(&mut adds, i);
+            adds.push(OpCode::Add as u8);
+        }
+        let limit = 500;
+
+        let result = VM::new().with_gas_limit(limit).execute(&stores);
+        assert!(matches!(result, Err(VmError::GasLimitExceeded(500))));
+
+        let result = VM::new().with_gas_limit(limit).execute(&adds).unwrap();
+        assert_eq!(result.steps_used, 60);
+        assert!(result.gas_used <= limit);
+    }
+
+    #[test]
+    fn test_lowered_stack_limit_overflows_earlier() {
+        let mut bytecode = Vec::new();
+        for i in 0..10 {
+            push_val(&mut bytecode, i);
+        }
+        bytecode.push(OpCode::Halt as u8);
+
+        assert_eq!(VM::new().execute(&bytecode).unwrap().stack.len(), 10);
+        let result = VM::new().with_stack_limit(8).execute(&bytecode);
+        assert!(matches!(result, Err(VmError::StackOverflow(8))));
+    }
+
+    #[test]
+    fn test_raised_step_limit_lets_loop_finish() {
+        // Counts down from 1000: five instructions per iteration, as
+        // `JUMPIF loop` compiles to a PUSH of the target and JUMPIF
+        let source = "PUSH 1000\nloop:\nPUSH 1\nSUB\nDUP\nJUMPIF loop\nHALT";
+        let bytecode = crate::compiler::compile(source).unwrap();
+
+        let result = VM::new().with_step_limit(2_000).execute(&bytecode);
+        assert!(matches!(result, Err(VmError::StepLimitExceeded(2_000))));
+
+        let result = VM::new().with_step_limit(10_000).execute(&bytecode).unwrap();
+        assert_eq!(result.stack, vec![0]);
+        assert_eq!(result.steps_used, 1 + 5 * 1000 + 1);
+    }
+
+    #[test]
+    fn test_jump_to_instruction_boundary() {
+        // PUSH 1; PUSH 29; JUMPIF; PUSH 99; HALT; PUSH 7 (at 29); HALT
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 1);
+        push_val(&mut bytecode, 29);
+        bytecode.push(OpCode::JumpIf as u8);
+        push_val(&mut bytecode, 99);
+        bytecode.push(OpCode::Halt as u8);
+        push_val(&mut bytecode, 7);
+        bytecode.push(OpCode::Halt as u8);
+
+        let result = VM::new().execute(&bytecode).unwrap();
+        assert_eq!(result.stack, vec![7]);
+    }
+
+    #[test]
+    fn test_jump_into_push_immediate() {
+        // PUSH 13; JUMP; PUSH <HALT bytes>. Offset 13 holds a HALT byte,
+        // but it's data inside the second PUSH's immediate
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 13);
+        bytecode.push(OpCode::Jump as u8);
+        push_val(&mut bytecode, i64::from_le_bytes([OpCode::Halt as u8; 8]));
+        assert_eq!(bytecode[13], OpCode::Halt as u8);
+        let boundaries = instruction_boundaries(&bytecode);
+        assert!(boundaries[10] && !boundaries[13]);
+
+        let result = VM::new().execute(&bytecode);
+        assert!(matches!(result, Err(VmError::JumpIntoImmediate(13))));
+
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 100);
+        bytecode.push(OpCode::Jump as u8);
+        let result = VM::new().execute(&bytecode);
+        assert!(matches!(result, Err(VmError::InvalidJump(100))));
+    }
+
+    #[test]
+    fn test_boundaries_follow_stepped_bytecode() {
+        let mut first = Vec::new();
+        push_val(&mut first, 1);
+        first.push(OpCode::Halt as u8);
+        let second = vec![OpCode::Halt as u8; first.len()];
+
+        let mut vm = VM::new();
+        vm.load_boundaries(&first);
+        assert!(!vm.boundaries[1]);
+        // Same length, different bytecode
+        vm.load_boundaries(&second);
+        assert_eq!(vm.boundaries, instruction_boundaries(&second));
+        assert!(vm.boundaries[1]);
+    }
+
+    #[test]
+    fn test_new_run_sees_bytecode_edited_in_place() {
+        // PUSH 13; JUMP; HALT x3; PUSH 7; HALT
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 13);
+        bytecode.push(OpCode::Jump as u8);
+        bytecode.extend([OpCode::Halt as u8; 3]);
+        push_val(&mut bytecode, 7);
+        bytecode.push(OpCode::Halt as u8);
+
+        let mut vm = VM::new();
+        assert_eq!(vm.execute(&bytecode).unwrap().stack, vec![7]);
+
+        // Offset 10 now starts a PUSH whose immediate covers offset 13
+        bytecode[10] = OpCode::Push as u8;
+        let result = vm.execute(&bytecode);
+        assert!(matches!(result, Err(VmError::JumpIntoImmediate(13))));
+    }
+
+    #[test]
+    fn test_step_through_add() {
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 10);
+        push_val(&mut bytecode, 20);
+        bytecode.push(OpCode::Add as u8);
+        bytecode.push(OpCode::Halt as u8);
+
+        let mut vm = VM::new();
+        let mut stacks = Vec::new();
+        loop {
+            let outcome = vm.step(&bytecode).unwrap();
+            stacks.push(vm.snapshot().stack);
+            if outcome.halted {
+                assert_eq!(outcome.stack_top, Some(30));
+                break;
+            }
+            assert_eq!(outcome.pc, vm.snapshot().pc);
+        }
+        assert_eq!(stacks, vec![vec![10], vec![10, 20], vec![30], vec![30]]);
+        assert_eq!(vm.gas_used(), 3 + 3 + 3 + 2);
+
+        // Stepping a halted VM is a no-op
+        let outcome = vm.step(&bytecode).unwrap();
+        assert!(outcome.halted);
+        assert_eq!(outcome.gas_used, 11);
+    }
+
+    /// Answers every CALL with the callee handle plus the depth.
+    struct EchoDepth;
+
+    impl CallHandler for EchoDepth {
+        fn call(&self, _: &str, handle: i64, depth: usize, _: u64) -> VmResult<NestedCall> {
+            Ok(NestedCall {
+                return_value: Some(handle + depth as i64),
+                gas_used: 7,
+                frame: CallFrame {
+                    address: handle.to_string(),
+                    input: Vec::new(),
+                    steps_used: 1,
+                    result: None,
+                    error: None,
+                    calls: Vec::new(),
+                },
+            })
+        }
+    }
+
+    #[test]
+    fn test_call_pushes_handler_result_and_respects_depth() {
+        let mut bytecode = Vec::new();
+        push_val(&mut bytecode, 40);
+        bytecode.push(OpCode::CallContract as u8);
+
+        let mut vm = VM::new().with_contract_address("0xa").with_call_handler(&EchoDepth);
+        let result = vm.execute(&bytecode).unwrap();
+        assert_eq!(result.stack, vec![41]);
+        assert_eq!(result.gas_used, 3 + 100 + 7);
+        assert_eq!(result.calls.len(), 1);
+
+        let mut vm = VM::new()
+            .with_contract_address("0xa")
+            .with_call_handler(&EchoDepth)
+            .with_depth(MAX_CALL_DEPTH);
+        assert!(matches!(vm.execute(&bytecode), Err(VmError::ContractError(_))));
+        assert!(matches!(VM::new().execute(&bytecode), Err(VmError::ContractError(_))));
+    }
+
     #[test]
     fn test_with_existing_storage() {
         let mut storage = HashMap::new();